@@ -31,6 +31,7 @@ pub enum Membership {
     Invited,
     Joined,
     Left,
+    Knocked,
 }
 
 pub struct Room {
@@ -127,6 +128,7 @@ impl Room {
             SdkRoom::Invited(_) => Membership::Invited,
             SdkRoom::Joined(_) => Membership::Joined,
             SdkRoom::Left(_) => Membership::Left,
+            SdkRoom::Knocked(_) => Membership::Knocked,
         }
     }
 
@@ -48,7 +48,7 @@ use ruma::{
     },
     push::{Action, PushConditionRoomCtx, Ruleset},
     serde::Raw,
-    MilliSecondsSinceUnixEpoch, OwnedUserId, RoomId, UInt, UserId,
+    MilliSecondsSinceUnixEpoch, OwnedUserId, RoomId, RoomVersionId, UInt, UserId,
 };
 use tracing::{debug, info, trace, warn};
 
@@ -139,6 +139,19 @@ impl BaseClient {
         self.store.set_session_tokens(tokens)
     }
 
+    /// Get the users ignored by the current user.
+    ///
+    /// This is kept up to date with the `m.ignored_user_list` account data
+    /// received from sync.
+    pub fn ignored_users(&self) -> ReadOnlyMutable<Vec<OwnedUserId>> {
+        self.store.ignored_users()
+    }
+
+    /// Whether the given user is ignored by the current user.
+    pub fn is_user_ignored(&self, user_id: &UserId) -> bool {
+        self.store.ignored_users().lock_ref().iter().any(|ignored| **ignored == *user_id)
+    }
+
     /// Get the user login session.
     ///
     /// If the client is currently logged in, this will return a
@@ -415,12 +428,23 @@ impl BaseClient {
         room_info: &mut RoomInfo,
         changes: &mut StateChanges,
     ) {
+        let own_user_id = self.session_meta().map(|meta| &meta.user_id);
         let mut members = BTreeMap::new();
         let mut state_events = BTreeMap::new();
 
         for raw_event in events {
             match raw_event.deserialize() {
                 Ok(AnyStrippedStateEvent::RoomMember(member)) => {
+                    if Some(&member.state_key) == own_user_id
+                        && member.content.membership == MembershipState::Invite
+                    {
+                        room_info.inviter = Some(member.sender.clone());
+
+                        if member.content.is_direct == Some(true) {
+                            room_info.base_info.dm_targets.insert(member.sender.clone());
+                        }
+                    }
+
                     members.insert(member.state_key.clone(), member);
                 }
                 Ok(e) => {
@@ -550,6 +574,32 @@ impl BaseClient {
                         }
                     }
                 }
+
+                // `m.direct` replaces the previous list of DMs, so the targets
+                // that were removed from it are not DM targets anymore. Only
+                // rooms that were DMs already can lose targets.
+                for room in self.store.get_rooms() {
+                    if matches!(room.room_type(), RoomType::Invited | RoomType::Knocked)
+                        || (!room.is_direct() && !changes.room_infos.contains_key(room.room_id()))
+                    {
+                        continue;
+                    }
+
+                    let room_id = room.room_id();
+                    let is_target = |user_id: &OwnedUserId| {
+                        e.content
+                            .get(user_id)
+                            .map_or(false, |rooms| rooms.iter().any(|r| r == room_id))
+                    };
+
+                    if let Some(info) = changes.room_infos.get_mut(room_id) {
+                        info.base_info.dm_targets.retain(is_target);
+                    } else if !room.direct_targets().iter().all(is_target) {
+                        let mut info = room.clone_info();
+                        info.base_info.dm_targets.retain(is_target);
+                        changes.add_room(info);
+                    }
+                }
             }
 
             account_data.insert(event.event_type(), raw_event.clone());
@@ -625,6 +675,26 @@ impl BaseClient {
         Ok(room)
     }
 
+    /// User has knocked on a room.
+    ///
+    /// Update the internal and cached state accordingly. Return the final Room.
+    pub async fn room_knocked(&self, room_id: &RoomId) -> Result<Room> {
+        let room = self.store.get_or_create_stripped_room(room_id).await;
+        if room.room_type() != RoomType::Knocked {
+            let _sync_lock = self.sync_lock().read().await;
+
+            let mut room_info = room.clone_info();
+            room_info.mark_as_knocked();
+            room_info.mark_state_partially_synced();
+            let mut changes = StateChanges::default();
+            changes.add_stripped_room(room_info.clone());
+            self.store.save_changes(&changes).await?; // Update the store
+            room.update_summary(room_info); // Update the cached room handle
+        }
+
+        Ok(room)
+    }
+
     /// Get access to the store's sync lock.
     pub fn sync_lock(&self) -> &RwLock<()> {
         self.store.sync_lock()
@@ -680,6 +750,9 @@ impl BaseClient {
         let push_rules = self.get_push_rules(&changes).await?;
 
         let mut new_rooms = Rooms::default();
+        // The timelines as they were received, to be added to the event cache
+        // without the decrypted events.
+        let mut cached_timelines = Vec::new();
 
         for (room_id, new_info) in rooms.join {
             let room = self.store.get_or_create_room(&room_id, RoomType::Joined).await;
@@ -712,6 +785,7 @@ impl BaseClient {
                 room_info.mark_members_missing();
             }
 
+            let raw_timeline = new_info.timeline.clone();
             let timeline = self
                 .handle_timeline(
                     &room,
@@ -748,6 +822,12 @@ impl BaseClient {
                 }
             }
 
+            cached_timelines.push((
+                room_id.clone(),
+                raw_timeline,
+                room_info.room_version().cloned(),
+            ));
+
             let notification_count = new_info.unread_notifications.into();
             room_info.update_notification_count(notification_count);
 
@@ -780,6 +860,7 @@ impl BaseClient {
                 )
                 .await?;
 
+            let raw_timeline = new_info.timeline.clone();
             let timeline = self
                 .handle_timeline(
                     &room,
@@ -797,6 +878,12 @@ impl BaseClient {
             self.handle_room_account_data(&room_id, &new_info.account_data.events, &mut changes)
                 .await;
 
+            cached_timelines.push((
+                room_id.clone(),
+                raw_timeline,
+                room_info.room_version().cloned(),
+            ));
+
             changes.add_room(room_info);
             new_rooms
                 .leave
@@ -806,6 +893,8 @@ impl BaseClient {
         for (room_id, new_info) in rooms.invite {
             let room = self.store.get_or_create_stripped_room(&room_id).await;
             let mut room_info = room.clone_info();
+            // The room might have been knocked on before.
+            room_info.mark_as_invited();
 
             if let Some(r) = self.store.get_room(&room_id) {
                 let mut room_info = r.clone_info();
@@ -821,6 +910,20 @@ impl BaseClient {
             new_rooms.invite.insert(room_id, new_info);
         }
 
+        for (room_id, new_info) in rooms.knock {
+            let room = self.store.get_or_create_stripped_room(&room_id).await;
+            let mut room_info = room.clone_info();
+            room_info.mark_as_knocked();
+
+            // The state of a knocked room is stripped, just like the state of an
+            // invited room.
+            self.handle_invited_state(&new_info.knock_state.events, &mut room_info, &mut changes);
+
+            changes.add_stripped_room(room_info);
+
+            new_rooms.knock.insert(room_id, new_info);
+        }
+
         // TODO remove this, we're processing account data events here again
         // because we want to have the push rules in place before we process
         // rooms and their events, but we want to create the rooms before we
@@ -842,6 +945,31 @@ impl BaseClient {
         self.store.save_changes(&changes).await?;
         *self.store.sync_token.write().await = Some(next_batch.clone());
         self.apply_changes(&changes).await;
+
+        for (room_id, timeline, room_version) in cached_timelines {
+            let events: Vec<SyncTimelineEvent> =
+                timeline.events.into_iter().map(Into::into).collect();
+            let room_version = room_version.unwrap_or_else(|| {
+                warn!(%room_id, "Unable to find the room version, assuming version 9");
+                RoomVersionId::V9
+            });
+
+            // The state of this sync was already saved, failing to update the
+            // event cache must not fail the whole sync.
+            if let Err(error) = self
+                .store
+                .append_timeline_events(
+                    &room_id,
+                    &events,
+                    timeline.limited,
+                    timeline.prev_batch.as_deref(),
+                    &room_version,
+                )
+                .await
+            {
+                warn!(%room_id, ?error, "Failed to add the new events to the event cache");
+            }
+        }
         drop(sync_lock);
 
         info!("Processed a sync response in {:?}", now.elapsed());
@@ -864,6 +992,10 @@ impl BaseClient {
     }
 
     pub(crate) async fn apply_changes(&self, changes: &StateChanges) {
+        if let Some(raw) = changes.account_data.get(&GlobalAccountDataEventType::IgnoredUserList) {
+            self.store.update_ignored_users(&raw.clone().cast());
+        }
+
         for (room_id, room_info) in &changes.room_infos {
             if let Some(room) = self.store.get_room(room_id) {
                 room.update_summary(room_info.clone())
@@ -877,6 +1009,29 @@ impl BaseClient {
         }
     }
 
+    /// Receive global account data events that were set by this client.
+    ///
+    /// This applies them right away instead of waiting for them to come back
+    /// in a sync response.
+    ///
+    /// # Arguments
+    ///
+    /// * `events` - The global account data events that were set.
+    pub async fn receive_account_data(
+        &self,
+        events: &[Raw<AnyGlobalAccountDataEvent>],
+    ) -> Result<()> {
+        let _sync_lock = self.sync_lock().write().await;
+
+        let mut changes = StateChanges::default();
+        self.handle_account_data(events, &mut changes).await;
+
+        self.store.save_changes(&changes).await?;
+        self.apply_changes(&changes).await;
+
+        Ok(())
+    }
+
     /// Receive a get member events response and convert it to a deserialized
     /// `MembersResponse`
     ///
@@ -1188,7 +1343,8 @@ impl Default for BaseClient {
 #[cfg(test)]
 mod tests {
     use matrix_sdk_test::{
-        async_test, response_from_file, EventBuilder, InvitedRoomBuilder, LeftRoomBuilder,
+        async_test, response_from_file, EventBuilder, GlobalAccountDataTestEvent,
+        InvitedRoomBuilder, JoinedRoomBuilder, KnockedRoomBuilder, LeftRoomBuilder,
         StrippedStateTestEvent, TimelineTestEvent,
     };
     use ruma::{
@@ -1253,6 +1409,118 @@ mod tests {
         assert_eq!(client.get_room(room_id).unwrap().room_type(), RoomType::Invited);
     }
 
+    #[async_test]
+    async fn invite_after_knocking() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+
+        let client = BaseClient::new();
+        client
+            .set_session_meta(SessionMeta {
+                user_id: user_id.to_owned(),
+                device_id: "FOOBAR".into(),
+            })
+            .await
+            .unwrap();
+
+        let mut ev_builder = EventBuilder::new();
+
+        let response = ev_builder
+            .add_knocked_room(KnockedRoomBuilder::new(room_id).add_state_event(
+                StrippedStateTestEvent::Custom(json!({
+                    "content": {
+                        "membership": "knock",
+                        "reason": "Let me in",
+                    },
+                    "sender": user_id,
+                    "state_key": user_id,
+                    "type": "m.room.member",
+                })),
+            ))
+            .build_sync_response();
+        let sync = client.receive_sync_response(response).await.unwrap();
+        assert!(sync.rooms.knock.contains_key(room_id));
+        assert_eq!(client.get_room(room_id).unwrap().room_type(), RoomType::Knocked);
+        assert_eq!(client.get_stripped_rooms().len(), 1);
+
+        let response = ev_builder
+            .add_invited_room(InvitedRoomBuilder::new(room_id).add_state_event(
+                StrippedStateTestEvent::Custom(json!({
+                    "content": {
+                        "membership": "invite",
+                    },
+                    "event_id": "$143273582443PhrSn:example.org",
+                    "origin_server_ts": 1432735824653u64,
+                    "sender": "@example:example.org",
+                    "state_key": user_id,
+                    "type": "m.room.member",
+                })),
+            ))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+        assert_eq!(client.get_room(room_id).unwrap().room_type(), RoomType::Invited);
+    }
+
+    #[async_test]
+    async fn direct_rooms() {
+        let user_id = user_id!("@alice:example.org");
+        let bob = user_id!("@bob:example.org");
+        let joined_room_id = room_id!("!joined:example.org");
+        let invited_room_id = room_id!("!invited:example.org");
+
+        let client = BaseClient::new();
+        client
+            .set_session_meta(SessionMeta {
+                user_id: user_id.to_owned(),
+                device_id: "FOOBAR".into(),
+            })
+            .await
+            .unwrap();
+
+        let mut ev_builder = EventBuilder::new();
+
+        // The joined room is marked as a DM in `m.direct`, the invite is marked
+        // as a DM by the inviter.
+        let response = ev_builder
+            .add_joined_room(JoinedRoomBuilder::new(joined_room_id))
+            .add_invited_room(InvitedRoomBuilder::new(invited_room_id).add_state_event(
+                StrippedStateTestEvent::Custom(json!({
+                    "content": {
+                        "is_direct": true,
+                        "membership": "invite",
+                    },
+                    "sender": bob,
+                    "state_key": user_id,
+                    "type": "m.room.member",
+                })),
+            ))
+            .add_global_account_data_event(GlobalAccountDataTestEvent::Custom(json!({
+                "content": {
+                    bob.as_str(): [joined_room_id],
+                },
+                "type": "m.direct",
+            })))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        let joined_room = client.get_room(joined_room_id).unwrap();
+        assert!(joined_room.direct_targets().contains(bob));
+        let invited_room = client.get_room(invited_room_id).unwrap();
+        assert!(invited_room.direct_targets().contains(bob));
+
+        // The joined room is removed from `m.direct`.
+        let response = ev_builder
+            .add_global_account_data_event(GlobalAccountDataTestEvent::Custom(json!({
+                "content": {},
+                "type": "m.direct",
+            })))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        assert!(!client.get_room(joined_room_id).unwrap().is_direct());
+        assert!(client.get_room(invited_room_id).unwrap().is_direct());
+    }
+
     #[async_test]
     async fn invite_displayname_integration_test() {
         let user_id = user_id!("@alice:example.org");
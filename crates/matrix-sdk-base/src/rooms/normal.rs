@@ -78,6 +78,8 @@ pub enum RoomType {
     Left,
     /// The room is in a invited state.
     Invited,
+    /// The room is in a knocked state.
+    Knocked,
 }
 
 impl Room {
@@ -119,6 +121,12 @@ impl Room {
         self.inner.read().unwrap().room_type
     }
 
+    /// Get the user that invited the current user to this room, if it is in
+    /// the invited state.
+    pub fn inviter(&self) -> Option<OwnedUserId> {
+        self.inner.read().unwrap().inviter.clone()
+    }
+
     /// Whether this room's [`RoomType`](CreateRoomType) is `m.space`.
     pub fn is_space(&self) -> bool {
         self.inner.read().unwrap().room_type().map_or(false, |t| *t == CreateRoomType::Space)
@@ -526,6 +534,10 @@ pub struct RoomInfo {
     /// Base room info which holds some basic event contents important for the
     /// room state.
     pub(crate) base_info: BaseRoomInfo,
+    /// The user that invited the current user, if this room is in the invited
+    /// state.
+    #[serde(default)]
+    pub(crate) inviter: Option<OwnedUserId>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -576,6 +588,7 @@ impl RoomInfo {
             sync_info: SyncInfo::NoState,
             encryption_state_synced: false,
             base_info: BaseRoomInfo::new(),
+            inviter: None,
         }
     }
 
@@ -594,6 +607,11 @@ impl RoomInfo {
         self.room_type = RoomType::Invited;
     }
 
+    /// Mark this Room as knocked.
+    pub fn mark_as_knocked(&mut self) {
+        self.room_type = RoomType::Knocked;
+    }
+
     /// Mark this Room as having all the members synced.
     pub fn mark_members_synced(&mut self) {
         self.members_synced = true;
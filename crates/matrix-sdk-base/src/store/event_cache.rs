@@ -0,0 +1,118 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeSet;
+
+use matrix_sdk_common::deserialized_responses::SyncTimelineEvent;
+use ruma::{
+    canonical_json::redact,
+    events::{
+        room::redaction::SyncRoomRedactionEvent, AnySyncMessageLikeEvent, AnySyncTimelineEvent,
+    },
+    serde::Raw,
+    CanonicalJsonObject, OwnedEventId, RoomVersionId,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{Result, StoreError};
+
+/// A chunk of consecutive timeline events of a room, as stored in the event
+/// cache of a [`StateStore`](super::StateStore).
+///
+/// The cached timeline of a room is a list of chunks, linked from the most
+/// recent one to older ones through [`previous`](Self::previous). There may
+/// be a gap of unknown events between a chunk and the previous one, in which
+/// case [`has_gap`](Self::has_gap) is set.
+///
+/// Events are cached as they were received from the server, encrypted events
+/// are never stored in their decrypted form.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EventChunk {
+    /// The identifier of this chunk, unique in its room.
+    ///
+    /// Chunks that were created later have a greater identifier, so the most
+    /// recent chunk of a room is the one with the greatest identifier.
+    pub id: u64,
+
+    /// The identifier of the chunk that precedes this one, if any.
+    pub previous: Option<u64>,
+
+    /// Whether there may be unknown events between the start of this chunk
+    /// and the end of the previous one, or before this chunk if it has no
+    /// previous one.
+    pub has_gap: bool,
+
+    /// The token to paginate backwards from the start of this chunk, if it is
+    /// known.
+    pub prev_batch: Option<String>,
+
+    /// The events of this chunk, in chronological order.
+    pub events: Vec<SyncTimelineEvent>,
+}
+
+impl EventChunk {
+    /// The number of events after which events received from sync are added
+    /// to a new chunk instead of the most recent one.
+    pub const MAX_LEN: usize = 100;
+
+    /// The maximum number of chunks that are kept per room, older chunks are
+    /// evicted when new ones are added.
+    pub const MAX_CHUNKS: u64 = 10;
+
+    /// Whether the beginning of the room's timeline is known to be reached
+    /// with this chunk.
+    pub fn is_start_of_timeline(&self) -> bool {
+        self.previous.is_none() && !self.has_gap
+    }
+
+    /// Redact the events of this chunk whose ID is in the given set, and
+    /// remove them from the set.
+    ///
+    /// Returns whether any event was redacted.
+    pub(crate) fn redact_events(
+        &mut self,
+        event_ids: &mut BTreeSet<OwnedEventId>,
+        room_version: &RoomVersionId,
+    ) -> Result<bool> {
+        let mut redacted_any = false;
+
+        for event in &mut self.events {
+            let Some(event_id) = event.event_id() else { continue };
+            if !event_ids.remove(&event_id) {
+                continue;
+            }
+
+            let redacted =
+                redact(&event.event.deserialize_as::<CanonicalJsonObject>()?, room_version)
+                    .map_err(StoreError::Redaction)?;
+            event.event = Raw::new(&redacted)?.cast();
+            redacted_any = true;
+        }
+
+        Ok(redacted_any)
+    }
+}
+
+/// Get the IDs of the events that are redacted by the given events.
+pub(crate) fn redacted_event_ids(events: &[SyncTimelineEvent]) -> BTreeSet<OwnedEventId> {
+    events
+        .iter()
+        .filter_map(|event| match event.event.deserialize() {
+            Ok(AnySyncTimelineEvent::MessageLike(AnySyncMessageLikeEvent::RoomRedaction(
+                SyncRoomRedactionEvent::Original(redaction),
+            ))) => Some(redaction.redacts),
+            _ => None,
+        })
+        .collect()
+}
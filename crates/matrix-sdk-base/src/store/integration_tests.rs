@@ -150,12 +150,14 @@ macro_rules! statestore_integration_tests {
             },
             room_id,
             serde::Raw,
-            user_id, EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, RoomId, UserId,
+            user_id, EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, RoomId, RoomVersionId,
+            UserId,
         };
         use serde_json::{json, Value as JsonValue};
 
         use $crate::{
-            store::{Result as StoreResult, StateChanges, StateStore, StateStoreExt},
+            deserialized_responses::SyncTimelineEvent,
+            store::{EventChunk, Result as StoreResult, StateChanges, StateStore, StateStoreExt},
             RoomInfo, RoomType,
         };
 
@@ -682,6 +684,132 @@ macro_rules! statestore_integration_tests {
 
             assert_eq!(Some(value.as_ref()), read.as_deref());
 
+            let removed = store.remove_custom_value(key.as_bytes()).await?;
+            assert_eq!(Some(value.as_ref()), removed.as_deref());
+            assert!(store.get_custom_value(key.as_bytes()).await?.is_none());
+
+            Ok(())
+        }
+
+        #[async_test]
+        async fn test_event_cache() -> StoreResult<()> {
+            let room_id = room_id();
+            let store = get_store().await?;
+            let room_version = RoomVersionId::V9;
+
+            let event = |id: &str| SyncTimelineEvent {
+                event: Raw::new(&json!({
+                    "content": { "body": "hi", "msgtype": "m.text" },
+                    "event_id": id,
+                    "origin_server_ts": 0,
+                    "sender": user_id(),
+                    "type": "m.room.message",
+                }))
+                .unwrap()
+                .cast(),
+                encryption_info: None,
+            };
+            let event_ids = |chunk: &EventChunk| -> Vec<String> {
+                chunk.events.iter().map(|e| e.event_id().unwrap().to_string()).collect()
+            };
+
+            assert!(store.get_last_event_chunk(room_id).await?.is_none());
+
+            // The first events start a chunk…
+            store
+                .append_timeline_events(room_id, &[event("$a")], false, Some("t1"), &room_version)
+                .await?;
+            // …that is extended by later syncs.
+            store
+                .append_timeline_events(room_id, &[event("$b")], false, Some("t2"), &room_version)
+                .await?;
+
+            let chunk = store.get_last_event_chunk(room_id).await?.unwrap();
+            assert_eq!(event_ids(&chunk), ["$a", "$b"]);
+            assert_eq!(chunk.prev_batch.as_deref(), Some("t1"));
+            // Whether there are events before it is not known yet.
+            assert!(chunk.has_gap);
+            assert!(!chunk.is_start_of_timeline());
+
+            // A limited sync leaves a gap.
+            store
+                .append_timeline_events(room_id, &[event("$e")], true, Some("t3"), &room_version)
+                .await?;
+
+            let chunk = store.get_last_event_chunk(room_id).await?.unwrap();
+            assert_eq!(event_ids(&chunk), ["$e"]);
+            assert_eq!(chunk.previous, Some(0));
+            assert!(chunk.has_gap);
+            assert_eq!(chunk.prev_batch.as_deref(), Some("t3"));
+
+            // Back-pagination fills the gap partially…
+            let chunk = store
+                .prepend_timeline_events(room_id, chunk.id, vec![event("$d")], Some("t4".to_owned()))
+                .await?
+                .unwrap();
+            assert_eq!(event_ids(&chunk), ["$d", "$e"]);
+            assert!(chunk.has_gap);
+            assert_eq!(chunk.prev_batch.as_deref(), Some("t4"));
+
+            // …then completely.
+            let chunk = store
+                .prepend_timeline_events(
+                    room_id,
+                    chunk.id,
+                    vec![event("$c"), event("$b"), event("$a")],
+                    Some("t5".to_owned()),
+                )
+                .await?
+                .unwrap();
+            assert_eq!(event_ids(&chunk), ["$c", "$d", "$e"]);
+            assert!(!chunk.has_gap);
+
+            let previous = store.get_event_chunk(room_id, chunk.previous.unwrap()).await?.unwrap();
+            assert_eq!(event_ids(&previous), ["$a", "$b"]);
+
+            // Reaching the start of the room is only known from back-pagination.
+            let previous =
+                store.prepend_timeline_events(room_id, previous.id, vec![], None).await?.unwrap();
+            assert!(previous.is_start_of_timeline());
+
+            // Redactions are applied to the events of all chunks.
+            let redaction = SyncTimelineEvent {
+                event: Raw::new(&json!({
+                    "content": {},
+                    "event_id": "$redaction",
+                    "origin_server_ts": 0,
+                    "redacts": "$a",
+                    "sender": user_id(),
+                    "type": "m.room.redaction",
+                }))
+                .unwrap()
+                .cast(),
+                encryption_info: None,
+            };
+            store
+                .append_timeline_events(room_id, &[redaction], false, Some("t6"), &room_version)
+                .await?;
+
+            let previous = store.get_event_chunk(room_id, previous.id).await?.unwrap();
+            let content = previous.events[0].event.get_field::<JsonValue>("content").unwrap();
+            assert_eq!(content, Some(json!({})));
+
+            // Old chunks are evicted.
+            for i in 0..EventChunk::MAX_CHUNKS {
+                let event = event(&format!("$limited{i}"));
+                store.append_timeline_events(room_id, &[event], true, None, &room_version).await?;
+            }
+
+            let last_chunk = store.get_last_event_chunk(room_id).await?.unwrap();
+            let oldest_id = last_chunk.id + 1 - EventChunk::MAX_CHUNKS;
+            assert!(store.get_event_chunk(room_id, oldest_id - 1).await?.is_none());
+            let oldest_chunk = store.get_event_chunk(room_id, oldest_id).await?.unwrap();
+            assert_eq!(oldest_chunk.previous, None);
+            assert!(oldest_chunk.has_gap);
+
+            store.remove_room(room_id).await?;
+            assert!(store.get_last_event_chunk(room_id).await?.is_none());
+
             Ok(())
         }
 
@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use std::{
-    collections::BTreeSet,
+    collections::{BTreeMap, BTreeSet},
     sync::{Arc, RwLock},
 };
 
@@ -36,7 +36,7 @@ use ruma::{
 };
 use tracing::{info, warn};
 
-use super::{Result, RoomInfo, StateChanges, StateStore, StoreError};
+use super::{EventChunk, Result, RoomInfo, StateChanges, StateStore, StoreError};
 use crate::{deserialized_responses::MemberEvent, media::MediaRequest, MinimalRoomMemberEvent};
 
 /// In-Memory, non-persistent implementation of the `StateStore`
@@ -72,6 +72,7 @@ pub struct MemoryStore {
         DashMap<OwnedRoomId, DashMap<String, DashMap<OwnedEventId, DashMap<OwnedUserId, Receipt>>>>,
     >,
     custom: Arc<DashMap<Vec<u8>, Vec<u8>>>,
+    event_chunks: Arc<DashMap<OwnedRoomId, BTreeMap<u64, EventChunk>>>,
 }
 
 impl Default for MemoryStore {
@@ -109,6 +110,7 @@ impl MemoryStore {
                 100.try_into().expect("100 is a non-zero usize"),
             ))),
             custom: DashMap::new().into(),
+            event_chunks: Default::default(),
         }
     }
 
@@ -521,6 +523,10 @@ impl MemoryStore {
         Ok(self.custom.insert(key.to_vec(), value))
     }
 
+    async fn remove_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.custom.remove(key).map(|(_, value)| value))
+    }
+
     // The in-memory store doesn't cache media
     async fn add_media_content(&self, _request: &MediaRequest, _data: Vec<u8>) -> Result<()> {
         Ok(())
@@ -549,6 +555,29 @@ impl MemoryStore {
         self.stripped_members.remove(room_id);
         self.room_user_receipts.remove(room_id);
         self.room_event_receipts.remove(room_id);
+        self.event_chunks.remove(room_id);
+
+        Ok(())
+    }
+
+    async fn get_event_chunk(&self, room_id: &RoomId, chunk_id: u64) -> Result<Option<EventChunk>> {
+        Ok(self.event_chunks.get(room_id).and_then(|chunks| chunks.get(&chunk_id).cloned()))
+    }
+
+    async fn get_last_event_chunk(&self, room_id: &RoomId) -> Result<Option<EventChunk>> {
+        Ok(self.event_chunks.get(room_id).and_then(|chunks| chunks.values().next_back().cloned()))
+    }
+
+    async fn save_event_chunk(&self, room_id: &RoomId, chunk: &EventChunk) -> Result<()> {
+        self.event_chunks.entry(room_id.to_owned()).or_default().insert(chunk.id, chunk.clone());
+
+        Ok(())
+    }
+
+    async fn remove_event_chunk(&self, room_id: &RoomId, chunk_id: u64) -> Result<()> {
+        if let Some(mut chunks) = self.event_chunks.get_mut(room_id) {
+            chunks.remove(&chunk_id);
+        }
 
         Ok(())
     }
@@ -691,6 +720,15 @@ impl StateStore for MemoryStore {
         self.set_custom_value(key, value).await
     }
 
+    async fn remove_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.remove_custom_value(key).await
+    }
+
+    // Nothing is persisted, so nothing is encrypted.
+    fn has_store_cipher(&self) -> bool {
+        false
+    }
+
     async fn add_media_content(&self, request: &MediaRequest, data: Vec<u8>) -> Result<()> {
         self.add_media_content(request, data).await
     }
@@ -707,6 +745,22 @@ impl StateStore for MemoryStore {
         self.remove_media_content_for_uri(uri).await
     }
 
+    async fn get_event_chunk(&self, room_id: &RoomId, chunk_id: u64) -> Result<Option<EventChunk>> {
+        self.get_event_chunk(room_id, chunk_id).await
+    }
+
+    async fn get_last_event_chunk(&self, room_id: &RoomId) -> Result<Option<EventChunk>> {
+        self.get_last_event_chunk(room_id).await
+    }
+
+    async fn save_event_chunk(&self, room_id: &RoomId, chunk: &EventChunk) -> Result<()> {
+        self.save_event_chunk(room_id, chunk).await
+    }
+
+    async fn remove_event_chunk(&self, room_id: &RoomId, chunk_id: u64) -> Result<()> {
+        self.remove_event_chunk(room_id, chunk_id).await
+    }
+
     async fn remove_room(&self, room_id: &RoomId) -> Result<()> {
         self.remove_room(room_id).await
     }
@@ -47,6 +47,7 @@ pub use matrix_sdk_store_encryption::Error as StoreEncryptionError;
 use ruma::{
     api::client::push::get_notifications::v3::Notification,
     events::{
+        ignored_user_list::{IgnoredUserListEvent, IgnoredUserListEventContent},
         presence::PresenceEvent,
         receipt::{Receipt, ReceiptEventContent, ReceiptType},
         room::{
@@ -60,24 +61,26 @@ use ruma::{
         StaticEventContent, SyncStateEvent,
     },
     serde::Raw,
-    EventId, MxcUri, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, UserId,
+    EventId, MxcUri, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, RoomVersionId, UserId,
 };
 use serde::de::DeserializeOwned;
+use tracing::warn;
 
 /// BoxStream of owned Types
 pub type BoxStream<T> = Pin<Box<dyn futures_util::Stream<Item = T> + Send>>;
 
 use crate::{
-    deserialized_responses::MemberEvent,
+    deserialized_responses::{MemberEvent, SyncTimelineEvent},
     media::MediaRequest,
     rooms::{RoomInfo, RoomType},
     MinimalRoomMemberEvent, Room, Session, SessionMeta, SessionTokens,
 };
 
 pub(crate) mod ambiguity_map;
+mod event_cache;
 mod memory_store;
 
-pub use self::memory_store::MemoryStore;
+pub use self::{event_cache::EventChunk, memory_store::MemoryStore};
 
 /// State store specific error type.
 #[derive(Debug, thiserror::Error)]
@@ -332,6 +335,17 @@ pub trait StateStore: AsyncTraitDeps {
     /// * `value` - The value to insert
     async fn set_custom_value(&self, key: &[u8], value: Vec<u8>) -> Result<Option<Vec<u8>>>;
 
+    /// Remove arbitrary data from the custom store and return it if existed
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key to remove data for
+    async fn remove_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Whether the values of this store are encrypted with a store cipher
+    /// before they are persisted.
+    fn has_store_cipher(&self) -> bool;
+
     /// Add a media file's content in the media store.
     ///
     /// # Arguments
@@ -363,6 +377,41 @@ pub trait StateStore: AsyncTraitDeps {
     /// * `uri` - The `MxcUri` of the media files.
     async fn remove_media_content_for_uri(&self, uri: &MxcUri) -> Result<()>;
 
+    /// Get a chunk of the cached timeline of a room.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room the chunk belongs to.
+    ///
+    /// * `chunk_id` - The id of the chunk.
+    async fn get_event_chunk(&self, room_id: &RoomId, chunk_id: u64) -> Result<Option<EventChunk>>;
+
+    /// Get the most recent chunk of the cached timeline of a room.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room the chunk belongs to.
+    async fn get_last_event_chunk(&self, room_id: &RoomId) -> Result<Option<EventChunk>>;
+
+    /// Save a chunk of the cached timeline of a room, replacing the chunk with
+    /// the same id if there is one.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room the chunk belongs to.
+    ///
+    /// * `chunk` - The chunk to save.
+    async fn save_event_chunk(&self, room_id: &RoomId, chunk: &EventChunk) -> Result<()>;
+
+    /// Remove a chunk from the cached timeline of a room.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room the chunk belongs to.
+    ///
+    /// * `chunk_id` - The id of the chunk.
+    async fn remove_event_chunk(&self, room_id: &RoomId, chunk_id: u64) -> Result<()>;
+
     /// Removes a room and all elements associated from the state store.
     ///
     /// # Arguments
@@ -458,6 +507,169 @@ pub trait StateStoreExt: StateStore {
     {
         Ok(self.get_room_account_data_event(room_id, C::TYPE.into()).await?.map(Raw::cast))
     }
+
+    /// Add events received from sync to the end of the cached timeline of a
+    /// room.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room the events belong to.
+    ///
+    /// * `events` - The events, in chronological order.
+    ///
+    /// * `limited` - Whether there may be a gap between the events that are
+    ///   already cached and the new ones.
+    ///
+    /// * `prev_batch` - The token to paginate backwards from the first of the
+    ///   new events.
+    ///
+    /// * `room_version` - The version of the room, used to apply the
+    ///   redactions among the new events to the cached events.
+    async fn append_timeline_events(
+        &self,
+        room_id: &RoomId,
+        events: &[SyncTimelineEvent],
+        limited: bool,
+        prev_batch: Option<&str>,
+        room_version: &RoomVersionId,
+    ) -> Result<()> {
+        let mut chunk = match self.get_last_event_chunk(room_id).await? {
+            Some(mut chunk) if !limited && chunk.events.len() < EventChunk::MAX_LEN => {
+                if events.is_empty() {
+                    return Ok(());
+                }
+
+                chunk.events.extend_from_slice(events);
+                chunk
+            }
+            Some(last_chunk) => {
+                if events.is_empty() && !limited {
+                    return Ok(());
+                }
+
+                EventChunk {
+                    id: last_chunk.id + 1,
+                    previous: Some(last_chunk.id),
+                    has_gap: limited,
+                    prev_batch: prev_batch.map(ToOwned::to_owned),
+                    events: events.to_vec(),
+                }
+            }
+            None => {
+                if events.is_empty() {
+                    return Ok(());
+                }
+
+                // Whether there are events before the first chunk is only known
+                // once back-pagination reaches the start of the room.
+                EventChunk {
+                    id: 0,
+                    previous: None,
+                    has_gap: true,
+                    prev_batch: prev_batch.map(ToOwned::to_owned),
+                    events: events.to_vec(),
+                }
+            }
+        };
+
+        let mut redacted_event_ids = event_cache::redacted_event_ids(events);
+        chunk.redact_events(&mut redacted_event_ids, room_version)?;
+        self.save_event_chunk(room_id, &chunk).await?;
+
+        let mut previous = chunk.previous;
+        while let Some(previous_id) = previous.filter(|_| !redacted_event_ids.is_empty()) {
+            let Some(mut previous_chunk) = self.get_event_chunk(room_id, previous_id).await? else {
+                break;
+            };
+
+            if previous_chunk.redact_events(&mut redacted_event_ids, room_version)? {
+                self.save_event_chunk(room_id, &previous_chunk).await?;
+            }
+            previous = previous_chunk.previous;
+        }
+
+        if let Some(evicted_id) = chunk.id.checked_sub(EventChunk::MAX_CHUNKS) {
+            self.remove_event_chunk(room_id, evicted_id).await?;
+
+            // The events before the oldest remaining chunk are unknown again.
+            if let Some(mut oldest_chunk) = self.get_event_chunk(room_id, evicted_id + 1).await? {
+                if oldest_chunk.previous == Some(evicted_id) {
+                    oldest_chunk.previous = None;
+                    oldest_chunk.has_gap = true;
+                    self.save_event_chunk(room_id, &oldest_chunk).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add events received from back-pagination to the start of a chunk of the
+    /// cached timeline of a room.
+    ///
+    /// If the events reach back to the events of the previous chunk, the gap
+    /// between both chunks is closed and the events that are already cached
+    /// are dropped.
+    ///
+    /// Returns the updated chunk, or `None` if the chunk doesn't exist.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room the events belong to.
+    ///
+    /// * `chunk_id` - The id of the chunk whose gap the events fill.
+    ///
+    /// * `events` - The events, in reverse-chronological order like in a
+    ///   backwards `/messages` response.
+    ///
+    /// * `prev_batch` - The token to continue paginating backwards from, `None`
+    ///   if the start of the room was reached.
+    async fn prepend_timeline_events(
+        &self,
+        room_id: &RoomId,
+        chunk_id: u64,
+        events: Vec<SyncTimelineEvent>,
+        prev_batch: Option<String>,
+    ) -> Result<Option<EventChunk>> {
+        let Some(mut chunk) = self.get_event_chunk(room_id, chunk_id).await? else {
+            return Ok(None);
+        };
+
+        let previous = match chunk.previous {
+            Some(previous_id) => self.get_event_chunk(room_id, previous_id).await?,
+            None => None,
+        };
+        let cached_event_ids: BTreeSet<_> =
+            previous.iter().flat_map(|c| &c.events).filter_map(|e| e.event_id()).collect();
+
+        let mut reached_previous = false;
+        let mut new_events = Vec::with_capacity(events.len() + chunk.events.len());
+        for event in events {
+            if event.event_id().map_or(false, |id| cached_event_ids.contains(&id)) {
+                reached_previous = true;
+                break;
+            }
+            new_events.push(event);
+        }
+        new_events.reverse();
+        new_events.append(&mut chunk.events);
+
+        chunk.events = new_events;
+        if reached_previous {
+            chunk.has_gap = false;
+            chunk.prev_batch = None;
+        } else if prev_batch.is_none() {
+            // This is the start of the room.
+            chunk.previous = None;
+            chunk.has_gap = false;
+            chunk.prev_batch = None;
+        } else {
+            chunk.prev_batch = prev_batch;
+        }
+        self.save_event_chunk(room_id, &chunk).await?;
+
+        Ok(Some(chunk))
+    }
 }
 
 #[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
@@ -503,6 +715,9 @@ pub(crate) struct Store {
     pub(super) session_tokens: Mutable<Option<SessionTokens>>,
     /// The current sync token that should be used for the next sync call.
     pub(super) sync_token: Arc<RwLock<Option<String>>>,
+    /// The users ignored by the current user, from the
+    /// `m.ignored_user_list` account data.
+    ignored_users: Mutable<Vec<OwnedUserId>>,
     rooms: Arc<DashMap<OwnedRoomId, Room>>,
     stripped_rooms: Arc<DashMap<OwnedRoomId, Room>>,
     /// A lock to synchronize access to the store, such that data by the sync is
@@ -521,6 +736,7 @@ impl Store {
             session_meta: Default::default(),
             session_tokens: Default::default(),
             sync_token: Default::default(),
+            ignored_users: Default::default(),
             rooms: Default::default(),
             stripped_rooms: Default::default(),
             sync_lock: Default::default(),
@@ -552,6 +768,12 @@ impl Store {
         let token = self.get_sync_token().await?;
         *self.sync_token.write().await = token;
 
+        if let Some(raw) =
+            self.inner.get_account_data_event_static::<IgnoredUserListEventContent>().await?
+        {
+            self.update_ignored_users(&raw);
+        }
+
         self.session_meta.set(session_meta).expect("Session Meta was already set");
 
         Ok(())
@@ -581,6 +803,20 @@ impl Store {
         Some(Session::from_parts(meta.to_owned(), tokens))
     }
 
+    /// The users ignored by the current user.
+    pub fn ignored_users(&self) -> ReadOnlyMutable<Vec<OwnedUserId>> {
+        self.ignored_users.read_only()
+    }
+
+    /// Update the users ignored by the current user from the given
+    /// `m.ignored_user_list` event.
+    pub(crate) fn update_ignored_users(&self, raw: &Raw<IgnoredUserListEvent>) {
+        match raw.deserialize() {
+            Ok(event) => self.ignored_users.set(event.content.ignored_users.into_keys().collect()),
+            Err(error) => warn!(?error, "Failed to deserialize the ignored user list"),
+        }
+    }
+
     /// Get all the rooms this store knows about.
     pub fn get_rooms(&self) -> Vec<Room> {
         self.rooms.iter().filter_map(|r| self.get_room(r.key())).collect()
@@ -593,7 +829,7 @@ impl Store {
             .and_then(|r| match r.room_type() {
                 RoomType::Joined => Some(r.clone()),
                 RoomType::Left => Some(r.clone()),
-                RoomType::Invited => self.get_stripped_room(room_id),
+                RoomType::Invited | RoomType::Knocked => self.get_stripped_room(room_id),
             })
             .or_else(|| self.get_stripped_room(room_id))
     }
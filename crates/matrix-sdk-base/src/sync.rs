@@ -21,7 +21,7 @@ use ruma::{
     api::client::{
         push::get_notifications::v3::Notification,
         sync::sync_events::{
-            v3::{Ephemeral, InvitedRoom, Presence, RoomAccountData, State},
+            v3::{Ephemeral, InvitedRoom, KnockedRoom, Presence, RoomAccountData, State},
             DeviceLists, UnreadNotificationsCount as RumaUnreadNotificationsCount,
         },
     },
@@ -69,6 +69,8 @@ pub struct Rooms {
     pub join: BTreeMap<OwnedRoomId, JoinedRoom>,
     /// The rooms that the user has been invited to.
     pub invite: BTreeMap<OwnedRoomId, InvitedRoom>,
+    /// The rooms that the user has knocked on.
+    pub knock: BTreeMap<OwnedRoomId, KnockedRoom>,
 }
 
 /// Updates to joined rooms.
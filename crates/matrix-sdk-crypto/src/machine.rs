@@ -19,6 +19,7 @@ use std::{
 };
 
 use dashmap::DashMap;
+use futures_core::Stream;
 use matrix_sdk_common::{
     deserialized_responses::{AlgorithmInfo, EncryptionInfo, TimelineEvent, VerificationState},
     locks::Mutex,
@@ -61,7 +62,7 @@ use crate::{
     session_manager::{GroupSessionManager, SessionManager},
     store::{
         Changes, CryptoStore, DeviceChanges, IdentityChanges, MemoryStore, Result as StoreResult,
-        SecretImportError, Store,
+        RoomKeyInfo, SecretImportError, Store,
     },
     types::{
         events::{
@@ -298,6 +299,17 @@ impl OlmMachine {
         self.store.tracked_users()
     }
 
+    /// Get a stream of the room keys that are received or imported from now
+    /// on.
+    ///
+    /// This includes keys received as `m.room_key` or `m.forwarded_room_key`
+    /// to-device events, and keys imported from an export or a backup. It can
+    /// be used to retry the decryption of events that couldn't be decrypted
+    /// before.
+    pub fn room_keys_received_stream(&self) -> impl Stream<Item = Vec<RoomKeyInfo>> {
+        self.store.room_keys_received_stream()
+    }
+
     /// Get the outgoing requests that need to be sent out.
     ///
     /// This returns a list of `OutGoingRequest`, those requests need to be sent
@@ -1001,7 +1013,9 @@ impl OlmMachine {
 
         changes.sessions.extend(changed_sessions);
 
+        let room_keys = changes.inbound_group_sessions.iter().map(RoomKeyInfo::from).collect();
         self.store.save_changes(changes).await?;
+        self.store.notify_room_keys_received(room_keys);
 
         Ok(events)
     }
@@ -1419,9 +1433,11 @@ impl OlmMachine {
 
         let imported_count = sessions.len();
 
+        let room_keys = sessions.iter().map(RoomKeyInfo::from).collect();
         let changes = Changes { inbound_group_sessions: sessions, ..Default::default() };
 
         self.store.save_changes(changes).await?;
+        self.store.notify_room_keys_received(room_keys);
 
         info!(total_count, imported_count, room_keys = ?keys, "Successfully imported room keys");
 
@@ -1578,6 +1594,7 @@ pub(crate) mod testing {
 pub(crate) mod tests {
     use std::{collections::BTreeMap, iter, sync::Arc};
 
+    use futures_util::{FutureExt, StreamExt};
     use matches::assert_matches;
     use matrix_sdk_test::{async_test, test_json};
     use ruma::{
@@ -2004,6 +2021,8 @@ pub(crate) mod tests {
         let alice_session =
             alice.group_session_manager.get_outbound_group_session(room_id).unwrap();
 
+        let mut room_keys_received_stream = Box::pin(bob.room_keys_received_stream());
+
         let decrypted = bob
             .receive_sync_changes(vec![event], &Default::default(), &Default::default(), None)
             .await
@@ -2022,6 +2041,15 @@ pub(crate) mod tests {
             bob.store.get_inbound_group_session(room_id, alice_session.session_id()).await;
 
         assert!(session.unwrap().is_some());
+
+        let room_keys = room_keys_received_stream
+            .next()
+            .now_or_never()
+            .flatten()
+            .expect("We should have received a notification about the new room key");
+        assert_eq!(room_keys.len(), 1);
+        assert_eq!(room_keys[0].room_id, room_id);
+        assert_eq!(room_keys[0].session_id, alice_session.session_id());
     }
 
     #[async_test]
@@ -51,15 +51,17 @@ use std::{
     fmt::Debug,
     io::Error as IoError,
     ops::Deref,
-    sync::Arc,
+    sync::{Arc, Mutex as StdMutex},
 };
 
 use async_trait::async_trait;
+use futures_channel::mpsc::{self, UnboundedSender};
+use futures_core::Stream;
 use matrix_sdk_common::{locks::Mutex, AsyncTraitDeps};
 pub use memorystore::MemoryStore;
 use ruma::{
-    events::secret::request::SecretName, DeviceId, IdParseError, OwnedDeviceId, OwnedUserId,
-    RoomId, TransactionId, UserId,
+    events::secret::request::SecretName, DeviceId, IdParseError, OwnedDeviceId, OwnedRoomId,
+    OwnedUserId, RoomId, TransactionId, UserId,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::Error as SerdeError;
@@ -77,6 +79,7 @@ use crate::{
         InboundGroupSession, OlmMessageHash, OutboundGroupSession, PrivateCrossSigningIdentity,
         ReadOnlyAccount, Session, SessionCreationError,
     },
+    types::EventEncryptionAlgorithm,
     utilities::encode,
     verification::VerificationMachine,
     CrossSigningStatus,
@@ -99,6 +102,31 @@ pub struct Store {
     identity: Arc<Mutex<PrivateCrossSigningIdentity>>,
     inner: Arc<dyn CryptoStore>,
     verification_machine: VerificationMachine,
+    room_keys_received_senders: Arc<StdMutex<Vec<UnboundedSender<Vec<RoomKeyInfo>>>>>,
+}
+
+/// Information about a room key that was received or imported.
+#[derive(Clone, Debug)]
+pub struct RoomKeyInfo {
+    /// The encryption algorithm the key can be used for.
+    pub algorithm: EventEncryptionAlgorithm,
+    /// The room the key belongs to.
+    pub room_id: OwnedRoomId,
+    /// The Curve25519 key of the device that created the key.
+    pub sender_key: Curve25519PublicKey,
+    /// The ID of the session the key belongs to.
+    pub session_id: String,
+}
+
+impl From<&InboundGroupSession> for RoomKeyInfo {
+    fn from(session: &InboundGroupSession) -> Self {
+        Self {
+            algorithm: session.algorithm().to_owned(),
+            room_id: session.room_id().to_owned(),
+            sender_key: session.sender_key(),
+            session_id: session.session_id().to_owned(),
+        }
+    }
 }
 
 #[derive(Default, Debug)]
@@ -268,7 +296,40 @@ impl Store {
         store: Arc<dyn CryptoStore>,
         verification_machine: VerificationMachine,
     ) -> Self {
-        Self { user_id, identity, inner: store, verification_machine }
+        Self {
+            user_id,
+            identity,
+            inner: store,
+            verification_machine,
+            room_keys_received_senders: Default::default(),
+        }
+    }
+
+    /// Get a stream of the room keys that are received or imported from now
+    /// on.
+    ///
+    /// Each item of the stream contains the keys that were saved to the store
+    /// at once.
+    pub fn room_keys_received_stream(&self) -> impl Stream<Item = Vec<RoomKeyInfo>> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.room_keys_received_senders.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Notify the listeners of [`room_keys_received_stream`] about new room
+    /// keys.
+    ///
+    /// [`room_keys_received_stream`]: Self::room_keys_received_stream
+    pub(crate) fn notify_room_keys_received(&self, room_keys: Vec<RoomKeyInfo>) {
+        if room_keys.is_empty() {
+            return;
+        }
+
+        // Drop the senders of streams that aren't listened to anymore.
+        self.room_keys_received_senders
+            .lock()
+            .unwrap()
+            .retain(|sender| sender.unbounded_send(room_keys.clone()).is_ok());
     }
 
     /// UserId associated with this store
@@ -29,7 +29,7 @@ use js_sys::Date as JsDate;
 use matrix_sdk_base::{
     deserialized_responses::MemberEvent,
     media::{MediaRequest, UniqueKey},
-    store::{Result as StoreResult, StateChanges, StateStore, StoreError},
+    store::{EventChunk, Result as StoreResult, StateChanges, StateStore, StoreError},
     MinimalStateEvent, RoomInfo,
 };
 use matrix_sdk_store_encryption::{Error as EncryptionError, StoreCipher};
@@ -109,7 +109,7 @@ impl From<IndexeddbStateStoreError> for StoreError {
 mod KEYS {
     // STORES
 
-    pub const CURRENT_DB_VERSION: f64 = 1.1;
+    pub const CURRENT_DB_VERSION: f64 = 1.2;
     pub const CURRENT_META_DB_VERSION: f64 = 2.0;
 
     pub const INTERNAL_STATE: &str = "matrix-sdk-state";
@@ -142,6 +142,8 @@ mod KEYS {
 
     pub const CUSTOM: &str = "custom";
 
+    pub const EVENT_CHUNKS: &str = "event_chunks";
+
     pub const SYNC_TOKEN: &str = "sync_token";
 
     /// All names of the state stores for convenience.
@@ -166,6 +168,7 @@ mod KEYS {
         ROOM_EVENT_RECEIPTS,
         MEDIA,
         CUSTOM,
+        EVENT_CHUNKS,
         SYNC_TOKEN,
     ];
 
@@ -173,6 +176,7 @@ mod KEYS {
 
     pub const STORE_KEY: &str = "store_key";
     pub const FILTER: &str = "filter";
+    pub const LAST_EVENT_CHUNK: &str = "last";
 }
 
 pub use KEYS::ALL_STORES;
@@ -367,6 +371,11 @@ impl IndexeddbStateStoreBuilder {
                 if recreate_stores {
                     drop_stores(evt.db())?;
                     create_stores(evt.db())?;
+                } else if evt.old_version() < 1.2
+                    && !evt.db().object_store_names().any(|n| n == KEYS::EVENT_CHUNKS)
+                {
+                    // migrating to version 1.2
+                    evt.db().create_object_store(KEYS::EVENT_CHUNKS)?;
                 }
                 Ok(())
             },
@@ -1155,6 +1164,20 @@ impl IndexeddbStateStore {
         Ok(prev)
     }
 
+    async fn remove_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let jskey = JsValue::from_str(core::str::from_utf8(key).map_err(StoreError::Codec)?);
+
+        let prev = self.get_custom_value_for_js(&jskey).await?;
+
+        let tx =
+            self.inner.transaction_on_one_with_mode(KEYS::CUSTOM, IdbTransactionMode::Readwrite)?;
+
+        tx.object_store(KEYS::CUSTOM)?.delete(&jskey)?;
+
+        tx.await.into_result().map_err(IndexeddbStateStoreError::from)?;
+        Ok(prev)
+    }
+
     async fn remove_media_content(&self, request: &MediaRequest) -> Result<()> {
         let key = self
             .encode_key(KEYS::MEDIA, (request.source.unique_key(), request.format.unique_key()));
@@ -1194,6 +1217,7 @@ impl IndexeddbStateStore {
             KEYS::ROOM_USER_RECEIPTS,
             KEYS::STRIPPED_ROOM_STATE,
             KEYS::STRIPPED_MEMBERS,
+            KEYS::EVENT_CHUNKS,
         ];
 
         let all_stores = {
@@ -1220,6 +1244,63 @@ impl IndexeddbStateStore {
         }
         tx.await.into_result().map_err(|e| e.into())
     }
+
+    async fn get_event_chunk(&self, room_id: &RoomId, chunk_id: u64) -> Result<Option<EventChunk>> {
+        self.inner
+            .transaction_on_one_with_mode(KEYS::EVENT_CHUNKS, IdbTransactionMode::Readonly)?
+            .object_store(KEYS::EVENT_CHUNKS)?
+            .get(&self.encode_key(KEYS::EVENT_CHUNKS, (room_id, chunk_id.to_string())))?
+            .await?
+            .map(|f| self.deserialize_event(f))
+            .transpose()
+    }
+
+    async fn get_last_event_chunk(&self, room_id: &RoomId) -> Result<Option<EventChunk>> {
+        let last_chunk_id: Option<u64> = self
+            .inner
+            .transaction_on_one_with_mode(KEYS::EVENT_CHUNKS, IdbTransactionMode::Readonly)?
+            .object_store(KEYS::EVENT_CHUNKS)?
+            .get(&self.encode_key(KEYS::EVENT_CHUNKS, (room_id, KEYS::LAST_EVENT_CHUNK)))?
+            .await?
+            .map(|f| self.deserialize_event(f))
+            .transpose()?;
+
+        match last_chunk_id {
+            Some(chunk_id) => self.get_event_chunk(room_id, chunk_id).await,
+            None => Ok(None),
+        }
+    }
+
+    async fn save_event_chunk(&self, room_id: &RoomId, chunk: &EventChunk) -> Result<()> {
+        let last_chunk_key = self.encode_key(KEYS::EVENT_CHUNKS, (room_id, KEYS::LAST_EVENT_CHUNK));
+        let tx = self
+            .inner
+            .transaction_on_one_with_mode(KEYS::EVENT_CHUNKS, IdbTransactionMode::Readwrite)?;
+        let store = tx.object_store(KEYS::EVENT_CHUNKS)?;
+
+        let last_chunk_id: Option<u64> =
+            store.get(&last_chunk_key)?.await?.map(|f| self.deserialize_event(f)).transpose()?;
+
+        store.put_key_val(
+            &self.encode_key(KEYS::EVENT_CHUNKS, (room_id, chunk.id.to_string())),
+            &self.serialize_event(chunk)?,
+        )?;
+        if last_chunk_id.map_or(true, |id| chunk.id >= id) {
+            store.put_key_val(&last_chunk_key, &self.serialize_event(&chunk.id)?)?;
+        }
+
+        tx.await.into_result().map_err(|e| e.into())
+    }
+
+    async fn remove_event_chunk(&self, room_id: &RoomId, chunk_id: u64) -> Result<()> {
+        let tx = self
+            .inner
+            .transaction_on_one_with_mode(KEYS::EVENT_CHUNKS, IdbTransactionMode::Readwrite)?;
+        tx.object_store(KEYS::EVENT_CHUNKS)?
+            .delete(&self.encode_key(KEYS::EVENT_CHUNKS, (room_id, chunk_id.to_string())))?;
+
+        tx.await.into_result().map_err(|e| e.into())
+    }
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -1364,6 +1445,14 @@ impl StateStore for IndexeddbStateStore {
         self.set_custom_value(key, value).await.map_err(|e| e.into())
     }
 
+    async fn remove_custom_value(&self, key: &[u8]) -> StoreResult<Option<Vec<u8>>> {
+        self.remove_custom_value(key).await.map_err(|e| e.into())
+    }
+
+    fn has_store_cipher(&self) -> bool {
+        self.store_cipher.is_some()
+    }
+
     async fn add_media_content(&self, request: &MediaRequest, data: Vec<u8>) -> StoreResult<()> {
         self.add_media_content(request, data).await.map_err(|e| e.into())
     }
@@ -1380,6 +1469,26 @@ impl StateStore for IndexeddbStateStore {
         self.remove_media_content_for_uri(uri).await.map_err(|e| e.into())
     }
 
+    async fn get_event_chunk(
+        &self,
+        room_id: &RoomId,
+        chunk_id: u64,
+    ) -> StoreResult<Option<EventChunk>> {
+        self.get_event_chunk(room_id, chunk_id).await.map_err(|e| e.into())
+    }
+
+    async fn get_last_event_chunk(&self, room_id: &RoomId) -> StoreResult<Option<EventChunk>> {
+        self.get_last_event_chunk(room_id).await.map_err(|e| e.into())
+    }
+
+    async fn save_event_chunk(&self, room_id: &RoomId, chunk: &EventChunk) -> StoreResult<()> {
+        self.save_event_chunk(room_id, chunk).await.map_err(|e| e.into())
+    }
+
+    async fn remove_event_chunk(&self, room_id: &RoomId, chunk_id: u64) -> StoreResult<()> {
+        self.remove_event_chunk(room_id, chunk_id).await.map_err(|e| e.into())
+    }
+
     async fn remove_room(&self, room_id: &RoomId) -> StoreResult<()> {
         self.remove_room(room_id).await.map_err(|e| e.into())
     }
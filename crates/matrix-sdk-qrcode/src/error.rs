@@ -38,6 +38,13 @@ pub enum DecodingError {
     /// The QR code data doesn't contain valid ed25519 keys.
     #[error("the QR code contains invalid ed25519 keys: {0}")]
     Keys(#[from] vodozemac::KeyError),
+    /// The login QR code data isn't valid JSON.
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    /// The login QR code uses an unsupported rendezvous algorithm or
+    /// transport.
+    #[error("the QR code uses an unsupported rendezvous algorithm or transport")]
+    Rendezvous,
 }
 
 /// Error type describing errors that happen while QR data is being encoded.
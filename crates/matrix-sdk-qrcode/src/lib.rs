@@ -17,10 +17,15 @@
 #![warn(missing_debug_implementations, missing_docs)]
 
 mod error;
+mod login;
 mod types;
 mod utils;
 
 pub use error::{DecodingError, EncodingError};
+pub use login::{
+    LoginIntent, LoginQrCodeData, Rendezvous, RendezvousTransport, RENDEZVOUS_ALGORITHM,
+    RENDEZVOUS_HTTP_TRANSPORT,
+};
 pub use qrcode;
 pub use types::{
     QrVerificationData, SelfVerificationData, SelfVerificationNoMasterKey, VerificationData,
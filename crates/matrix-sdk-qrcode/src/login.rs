@@ -0,0 +1,181 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use qrcode::{EcLevel, QrCode};
+use serde::{Deserialize, Serialize};
+use vodozemac::{Curve25519PublicKey, KeyError};
+
+use crate::error::{DecodingError, EncodingError};
+
+/// The algorithm of the secure channel set up over the rendezvous, as defined
+/// in [MSC3903].
+///
+/// [MSC3903]: https://github.com/matrix-org/matrix-spec-proposals/pull/3903
+pub const RENDEZVOUS_ALGORITHM: &str = "org.matrix.msc3903.rendezvous.v1.curve25519-aes-sha256";
+
+/// The HTTP rendezvous transport, as defined in [MSC3886].
+///
+/// [MSC3886]: https://github.com/matrix-org/matrix-spec-proposals/pull/3886
+pub const RENDEZVOUS_HTTP_TRANSPORT: &str = "org.matrix.msc3886.http.v1";
+
+/// The intent of the device showing a login QR code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LoginIntent {
+    /// The QR code is shown by the new device that wants to log in.
+    #[serde(rename = "login.start")]
+    Start,
+
+    /// The QR code is shown by a device that is already logged in.
+    #[serde(rename = "login.reconcile")]
+    Reconcile,
+}
+
+/// The transport of a rendezvous session.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RendezvousTransport {
+    /// The type of the transport.
+    #[serde(rename = "type")]
+    pub transport_type: String,
+
+    /// The URI of the rendezvous session.
+    pub uri: String,
+}
+
+/// The rendezvous session of a login QR code.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rendezvous {
+    /// The algorithm of the secure channel.
+    pub algorithm: String,
+
+    /// The base64-encoded Curve25519 public key of the device showing the QR
+    /// code.
+    pub key: String,
+
+    /// The transport of the rendezvous session.
+    pub transport: RendezvousTransport,
+}
+
+/// The data of a QR code to log in a new device, as defined in [MSC3906].
+///
+/// [MSC3906]: https://github.com/matrix-org/matrix-spec-proposals/pull/3906
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoginQrCodeData {
+    /// The intent of the device showing the QR code.
+    pub intent: LoginIntent,
+
+    /// The rendezvous session to connect to.
+    pub rendezvous: Rendezvous,
+}
+
+impl LoginQrCodeData {
+    /// Create a new `LoginQrCodeData` for the given rendezvous session over
+    /// HTTP.
+    ///
+    /// # Arguments
+    ///
+    /// * `intent` - The intent of the device showing the QR code.
+    ///
+    /// * `key` - The public key of the secure channel of the device showing
+    ///   the QR code.
+    ///
+    /// * `uri` - The URI of the rendezvous session.
+    pub fn new(intent: LoginIntent, key: Curve25519PublicKey, uri: String) -> Self {
+        Self {
+            intent,
+            rendezvous: Rendezvous {
+                algorithm: RENDEZVOUS_ALGORITHM.to_owned(),
+                key: key.to_base64(),
+                transport: RendezvousTransport {
+                    transport_type: RENDEZVOUS_HTTP_TRANSPORT.to_owned(),
+                    uri,
+                },
+            },
+        }
+    }
+
+    /// Decode the data of a scanned login QR code.
+    ///
+    /// This fails if the QR code uses an unsupported rendezvous algorithm or
+    /// transport.
+    pub fn from_bytes(bytes: impl AsRef<[u8]>) -> Result<Self, DecodingError> {
+        let data: Self = serde_json::from_slice(bytes.as_ref())?;
+
+        if data.rendezvous.algorithm != RENDEZVOUS_ALGORITHM
+            || data.rendezvous.transport.transport_type != RENDEZVOUS_HTTP_TRANSPORT
+        {
+            return Err(DecodingError::Rendezvous);
+        }
+
+        Ok(data)
+    }
+
+    /// Encode the `LoginQrCodeData` into a vector of bytes that can be encoded
+    /// as a QR code.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("login QR code data should always serialize")
+    }
+
+    /// Encode the `LoginQrCodeData` into a `QrCode`.
+    pub fn to_qr_code(&self) -> Result<QrCode, EncodingError> {
+        Ok(QrCode::with_error_correction_level(self.to_bytes(), EcLevel::L)?)
+    }
+
+    /// The public key of the secure channel of the device showing the QR code.
+    pub fn key(&self) -> Result<Curve25519PublicKey, KeyError> {
+        Curve25519PublicKey::from_base64(&self.rendezvous.key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vodozemac::Curve25519PublicKey;
+
+    use super::{LoginIntent, LoginQrCodeData, RENDEZVOUS_HTTP_TRANSPORT};
+    use crate::DecodingError;
+
+    #[test]
+    fn login_data_roundtrip() {
+        let key = Curve25519PublicKey::from_bytes([7; 32]);
+        let data = LoginQrCodeData::new(
+            LoginIntent::Reconcile,
+            key,
+            "https://rendezvous.example.org/abcdef".to_owned(),
+        );
+
+        let decoded = LoginQrCodeData::from_bytes(data.to_bytes()).unwrap();
+        assert_eq!(decoded, data);
+        assert_eq!(decoded.key().unwrap(), key);
+        assert_eq!(decoded.rendezvous.transport.transport_type, RENDEZVOUS_HTTP_TRANSPORT);
+
+        data.to_qr_code().unwrap();
+    }
+
+    #[test]
+    fn decode_unsupported_algorithm() {
+        let data = br#"{
+            "intent": "login.start",
+            "rendezvous": {
+                "algorithm": "m.rendezvous.unknown",
+                "key": "BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwc",
+                "transport": {
+                    "type": "org.matrix.msc3886.http.v1",
+                    "uri": "https://rendezvous.example.org/abcdef"
+                }
+            }
+        }"#;
+
+        let result = LoginQrCodeData::from_bytes(data);
+        assert!(matches!(result, Err(DecodingError::Rendezvous)));
+    }
+}
@@ -26,7 +26,7 @@ use futures_util::stream::{self, StreamExt, TryStreamExt};
 use matrix_sdk_base::{
     deserialized_responses::MemberEvent,
     media::{MediaRequest, UniqueKey},
-    store::{Result as StoreResult, StateChanges, StateStore, StoreError},
+    store::{EventChunk, Result as StoreResult, StateChanges, StateStore, StoreError},
     MinimalStateEvent, RoomInfo,
 };
 use matrix_sdk_store_encryption::{Error as KeyEncryptionError, StoreCipher};
@@ -123,8 +123,10 @@ const ACCOUNT_DATA: &str = "account-data";
 const CUSTOM: &str = "custom";
 const SYNC_TOKEN: &str = "sync_token";
 const DISPLAY_NAME: &str = "display-name";
+const EVENT_CHUNK: &str = "event-chunk";
 const INVITED_USER_ID: &str = "invited-user-id";
 const JOINED_USER_ID: &str = "joined-user-id";
+const LAST_EVENT_CHUNK: &str = "last-event-chunk";
 const MEDIA: &str = "media";
 const MEMBER: &str = "member";
 const PRESENCE: &str = "presence";
@@ -146,6 +148,7 @@ const ALL_DB_STORES: &[&str] = &[
     ACCOUNT_DATA,
     SYNC_TOKEN,
     DISPLAY_NAME,
+    EVENT_CHUNK,
     INVITED_USER_ID,
     JOINED_USER_ID,
     MEDIA,
@@ -324,6 +327,7 @@ pub struct SledStateStore {
     room_event_receipts: Tree,
     media: Tree,
     custom: Tree,
+    event_chunks: Tree,
 }
 
 impl std::fmt::Debug for SledStateStore {
@@ -369,6 +373,8 @@ impl SledStateStore {
 
         let custom = db.open_tree(CUSTOM)?;
 
+        let event_chunks = db.open_tree(EVENT_CHUNK)?;
+
         Ok(Self {
             path,
             inner: db,
@@ -393,6 +399,7 @@ impl SledStateStore {
             room_event_receipts,
             media,
             custom,
+            event_chunks,
         })
     }
 
@@ -1146,6 +1153,14 @@ impl SledStateStore {
         ret
     }
 
+    async fn remove_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let key = self.encode_key(CUSTOM, EncodeUnchecked::from(key));
+        let ret = self.custom.remove(key)?.map(|v| self.deserialize_value(&v)).transpose();
+        self.inner.flush_async().await?;
+
+        ret
+    }
+
     async fn remove_media_content(&self, request: &MediaRequest) -> Result<()> {
         self.media.remove(
             self.encode_key(MEDIA, (request.source.unique_key(), request.format.unique_key())),
@@ -1317,6 +1332,66 @@ impl SledStateStore {
             );
         ret?;
 
+        let mut event_chunks_batch = sled::Batch::default();
+        for key in self.event_chunks.scan_prefix(self.encode_key(EVENT_CHUNK, room_id)).keys() {
+            event_chunks_batch.remove(key?);
+        }
+        event_chunks_batch.remove(self.encode_key(LAST_EVENT_CHUNK, room_id));
+        self.event_chunks.apply_batch(event_chunks_batch)?;
+
+        self.inner.flush_async().await?;
+
+        Ok(())
+    }
+
+    async fn get_event_chunk(&self, room_id: &RoomId, chunk_id: u64) -> Result<Option<EventChunk>> {
+        let db = self.clone();
+        let key = self.encode_key(EVENT_CHUNK, (room_id, chunk_id.to_string()));
+        spawn_blocking(move || {
+            db.event_chunks.get(key)?.map(|c| db.deserialize_value(&c)).transpose()
+        })
+        .await?
+    }
+
+    async fn get_last_event_chunk(&self, room_id: &RoomId) -> Result<Option<EventChunk>> {
+        let db = self.clone();
+        let key = self.encode_key(LAST_EVENT_CHUNK, room_id);
+        let last_chunk_id = spawn_blocking(move || {
+            db.event_chunks.get(key)?.map(|id| db.deserialize_value::<u64>(&id)).transpose()
+        })
+        .await??;
+
+        match last_chunk_id {
+            Some(chunk_id) => self.get_event_chunk(room_id, chunk_id).await,
+            None => Ok(None),
+        }
+    }
+
+    async fn save_event_chunk(&self, room_id: &RoomId, chunk: &EventChunk) -> Result<()> {
+        let chunk_key = self.encode_key(EVENT_CHUNK, (room_id, chunk.id.to_string()));
+        let last_chunk_key = self.encode_key(LAST_EVENT_CHUNK, room_id);
+
+        let last_chunk_id = self
+            .event_chunks
+            .get(&last_chunk_key)?
+            .map(|id| self.deserialize_value::<u64>(&id))
+            .transpose()?;
+
+        let mut batch = sled::Batch::default();
+        batch.insert(chunk_key, self.serialize_value(chunk)?);
+        if last_chunk_id.map_or(true, |id| chunk.id >= id) {
+            batch.insert(last_chunk_key, self.serialize_value(&chunk.id)?);
+        }
+        self.event_chunks.apply_batch(batch)?;
+
+        self.inner.flush_async().await?;
+
+        Ok(())
+    }
+
+    async fn remove_event_chunk(&self, room_id: &RoomId, chunk_id: u64) -> Result<()> {
+        self.event_chunks.remove(self.encode_key(EVENT_CHUNK, (room_id, chunk_id.to_string())))?;
+
         self.inner.flush_async().await?;
 
         Ok(())
@@ -1477,6 +1552,14 @@ impl StateStore for SledStateStore {
         self.set_custom_value(key, value).await.map_err(Into::into)
     }
 
+    async fn remove_custom_value(&self, key: &[u8]) -> StoreResult<Option<Vec<u8>>> {
+        self.remove_custom_value(key).await.map_err(Into::into)
+    }
+
+    fn has_store_cipher(&self) -> bool {
+        self.store_cipher.is_some()
+    }
+
     async fn add_media_content(&self, request: &MediaRequest, data: Vec<u8>) -> StoreResult<()> {
         self.add_media_content(request, data).await.map_err(Into::into)
     }
@@ -1493,6 +1576,26 @@ impl StateStore for SledStateStore {
         self.remove_media_content_for_uri(uri).await.map_err(Into::into)
     }
 
+    async fn get_event_chunk(
+        &self,
+        room_id: &RoomId,
+        chunk_id: u64,
+    ) -> StoreResult<Option<EventChunk>> {
+        self.get_event_chunk(room_id, chunk_id).await.map_err(Into::into)
+    }
+
+    async fn get_last_event_chunk(&self, room_id: &RoomId) -> StoreResult<Option<EventChunk>> {
+        self.get_last_event_chunk(room_id).await.map_err(Into::into)
+    }
+
+    async fn save_event_chunk(&self, room_id: &RoomId, chunk: &EventChunk) -> StoreResult<()> {
+        self.save_event_chunk(room_id, chunk).await.map_err(Into::into)
+    }
+
+    async fn remove_event_chunk(&self, room_id: &RoomId, chunk_id: u64) -> StoreResult<()> {
+        self.remove_event_chunk(room_id, chunk_id).await.map_err(Into::into)
+    }
+
     async fn remove_room(&self, room_id: &RoomId) -> StoreResult<()> {
         self.remove_room(room_id).await.map_err(Into::into)
     }
@@ -14,6 +14,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::atomic::Ordering;
+
+use futures_signals::signal::Signal;
 use matrix_sdk_base::{
     media::{MediaFormat, MediaRequest},
     store::StateStoreExt,
@@ -26,6 +29,7 @@ use ruma::{
             request_3pid_management_token_via_email, request_3pid_management_token_via_msisdn,
         },
         config::set_global_account_data,
+        presence::set_presence,
         profile::{
             get_avatar_url, get_display_name, get_profile, set_avatar_url, set_display_name,
         },
@@ -33,16 +37,19 @@ use ruma::{
     },
     assign,
     events::{
-        room::MediaSource, AnyGlobalAccountDataEventContent, GlobalAccountDataEventContent,
+        ignored_user_list::{IgnoredUser, IgnoredUserListEventContent},
+        room::MediaSource,
+        AnyGlobalAccountDataEventContent, GlobalAccountDataEventContent,
         GlobalAccountDataEventType, StaticEventContent,
     },
+    presence::PresenceState,
     serde::Raw,
     thirdparty::Medium,
-    ClientSecret, MxcUri, OwnedMxcUri, SessionId, UInt,
+    ClientSecret, MxcUri, OwnedMxcUri, OwnedUserId, SessionId, UInt, UserId,
 };
 use serde::Deserialize;
 
-use crate::{config::RequestConfig, Client, Error, HttpError, Result};
+use crate::{config::RequestConfig, Client, Error, Feature, HttpError, Result};
 
 /// A high-level API to manage the client owner's account.
 ///
@@ -246,6 +253,82 @@ impl Account {
         Ok(self.client.send(request, Some(request_config)).await?)
     }
 
+    /// Set the presence of the account.
+    ///
+    /// Note that the presence is also set by every sync request, according to
+    /// [`SyncSettings::set_presence`]. To prevent it from overriding the
+    /// presence set here, the same presence should be used when syncing.
+    ///
+    /// # Arguments
+    ///
+    /// * `presence` - The new presence state.
+    ///
+    /// * `status_msg` - An optional message to show alongside the presence.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # block_on(async {
+    /// # let homeserver = Url::parse("http://localhost:8080")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// use matrix_sdk::ruma::presence::PresenceState;
+    ///
+    /// client.account().set_presence(PresenceState::Online, Some("Making cupcakes")).await?;
+    /// # anyhow::Ok(()) });
+    /// ```
+    ///
+    /// [`SyncSettings::set_presence`]: crate::config::SyncSettings::set_presence
+    pub async fn set_presence(
+        &self,
+        presence: PresenceState,
+        status_msg: Option<&str>,
+    ) -> Result<()> {
+        let user_id = self.client.user_id().ok_or(Error::AuthenticationRequired)?;
+        let request = assign!(set_presence::v3::Request::new(user_id.to_owned(), presence), {
+            status_msg: status_msg.map(ToOwned::to_owned),
+        });
+        self.client.send(request, None).await?;
+        Ok(())
+    }
+
+    /// Report whether the user is idle.
+    ///
+    /// This is a hook for clients that can detect when the user is away, like
+    /// desktop clients using the idle time of the system. While the user is
+    /// idle, the presence is set to `unavailable` instead of `online`,
+    /// including by sync requests. When the user is active again, the presence
+    /// is set back to `online`.
+    ///
+    /// The current status message of the account is kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `idle` - Whether the user is currently idle.
+    pub async fn set_idle(&self, idle: bool) -> Result<()> {
+        if self.is_idle() == idle {
+            return Ok(());
+        }
+
+        let user_id = self.client.user_id().ok_or(Error::AuthenticationRequired)?;
+        let status_msg =
+            self.client.cached_presence(user_id).await?.and_then(|content| content.status_msg);
+        let presence = if idle { PresenceState::Unavailable } else { PresenceState::Online };
+
+        // Only remember the new state once the presence was set, so a failure
+        // can be retried.
+        self.set_presence(presence, status_msg.as_deref()).await?;
+        self.client.inner.idle.store(idle, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /// Whether the user was reported as idle with [`set_idle`](Self::set_idle).
+    pub fn is_idle(&self) -> bool {
+        self.client.inner.idle.load(Ordering::SeqCst)
+    }
+
     /// Change the password of the account.
     ///
     /// # Arguments
@@ -264,6 +347,9 @@ impl Account {
     /// password is considered insecure by the homeserver, with details about
     /// the strength requirements in the error's message.
     ///
+    /// It returns [`Error::UnsupportedFeature`] without sending the request if
+    /// the homeserver doesn't allow to change the password.
+    ///
     /// # Example
     /// ```no_run
     /// # use matrix_sdk::Client;
@@ -293,6 +379,8 @@ impl Account {
         new_password: &str,
         auth_data: Option<AuthData>,
     ) -> Result<change_password::v3::Response> {
+        self.client.ensure_supported(Feature::ChangePassword).await?;
+
         let request = assign!(change_password::v3::Request::new(new_password.to_owned()), {
             auth: auth_data,
         });
@@ -633,6 +721,74 @@ impl Account {
         Ok(self.client.send(request, None).await?)
     }
 
+    /// Get the users ignored by the account.
+    ///
+    /// This uses the `m.ignored_user_list` account data received from sync.
+    pub fn ignored_users(&self) -> Vec<OwnedUserId> {
+        self.client.base_client().ignored_users().get_cloned()
+    }
+
+    /// Get the users ignored by the account as a [`Signal`].
+    ///
+    /// The value is updated whenever a new `m.ignored_user_list` account data
+    /// event is received from sync.
+    pub fn ignored_users_signal(&self) -> impl Signal<Item = Vec<OwnedUserId>> {
+        self.client.base_client().ignored_users().signal_cloned()
+    }
+
+    /// Ignore the given user.
+    ///
+    /// The events sent by ignored users are not passed to the event handlers,
+    /// and they are removed from the timelines. The invites they send are left
+    /// out of [`Client::invited_rooms`].
+    ///
+    /// The list of ignored users is updated once the change is received from
+    /// sync.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user to ignore.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # async {
+    /// # let client = Client::new("http://localhost:8080".parse()?).await?;
+    /// use matrix_sdk::ruma::user_id;
+    ///
+    /// client.account().ignore_user(user_id!("@spammer:example.org")).await?;
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub async fn ignore_user(&self, user_id: &UserId) -> Result<()> {
+        let mut content = self.ignored_user_list().await?;
+        content.ignored_users.insert(user_id.to_owned(), IgnoredUser::new());
+        self.set_account_data(content).await?;
+        Ok(())
+    }
+
+    /// Stop ignoring the given user.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user to stop ignoring.
+    pub async fn unignore_user(&self, user_id: &UserId) -> Result<()> {
+        let mut content = self.ignored_user_list().await?;
+        if content.ignored_users.remove(user_id).is_some() {
+            self.set_account_data(content).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn ignored_user_list(&self) -> Result<IgnoredUserListEventContent> {
+        Ok(self
+            .account_data::<IgnoredUserListEventContent>()
+            .await?
+            .map(|c| c.deserialize())
+            .transpose()?
+            .unwrap_or_default())
+    }
+
     /// Get the content of an account data event of statically-known type.
     ///
     /// # Example
@@ -15,8 +15,6 @@
 
 use std::{fmt, sync::Arc};
 
-#[cfg(target_arch = "wasm32")]
-use async_once_cell::OnceCell;
 use matrix_sdk_base::{
     locks::{Mutex, RwLock},
     store::StoreConfig,
@@ -27,11 +25,9 @@ use ruma::{
     OwnedServerName, ServerName,
 };
 use thiserror::Error;
-#[cfg(not(target_arch = "wasm32"))]
-use tokio::sync::OnceCell;
 use url::Url;
 
-use super::{Client, ClientInner};
+use super::{server_info::ServerVersions, Client, ClientInner};
 use crate::{
     config::RequestConfig,
     error::RumaApiError,
@@ -88,6 +84,7 @@ pub struct ClientBuilder {
     appservice_mode: bool,
     server_versions: Option<Box<[MatrixVersion]>>,
     handle_refresh_tokens: bool,
+    authentication_issuer: Option<Url>,
 }
 
 impl ClientBuilder {
@@ -101,6 +98,7 @@ impl ClientBuilder {
             appservice_mode: false,
             server_versions: None,
             handle_refresh_tokens: false,
+            authentication_issuer: None,
         }
     }
 
@@ -275,7 +273,12 @@ impl ClientBuilder {
     }
 
     /// Specify the Matrix versions supported by the homeserver manually, rather
-    /// than `build()` doing it using a `get_supported_versions` request.
+    /// than requesting them with a `get_supported_versions` request when they
+    /// are first needed.
+    ///
+    /// The versions are still requested once they become stale, or when the
+    /// unstable features of the homeserver are needed to check whether it
+    /// [`supports`](Client::supports) a feature.
     ///
     /// This is helpful for test code that doesn't care to mock that endpoint.
     pub fn server_versions(mut self, value: impl IntoIterator<Item = MatrixVersion>) -> Self {
@@ -314,6 +317,14 @@ impl ClientBuilder {
         self
     }
 
+    /// Set the OpenID Connect Provider that is trusted by the homeserver
+    /// manually, rather than discovering it from the well-known of the server
+    /// name.
+    pub fn authentication_issuer(mut self, issuer: Url) -> Self {
+        self.authentication_issuer = Some(issuer);
+        self
+    }
+
     /// Create a [`Client`] with the options set on this builder.
     ///
     /// # Errors
@@ -323,9 +334,7 @@ impl ClientBuilder {
     /// * Invalid input: a missing or invalid homeserver URL or invalid proxy
     ///   URL
     /// * HTTP error: If you supplied a user ID instead of a homeserver URL, a
-    ///   server discovery request is made which can fail; if you didn't set
-    ///   [`server_versions(false)`][Self::server_versions], that amounts to
-    ///   another request that can fail
+    ///   server discovery request is made which can fail
     pub async fn build(self) -> Result<Client, ClientBuildError> {
         let homeserver_cfg = self.homeserver_cfg.ok_or(ClientBuildError::MissingHomeserver)?;
 
@@ -358,7 +367,8 @@ impl ClientBuilder {
         let base_client = BaseClient::with_store_config(store_config);
         let http_client = HttpClient::new(inner_http_client.clone(), self.request_config);
 
-        let mut authentication_issuer: Option<Url> = None;
+        let mut authentication_issuer = self.authentication_issuer;
+        let mut account_management_url: Option<Url> = None;
         let homeserver = match homeserver_cfg {
             HomeserverConfig::Url(url) => url,
             HomeserverConfig::ServerName(server_name) => {
@@ -378,8 +388,11 @@ impl ClientBuilder {
                         err => ClientBuildError::Http(err),
                     })?;
 
-                if let Some(issuer) = well_known.authentication.map(|auth| auth.issuer) {
-                    authentication_issuer = Url::parse(&issuer).ok();
+                if let Some(auth) = well_known.authentication {
+                    if authentication_issuer.is_none() {
+                        authentication_issuer = Url::parse(&auth.issuer).ok();
+                    }
+                    account_management_url = auth.account.and_then(|url| Url::parse(&url).ok());
                 }
 
                 well_known.homeserver.base_url
@@ -392,23 +405,32 @@ impl ClientBuilder {
         let inner = Arc::new(ClientInner {
             homeserver,
             authentication_issuer,
+            account_management_url,
             http_client,
             base_client,
-            server_versions: OnceCell::new_with(self.server_versions),
+            server_versions: RwLock::new(self.server_versions.map(ServerVersions::preset)),
+            capabilities: Default::default(),
             #[cfg(feature = "e2e-encryption")]
             group_session_locks: Default::default(),
             #[cfg(feature = "e2e-encryption")]
             key_claim_lock: Default::default(),
+            direct_rooms_lock: Default::default(),
             members_request_locks: Default::default(),
             encryption_state_request_locks: Default::default(),
             typing_notice_times: Default::default(),
             event_handlers: Default::default(),
             notification_handlers: Default::default(),
+            notification_senders: Default::default(),
             appservice_mode: self.appservice_mode,
             respect_login_well_known: self.respect_login_well_known,
             sync_beat: event_listener::Event::new(),
             handle_refresh_tokens: self.handle_refresh_tokens,
             refresh_token_lock: Mutex::new(Ok(())),
+            send_queue: Default::default(),
+            presence_observers: Default::default(),
+            idle: Default::default(),
+            #[cfg(feature = "experimental-oidc")]
+            oidc: Default::default(),
         });
 
         Ok(Client { inner })
@@ -15,17 +15,20 @@
 // limitations under the License.
 
 use std::{
+    collections::{BTreeSet, HashSet, VecDeque},
     fmt::{self, Debug},
     future::Future,
     pin::Pin,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex as StdMutex, Weak,
+    },
 };
 
-#[cfg(target_arch = "wasm32")]
-use async_once_cell::OnceCell;
 use dashmap::DashMap;
+use futures_channel::mpsc;
 use futures_core::stream::Stream;
-use futures_signals::signal::Signal;
+use futures_signals::signal::{Mutable, Signal, SignalExt};
 use matrix_sdk_base::{
     BaseClient, RoomType, SendOutsideWasm, Session, SessionMeta, SessionTokens, StateStore,
     SyncOutsideWasm,
@@ -41,6 +44,7 @@ use ruma::{
         client::{
             account::{register, whoami},
             alias::get_alias,
+            config::get_global_account_data,
             device::{delete_devices, get_devices},
             directory::{get_public_rooms, get_public_rooms_filtered},
             discovery::{
@@ -49,7 +53,9 @@ use ruma::{
             },
             error::ErrorKind,
             filter::{create_filter::v3::Request as FilterUploadRequest, FilterDefinition},
+            knock::knock_room,
             membership::{join_room_by_id, join_room_by_id_or_alias},
+            presence::get_presence,
             push::get_notifications::v3::Notification,
             room::create_room,
             session::{
@@ -61,15 +67,20 @@ use ruma::{
         error::FromHttpResponseError,
         MatrixVersion, OutgoingRequest, SendAccessToken,
     },
-    assign, DeviceId, OwnedDeviceId, OwnedRoomId, OwnedServerName, RoomAliasId, RoomId,
-    RoomOrAliasId, ServerName, UInt, UserId,
+    assign,
+    events::{
+        direct::DirectEventContent, presence::PresenceEventContent,
+        room::encryption::RoomEncryptionEventContent, GlobalAccountDataEventType,
+        InitialStateEvent,
+    },
+    presence::PresenceState,
+    serde::Raw,
+    DeviceId, EventId, OwnedDeviceId, OwnedRoomId, OwnedServerName, OwnedUserId, RoomAliasId,
+    RoomId, RoomOrAliasId, ServerName, UInt, UserId,
 };
 use serde::de::DeserializeOwned;
-#[cfg(not(target_arch = "wasm32"))]
-use tokio::sync::OnceCell;
-#[cfg(feature = "e2e-encryption")]
-use tracing::error;
-use tracing::{debug, info, instrument};
+use serde_json::json;
+use tracing::{debug, error, info, instrument, trace, warn};
 use url::Url;
 
 #[cfg(feature = "e2e-encryption")]
@@ -80,20 +91,25 @@ use crate::{
     event_handler::{
         EventHandler, EventHandlerDropGuard, EventHandlerHandle, EventHandlerStore, SyncEvent,
     },
-    http_client::HttpClient,
+    http_client::{HttpClient, RateLimit},
     room,
+    send_queue::SendQueueInner,
     sync::SyncResponse,
-    Account, Error, Media, RefreshTokenError, Result, RumaApiError,
+    Account, Error, Media, NotificationItem, NotificationSettings, RefreshTokenError, Result,
+    RumaApiError, SendQueue,
 };
 
 mod builder;
 mod login_builder;
+mod server_info;
 
 #[cfg(feature = "sso-login")]
 pub use self::login_builder::SsoLoginBuilder;
+use self::server_info::{CachedCapabilities, ServerVersions};
 pub use self::{
     builder::{ClientBuildError, ClientBuilder},
     login_builder::LoginBuilder,
+    server_info::Feature,
 };
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -135,12 +151,18 @@ pub(crate) struct ClientInner {
     homeserver: RwLock<Url>,
     /// The OIDC Provider that is trusted by the homeserver.
     authentication_issuer: Option<RwLock<Url>>,
+    /// The URL where the user can manage their account, as advertised by the
+    /// homeserver.
+    pub(crate) account_management_url: Option<Url>,
     /// The underlying HTTP client.
-    http_client: HttpClient,
+    pub(crate) http_client: HttpClient,
     /// User session data.
     base_client: BaseClient,
     /// The Matrix versions the server supports (well-known ones only)
-    server_versions: OnceCell<Box<[MatrixVersion]>>,
+    /// The cached response of the `/versions` endpoint.
+    server_versions: RwLock<Option<ServerVersions>>,
+    /// The cached response of the `/capabilities` endpoint.
+    capabilities: RwLock<Option<CachedCapabilities>>,
     /// Locks making sure we only have one group session sharing request in
     /// flight per room.
     #[cfg(feature = "e2e-encryption")]
@@ -148,6 +170,8 @@ pub(crate) struct ClientInner {
     /// Lock making sure we're only doing one key claim request at a time.
     #[cfg(feature = "e2e-encryption")]
     pub(crate) key_claim_lock: Mutex<()>,
+    /// Lock making sure we're only updating `m.direct` once at a time.
+    pub(crate) direct_rooms_lock: Mutex<()>,
     pub(crate) members_request_locks: DashMap<OwnedRoomId, Arc<Mutex<()>>>,
     /// Locks for requests on the encryption state of rooms.
     pub(crate) encryption_state_request_locks: DashMap<OwnedRoomId, Arc<Mutex<()>>>,
@@ -156,6 +180,8 @@ pub(crate) struct ClientInner {
     pub(crate) event_handlers: EventHandlerStore,
     /// Notification handlers. See `register_notification_handler`.
     notification_handlers: RwLock<Vec<NotificationHandlerFn>>,
+    /// The senders of the streams returned by `notifications`.
+    pub(crate) notification_senders: StdMutex<Vec<mpsc::UnboundedSender<NotificationItem>>>,
     /// Whether the client should operate in application service style mode.
     /// This is low-level functionality. For an high-level API check the
     /// `matrix_sdk_appservice` crate.
@@ -168,12 +194,24 @@ pub(crate) struct ClientInner {
     handle_refresh_tokens: bool,
     /// Lock making sure we're only doing one token refresh at a time.
     refresh_token_lock: Mutex<Result<(), RefreshTokenError>>,
+    /// The persistent queue of events to send. See `send_queue`.
+    pub(crate) send_queue: SendQueueInner,
     /// An event that can be listened on to wait for a successful sync. The
     /// event will only be fired if a sync loop is running. Can be used for
     /// synchronization, e.g. if we send out a request to create a room, we can
     /// wait for the sync to get the data to fetch a room object from the state
     /// store.
     pub(crate) sync_beat: event_listener::Event,
+    /// The latest presence of the users that are observed with
+    /// `presence_signal`, updated from sync, and a handle that is alive as
+    /// long as one of the returned signals is.
+    pub(crate) presence_observers:
+        DashMap<OwnedUserId, (Mutable<Option<PresenceEventContent>>, Weak<()>)>,
+    /// Whether the user was reported as idle, see `Account::set_idle`.
+    pub(crate) idle: AtomicBool,
+    /// The OpenID Connect state of the client. See `oidc`.
+    #[cfg(feature = "experimental-oidc")]
+    pub(crate) oidc: crate::oidc::OidcData,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -211,7 +249,7 @@ impl Client {
     /// # Arguments
     ///
     /// * `homeserver_url` - The new URL to use.
-    async fn set_homeserver(&self, homeserver_url: Url) {
+    pub(crate) async fn set_homeserver(&self, homeserver_url: Url) {
         let mut homeserver = self.inner.homeserver.write().await;
         *homeserver = homeserver_url;
     }
@@ -219,7 +257,9 @@ impl Client {
     /// Get the capabilities of the homeserver.
     ///
     /// This method should be used to check what features are supported by the
-    /// homeserver.
+    /// homeserver. It always sends a request, and updates the capabilities
+    /// cached for [`capabilities`](Self::capabilities) and
+    /// [`supports`](Self::supports).
     ///
     /// # Example
     /// ```no_run
@@ -240,6 +280,12 @@ impl Client {
     /// ```
     pub async fn get_capabilities(&self) -> HttpResult<Capabilities> {
         let res = self.send(get_capabilities::v3::Request::new(), None).await?;
+
+        *self.inner.capabilities.write().await = Some(CachedCapabilities {
+            capabilities: res.capabilities.clone(),
+            fetched_at: Instant::now(),
+        });
+
         Ok(res.capabilities)
     }
 
@@ -300,6 +346,20 @@ impl Client {
         self.inner.http_client.request_config
     }
 
+    /// Get the last rate limit received from the homeserver as a [`Signal`].
+    ///
+    /// When the homeserver responds with an `M_LIMIT_EXCEEDED` error, the
+    /// client waits for the time requested by the homeserver before retrying,
+    /// and holds back the other requests of the same [`RateLimitClass`] in the
+    /// meantime. This signal allows to show that to the user. It is reset to
+    /// `None` once the rate limit is over, or once a request of its class
+    /// went through if the homeserver didn't say how long to wait.
+    ///
+    /// [`RateLimitClass`]: crate::RateLimitClass
+    pub fn rate_limit_signal(&self) -> impl Signal<Item = Option<RateLimit>> {
+        self.inner.http_client.last_rate_limit.signal_cloned()
+    }
+
     /// Is the client logged in.
     pub fn logged_in(&self) -> bool {
         self.inner.base_client.logged_in()
@@ -316,6 +376,19 @@ impl Client {
         Some(server.read().await.clone())
     }
 
+    /// Get the API to log in with the OpenID Connect Provider that is trusted
+    /// by the homeserver.
+    #[cfg(feature = "experimental-oidc")]
+    pub fn oidc(&self) -> crate::oidc::Oidc {
+        crate::oidc::Oidc::new(self.clone())
+    }
+
+    /// Get the API to log in a new device with a QR code.
+    #[cfg(feature = "experimental-qr-login")]
+    pub fn qr_login(&self) -> crate::qr_login::QrLogin {
+        crate::qr_login::QrLogin::new(self.clone())
+    }
+
     fn session_meta(&self) -> Option<&SessionMeta> {
         self.base_client().session_meta()
     }
@@ -502,6 +575,127 @@ impl Client {
         Account::new(self.clone())
     }
 
+    /// Get the presence of the given user.
+    ///
+    /// The presence received from sync is used if it is known, otherwise it
+    /// is requested from the homeserver.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user whose presence should be fetched.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # futures::executor::block_on(async {
+    /// # let homeserver = url::Url::parse("http://localhost:8080")?;
+    /// # let client = matrix_sdk::Client::new(homeserver).await?;
+    /// use matrix_sdk::ruma::user_id;
+    ///
+    /// let presence = client.get_presence(user_id!("@alice:localhost")).await?;
+    /// println!("Alice is {}", presence.presence);
+    /// # anyhow::Ok(()) });
+    /// ```
+    pub async fn get_presence(&self, user_id: &UserId) -> Result<PresenceEventContent> {
+        if let Some(content) = self.cached_presence(user_id).await? {
+            return Ok(content);
+        }
+
+        let request = get_presence::v3::Request::new(user_id.to_owned());
+        let response = self.send(request, None).await?;
+
+        let last_active_ago = response
+            .last_active_ago
+            .and_then(|duration| u64::try_from(duration.as_millis()).ok())
+            .and_then(UInt::new);
+        Ok(assign!(PresenceEventContent::new(response.presence), {
+            currently_active: response.currently_active,
+            last_active_ago,
+            status_msg: response.status_msg,
+        }))
+    }
+
+    /// Get the presence of the given user as a [`Signal`].
+    ///
+    /// The value is the latest presence of the user that was received from
+    /// sync, or `None` if it is unknown. It is updated with the presence
+    /// events of the following sync responses.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user whose presence should be observed.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # futures::executor::block_on(async {
+    /// # let homeserver = url::Url::parse("http://localhost:8080")?;
+    /// # let client = matrix_sdk::Client::new(homeserver).await?;
+    /// use futures_signals::signal::SignalExt;
+    /// use matrix_sdk::ruma::user_id;
+    ///
+    /// let signal = client.presence_signal(user_id!("@alice:localhost")).await?;
+    /// let future = signal.for_each(|presence| async move {
+    ///     if let Some(presence) = presence {
+    ///         println!("Alice is now {}", presence.presence);
+    ///     }
+    /// });
+    ///
+    /// tokio::spawn(future);
+    /// # anyhow::Ok(()) });
+    /// ```
+    pub async fn presence_signal(
+        &self,
+        user_id: &UserId,
+    ) -> Result<impl Signal<Item = Option<PresenceEventContent>>> {
+        let cached = self.cached_presence(user_id).await?;
+        let mut observer = self
+            .inner
+            .presence_observers
+            .entry(user_id.to_owned())
+            .or_insert_with(|| (Mutable::new(cached), Weak::new()));
+        let handle = observer.1.upgrade().unwrap_or_else(|| {
+            let handle = Arc::new(());
+            observer.1 = Arc::downgrade(&handle);
+            handle
+        });
+        let signal = observer.0.signal_cloned();
+
+        // The observer is dropped on the next sync once none of its signals are
+        // alive anymore.
+        Ok(signal.map(move |presence| {
+            let _handle = &handle;
+            presence
+        }))
+    }
+
+    /// The presence to set with a sync request, taking into account whether
+    /// the user is idle.
+    fn sync_presence(&self, presence: PresenceState) -> PresenceState {
+        if presence == PresenceState::Online && self.inner.idle.load(Ordering::SeqCst) {
+            PresenceState::Unavailable
+        } else {
+            presence
+        }
+    }
+
+    pub(crate) async fn cached_presence(
+        &self,
+        user_id: &UserId,
+    ) -> Result<Option<PresenceEventContent>> {
+        let Some(raw) = self.store().get_presence_event(user_id).await? else {
+            return Ok(None);
+        };
+
+        match raw.deserialize() {
+            Ok(event) => Ok(Some(event.content)),
+            Err(error) => {
+                warn!(%user_id, ?error, "Failed to deserialize cached presence event");
+                Ok(None)
+            }
+        }
+    }
+
     /// Get the encryption manager of the client.
     #[cfg(feature = "e2e-encryption")]
     pub fn encryption(&self) -> Encryption {
@@ -513,6 +707,16 @@ impl Client {
         Media::new(self.clone())
     }
 
+    /// Get the notification settings of the client.
+    pub fn notification_settings(&self) -> NotificationSettings {
+        NotificationSettings::new(self.clone())
+    }
+
+    /// Get the persistent send queue of the client.
+    pub fn send_queue(&self) -> SendQueue {
+        SendQueue::new(self.clone())
+    }
+
     /// Register a handler for a specific event type.
     ///
     /// The handler is a function or closure with one or more arguments. The
@@ -806,6 +1010,57 @@ impl Client {
         self.inner.notification_handlers.read().await
     }
 
+    /// Get a stream of the notifications received from sync.
+    ///
+    /// Unlike the notification handlers, the items of the stream are resolved
+    /// for display: they contain the decrypted event and the names of the room
+    /// and of the sender.
+    pub fn notifications(&self) -> impl Stream<Item = NotificationItem> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.inner.notification_senders.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Send the given notification to the streams returned by
+    /// [`notifications`](Self::notifications).
+    pub(crate) fn broadcast_notification(&self, item: NotificationItem) {
+        let mut senders = self.inner.notification_senders.lock().unwrap();
+        // Drop the senders of the streams that were dropped.
+        senders.retain(|sender| sender.unbounded_send(item.clone()).is_ok());
+    }
+
+    /// Whether there are streams listening to notifications.
+    pub(crate) fn has_notification_listeners(&self) -> bool {
+        !self.inner.notification_senders.lock().unwrap().is_empty()
+    }
+
+    /// Get the notification for the given event.
+    ///
+    /// This fetches the event from the homeserver, decrypts it if needed and
+    /// evaluates the push rules against it, so it can be used to display a
+    /// notification after receiving a push.
+    ///
+    /// Returns `None` if the room is not known or if the event doesn't trigger
+    /// a notification.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The ID of the room where the event was sent.
+    ///
+    /// * `event_id` - The ID of the event.
+    pub async fn get_notification(
+        &self,
+        room_id: &RoomId,
+        event_id: &EventId,
+    ) -> Result<Option<NotificationItem>> {
+        let Some(room) = self.get_room(room_id) else {
+            return Ok(None);
+        };
+
+        let event = room.event(event_id).await?;
+        NotificationItem::from_event(&room, event).await
+    }
+
     /// Get all the rooms the client knows about.
     ///
     /// This will return the list of joined, invited, and left rooms.
@@ -827,14 +1082,30 @@ impl Client {
     }
 
     /// Returns the invited rooms this client knows about.
+    ///
+    /// Invites from users that are ignored by the current user are left out.
     pub fn invited_rooms(&self) -> Vec<room::Invited> {
         self.base_client()
             .get_stripped_rooms()
             .into_iter()
+            .filter(|room| {
+                !room
+                    .inviter()
+                    .map_or(false, |inviter| self.base_client().is_user_ignored(&inviter))
+            })
             .filter_map(|room| room::Invited::new(self, room))
             .collect()
     }
 
+    /// Returns the knocked rooms this client knows about.
+    pub fn knocked_rooms(&self) -> Vec<room::Knocked> {
+        self.base_client()
+            .get_stripped_rooms()
+            .into_iter()
+            .filter_map(|room| room::Knocked::new(self, room))
+            .collect()
+    }
+
     /// Returns the left rooms this client knows about.
     pub fn left_rooms(&self) -> Vec<room::Left> {
         self.base_client()
@@ -882,6 +1153,67 @@ impl Client {
         self.base_client().get_room(room_id).and_then(|room| room::Left::new(self, room))
     }
 
+    /// Get a knocked room with the given room id.
+    ///
+    /// # Arguments
+    ///
+    /// `room_id` - The unique id of the room that should be fetched.
+    pub fn get_knocked_room(&self, room_id: &RoomId) -> Option<room::Knocked> {
+        self.base_client().get_room(room_id).and_then(|room| room::Knocked::new(self, room))
+    }
+
+    /// Get a space with the given room id.
+    ///
+    /// Returns `None` if the room is not known or is not a space.
+    ///
+    /// # Arguments
+    ///
+    /// `room_id` - The unique id of the space that should be fetched.
+    pub fn get_space(&self, room_id: &RoomId) -> Option<room::Space> {
+        self.base_client().get_room(room_id).and_then(|room| room::Space::new(self, room))
+    }
+
+    /// Get the known rooms that are part of the given space, recursively.
+    ///
+    /// This only uses the locally known state: rooms that are not known to
+    /// the client are skipped, and so are their children if they are spaces.
+    /// Child spaces are part of the result too. Use
+    /// [`Space::hierarchy`](room::Space::hierarchy) to get the rooms from the
+    /// homeserver instead.
+    ///
+    /// The children of each space are listed in the order of
+    /// [`Space::children`](room::Space::children), before the children of
+    /// child spaces.
+    ///
+    /// # Arguments
+    ///
+    /// `space_id` - The unique id of the space.
+    pub async fn rooms_in_space(&self, space_id: &RoomId) -> Result<Vec<room::Room>> {
+        let mut rooms = Vec::new();
+        let mut visited = BTreeSet::from([space_id.to_owned()]);
+        let mut spaces = VecDeque::from([space_id.to_owned()]);
+
+        while let Some(space_id) = spaces.pop_front() {
+            let Some(space) = self.get_space(&space_id) else { continue };
+
+            for child in space.children().await? {
+                // Spaces can form cycles.
+                if !visited.insert(child.room_id().to_owned()) {
+                    continue;
+                }
+
+                let Some(room) = self.get_room(child.room_id()) else { continue };
+                if room.is_space() {
+                    spaces.push_back(child.room_id().to_owned());
+                }
+
+                rooms.push(room);
+            }
+        }
+
+        Ok(rooms)
+    }
+
     /// Resolve a room alias to a room id and a list of servers which know
     /// about it.
     ///
@@ -934,13 +1266,13 @@ impl Client {
                 .try_into_http_request::<Vec<u8>>(
                     homeserver.as_str(),
                     SendAccessToken::None,
-                    server_versions,
+                    &server_versions,
                 )
         } else {
             sso_login::v3::Request::new(redirect_url.to_owned()).try_into_http_request::<Vec<u8>>(
                 homeserver.as_str(),
                 SendAccessToken::None,
-                server_versions,
+                &server_versions,
             )
         };
 
@@ -1231,6 +1563,10 @@ impl Client {
     /// It can also be called at any time when a refresh token is available, it
     /// will invalidate the previous access token.
     ///
+    /// With the `experimental-oidc` feature, if a client is registered with the
+    /// OpenID Connect Provider of the homeserver, the access token is refreshed
+    /// with the Provider instead.
+    ///
     /// The new tokens in the response will be used by the `Client` and should
     /// be persisted to be able to [restore the session]. The response will
     /// always contain an access token that replaces the previous one. It
@@ -1311,20 +1647,15 @@ impl Client {
                 .refresh_token
                 .clone()
                 .ok_or(RefreshTokenError::RefreshTokenRequired)?;
-            let request = refresh_token::v3::Request::new(refresh_token);
-
-            let res = self
-                .inner
-                .http_client
-                .send(
-                    request,
-                    None,
-                    self.homeserver().await.to_string(),
-                    self.access_token().as_deref(),
-                    self.user_id(),
-                    self.server_versions().await?,
-                )
-                .await;
+
+            #[cfg(feature = "experimental-oidc")]
+            let res = if self.oidc().client_id().is_some() {
+                self.oidc().refresh_access_token(refresh_token).await
+            } else {
+                self.request_refreshed_token(refresh_token).await
+            };
+            #[cfg(not(feature = "experimental-oidc"))]
+            let res = self.request_refreshed_token(refresh_token).await;
 
             match res {
                 Ok(res) => {
@@ -1339,8 +1670,9 @@ impl Client {
                     Ok(Some(res))
                 }
                 Err(error) => {
-                    *guard = match error.as_ruma_api_error() {
-                        Some(RumaApiError::ClientApi(api_error)) => {
+                    *guard = match (&error, error.as_ruma_api_error()) {
+                        (HttpError::RefreshToken(refresh_error), _) => Err(refresh_error.clone()),
+                        (_, Some(RumaApiError::ClientApi(api_error))) => {
                             Err(RefreshTokenError::ClientApi(api_error.to_owned()))
                         }
                         _ => Err(RefreshTokenError::UnableToRefreshToken),
@@ -1512,6 +1844,38 @@ impl Client {
         room::Joined::new(self, base_room).ok_or(Error::InconsistentState)
     }
 
+    /// Request to join a room by `RoomId` or `RoomAliasId`.
+    ///
+    /// This only works for rooms whose join rule is `knock` or
+    /// `knock_restricted`. The room stays in the knocked state until a
+    /// moderator of the room accepts the request, by inviting the user, or
+    /// denies it.
+    ///
+    /// # Arguments
+    ///
+    /// * `alias` - The `RoomId` or `RoomAliasId` of the room to knock on.
+    /// An alias looks like `#name:example.com`.
+    ///
+    /// * `reason` - Optional reason for wanting to join the room, shown to the
+    /// moderators of the room.
+    ///
+    /// * `server_names` - Servers to knock through, required if the room ID
+    /// is not known to the homeserver.
+    pub async fn knock(
+        &self,
+        alias: &RoomOrAliasId,
+        reason: Option<&str>,
+        server_names: &[OwnedServerName],
+    ) -> Result<room::Knocked> {
+        let request = assign!(knock_room::v3::Request::new(alias.to_owned()), {
+            reason: reason.map(ToOwned::to_owned),
+            server_name: server_names.to_owned(),
+        });
+        let response = self.send(request, None).await?;
+        let base_room = self.base_client().room_knocked(&response.room_id).await?;
+        room::Knocked::new(self, base_room).ok_or(Error::InconsistentState)
+    }
+
     /// Search the homeserver's directory of public rooms.
     ///
     /// Sends a request to "_matrix/client/r0/publicRooms", returns
@@ -1594,6 +1958,169 @@ impl Client {
         Ok(room::Joined::new(self, base_room).unwrap())
     }
 
+    /// Create a DM room with the given users.
+    ///
+    /// The users are invited to the room, and the room is added to the
+    /// `m.direct` account data event of the current user.
+    ///
+    /// If the room was created but `m.direct` couldn't be updated,
+    /// [`Error::DmNotMarked`] is returned with the ID of the room, so that it
+    /// can be marked as a DM again instead of creating another room.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_ids` - The users to invite to the DM room.
+    ///
+    /// * `encrypted` - Whether to enable encryption right away, by adding the
+    ///   `m.room.encryption` event to the initial state of the room.
+    pub async fn create_dm(
+        &self,
+        user_ids: &[OwnedUserId],
+        encrypted: bool,
+    ) -> Result<room::Joined> {
+        let mut initial_state = Vec::new();
+        if encrypted {
+            let content = RoomEncryptionEventContent::with_recommended_defaults();
+            initial_state.push(InitialStateEvent::new(content).to_raw_any());
+        }
+
+        let request = assign!(create_room::v3::Request::new(), {
+            invite: user_ids.to_vec(),
+            is_direct: true,
+            preset: Some(create_room::v3::RoomPreset::TrustedPrivateChat),
+            initial_state,
+        });
+        let room = self.create_room(request).await?;
+        self.add_direct_room(room.room_id(), user_ids.iter().cloned()).await?;
+
+        Ok(room)
+    }
+
+    /// Get the DM room with exactly the given users, if any.
+    ///
+    /// Joined rooms are preferred, but this also looks at the rooms that the
+    /// current user was invited to as a DM.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_ids` - The users that the DM room is shared with.
+    pub fn get_dm_room(&self, user_ids: &[OwnedUserId]) -> Option<room::Room> {
+        let user_ids: HashSet<_> = user_ids.iter().cloned().collect();
+        let is_dm = |targets: HashSet<OwnedUserId>| !targets.is_empty() && targets == user_ids;
+
+        let room = self
+            .joined_rooms()
+            .into_iter()
+            .find(|room| is_dm(room.direct_targets()))
+            .map(room::Room::Joined)
+            .or_else(|| {
+                self.invited_rooms()
+                    .into_iter()
+                    .find(|room| is_dm(room.direct_targets()))
+                    .map(room::Room::Invited)
+            });
+
+        trace!(?room, "Found DM room");
+        room
+    }
+
+    /// Remove the stale entries from the `m.direct` account data event.
+    ///
+    /// This removes the rooms that the current user left and the duplicate
+    /// rooms, and the users that don't have any DM room left.
+    ///
+    /// Rooms that are not known to the client are kept, since they might not
+    /// have been received from the homeserver yet, e.g. before the first sync
+    /// completed.
+    pub async fn repair_dm_rooms(&self) -> Result<()> {
+        self.update_direct_rooms(|content| {
+            let mut changed = false;
+
+            for rooms in content.values_mut() {
+                let mut seen = HashSet::new();
+                let len = rooms.len();
+                rooms.retain(|room_id| {
+                    let left = matches!(self.get_room(room_id), Some(room::Room::Left(_)));
+                    !left && seen.insert(room_id.clone())
+                });
+                changed |= rooms.len() != len;
+            }
+
+            let len = content.len();
+            content.retain(|_, rooms| !rooms.is_empty());
+            changed || content.len() != len
+        })
+        .await
+    }
+
+    /// Add the given room to the `m.direct` account data event, as a DM with
+    /// the given users.
+    ///
+    /// Returns [`Error::DmNotMarked`] if `m.direct` couldn't be updated.
+    pub(crate) async fn add_direct_room(
+        &self,
+        room_id: &RoomId,
+        user_ids: impl IntoIterator<Item = OwnedUserId>,
+    ) -> Result<()> {
+        self.update_direct_rooms(|content| {
+            let mut changed = false;
+            for user_id in user_ids {
+                let rooms = content.entry(user_id).or_default();
+                if !rooms.iter().any(|r| r == room_id) {
+                    rooms.push(room_id.to_owned());
+                    changed = true;
+                }
+            }
+            changed
+        })
+        .await
+        .map_err(|error| {
+            warn!(%room_id, "Failed to add the room to m.direct: {error}");
+            Error::DmNotMarked { room_id: room_id.to_owned(), source: Box::new(error) }
+        })
+    }
+
+    /// Apply the given change to the `m.direct` account data event.
+    ///
+    /// The current content is fetched from the homeserver so changes made by
+    /// other clients are not lost. It is uploaded again only if `f` returns
+    /// `true`.
+    pub(crate) async fn update_direct_rooms(
+        &self,
+        f: impl FnOnce(&mut DirectEventContent) -> bool,
+    ) -> Result<()> {
+        let _guard = self.inner.direct_rooms_lock.lock().await;
+        let user_id = self.user_id().ok_or(Error::AuthenticationRequired)?;
+
+        let request = get_global_account_data::v3::Request::new(
+            user_id.to_owned(),
+            GlobalAccountDataEventType::Direct,
+        );
+        let mut content = match self.send(request, None).await {
+            Ok(response) => response.account_data.deserialize_as::<DirectEventContent>()?,
+            Err(err) if err.client_api_error_kind() == Some(&ErrorKind::NotFound) => {
+                DirectEventContent::default()
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        if !f(&mut content) {
+            return Ok(());
+        }
+
+        self.account().set_account_data(content.clone()).await?;
+
+        // Apply the change right away, so the rooms are marked as DMs before
+        // the next sync.
+        let event = Raw::new(&json!({
+            "type": GlobalAccountDataEventType::Direct.to_string(),
+            "content": content,
+        }))?;
+        self.base_client().receive_account_data(&[event.cast()]).await?;
+
+        Ok(())
+    }
+
     /// Search the homeserver's directory for public rooms with a filter.
     ///
     /// # Arguments
@@ -1708,6 +2235,27 @@ impl Client {
         res
     }
 
+    /// Request a new access token from the homeserver with the given refresh
+    /// token.
+    async fn request_refreshed_token(
+        &self,
+        refresh_token: String,
+    ) -> HttpResult<refresh_token::v3::Response> {
+        let request = refresh_token::v3::Request::new(refresh_token);
+
+        self.inner
+            .http_client
+            .send(
+                request,
+                None,
+                self.homeserver().await.to_string(),
+                self.access_token().as_deref(),
+                self.user_id(),
+                &self.server_versions().await?,
+            )
+            .await
+    }
+
     #[cfg(feature = "sliding-sync")]
     // FIXME: remove this as soon as Sliding-Sync isn't needing an external server
     // anymore
@@ -1772,13 +2320,13 @@ impl Client {
                 homeserver,
                 self.access_token().as_deref(),
                 self.user_id(),
-                self.server_versions().await?,
+                &self.server_versions().await?,
             )
             .await
     }
 
-    async fn request_server_versions(&self) -> HttpResult<Box<[MatrixVersion]>> {
-        let server_versions: Box<[MatrixVersion]> = self
+    async fn request_server_versions(&self) -> HttpResult<ServerVersions> {
+        let response = self
             .inner
             .http_client
             .send(
@@ -1789,28 +2337,130 @@ impl Client {
                 None,
                 &[MatrixVersion::V1_0],
             )
-            .await?
-            .known_versions()
-            .into_iter()
-            .collect();
+            .await?;
 
-        if server_versions.is_empty() {
-            Ok(vec![MatrixVersion::V1_0].into())
-        } else {
-            Ok(server_versions)
+        let mut versions: Box<[MatrixVersion]> = response.known_versions().into_iter().collect();
+        if versions.is_empty() {
+            versions = vec![MatrixVersion::V1_0].into();
         }
+
+        Ok(ServerVersions {
+            versions,
+            unstable_features: Some(response.unstable_features),
+            fetched_at: Instant::now(),
+        })
     }
 
-    pub(crate) async fn server_versions(&self) -> HttpResult<&[MatrixVersion]> {
-        #[cfg(target_arch = "wasm32")]
-        let server_versions =
-            self.inner.server_versions.get_or_try_init(self.request_server_versions()).await?;
+    /// Call `f` with the cached response of the `/versions` endpoint,
+    /// requesting it first if it is missing or stale, or if
+    /// `needs_unstable_features` is set and they are not known.
+    async fn with_server_versions<T>(
+        &self,
+        needs_unstable_features: bool,
+        f: impl FnOnce(&ServerVersions) -> T,
+    ) -> HttpResult<T> {
+        let is_usable = |server_versions: &ServerVersions| {
+            !server_versions.is_stale()
+                && (!needs_unstable_features || server_versions.unstable_features.is_some())
+        };
 
-        #[cfg(not(target_arch = "wasm32"))]
-        let server_versions =
-            self.inner.server_versions.get_or_try_init(|| self.request_server_versions()).await?;
+        if let Some(server_versions) = &*self.inner.server_versions.read().await {
+            if is_usable(server_versions) {
+                return Ok(f(server_versions));
+            }
+        }
 
-        Ok(server_versions)
+        let mut server_versions = self.inner.server_versions.write().await;
+        match &*server_versions {
+            // Another task refreshed them while we were waiting for the lock.
+            Some(cached) if is_usable(cached) => Ok(f(cached)),
+            _ => {
+                let fetched = self.request_server_versions().await?;
+                let result = f(&fetched);
+                *server_versions = Some(fetched);
+                Ok(result)
+            }
+        }
+    }
+
+    pub(crate) async fn server_versions(&self) -> HttpResult<Box<[MatrixVersion]>> {
+        self.with_server_versions(false, |server_versions| server_versions.versions.clone()).await
+    }
+
+    /// Get the capabilities of the homeserver.
+    ///
+    /// Unlike [`get_capabilities`](Self::get_capabilities), this uses the
+    /// cached capabilities if they were requested recently.
+    pub async fn capabilities(&self) -> HttpResult<Capabilities> {
+        if let Some(cached) = &*self.inner.capabilities.read().await {
+            if !cached.is_stale() {
+                return Ok(cached.capabilities.clone());
+            }
+        }
+
+        self.get_capabilities().await
+    }
+
+    /// Whether the homeserver supports the given feature.
+    ///
+    /// This uses the cached responses of the `/versions` and `/capabilities`
+    /// endpoints, that are refreshed periodically.
+    ///
+    /// The methods of the SDK that need one of these features return
+    /// [`Error::UnsupportedFeature`] when the homeserver doesn't support it.
+    pub async fn supports(&self, feature: Feature) -> Result<bool> {
+        let supported = match feature {
+            Feature::Threads => {
+                self.versions_or_unstable_feature_supported(
+                    MatrixVersion::V1_4,
+                    "org.matrix.msc3440.stable",
+                )
+                .await?
+            }
+            Feature::Relations => {
+                self.versions_or_unstable_feature_supported(
+                    MatrixVersion::V1_3,
+                    "org.matrix.msc2675",
+                )
+                .await?
+            }
+            Feature::SlidingSync => {
+                self.with_server_versions(true, |server_versions| {
+                    server_versions.supports_unstable_feature("org.matrix.msc3575")
+                })
+                .await?
+            }
+            Feature::ChangePassword => self.capabilities().await?.change_password.enabled,
+        };
+
+        Ok(supported)
+    }
+
+    /// Whether the homeserver supports the given Matrix version, or else the
+    /// given unstable feature.
+    ///
+    /// The unstable features are only requested if the version is not
+    /// supported.
+    async fn versions_or_unstable_feature_supported(
+        &self,
+        version: MatrixVersion,
+        unstable_feature: &str,
+    ) -> HttpResult<bool> {
+        if self.with_server_versions(false, |v| v.versions.contains(&version)).await? {
+            return Ok(true);
+        }
+
+        self.with_server_versions(true, |v| v.supports_unstable_feature(unstable_feature)).await
+    }
+
+    /// Return [`Error::UnsupportedFeature`] if the homeserver doesn't support
+    /// the given feature.
+    pub(crate) async fn ensure_supported(&self, feature: Feature) -> Result<()> {
+        if self.supports(feature).await? {
+            Ok(())
+        } else {
+            Err(Error::UnsupportedFeature(feature))
+        }
     }
 
     /// Get information of all our own devices.
@@ -2007,7 +2657,7 @@ impl Client {
             filter: sync_settings.filter,
             since: sync_settings.token,
             full_state: sync_settings.full_state,
-            set_presence: sync_settings.set_presence,
+            set_presence: self.sync_presence(sync_settings.set_presence),
             timeout: sync_settings.timeout,
         });
         let mut request_config = self.request_config();
@@ -2024,6 +2674,10 @@ impl Client {
             error!(error = ?e, "Error while sending outgoing E2EE requests");
         }
 
+        // The sync went through, so we're online and can try to send the
+        // events that are still waiting in the send queue.
+        self.send_queue().flush_in_background();
+
         self.inner.sync_beat.notify(usize::MAX);
 
         Ok(SyncResponse::new(next_batch, response))
@@ -0,0 +1,96 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use matrix_sdk_common::instant::Instant;
+use ruma::api::{client::discovery::get_capabilities::Capabilities, MatrixVersion};
+
+/// How long the information about the homeserver is cached before it is
+/// requested again.
+pub(crate) const SERVER_INFO_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// A feature that might not be supported by the homeserver.
+///
+/// See [`Client::supports`](crate::Client::supports).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Feature {
+    /// Threads, stable since Matrix 1.4.
+    Threads,
+
+    /// Fetching the events that relate to an event, stable since Matrix 1.3.
+    Relations,
+
+    /// Sliding sync, advertised by the homeserver with the `org.matrix.msc3575`
+    /// unstable feature.
+    ///
+    /// This is not required when sliding sync uses a separate proxy.
+    SlidingSync,
+
+    /// Changing the password of the account, according to the
+    /// `m.change_password` capability.
+    ChangePassword,
+}
+
+/// The cached response of the `/versions` endpoint.
+#[derive(Debug)]
+pub(crate) struct ServerVersions {
+    /// The Matrix versions supported by the homeserver.
+    pub(crate) versions: Box<[MatrixVersion]>,
+
+    /// The unstable features advertised by the homeserver, or `None` if the
+    /// versions were set with
+    /// [`ClientBuilder::server_versions`](super::ClientBuilder::server_versions)
+    /// and the homeserver wasn't asked yet.
+    pub(crate) unstable_features: Option<BTreeMap<String, bool>>,
+
+    /// When the versions were received or set.
+    pub(crate) fetched_at: Instant,
+}
+
+impl ServerVersions {
+    /// Versions set with
+    /// [`ClientBuilder::server_versions`](super::ClientBuilder::server_versions).
+    ///
+    /// They are replaced by the response of the homeserver when they become
+    /// stale, or when the unstable features are needed.
+    pub(crate) fn preset(versions: Box<[MatrixVersion]>) -> Self {
+        Self { versions, unstable_features: None, fetched_at: Instant::now() }
+    }
+
+    pub(crate) fn is_stale(&self) -> bool {
+        self.fetched_at.elapsed() > SERVER_INFO_TTL
+    }
+
+    pub(crate) fn supports_unstable_feature(&self, feature: &str) -> bool {
+        self.unstable_features
+            .as_ref()
+            .and_then(|features| features.get(feature).copied())
+            .unwrap_or(false)
+    }
+}
+
+/// The cached response of the `/capabilities` endpoint.
+#[derive(Debug)]
+pub(crate) struct CachedCapabilities {
+    pub(crate) capabilities: Capabilities,
+    pub(crate) fetched_at: Instant,
+}
+
+impl CachedCapabilities {
+    pub(crate) fn is_stale(&self) -> bool {
+        self.fetched_at.elapsed() > SERVER_INFO_TTL
+    }
+}
@@ -476,7 +476,7 @@ impl OtherUserIdentity {
             }
             room.clone()
         } else {
-            self.client.create_dm_room(self.inner.user_id().to_owned()).await?
+            self.client.create_dm(&[self.inner.user_id().to_owned()], true).await?
         };
 
         let response = room
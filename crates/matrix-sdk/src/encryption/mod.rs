@@ -54,7 +54,7 @@ use ruma::{
     },
     assign, DeviceId, OwnedUserId, TransactionId, UserId,
 };
-use tracing::{debug, instrument, trace, warn};
+use tracing::{debug, instrument, warn};
 
 pub use crate::error::RoomKeyImportError;
 use crate::{
@@ -225,45 +225,6 @@ impl Client {
         })
     }
 
-    #[cfg(feature = "e2e-encryption")]
-    pub(crate) async fn create_dm_room(&self, user_id: OwnedUserId) -> Result<room::Joined> {
-        use ruma::{
-            api::client::room::create_room::v3::RoomPreset, events::direct::DirectEventContent,
-        };
-
-        // First we create the DM room, where we invite the user and tell the
-        // invitee that the room should be a DM.
-        let invite = vec![user_id.clone()];
-
-        let request = assign!(ruma::api::client::room::create_room::v3::Request::new(), {
-            invite,
-            is_direct: true,
-            preset: Some(RoomPreset::TrustedPrivateChat),
-        });
-
-        let room = self.create_room(request).await?;
-
-        // Now we need to mark the room as a DM for ourselves, we fetch the
-        // existing `m.direct` event and append the room to the list of DMs we
-        // have with this user.
-        let mut content = self
-            .account()
-            .account_data::<DirectEventContent>()
-            .await?
-            .map(|c| c.deserialize())
-            .transpose()?
-            .unwrap_or_default();
-
-        content.entry(user_id.to_owned()).or_default().push(room.room_id().to_owned());
-
-        // TODO We should probably save the fact that we need to send this out
-        // because otherwise we might end up in a state where we have a DM that
-        // isn't marked as one.
-        self.account().set_account_data(content).await?;
-
-        Ok(room)
-    }
-
     /// Claim one-time keys creating new Olm sessions.
     ///
     /// # Arguments
@@ -365,20 +326,6 @@ impl Client {
         Ok(())
     }
 
-    #[cfg(feature = "e2e-encryption")]
-    fn get_dm_room(&self, user_id: &UserId) -> Option<room::Joined> {
-        let rooms = self.joined_rooms();
-
-        // Find the room we share with the `user_id` and only with `user_id`
-        let room = rooms.into_iter().find(|r| {
-            let targets = r.direct_targets();
-            targets.len() == 1 && targets.contains(user_id)
-        });
-
-        trace!(?room, "Found room");
-        room
-    }
-
     async fn send_outgoing_request(&self, r: OutgoingRequest) -> Result<()> {
         use matrix_sdk_base::crypto::OutgoingRequests;
 
@@ -648,7 +595,11 @@ impl Encryption {
                 UserIdentity::new_own(self.client.clone(), i)
             }
             matrix_sdk_base::crypto::UserIdentities::Other(i) => {
-                UserIdentity::new(self.client.clone(), i, self.client.get_dm_room(user_id))
+                let room = match self.client.get_dm_room(&[user_id.to_owned()]) {
+                    Some(room::Room::Joined(room)) => Some(room),
+                    _ => None,
+                };
+                UserIdentity::new(self.client.clone(), i, room)
             }
         }))
     }
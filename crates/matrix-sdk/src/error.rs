@@ -30,7 +30,7 @@ use ruma::{
         error::{FromHttpResponseError, IntoHttpError},
     },
     events::tag::InvalidUserTagName,
-    IdParseError,
+    IdParseError, OwnedRoomId,
 };
 use serde_json::Error as JsonError;
 use thiserror::Error;
@@ -101,6 +101,11 @@ pub enum HttpError {
     #[error(transparent)]
     IntoHttp(#[from] IntoHttpError),
 
+    /// The homeserver doesn't support the endpoint of the request, according
+    /// to the Matrix versions it advertises.
+    #[error("the homeserver doesn't support the endpoint of {0}")]
+    UnsupportedEndpoint(&'static str),
+
     /// The given request can't be cloned and thus can't be retried.
     #[error("The request cannot be cloned")]
     UnableToCloneRequest,
@@ -241,6 +246,43 @@ pub enum Error {
     #[error(transparent)]
     SlidingSync(#[from] crate::sliding_sync::Error),
 
+    /// An error occurred in the timeline.
+    #[cfg(feature = "experimental-timeline")]
+    #[error(transparent)]
+    Timeline(#[from] crate::room::timeline::Error),
+
+    /// An error occurred with OpenID Connect.
+    #[cfg(feature = "experimental-oidc")]
+    #[error(transparent)]
+    Oidc(#[from] crate::oidc::Error),
+
+    /// An error occurred while logging in with a QR code.
+    #[cfg(feature = "experimental-qr-login")]
+    #[error(transparent)]
+    QrLogin(#[from] crate::qr_login::Error),
+
+    /// The homeserver doesn't support the given feature.
+    #[error("the homeserver doesn't support the {0:?} feature")]
+    UnsupportedFeature(crate::Feature),
+
+    /// The push rule with the given ID is not known.
+    #[error("the push rule `{0}` is not known")]
+    UnknownPushRule(String),
+
+    /// The DM room was created or joined, but it couldn't be added to the
+    /// `m.direct` account data event.
+    ///
+    /// The room can be marked as a DM again with
+    /// [`Common::set_is_direct()`](crate::room::Common::set_is_direct).
+    #[error("the room {room_id} couldn't be marked as a DM")]
+    DmNotMarked {
+        /// The ID of the DM room.
+        room_id: OwnedRoomId,
+        /// The error that occurred while updating `m.direct`.
+        #[source]
+        source: Box<Error>,
+    },
+
     /// The client is in inconsistent state. This happens when we set a room to
     /// a specific type, but then cannot get it in this type.
     #[error("The internal client state is inconsistent.")]
@@ -417,4 +459,10 @@ pub enum RefreshTokenError {
     /// not be forwarded.
     #[error("the access token could not be refreshed")]
     UnableToRefreshToken,
+
+    /// An error occurred while refreshing the access token with the OpenID
+    /// Connect Provider.
+    #[cfg(feature = "experimental-oidc")]
+    #[error(transparent)]
+    Oidc(std::sync::Arc<crate::oidc::Error>),
 }
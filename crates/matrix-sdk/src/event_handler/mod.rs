@@ -334,10 +334,16 @@ impl Client {
         struct ExtractType<'a> {
             #[serde(borrow, rename = "type")]
             event_type: Cow<'a, str>,
+            #[serde(borrow)]
+            sender: Option<Cow<'a, str>>,
         }
 
         for raw_event in events {
-            let event_type = raw_event.deserialize_as::<ExtractType<'_>>()?.event_type;
+            let ExtractType { event_type, sender } = raw_event.deserialize_as()?;
+            if kind != HandlerKind::State && self.is_sender_ignored(sender.as_deref()) {
+                continue;
+            }
+
             self.call_event_handlers(room, raw_event.json(), kind, &event_type, None).await;
         }
 
@@ -380,13 +386,18 @@ impl Client {
         struct TimelineEventDetails<'a> {
             #[serde(borrow, rename = "type")]
             event_type: Cow<'a, str>,
+            #[serde(borrow)]
+            sender: Option<Cow<'a, str>>,
             state_key: Option<serde::de::IgnoredAny>,
             unsigned: Option<UnsignedDetails>,
         }
 
         for item in timeline_events {
-            let TimelineEventDetails { event_type, state_key, unsigned } =
+            let TimelineEventDetails { event_type, sender, state_key, unsigned } =
                 item.event.deserialize_as()?;
+            if state_key.is_none() && self.is_sender_ignored(sender.as_deref()) {
+                continue;
+            }
 
             let redacted = unsigned.and_then(|u| u.redacted_because).is_some();
             let (handler_kind_g, handler_kind_r) = match state_key {
@@ -413,6 +424,20 @@ impl Client {
         Ok(())
     }
 
+    /// Whether the given sender of an event is ignored by the current user, in
+    /// which case the event handlers are not called for the event.
+    ///
+    /// State events still apply to the room, so their handlers are called
+    /// regardless of the sender.
+    fn is_sender_ignored(&self, sender: Option<&str>) -> bool {
+        let Some(sender) = sender else { return false };
+        self.base_client()
+            .ignored_users()
+            .lock_ref()
+            .iter()
+            .any(|user_id| user_id.as_str() == sender)
+    }
+
     #[instrument(level = "debug", skip_all, fields(?event_kind, %event_type, room_id))]
     async fn call_event_handlers(
         &self,
@@ -16,17 +16,21 @@ use std::{any::type_name, fmt::Debug, sync::Arc, time::Duration};
 
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
+use dashmap::DashMap;
+use futures_signals::signal::Mutable;
 use http::Response as HttpResponse;
-use matrix_sdk_common::AsyncTraitDeps;
+use matrix_sdk_common::{executor::spawn, instant::Instant, locks::Mutex, AsyncTraitDeps};
 use reqwest::Response;
 use ruma::{
     api::{
-        error::FromHttpResponseError, AuthScheme, IncomingResponse, MatrixVersion, OutgoingRequest,
-        OutgoingRequestAppserviceExt, SendAccessToken,
+        client::error::ErrorKind as ClientApiErrorKind,
+        error::{FromHttpResponseError, IntoHttpError},
+        AuthScheme, IncomingResponse, MatrixVersion, OutgoingRequest, OutgoingRequestAppserviceExt,
+        SendAccessToken,
     },
     UserId,
 };
-use tracing::trace;
+use tracing::{trace, warn};
 
 use crate::{config::RequestConfig, error::HttpError};
 
@@ -93,15 +97,181 @@ pub trait HttpSend: AsyncTraitDeps {
     ) -> Result<http::Response<Bytes>, HttpError>;
 }
 
+/// A rate limit reported by the homeserver with an `M_LIMIT_EXCEEDED` error.
+#[derive(Clone, Debug)]
+pub struct RateLimit {
+    /// The type of the request that was rate limited.
+    pub request_type: &'static str,
+
+    /// The class of the request that was rate limited.
+    ///
+    /// Requests of the same class are held back until the end of the rate
+    /// limit.
+    pub class: RateLimitClass,
+
+    /// How long the homeserver asked to wait before retrying, if it said so.
+    pub retry_after: Option<Duration>,
+
+    /// When the rate limit was received.
+    pub received_at: Instant,
+}
+
+/// The class of a request, for rate limiting.
+///
+/// Homeservers apply the same rate limit to the endpoints of a class, like all
+/// the endpoints sending events to a room.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum RateLimitClass {
+    /// Sending, redacting or setting the state of an event in a room.
+    Message,
+    /// Joining or knocking on a room.
+    Join,
+    /// Inviting a user to a room.
+    Invite,
+    /// Logging in.
+    Login,
+    /// Registering an account.
+    Registration,
+    /// Uploading media.
+    MediaUpload,
+    /// Any other request.
+    Other,
+}
+
+impl RateLimitClass {
+    /// Get the class of the request with the given method and path.
+    fn of(method: &http::Method, path: &str) -> Self {
+        let is_post = *method == http::Method::POST;
+        let is_put = *method == http::Method::PUT;
+        let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+
+        match segments.as_slice() {
+            ["_matrix", "media", _, "upload", ..] => Self::MediaUpload,
+            ["_matrix", "client", _, "login"] if is_post => Self::Login,
+            ["_matrix", "client", _, "register"] if is_post => Self::Registration,
+            ["_matrix", "client", _, "join" | "knock", _]
+            | ["_matrix", "client", _, "rooms", _, "join"]
+                if is_post =>
+            {
+                Self::Join
+            }
+            ["_matrix", "client", _, "rooms", _, "invite"] if is_post => Self::Invite,
+            ["_matrix", "client", _, "rooms", _, "send" | "state" | "redact", ..] if is_put => {
+                Self::Message
+            }
+            _ => Self::Other,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct HttpClient {
     pub(crate) inner: Arc<dyn HttpSend>,
     pub(crate) request_config: RequestConfig,
+    /// The time until which requests are held back because of a rate limit,
+    /// per request class.
+    rate_limited_until: DashMap<RateLimitClass, Arc<Mutex<Option<Instant>>>>,
+    /// The last rate limit received from the homeserver, until requests of
+    /// its class are sent again.
+    pub(crate) last_rate_limit: Mutable<Option<RateLimit>>,
 }
 
 impl HttpClient {
     pub(crate) fn new(inner: Arc<dyn HttpSend>, request_config: RequestConfig) -> Self {
-        HttpClient { inner, request_config }
+        HttpClient {
+            inner,
+            request_config,
+            rate_limited_until: Default::default(),
+            last_rate_limit: Default::default(),
+        }
+    }
+
+    /// Send a request that is not a Matrix API request, with the default
+    /// timeout.
+    pub(crate) async fn send_raw(
+        &self,
+        request: http::Request<Bytes>,
+    ) -> Result<http::Response<Bytes>, HttpError> {
+        self.inner.send_request(request, self.request_config.timeout).await
+    }
+
+    /// Wait until the end of the rate limit for the given request class, if
+    /// there is one.
+    ///
+    /// The requests that arrive during the rate limit wait in line, in order.
+    async fn wait_for_rate_limit(&self, class: RateLimitClass) {
+        let lock = self.rate_limited_until.entry(class).or_default().clone();
+        let mut rate_limited_until = lock.lock().await;
+
+        if let Some(until) = rate_limited_until.take() {
+            let now = Instant::now();
+            if until > now {
+                trace!(?class, "Waiting for the end of the rate limit");
+                sleep(until - now).await;
+            }
+        }
+    }
+
+    /// Record a rate limit received for the given request.
+    async fn rate_limited(
+        &self,
+        request_type: &'static str,
+        class: RateLimitClass,
+        retry_after: Option<Duration>,
+    ) {
+        warn!(request_type, ?class, ?retry_after, "The homeserver rate limited the request");
+
+        let received_at = Instant::now();
+        if let Some(retry_after) = retry_after {
+            let lock = self.rate_limited_until.entry(class).or_default().clone();
+            let mut rate_limited_until = lock.lock().await;
+            let until = received_at + retry_after;
+            if rate_limited_until.map_or(true, |previous| previous < until) {
+                *rate_limited_until = Some(until);
+            }
+        }
+
+        let rate_limit = RateLimit { request_type, class, retry_after, received_at };
+        self.last_rate_limit.set(Some(rate_limit));
+
+        if let Some(retry_after) = retry_after {
+            // Forget the rate limit once it is over, unless another one was
+            // received in the meantime.
+            let last_rate_limit = self.last_rate_limit.clone();
+            spawn(async move {
+                sleep(retry_after).await;
+                let mut last_rate_limit = last_rate_limit.lock_mut();
+                if last_rate_limit.as_ref().map_or(false, |r| r.received_at == received_at) {
+                    *last_rate_limit = None;
+                }
+            });
+        }
+    }
+
+    /// Record the rate limit in the given error, if there is one.
+    async fn handle_rate_limit(
+        &self,
+        request_type: &'static str,
+        class: RateLimitClass,
+        error: &HttpError,
+    ) {
+        if let Some(ClientApiErrorKind::LimitExceeded { retry_after_ms }) =
+            error.client_api_error_kind()
+        {
+            self.rate_limited(request_type, class, *retry_after_ms).await;
+        }
+    }
+
+    /// Forget the last rate limit if it applies to the given request class,
+    /// since a request of that class went through.
+    ///
+    /// This is needed for rate limits without a `retry_after_ms`.
+    fn rate_limit_ended(&self, class: RateLimitClass) {
+        let mut last_rate_limit = self.last_rate_limit.lock_mut();
+        if last_rate_limit.as_ref().map_or(false, |rate_limit| rate_limit.class == class) {
+            *last_rate_limit = None;
+        }
     }
 
     #[tracing::instrument(
@@ -141,7 +311,7 @@ impl HttpClient {
                 SendAccessToken::Always(access_token),
                 user_id,
                 server_versions,
-            )?
+            )
         } else {
             let send_access_token = match access_token {
                 Some(access_token) => {
@@ -158,10 +328,20 @@ impl HttpClient {
                 &homeserver,
                 send_access_token,
                 server_versions,
-            )?
+            )
         };
 
-        let request = request.map(|body| body.freeze());
+        let request_type = type_name::<Request>();
+        let request = request
+            .map_err(|error| match error {
+                // Fail early when the homeserver doesn't support the endpoint.
+                IntoHttpError::NoUnstablePath | IntoHttpError::EndpointRemoved(_) => {
+                    HttpError::UnsupportedEndpoint(request_type)
+                }
+                error => error.into(),
+            })?
+            .map(|body| body.freeze());
+        let class = RateLimitClass::of(request.method(), request.uri().path());
 
         trace!("Sending request");
 
@@ -170,9 +350,7 @@ impl HttpClient {
             use std::sync::atomic::{AtomicU64, Ordering};
 
             use backoff::{future::retry, Error as RetryError, ExponentialBackoff};
-            use ruma::api::client::error::{
-                ErrorBody as ClientApiErrorBody, ErrorKind as ClientApiErrorKind,
-            };
+            use ruma::api::client::error::ErrorBody as ClientApiErrorBody;
 
             use crate::RumaApiError;
 
@@ -221,6 +399,8 @@ impl HttpClient {
                     }
                 };
 
+                self.wait_for_rate_limit(class).await;
+
                 let raw_response = self
                     .inner
                     .send_request(clone_request(&request), config.timeout)
@@ -229,10 +409,17 @@ impl HttpClient {
 
                 trace!("Got response: {raw_response:?}");
 
-                let response = Request::IncomingResponse::try_from_http_response(raw_response)
-                    .map_err(|e| error_type(HttpError::from(e)))?;
-
-                Ok(response)
+                match Request::IncomingResponse::try_from_http_response(raw_response) {
+                    Ok(response) => {
+                        self.rate_limit_ended(class);
+                        Ok(response)
+                    }
+                    Err(e) => {
+                        let error = HttpError::from(e);
+                        self.handle_rate_limit(request_type, class, &error).await;
+                        Err(error_type(error))
+                    }
+                }
             };
 
             retry::<_, HttpError, _, _, _>(backoff, send_request).await?
@@ -240,10 +427,22 @@ impl HttpClient {
 
         #[cfg(target_arch = "wasm32")]
         let response = {
+            self.wait_for_rate_limit(class).await;
+
             let raw_response = self.inner.send_request(request, config.timeout).await?;
             trace!("Got response: {raw_response:?}");
 
-            Request::IncomingResponse::try_from_http_response(raw_response)?
+            match Request::IncomingResponse::try_from_http_response(raw_response) {
+                Ok(response) => {
+                    self.rate_limit_ended(class);
+                    response
+                }
+                Err(e) => {
+                    let error = HttpError::from(e);
+                    self.handle_rate_limit(request_type, class, &error).await;
+                    return Err(error);
+                }
+            }
         };
 
         Ok(response)
@@ -304,6 +503,14 @@ impl HttpSettings {
     }
 }
 
+pub(crate) async fn sleep(duration: Duration) {
+    #[cfg(target_arch = "wasm32")]
+    let _ = wasm_timer::Delay::new(duration).await;
+
+    #[cfg(not(target_arch = "wasm32"))]
+    tokio::time::sleep(duration).await;
+}
+
 // Clones all request parts except the extensions which can't be cloned.
 // See also https://github.com/hyperium/http/issues/395
 #[cfg(not(target_arch = "wasm32"))]
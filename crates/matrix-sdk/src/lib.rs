@@ -35,7 +35,14 @@ mod error;
 pub mod event_handler;
 mod http_client;
 pub mod media;
+mod notification;
+pub mod notification_settings;
+#[cfg(feature = "experimental-oidc")]
+pub mod oidc;
+#[cfg(feature = "experimental-qr-login")]
+pub mod qr_login;
 pub mod room;
+mod send_queue;
 pub mod sync;
 
 #[cfg(feature = "sliding-sync")]
@@ -49,12 +56,17 @@ mod events;
 pub use account::Account;
 #[cfg(feature = "sso-login")]
 pub use client::SsoLoginBuilder;
-pub use client::{Client, ClientBuildError, ClientBuilder, LoginBuilder, LoopCtrl};
+pub use client::{Client, ClientBuildError, ClientBuilder, Feature, LoginBuilder, LoopCtrl};
 #[cfg(feature = "image-proc")]
 pub use error::ImageError;
 pub use error::{Error, HttpError, HttpResult, RefreshTokenError, Result, RumaApiError};
-pub use http_client::HttpSend;
+pub use http_client::{HttpSend, RateLimit, RateLimitClass};
 pub use media::Media;
+pub use notification::NotificationItem;
+pub use notification_settings::NotificationSettings;
+#[cfg(feature = "experimental-oidc")]
+pub use oidc::Oidc;
+pub use send_queue::{QueuedAttachment, QueuedEvent, SendQueue, SendQueueState};
 #[cfg(feature = "sliding-sync")]
 pub use sliding_sync::{
     RoomListEntry, SlidingSync, SlidingSyncBuilder, SlidingSyncMode, SlidingSyncRoom,
@@ -0,0 +1,150 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use matrix_sdk_base::{
+    deserialized_responses::{SyncTimelineEvent, TimelineEvent},
+    StateChanges,
+};
+use ruma::{
+    events::{AnySyncTimelineEvent, AnyTimelineEvent},
+    push::{Action, Tweak},
+    serde::Raw,
+    OwnedMxcUri, OwnedRoomId, OwnedUserId,
+};
+use serde_json::{value::to_raw_value, Map as JsonMap, Value as JsonValue};
+
+use crate::{room, Result};
+
+/// A notification with the data needed to display it.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct NotificationItem {
+    /// The event that triggered the notification, decrypted if possible.
+    pub event: TimelineEvent,
+
+    /// The ID of the room where the event was sent.
+    pub room_id: OwnedRoomId,
+
+    /// The display name of the room.
+    pub room_display_name: String,
+
+    /// Whether the room is a direct message room.
+    pub is_direct: bool,
+
+    /// The sender of the event, if it could be deserialized.
+    pub sender: Option<OwnedUserId>,
+
+    /// The display name of the sender in the room, if it is known.
+    pub sender_display_name: Option<String>,
+
+    /// The avatar URL of the sender in the room, if it is known.
+    pub sender_avatar_url: Option<OwnedMxcUri>,
+
+    /// The push actions triggered by the event.
+    pub actions: Vec<Action>,
+}
+
+impl NotificationItem {
+    /// Resolve the data of a notification for the given event in the given
+    /// room.
+    ///
+    /// This only uses local data, so it doesn't request the room members.
+    pub(crate) async fn new(
+        room: &room::Common,
+        event: TimelineEvent,
+        actions: Vec<Action>,
+    ) -> Result<Self> {
+        let sender = event.event.get_field::<OwnedUserId>("sender").ok().flatten();
+        let member = match &sender {
+            Some(sender) => room.get_member_no_sync(sender).await?,
+            None => None,
+        };
+
+        Ok(Self {
+            event,
+            room_id: room.room_id().to_owned(),
+            room_display_name: room.display_name().await?.to_string(),
+            is_direct: room.is_direct(),
+            sender,
+            sender_display_name: member
+                .as_ref()
+                .and_then(|m| m.display_name().map(ToOwned::to_owned)),
+            sender_avatar_url: member.as_ref().and_then(|m| m.avatar_url().map(ToOwned::to_owned)),
+            actions,
+        })
+    }
+
+    /// Resolve the data of a notification for the given event received from
+    /// sync.
+    pub(crate) async fn from_sync(
+        room: &room::Common,
+        event: &SyncTimelineEvent,
+        actions: Vec<Action>,
+    ) -> Result<Self> {
+        let event = TimelineEvent {
+            event: full_event(&event.event, room)?,
+            encryption_info: event.encryption_info.clone(),
+        };
+        Self::new(room, event, actions).await
+    }
+
+    /// Resolve the data of a notification for the given event, computing the
+    /// push actions with the current push rules.
+    ///
+    /// Returns `None` if the event doesn't trigger a notification.
+    pub(crate) async fn from_event(
+        room: &room::Common,
+        event: TimelineEvent,
+    ) -> Result<Option<Self>> {
+        let client = room.client.base_client();
+        let changes = StateChanges::default();
+
+        let push_rules = client.get_push_rules(&changes).await?;
+        let Some(context) =
+            client.get_push_room_context(room, &room.clone_info(), &changes).await?
+        else {
+            return Ok(None);
+        };
+
+        let actions = push_rules.get_actions(&event.event, &context).to_owned();
+        if !actions.iter().any(|action| matches!(action, Action::Notify)) {
+            return Ok(None);
+        }
+
+        Ok(Some(Self::new(room, event, actions).await?))
+    }
+
+    /// Whether the notification should be highlighted.
+    pub fn is_highlighted(&self) -> bool {
+        self.actions.iter().any(|action| matches!(action, Action::SetTweak(Tweak::Highlight(true))))
+    }
+
+    /// The sound to play for the notification, if any.
+    pub fn sound(&self) -> Option<&str> {
+        self.actions.iter().find_map(|action| match action {
+            Action::SetTweak(Tweak::Sound(sound)) => Some(sound.as_str()),
+            _ => None,
+        })
+    }
+}
+
+/// Add the room ID to an event received from sync.
+fn full_event(
+    event: &Raw<AnySyncTimelineEvent>,
+    room: &room::Common,
+) -> Result<Raw<AnyTimelineEvent>> {
+    let mut object = event.deserialize_as::<JsonMap<String, JsonValue>>()?;
+    object.insert("room_id".to_owned(), room.room_id().as_str().into());
+    Ok(Raw::from_json(to_raw_value(&object)?))
+}
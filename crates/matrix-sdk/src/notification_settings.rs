@@ -0,0 +1,467 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! High-level push rules API.
+
+use std::{borrow::Borrow, hash::Hash, mem};
+
+use indexmap::IndexSet;
+use matrix_sdk_base::StateChanges;
+use ruma::{
+    api::client::push::{
+        delete_pushrule, set_pushrule, set_pushrule_actions, set_pushrule_enabled, RuleKind,
+        RuleScope,
+    },
+    assign,
+    events::GlobalAccountDataEventType,
+    push::{
+        Action, ConditionalPushRuleInit, PatternedPushRuleInit, PushCondition, Ruleset,
+        SimplePushRuleInit, Tweak,
+    },
+    serde::Raw,
+    RoomId,
+};
+use serde_json::json;
+
+use crate::{Client, Error, Result};
+
+/// The ID of the override rule that disables all notifications.
+const MASTER_RULE_ID: &str = ".m.rule.master";
+
+/// Run `$body` with `$rules` bound to the set of rules of the given kind in
+/// `$ruleset`.
+macro_rules! with_rules {
+    ($ruleset:expr, $kind:expr, |$rules:ident| $body:expr) => {
+        match $kind {
+            RuleKind::Override => {
+                let $rules = &mut $ruleset.override_;
+                $body
+            }
+            RuleKind::Underride => {
+                let $rules = &mut $ruleset.underride;
+                $body
+            }
+            RuleKind::Sender => {
+                let $rules = &mut $ruleset.sender;
+                $body
+            }
+            RuleKind::Room => {
+                let $rules = &mut $ruleset.room;
+                $body
+            }
+            RuleKind::Content => {
+                let $rules = &mut $ruleset.content;
+                $body
+            }
+            _ => {}
+        }
+    };
+}
+
+/// The notification mode of a room.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoomNotificationMode {
+    /// Notify for all the messages in the room.
+    AllMessages,
+
+    /// Only notify for mentions and keywords.
+    MentionsAndKeywordsOnly,
+
+    /// Never notify.
+    Mute,
+}
+
+/// A high-level API to manage the push rules of the account.
+///
+/// All the methods that edit the push rules send a request to the homeserver
+/// and, if it succeeds, update the local push rules right away, without
+/// waiting for the next sync.
+#[derive(Debug, Clone)]
+pub struct NotificationSettings {
+    /// The underlying HTTP client.
+    client: Client,
+}
+
+impl NotificationSettings {
+    pub(crate) fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// Get the push rules of the account.
+    ///
+    /// Falls back to the server default push rules if they were not received
+    /// yet.
+    pub async fn push_rules(&self) -> Result<Ruleset> {
+        Ok(self.client.base_client().get_push_rules(&StateChanges::default()).await?)
+    }
+
+    /// Enable or disable a push rule.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The kind of the push rule.
+    ///
+    /// * `rule_id` - The ID of the push rule.
+    ///
+    /// * `enabled` - Whether the push rule should be enabled.
+    pub async fn set_push_rule_enabled(
+        &self,
+        kind: RuleKind,
+        rule_id: &str,
+        enabled: bool,
+    ) -> Result<()> {
+        let request = set_pushrule_enabled::v3::Request::new(
+            RuleScope::Global,
+            kind.clone(),
+            rule_id.to_owned(),
+            enabled,
+        );
+        self.client.send(request, None).await?;
+
+        self.update_push_rules(|ruleset| {
+            with_rules!(ruleset, &kind, |rules| {
+                if let Some(mut rule) = rules.get(rule_id).cloned() {
+                    rule.enabled = enabled;
+                    rules.replace(rule);
+                }
+            })
+        })
+        .await
+    }
+
+    /// Set the actions of a push rule.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The kind of the push rule.
+    ///
+    /// * `rule_id` - The ID of the push rule.
+    ///
+    /// * `actions` - The actions to perform when the push rule matches.
+    pub async fn set_push_rule_actions(
+        &self,
+        kind: RuleKind,
+        rule_id: &str,
+        actions: Vec<Action>,
+    ) -> Result<()> {
+        let request = set_pushrule_actions::v3::Request::new(
+            RuleScope::Global,
+            kind.clone(),
+            rule_id.to_owned(),
+            actions.clone(),
+        );
+        self.client.send(request, None).await?;
+
+        self.update_push_rules(|ruleset| {
+            with_rules!(ruleset, &kind, |rules| {
+                if let Some(mut rule) = rules.get(rule_id).cloned() {
+                    rule.actions = actions;
+                    rules.replace(rule);
+                }
+            })
+        })
+        .await
+    }
+
+    /// Move a user-defined push rule before or after another push rule of the
+    /// same kind.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The kind of the push rule.
+    ///
+    /// * `rule_id` - The ID of the push rule to move.
+    ///
+    /// * `before` - The ID of the push rule that should come right after the
+    /// moved rule.
+    ///
+    /// * `after` - The ID of the push rule that should come right before the
+    /// moved rule. Ignored if `before` is set.
+    pub async fn move_push_rule(
+        &self,
+        kind: RuleKind,
+        rule_id: &str,
+        before: Option<&str>,
+        after: Option<&str>,
+    ) -> Result<()> {
+        let ruleset = self.push_rules().await?;
+        let unknown_rule = || Error::UnknownPushRule(rule_id.to_owned());
+
+        let mut request = set_pushrule::v3::Request::new(
+            RuleScope::Global,
+            kind.clone(),
+            rule_id.to_owned(),
+            Vec::new(),
+        );
+        match &kind {
+            RuleKind::Override | RuleKind::Underride => {
+                let rules = if kind == RuleKind::Override {
+                    &ruleset.override_
+                } else {
+                    &ruleset.underride
+                };
+                let rule = rules.get(rule_id).ok_or_else(unknown_rule)?;
+                request.actions = rule.actions.clone();
+                request.conditions = rule.conditions.clone();
+            }
+            RuleKind::Sender | RuleKind::Room => {
+                let rules = if kind == RuleKind::Sender { &ruleset.sender } else { &ruleset.room };
+                request.actions = rules.get(rule_id).ok_or_else(unknown_rule)?.actions.clone();
+            }
+            RuleKind::Content => {
+                let rule = ruleset.content.get(rule_id).ok_or_else(unknown_rule)?;
+                request.actions = rule.actions.clone();
+                request.pattern = Some(rule.pattern.clone());
+            }
+            _ => return Err(unknown_rule()),
+        }
+
+        if before.is_some() {
+            request.before = before.map(ToOwned::to_owned);
+        } else {
+            request.after = after.map(ToOwned::to_owned);
+        }
+        self.client.send(request, None).await?;
+
+        self.update_push_rules(|ruleset| {
+            with_rules!(ruleset, &kind, |rules| {
+                if let Some(rule) = rules.shift_take(rule_id) {
+                    insert_rule(rules, rule, before, after);
+                }
+            })
+        })
+        .await
+    }
+
+    /// Get the keywords that trigger a notification.
+    pub async fn keywords(&self) -> Result<Vec<String>> {
+        let ruleset = self.push_rules().await?;
+        Ok(ruleset
+            .content
+            .into_iter()
+            .filter(|rule| !rule.default)
+            .map(|rule| rule.pattern)
+            .collect())
+    }
+
+    /// Notify when a message contains the given keyword.
+    pub async fn add_keyword(&self, keyword: &str) -> Result<()> {
+        let actions = vec![
+            Action::Notify,
+            Action::SetTweak(Tweak::Sound("default".to_owned())),
+            Action::SetTweak(Tweak::Highlight(true)),
+        ];
+        let request = assign!(
+            set_pushrule::v3::Request::new(
+                RuleScope::Global,
+                RuleKind::Content,
+                keyword.to_owned(),
+                actions.clone(),
+            ),
+            { pattern: Some(keyword.to_owned()) }
+        );
+        self.client.send(request, None).await?;
+
+        self.update_push_rules(|ruleset| {
+            let rule = PatternedPushRuleInit {
+                actions,
+                default: false,
+                enabled: true,
+                rule_id: keyword.to_owned(),
+                pattern: keyword.to_owned(),
+            };
+            insert_rule(&mut ruleset.content, rule.into(), None, None);
+        })
+        .await
+    }
+
+    /// Stop notifying when a message contains the given keyword.
+    pub async fn remove_keyword(&self, keyword: &str) -> Result<()> {
+        self.delete_push_rule(RuleKind::Content, keyword).await
+    }
+
+    /// Get the notification mode of the given room.
+    ///
+    /// Returns `None` if the room uses the default notification settings of
+    /// the account.
+    pub async fn room_notification_mode(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Option<RoomNotificationMode>> {
+        let ruleset = self.push_rules().await?;
+
+        if ruleset.override_.get(room_id.as_str()).map_or(false, |rule| {
+            rule.enabled && !rule.actions.iter().any(|action| matches!(action, Action::Notify))
+        }) {
+            return Ok(Some(RoomNotificationMode::Mute));
+        }
+
+        Ok(ruleset.room.get(room_id.as_str()).filter(|rule| rule.enabled).map(|rule| {
+            if rule.actions.iter().any(|action| matches!(action, Action::Notify)) {
+                RoomNotificationMode::AllMessages
+            } else {
+                RoomNotificationMode::MentionsAndKeywordsOnly
+            }
+        }))
+    }
+
+    /// Set the notification mode of the given room.
+    ///
+    /// [`RoomNotificationMode::Mute`] is implemented with an override push
+    /// rule, while the other modes use a room push rule. The rules of the
+    /// previous mode are removed.
+    pub async fn set_room_notification_mode(
+        &self,
+        room_id: &RoomId,
+        mode: RoomNotificationMode,
+    ) -> Result<()> {
+        let ruleset = self.push_rules().await?;
+        let rule_id = room_id.as_str();
+
+        match mode {
+            RoomNotificationMode::Mute => {
+                if ruleset.room.contains(rule_id) {
+                    self.delete_push_rule(RuleKind::Room, rule_id).await?;
+                }
+
+                let actions = vec![Action::DontNotify];
+                let conditions = vec![PushCondition::EventMatch {
+                    key: "room_id".to_owned(),
+                    pattern: rule_id.to_owned(),
+                }];
+                let request = assign!(
+                    set_pushrule::v3::Request::new(
+                        RuleScope::Global,
+                        RuleKind::Override,
+                        rule_id.to_owned(),
+                        actions.clone(),
+                    ),
+                    { conditions: conditions.clone() }
+                );
+                self.client.send(request, None).await?;
+
+                self.update_push_rules(|ruleset| {
+                    let rule = ConditionalPushRuleInit {
+                        actions,
+                        default: false,
+                        enabled: true,
+                        rule_id: rule_id.to_owned(),
+                        conditions,
+                    };
+                    insert_rule(&mut ruleset.override_, rule.into(), None, None);
+                })
+                .await
+            }
+            RoomNotificationMode::AllMessages | RoomNotificationMode::MentionsAndKeywordsOnly => {
+                if ruleset.override_.contains(rule_id) {
+                    self.delete_push_rule(RuleKind::Override, rule_id).await?;
+                }
+
+                let actions = if mode == RoomNotificationMode::AllMessages {
+                    vec![Action::Notify, Action::SetTweak(Tweak::Sound("default".to_owned()))]
+                } else {
+                    vec![Action::DontNotify]
+                };
+                let request = set_pushrule::v3::Request::new(
+                    RuleScope::Global,
+                    RuleKind::Room,
+                    rule_id.to_owned(),
+                    actions.clone(),
+                );
+                self.client.send(request, None).await?;
+
+                self.update_push_rules(|ruleset| {
+                    let rule = SimplePushRuleInit {
+                        actions,
+                        default: false,
+                        enabled: true,
+                        rule_id: rule_id.to_owned(),
+                    };
+                    insert_rule(&mut ruleset.room, rule.into(), None, None);
+                })
+                .await
+            }
+        }
+    }
+
+    /// Go back to the default notification settings of the account for the
+    /// given room.
+    pub async fn reset_room_notification_mode(&self, room_id: &RoomId) -> Result<()> {
+        let ruleset = self.push_rules().await?;
+        let rule_id = room_id.as_str();
+
+        if ruleset.override_.contains(rule_id) {
+            self.delete_push_rule(RuleKind::Override, rule_id).await?;
+        }
+        if ruleset.room.contains(rule_id) {
+            self.delete_push_rule(RuleKind::Room, rule_id).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Delete a user-defined push rule.
+    async fn delete_push_rule(&self, kind: RuleKind, rule_id: &str) -> Result<()> {
+        let request =
+            delete_pushrule::v3::Request::new(RuleScope::Global, kind.clone(), rule_id.to_owned());
+        self.client.send(request, None).await?;
+
+        self.update_push_rules(|ruleset| {
+            with_rules!(ruleset, &kind, |rules| {
+                rules.shift_remove(rule_id);
+            })
+        })
+        .await
+    }
+
+    /// Apply the given change to the local push rules.
+    ///
+    /// The next sync will replace them with the ones from the homeserver.
+    async fn update_push_rules(&self, f: impl FnOnce(&mut Ruleset)) -> Result<()> {
+        let mut ruleset = self.push_rules().await?;
+        f(&mut ruleset);
+
+        let event = Raw::new(&json!({
+            "type": GlobalAccountDataEventType::PushRules.to_string(),
+            "content": { "global": ruleset },
+        }))?;
+        self.client.base_client().receive_account_data(&[event.cast()]).await?;
+
+        Ok(())
+    }
+}
+
+/// Insert `rule` in `rules` like the homeserver would, replacing a rule with
+/// the same ID.
+///
+/// Rules without `before` or `after` get the highest priority, except over the
+/// master rule.
+fn insert_rule<T>(rules: &mut IndexSet<T>, rule: T, before: Option<&str>, after: Option<&str>)
+where
+    T: Hash + Eq + Borrow<str>,
+{
+    rules.shift_remove(rule.borrow());
+
+    let index = if let Some(index) = before.and_then(|rule_id| rules.get_index_of(rule_id)) {
+        index
+    } else if let Some(index) = after.and_then(|rule_id| rules.get_index_of(rule_id)) {
+        index + 1
+    } else {
+        rules.get_index_of(MASTER_RULE_ID).map_or(0, |index| index + 1)
+    };
+
+    let mut list: Vec<_> = mem::take(rules).into_iter().collect();
+    list.insert(index.min(list.len()), rule);
+    *rules = list.into_iter().collect();
+}
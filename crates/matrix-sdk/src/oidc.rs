@@ -0,0 +1,635 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! High-level API to log in with OpenID Connect.
+//!
+//! When the homeserver delegates authentication to an OpenID Connect Provider,
+//! as defined in [MSC2965] and [MSC3861], the client gets its tokens from the
+//! Provider with the authorization code flow, secured with [PKCE].
+//!
+//! The usual steps to log in are:
+//!
+//! 1. Register the client with the Provider with [`Oidc::register_client()`],
+//!    or restore a previous registration with
+//!    [`Oidc::restore_registered_client()`].
+//! 2. Get the URL to open in a browser with [`Oidc::login_url()`].
+//! 3. Once the Provider redirects to the redirect URI, finish the login with
+//!    [`Oidc::finish_login()`].
+//!
+//! Refreshing the access token is done by [`Client::refresh_access_token()`],
+//! like with a regular Matrix login, and the new tokens are published with
+//! [`Client::session_tokens_signal()`].
+//!
+//! [MSC2965]: https://github.com/matrix-org/matrix-spec-proposals/pull/2965
+//! [MSC3861]: https://github.com/matrix-org/matrix-spec-proposals/pull/3861
+//! [PKCE]: https://datatracker.ietf.org/doc/html/rfc7636
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use http::{header::CONTENT_TYPE, Method, StatusCode};
+use matrix_sdk_common::{instant::Instant, locks::RwLock};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use ruma::{
+    api::client::{account::whoami, session::refresh_token},
+    assign, DeviceId, OwnedDeviceId,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use url::{form_urlencoded, Url};
+
+use crate::{Client, HttpResult, RefreshTokenError, Result, Session};
+
+/// The scope to get full access to the Matrix client API.
+const API_SCOPE: &str = "urn:matrix:org.matrix.msc2967.client:api:*";
+
+/// The prefix of the scope that sets the device ID of the session.
+const DEVICE_SCOPE_PREFIX: &str = "urn:matrix:org.matrix.msc2967.client:device:";
+
+/// How long a login started with [`Oidc::login_url()`] can be finished.
+const PENDING_LOGIN_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+/// Errors specific to the OpenID Connect API.
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The homeserver doesn't advertise an OpenID Connect Provider.
+    #[error("the homeserver doesn't advertise an OpenID Connect Provider")]
+    NotSupported,
+
+    /// The Provider doesn't have the endpoint needed for the operation.
+    #[error("the OpenID Connect Provider doesn't have a {0} endpoint")]
+    MissingEndpoint(&'static str),
+
+    /// The issuer in the metadata of the Provider is not the one trusted by
+    /// the homeserver.
+    #[error("the issuer of the OpenID Connect Provider is {got} instead of {expected}")]
+    IssuerMismatch {
+        /// The issuer trusted by the homeserver.
+        expected: Url,
+        /// The issuer in the metadata of the Provider.
+        got: Url,
+    },
+
+    /// No client is registered with the Provider.
+    #[error("the client is not registered with the OpenID Connect Provider")]
+    NotRegistered,
+
+    /// The callback URL is missing a parameter.
+    #[error("the callback URL is missing the `{0}` parameter")]
+    MissingCallbackParameter(&'static str),
+
+    /// The state in the callback URL doesn't match a login in progress, or
+    /// the login expired.
+    #[error("the state in the callback URL doesn't match a login in progress")]
+    UnknownState,
+
+    /// The session the Provider granted is for another device than the one
+    /// that was requested.
+    #[error("the session is for device {got} instead of the requested device {expected}")]
+    DeviceIdMismatch {
+        /// The ID of the device that was requested.
+        expected: OwnedDeviceId,
+        /// The ID of the device of the session.
+        got: OwnedDeviceId,
+    },
+
+    /// The Provider returned an error.
+    #[error("the OpenID Connect Provider returned an error: {0}")]
+    Provider(ErrorResponse),
+
+    /// The Provider returned an unexpected HTTP status code.
+    #[error("the OpenID Connect Provider returned an unexpected status code: {0}")]
+    UnexpectedStatus(StatusCode),
+}
+
+/// An error returned by the Provider, as defined in [RFC 6749].
+///
+/// [RFC 6749]: https://datatracker.ietf.org/doc/html/rfc6749#section-5.2
+#[derive(Clone, Debug, Deserialize)]
+#[non_exhaustive]
+pub struct ErrorResponse {
+    /// The error code.
+    pub error: String,
+
+    /// A human-readable description of the error, if any.
+    #[serde(default)]
+    pub error_description: Option<String>,
+}
+
+impl fmt::Display for ErrorResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.error_description {
+            Some(description) => write!(f, "{}: {description}", self.error),
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+/// The metadata of an OpenID Connect Provider, as returned by its discovery
+/// endpoint.
+#[derive(Clone, Debug, Deserialize)]
+#[non_exhaustive]
+pub struct ProviderMetadata {
+    /// The issuer identifier of the Provider.
+    pub issuer: Url,
+
+    /// The URL of the authorization endpoint.
+    pub authorization_endpoint: Url,
+
+    /// The URL of the token endpoint.
+    pub token_endpoint: Url,
+
+    /// The URL of the dynamic client registration endpoint, if any.
+    #[serde(default)]
+    pub registration_endpoint: Option<Url>,
+
+    /// The URL of the token revocation endpoint, if any.
+    #[serde(default)]
+    pub revocation_endpoint: Option<Url>,
+
+    /// The URL where the user can manage their account, if any.
+    #[serde(default)]
+    pub account_management_uri: Option<Url>,
+}
+
+/// The type of a client application.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApplicationType {
+    /// A web application.
+    Web,
+
+    /// A native application.
+    Native,
+}
+
+/// The metadata sent to the Provider to register the client.
+#[derive(Clone, Debug, Serialize)]
+#[non_exhaustive]
+pub struct ClientMetadata {
+    /// The type of the application.
+    pub application_type: ApplicationType,
+
+    /// The URIs the Provider can redirect to after authorization.
+    pub redirect_uris: Vec<Url>,
+
+    /// The name of the client, presented to the user.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_name: Option<String>,
+
+    /// The URL of the home page of the client.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_uri: Option<Url>,
+
+    /// The URL of the logo of the client.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logo_uri: Option<Url>,
+
+    /// The URL of the privacy policy of the client.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy_uri: Option<Url>,
+
+    /// The URL of the terms of service of the client.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tos_uri: Option<Url>,
+}
+
+impl ClientMetadata {
+    /// Create a new `ClientMetadata` with the given application type and
+    /// redirect URIs.
+    pub fn new(application_type: ApplicationType, redirect_uris: Vec<Url>) -> Self {
+        Self {
+            application_type,
+            redirect_uris,
+            client_name: None,
+            client_uri: None,
+            logo_uri: None,
+            policy_uri: None,
+            tos_uri: None,
+        }
+    }
+}
+
+/// The body of a dynamic client registration request.
+#[derive(Serialize)]
+struct RegistrationRequest<'a> {
+    #[serde(flatten)]
+    metadata: &'a ClientMetadata,
+    grant_types: [&'static str; 2],
+    response_types: [&'static str; 1],
+    token_endpoint_auth_method: &'static str,
+}
+
+/// A client registered with the Provider.
+#[derive(Clone, Debug, Deserialize)]
+#[non_exhaustive]
+pub struct RegisteredClient {
+    /// The ID of the client, that must be persisted to restore the session
+    /// later with [`Oidc::restore_registered_client()`].
+    pub client_id: String,
+}
+
+/// The data needed to start the authorization code flow.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct AuthorizationData {
+    /// The URL to open in a browser to log in.
+    pub url: Url,
+
+    /// The state of the login, that the Provider adds to the redirect URI.
+    pub state: String,
+}
+
+/// A login that was started with [`Oidc::login_url()`].
+#[derive(Debug)]
+struct PendingLogin {
+    code_verifier: String,
+    redirect_uri: Url,
+    device_id: OwnedDeviceId,
+    started_at: Instant,
+}
+
+impl PendingLogin {
+    fn is_expired(&self) -> bool {
+        self.started_at.elapsed() > PENDING_LOGIN_LIFETIME
+    }
+}
+
+/// The response of the token endpoint.
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    refresh_token: Option<String>,
+    #[serde(default)]
+    expires_in: Option<u64>,
+}
+
+/// The OpenID Connect state of a [`Client`].
+#[derive(Debug, Default)]
+pub(crate) struct OidcData {
+    /// The cached metadata of the Provider.
+    provider_metadata: RwLock<Option<ProviderMetadata>>,
+    /// The ID of the client registered with the Provider.
+    client_id: StdMutex<Option<String>>,
+    /// The logins in progress, by state. Expired logins are removed when a
+    /// new login is started or finished.
+    pending_logins: StdMutex<HashMap<String, PendingLogin>>,
+}
+
+/// A high-level API to log in with an OpenID Connect Provider.
+#[derive(Debug, Clone)]
+pub struct Oidc {
+    client: Client,
+}
+
+impl Oidc {
+    pub(crate) fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    fn data(&self) -> &OidcData {
+        &self.client.inner.oidc
+    }
+
+    /// Get the metadata of the OpenID Connect Provider trusted by the
+    /// homeserver.
+    ///
+    /// The metadata is requested from the discovery endpoint of the issuer
+    /// the first time and cached afterwards. It is rejected if its `issuer`
+    /// is not the issuer trusted by the homeserver.
+    pub async fn provider_metadata(&self) -> Result<ProviderMetadata> {
+        if let Some(metadata) = &*self.data().provider_metadata.read().await {
+            return Ok(metadata.clone());
+        }
+
+        let mut guard = self.data().provider_metadata.write().await;
+        if let Some(metadata) = &*guard {
+            return Ok(metadata.clone());
+        }
+
+        let issuer = self.client.authentication_issuer().await.ok_or(Error::NotSupported)?;
+        let url =
+            format!("{}/.well-known/openid-configuration", issuer.as_str().trim_end_matches('/'));
+        let request = http::Request::get(url).body(Bytes::new()).expect("valid request");
+        let metadata: ProviderMetadata = self.send_request(request).await?;
+
+        if metadata.issuer != issuer {
+            return Err(Error::IssuerMismatch { expected: issuer, got: metadata.issuer }.into());
+        }
+
+        *guard = Some(metadata.clone());
+        Ok(metadata)
+    }
+
+    /// Get the URL where the user can manage their account, if any.
+    ///
+    /// This is the `account` advertised by the homeserver in its well-known,
+    /// or the `account_management_uri` in the metadata of the Provider.
+    pub async fn account_management_url(&self) -> Result<Option<Url>> {
+        if let Some(url) = &self.client.inner.account_management_url {
+            return Ok(Some(url.clone()));
+        }
+
+        Ok(self.provider_metadata().await?.account_management_uri)
+    }
+
+    /// Register the client with the Provider, with [dynamic client
+    /// registration].
+    ///
+    /// The client ID in the response should be persisted, to restore the
+    /// registration later with [`Oidc::restore_registered_client()`].
+    ///
+    /// [dynamic client registration]: https://datatracker.ietf.org/doc/html/rfc7591
+    pub async fn register_client(&self, metadata: &ClientMetadata) -> Result<RegisteredClient> {
+        let provider_metadata = self.provider_metadata().await?;
+        let endpoint = provider_metadata
+            .registration_endpoint
+            .ok_or(Error::MissingEndpoint("registration"))?;
+
+        let body = RegistrationRequest {
+            metadata,
+            grant_types: ["authorization_code", "refresh_token"],
+            response_types: ["code"],
+            token_endpoint_auth_method: "none",
+        };
+        let request = http::Request::post(endpoint.as_str())
+            .header(CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(&body)?.into())
+            .expect("valid request");
+        let registered_client: RegisteredClient = self.send_request(request).await?;
+
+        self.restore_registered_client(registered_client.client_id.clone());
+        Ok(registered_client)
+    }
+
+    /// Use a client that was previously registered with the Provider.
+    ///
+    /// This must be called before restoring a session that was logged in
+    /// with OpenID Connect, so the access token can be refreshed and revoked.
+    pub fn restore_registered_client(&self, client_id: String) {
+        *self.data().client_id.lock().unwrap() = Some(client_id);
+    }
+
+    /// The ID of the client registered with the Provider, if any.
+    pub fn client_id(&self) -> Option<String> {
+        self.data().client_id.lock().unwrap().clone()
+    }
+
+    /// Get the URL to open in a browser to log in with the authorization code
+    /// flow.
+    ///
+    /// # Arguments
+    ///
+    /// * `redirect_uri` - The URI where the Provider redirects after the
+    ///   authorization, it must be one of the URIs of the registered client.
+    ///
+    /// * `device_id` - The ID of the device of the session. If this is `None`,
+    ///   a new device ID is generated.
+    ///
+    /// The login must be finished with [`Oidc::finish_login()`] within an
+    /// hour.
+    pub async fn login_url(
+        &self,
+        redirect_uri: &Url,
+        device_id: Option<&DeviceId>,
+    ) -> Result<AuthorizationData> {
+        let client_id = self.client_id().ok_or(Error::NotRegistered)?;
+        let mut url = self.provider_metadata().await?.authorization_endpoint;
+
+        let device_id = device_id.map(ToOwned::to_owned).unwrap_or_else(DeviceId::new);
+        let state = random_string(32);
+        let code_verifier = random_string(64);
+        let code_challenge = base64::encode_config(
+            Sha256::digest(code_verifier.as_bytes()),
+            base64::URL_SAFE_NO_PAD,
+        );
+
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &client_id)
+            .append_pair("redirect_uri", redirect_uri.as_str())
+            .append_pair("scope", &format!("openid {API_SCOPE} {DEVICE_SCOPE_PREFIX}{device_id}"))
+            .append_pair("state", &state)
+            .append_pair("code_challenge", &code_challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        let mut pending_logins = self.data().pending_logins.lock().unwrap();
+        pending_logins.retain(|_, login| !login.is_expired());
+        pending_logins.insert(
+            state.clone(),
+            PendingLogin {
+                code_verifier,
+                redirect_uri: redirect_uri.clone(),
+                device_id,
+                started_at: Instant::now(),
+            },
+        );
+
+        Ok(AuthorizationData { url, state })
+    }
+
+    /// Finish the login with the URL the Provider redirected to.
+    ///
+    /// This exchanges the authorization code for the tokens and restores the
+    /// session, which is then available with [`Client::session()`].
+    ///
+    /// Returns an error if the session is not for the device that was
+    /// requested with [`Oidc::login_url()`].
+    pub async fn finish_login(&self, callback_url: &Url) -> Result<()> {
+        let mut error = None;
+        let mut error_description = None;
+        let mut state = None;
+        let mut code = None;
+
+        for (key, value) in callback_url.query_pairs() {
+            match &*key {
+                "error" => error = Some(value.into_owned()),
+                "error_description" => error_description = Some(value.into_owned()),
+                "state" => state = Some(value.into_owned()),
+                "code" => code = Some(value.into_owned()),
+                _ => {}
+            }
+        }
+
+        let state = state.ok_or(Error::MissingCallbackParameter("state"))?;
+        let pending_login = {
+            let mut pending_logins = self.data().pending_logins.lock().unwrap();
+            pending_logins.retain(|_, login| !login.is_expired());
+            pending_logins.remove(&state).ok_or(Error::UnknownState)?
+        };
+
+        if let Some(error) = error {
+            return Err(Error::Provider(ErrorResponse { error, error_description }).into());
+        }
+        let code = code.ok_or(Error::MissingCallbackParameter("code"))?;
+
+        let client_id = self.client_id().ok_or(Error::NotRegistered)?;
+        let response = self
+            .request_token(&[
+                ("grant_type", "authorization_code"),
+                ("code", &code),
+                ("redirect_uri", pending_login.redirect_uri.as_str()),
+                ("client_id", &client_id),
+                ("code_verifier", &pending_login.code_verifier),
+            ])
+            .await?;
+
+        let whoami = self
+            .client
+            .inner
+            .http_client
+            .send(
+                whoami::v3::Request::new(),
+                None,
+                self.client.homeserver().await.to_string(),
+                Some(&response.access_token),
+                None,
+                &self.client.server_versions().await?,
+            )
+            .await?;
+
+        // The device ID is only part of the response with newer homeservers.
+        if let Some(device_id) = whoami.device_id {
+            if device_id != pending_login.device_id {
+                return Err(Error::DeviceIdMismatch {
+                    expected: pending_login.device_id,
+                    got: device_id,
+                }
+                .into());
+            }
+        }
+
+        self.client
+            .restore_session(Session {
+                access_token: response.access_token,
+                refresh_token: response.refresh_token,
+                user_id: whoami.user_id,
+                device_id: pending_login.device_id,
+            })
+            .await
+    }
+
+    /// Refresh the access token with the token endpoint of the Provider.
+    ///
+    /// This is called by [`Client::refresh_access_token()`] when the client is
+    /// registered with a Provider.
+    pub(crate) async fn refresh_access_token(
+        &self,
+        refresh_token: String,
+    ) -> HttpResult<refresh_token::v3::Response> {
+        match self.request_refreshed_token(&refresh_token).await {
+            Ok(response) => Ok(assign!(refresh_token::v3::Response::new(response.access_token), {
+                refresh_token: response.refresh_token,
+                expires_in_ms: response.expires_in.map(Duration::from_secs),
+            })),
+            Err(crate::Error::Http(error)) => Err(error),
+            Err(crate::Error::Oidc(error)) => Err(RefreshTokenError::Oidc(Arc::new(error)).into()),
+            Err(_) => Err(RefreshTokenError::UnableToRefreshToken.into()),
+        }
+    }
+
+    async fn request_refreshed_token(&self, refresh_token: &str) -> Result<TokenResponse> {
+        let client_id = self.client_id().ok_or(Error::NotRegistered)?;
+        self.request_token(&[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+            ("client_id", &client_id),
+        ])
+        .await
+    }
+
+    /// Log out by revoking the tokens of the session with the Provider.
+    ///
+    /// Like [`Client::logout()`], this doesn't clear the local state of the
+    /// session.
+    pub async fn logout(&self) -> Result<()> {
+        let tokens = self.client.session_tokens().ok_or(crate::Error::AuthenticationRequired)?;
+        let client_id = self.client_id().ok_or(Error::NotRegistered)?;
+        let endpoint = self
+            .provider_metadata()
+            .await?
+            .revocation_endpoint
+            .ok_or(Error::MissingEndpoint("revocation"))?;
+
+        // Revoking the refresh token first makes sure no new access token can be
+        // obtained with it.
+        if let Some(refresh_token) = &tokens.refresh_token {
+            self.revoke_token(&endpoint, &client_id, refresh_token, "refresh_token").await?;
+        }
+        self.revoke_token(&endpoint, &client_id, &tokens.access_token, "access_token").await
+    }
+
+    async fn revoke_token(
+        &self,
+        endpoint: &Url,
+        client_id: &str,
+        token: &str,
+        token_type_hint: &str,
+    ) -> Result<()> {
+        let request = form_request(
+            endpoint,
+            &[("token", token), ("token_type_hint", token_type_hint), ("client_id", client_id)],
+        );
+        let response = self.client.inner.http_client.send_raw(request).await?;
+        ensure_success(response.status(), response.body())?;
+        Ok(())
+    }
+
+    async fn request_token(&self, params: &[(&str, &str)]) -> Result<TokenResponse> {
+        let endpoint = self.provider_metadata().await?.token_endpoint;
+        self.send_request(form_request(&endpoint, params)).await
+    }
+
+    async fn send_request<T: DeserializeOwned>(&self, request: http::Request<Bytes>) -> Result<T> {
+        let response = self.client.inner.http_client.send_raw(request).await?;
+        ensure_success(response.status(), response.body())?;
+        Ok(serde_json::from_slice(response.body())?)
+    }
+}
+
+/// Build a `POST` request with the given parameters in a form-encoded body.
+fn form_request(endpoint: &Url, params: &[(&str, &str)]) -> http::Request<Bytes> {
+    let body = form_urlencoded::Serializer::new(String::new()).extend_pairs(params).finish();
+
+    http::Request::builder()
+        .method(Method::POST)
+        .uri(endpoint.as_str())
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(body.into())
+        .expect("valid request")
+}
+
+/// Convert an unsuccessful response of the Provider to an error.
+fn ensure_success(status: StatusCode, body: &[u8]) -> Result<(), Error> {
+    if status.is_success() {
+        return Ok(());
+    }
+
+    match serde_json::from_slice(body) {
+        Ok(error) => Err(Error::Provider(error)),
+        Err(_) => Err(Error::UnexpectedStatus(status)),
+    }
+}
+
+fn random_string(len: usize) -> String {
+    thread_rng().sample_iter(&Alphanumeric).take(len).map(char::from).collect()
+}
@@ -0,0 +1,588 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! High-level API to log in a new device with a QR code, as defined in
+//! [MSC3906].
+//!
+//! One of the two devices shows a QR code with [`QrLogin::start()`], and the
+//! other one scans it and calls [`QrLogin::scan()`]. Either the new device or
+//! the device that is already logged in can show the QR code.
+//!
+//! The two devices then set up a secure channel over an HTTP rendezvous
+//! session. The device that scanned the QR code shows the
+//! [check code](QrLoginSession::check_code) of the channel, and the user
+//! enters it on the device that shows the QR code, which passes it to
+//! [`QrLoginSession::confirm_check_code()`]. Then:
+//!
+//! * the device that is already logged in calls
+//!   [`QrLoginSession::approve()`] to send a login token to the new device and
+//!   cross-sign it, or [`QrLoginSession::decline()`],
+//! * the new device calls [`QrLoginSession::login()`] to log in with the login
+//!   token and trust the device that is already logged in.
+//!
+//! [MSC3906]: https://github.com/matrix-org/matrix-spec-proposals/pull/3906
+
+use std::{collections::BTreeMap, fmt, mem};
+
+use bytes::Bytes;
+use http::{
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    StatusCode,
+};
+pub use matrix_sdk_qrcode::{LoginIntent, LoginQrCodeData};
+use ruma::{OwnedDeviceId, TransactionId};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use url::Url;
+use vodozemac::{Curve25519PublicKey, Curve25519SecretKey, KeyError};
+
+use self::{rendezvous::RendezvousChannel, secure_channel::SecureChannel};
+use crate::{
+    encryption::{identities::ManualVerifyError, LocalTrust},
+    Client, Result,
+};
+
+mod rendezvous;
+mod secure_channel;
+
+/// The protocol to log in the new device with a login token, as defined in
+/// [MSC3882].
+///
+/// [MSC3882]: https://github.com/matrix-org/matrix-spec-proposals/pull/3882
+const LOGIN_TOKEN_PROTOCOL: &str = "org.matrix.msc3906.login_token";
+
+/// Errors specific to the QR code login.
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The rendezvous server returned an unexpected HTTP status code.
+    #[error("the rendezvous server returned an unexpected status code: {0}")]
+    UnexpectedStatus(StatusCode),
+
+    /// The response of the rendezvous server is missing a header.
+    #[error("the response of the rendezvous server is missing the `{0}` header")]
+    MissingHeader(&'static str),
+
+    /// The rendezvous session expired or was deleted.
+    #[error("the rendezvous session expired")]
+    Expired,
+
+    /// The QR code was shown by a device with the same role as this one.
+    #[error("the QR code is not meant to be scanned by this device")]
+    InvalidIntent,
+
+    /// The other device uses an unsupported secure channel algorithm.
+    #[error("the other device uses an unsupported secure channel algorithm")]
+    UnsupportedAlgorithm,
+
+    /// The public key of the other device is invalid.
+    #[error(transparent)]
+    InvalidKey(#[from] KeyError),
+
+    /// The other device sent an invalid or unexpected message.
+    #[error("the other device sent an invalid or unexpected message")]
+    InvalidMessage,
+
+    /// A message from the other device could not be decrypted.
+    #[error("a message from the other device could not be decrypted")]
+    Decryption,
+
+    /// The two devices don't support a common login protocol.
+    #[error("the other device doesn't support a common login protocol")]
+    UnsupportedProtocol,
+
+    /// The login was declined on the device that is already logged in.
+    #[error("the login was declined on the other device")]
+    Declined,
+
+    /// The keys of the other device could not be found.
+    #[error("the keys of the other device could not be found")]
+    DeviceNotFound,
+
+    /// The keys of the other device don't match the ones it sent.
+    #[error("the keys of the other device don't match the ones it sent")]
+    KeyMismatch,
+
+    /// The new device could not be cross-signed.
+    #[error(transparent)]
+    Verification(#[from] ManualVerifyError),
+
+    /// The check code entered by the user doesn't match the one of the secure
+    /// channel.
+    #[error("the check code doesn't match")]
+    CheckCodeMismatch,
+
+    /// The check code of the secure channel wasn't confirmed by the user.
+    #[error("the check code wasn't confirmed")]
+    CheckCodeNotConfirmed,
+
+    /// The homeserver sent by the other device is not a valid homeserver.
+    #[error("the homeserver sent by the other device is not a valid homeserver")]
+    InvalidHomeserver,
+}
+
+/// The outcome of the login, sent by the device that is already logged in.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Outcome {
+    Verified,
+    Declined,
+    Unsupported,
+}
+
+/// The messages sent over the secure channel.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+enum Message {
+    /// The new device lists the protocols it supports.
+    #[serde(rename = "m.login.progress")]
+    Progress { protocols: Vec<String> },
+
+    /// The device that is already logged in approves the login.
+    #[serde(rename = "m.login.approved")]
+    Approved { login_token: String, homeserver: String },
+
+    /// The device that is already logged in declines the login.
+    #[serde(rename = "m.login.declined")]
+    Declined,
+
+    /// The new device is logged in.
+    #[serde(rename = "m.login.success")]
+    Success { device_id: OwnedDeviceId, device_key: String },
+
+    /// The device that is already logged in verified the new device, or
+    /// couldn't.
+    #[serde(rename = "m.login.finish")]
+    Finish {
+        outcome: Outcome,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        verifying_device_id: Option<OwnedDeviceId>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        verifying_device_key: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        master_key: Option<String>,
+    },
+}
+
+impl Message {
+    fn finish(outcome: Outcome) -> Self {
+        Self::Finish {
+            outcome,
+            verifying_device_id: None,
+            verifying_device_key: None,
+            master_key: None,
+        }
+    }
+}
+
+/// A high-level API to log in a new device with a QR code.
+///
+/// To get this, use [`Client::qr_login()`].
+#[derive(Debug, Clone)]
+pub struct QrLogin {
+    client: Client,
+}
+
+impl QrLogin {
+    pub(crate) fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// Create a rendezvous session and get the data of the QR code to show on
+    /// this device.
+    ///
+    /// If the client is logged in, the QR code is meant to be scanned by the
+    /// new device, otherwise it is meant to be scanned by a device that is
+    /// already logged in.
+    ///
+    /// # Arguments
+    ///
+    /// * `rendezvous_server` - The URL of the HTTP rendezvous server where the
+    ///   session is created.
+    pub async fn start(
+        &self,
+        rendezvous_server: &Url,
+    ) -> Result<(QrLoginSession, LoginQrCodeData)> {
+        let intent =
+            if self.client.logged_in() { LoginIntent::Reconcile } else { LoginIntent::Start };
+
+        let rendezvous = RendezvousChannel::create(self.client.clone(), rendezvous_server).await?;
+        let secret_key = Curve25519SecretKey::new();
+        let data = LoginQrCodeData::new(
+            intent,
+            Curve25519PublicKey::from(&secret_key),
+            rendezvous.uri().to_string(),
+        );
+
+        let session = QrLoginSession {
+            client: self.client.clone(),
+            channel: Channel::Pending { rendezvous, secret_key },
+            check_code_confirmed: false,
+        };
+
+        Ok((session, data))
+    }
+
+    /// Join the rendezvous session of a QR code shown by the other device.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The data of the scanned QR code.
+    pub async fn scan(&self, data: &LoginQrCodeData) -> Result<QrLoginSession> {
+        let expected_intent =
+            if self.client.logged_in() { LoginIntent::Start } else { LoginIntent::Reconcile };
+        if data.intent != expected_intent {
+            return Err(Error::InvalidIntent.into());
+        }
+
+        let their_key = data.key().map_err(Error::from)?;
+        let uri = Url::parse(&data.rendezvous.transport.uri)?;
+        let rendezvous = RendezvousChannel::connect(self.client.clone(), uri).await?;
+
+        // The new device speaks first, so it waits for the confirmation of the
+        // device showing the QR code.
+        let wait_for_confirmation = !self.client.logged_in();
+        let channel = SecureChannel::connect(rendezvous, their_key, wait_for_confirmation).await?;
+
+        // The key of the other device comes from the QR code, so the channel
+        // is authenticated.
+        Ok(QrLoginSession {
+            client: self.client.clone(),
+            channel: Channel::Established(channel),
+            check_code_confirmed: true,
+        })
+    }
+}
+
+enum Channel {
+    /// The QR code is shown and the other device didn't send its key yet.
+    Pending { rendezvous: RendezvousChannel, secret_key: Curve25519SecretKey },
+    /// The secure channel is set up.
+    Established(SecureChannel),
+    /// Setting up the secure channel failed.
+    Failed,
+}
+
+/// A QR code login in progress, from [`QrLogin::start()`] or
+/// [`QrLogin::scan()`].
+pub struct QrLoginSession {
+    client: Client,
+    channel: Channel,
+    /// Whether the user confirmed that the check code is the same on both
+    /// devices. Always `true` on the device that scanned the QR code.
+    check_code_confirmed: bool,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Debug for QrLoginSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QrLoginSession").finish_non_exhaustive()
+    }
+}
+
+impl QrLoginSession {
+    /// Get the check code of the secure channel.
+    ///
+    /// On the device that scanned the QR code, it must be shown to the user,
+    /// who enters it on the other device.
+    ///
+    /// On the device that shows the QR code, this waits for the other device
+    /// to join the rendezvous session.
+    pub async fn check_code(&mut self) -> Result<String> {
+        Ok(self.establish().await?.check_code().to_owned())
+    }
+
+    /// Confirm the check code entered by the user, on the device that shows
+    /// the QR code.
+    ///
+    /// This must be called before [`approve()`](Self::approve) or
+    /// [`login()`](Self::login) on that device, because anyone could have
+    /// joined the rendezvous session. It waits for the other device to join
+    /// the rendezvous session if needed.
+    ///
+    /// Returns an error if the check code doesn't match the one of the secure
+    /// channel, in which case the login must be aborted.
+    ///
+    /// # Arguments
+    ///
+    /// * `check_code` - The check code shown on the device that scanned the QR
+    ///   code.
+    pub async fn confirm_check_code(&mut self, check_code: &str) -> Result<()> {
+        if self.establish().await?.check_code() != check_code.trim() {
+            return Err(Error::CheckCodeMismatch.into());
+        }
+
+        self.check_code_confirmed = true;
+        Ok(())
+    }
+
+    /// Wait for the secure channel to be set up, if needed.
+    async fn establish(&mut self) -> Result<&mut SecureChannel> {
+        // If setting up the channel fails, it stays in the `Failed` state.
+        self.channel = match mem::replace(&mut self.channel, Channel::Failed) {
+            Channel::Pending { rendezvous, secret_key } => {
+                // The new device speaks first, so the device that is already
+                // logged in confirms that the channel is set up.
+                let confirm = self.client.logged_in();
+                Channel::Established(SecureChannel::accept(rendezvous, secret_key, confirm).await?)
+            }
+            channel => channel,
+        };
+
+        match &mut self.channel {
+            Channel::Established(channel) => Ok(channel),
+            // The rendezvous session can't be used anymore.
+            Channel::Pending { .. } | Channel::Failed => Err(Error::Expired.into()),
+        }
+    }
+
+    /// Wait for the secure channel to be set up, if needed, and take it.
+    ///
+    /// If `require_check_code` is `true`, this fails if the user didn't
+    /// confirm the check code.
+    async fn into_channel(mut self, require_check_code: bool) -> Result<(Client, SecureChannel)> {
+        self.establish().await?;
+        if require_check_code && !self.check_code_confirmed {
+            return Err(Error::CheckCodeNotConfirmed.into());
+        }
+
+        let Channel::Established(channel) = self.channel else {
+            unreachable!("the channel was established");
+        };
+        Ok((self.client, channel))
+    }
+
+    /// Approve the login of the new device, on the device that is already
+    /// logged in.
+    ///
+    /// This sends a login token to the new device, waits for it to log in and
+    /// cross-signs it. This requires the private cross-signing keys.
+    ///
+    /// If this device shows the QR code, the check code must have been
+    /// confirmed with [`confirm_check_code()`](Self::confirm_check_code)
+    /// first.
+    ///
+    /// Returns the ID of the new device.
+    pub async fn approve(self) -> Result<OwnedDeviceId> {
+        let user_id = self.client.user_id().ok_or(crate::Error::AuthenticationRequired)?.to_owned();
+        let (client, mut channel) = self.into_channel(true).await?;
+
+        let Message::Progress { protocols } = channel.receive().await? else {
+            return Err(Error::InvalidMessage.into());
+        };
+        if !protocols.iter().any(|protocol| protocol == LOGIN_TOKEN_PROTOCOL) {
+            channel.send(&Message::finish(Outcome::Unsupported)).await?;
+            return Err(Error::UnsupportedProtocol.into());
+        }
+
+        let login_token = request_login_token(&client).await?;
+        let homeserver = client.homeserver().await.to_string();
+        channel.send(&Message::Approved { login_token, homeserver }).await?;
+
+        let Message::Success { device_id, device_key } = channel.receive().await? else {
+            return Err(Error::InvalidMessage.into());
+        };
+
+        // The new device uploaded its keys before sending its key, so we can
+        // get them right away.
+        client
+            .keys_query(&TransactionId::new(), BTreeMap::from([(user_id.clone(), vec![])]))
+            .await?;
+        let encryption = client.encryption();
+        let device =
+            encryption.get_device(&user_id, &device_id).await?.ok_or(Error::DeviceNotFound)?;
+
+        if device.ed25519_key().map(|key| key.to_base64()).as_deref() != Some(&*device_key) {
+            channel.send(&Message::finish(Outcome::Declined)).await?;
+            return Err(Error::KeyMismatch.into());
+        }
+
+        device.verify().await.map_err(Error::from)?;
+
+        let master_key = encryption
+            .get_user_identity(&user_id)
+            .await?
+            .and_then(|identity| identity.master_key().get_first_key())
+            .map(|key| key.to_base64());
+        channel
+            .send(&Message::Finish {
+                outcome: Outcome::Verified,
+                verifying_device_id: client.device_id().map(ToOwned::to_owned),
+                verifying_device_key: encryption.ed25519_key().await,
+                master_key,
+            })
+            .await?;
+
+        Ok(device_id)
+    }
+
+    /// Decline the login of the new device, on the device that is already
+    /// logged in.
+    pub async fn decline(self) -> Result<()> {
+        let (_, mut channel) = self.into_channel(false).await?;
+
+        let Message::Progress { .. } = channel.receive().await? else {
+            return Err(Error::InvalidMessage.into());
+        };
+        channel.send(&Message::Declined).await
+    }
+
+    /// Log in on the new device.
+    ///
+    /// This waits for the device that is already logged in to approve the
+    /// login, logs in with the login token it sent and trusts it once it has
+    /// cross-signed this device.
+    ///
+    /// If this device shows the QR code, the check code must have been
+    /// confirmed with [`confirm_check_code()`](Self::confirm_check_code)
+    /// first.
+    pub async fn login(self) -> Result<()> {
+        let (client, mut channel) = self.into_channel(true).await?;
+
+        channel
+            .send(&Message::Progress { protocols: vec![LOGIN_TOKEN_PROTOCOL.to_owned()] })
+            .await?;
+
+        let (login_token, homeserver) = match channel.receive().await? {
+            Message::Approved { login_token, homeserver } => (login_token, homeserver),
+            Message::Declined => {
+                channel.close().await?;
+                return Err(Error::Declined.into());
+            }
+            Message::Finish { outcome: Outcome::Unsupported, .. } => {
+                channel.close().await?;
+                return Err(Error::UnsupportedProtocol.into());
+            }
+            _ => return Err(Error::InvalidMessage.into()),
+        };
+
+        let homeserver = Url::parse(&homeserver).map_err(|_| Error::InvalidHomeserver)?;
+        if homeserver != client.homeserver().await {
+            check_homeserver(&client, &homeserver).await?;
+            client.set_homeserver(homeserver).await;
+        }
+        client.login_token(&login_token).send().await?;
+
+        // Upload the keys of this device so the other device can cross-sign
+        // it.
+        client.send_outgoing_requests().await?;
+
+        let device_id = client.device_id().ok_or(crate::Error::AuthenticationRequired)?.to_owned();
+        let device_key =
+            client.encryption().ed25519_key().await.ok_or(crate::Error::AuthenticationRequired)?;
+        channel.send(&Message::Success { device_id, device_key }).await?;
+
+        let message = channel.receive().await?;
+        channel.close().await?;
+
+        let Message::Finish { outcome, verifying_device_id, verifying_device_key, master_key } =
+            message
+        else {
+            return Err(Error::InvalidMessage.into());
+        };
+        if outcome != Outcome::Verified {
+            return Err(Error::Declined.into());
+        }
+
+        trust_verifying_device(&client, verifying_device_id, verifying_device_key, master_key).await
+    }
+}
+
+/// Trust the device that verified this device, and the master key it sent.
+async fn trust_verifying_device(
+    client: &Client,
+    device_id: Option<OwnedDeviceId>,
+    device_key: Option<String>,
+    master_key: Option<String>,
+) -> Result<()> {
+    let (Some(device_id), Some(device_key)) = (device_id, device_key) else {
+        return Err(Error::InvalidMessage.into());
+    };
+    let user_id = client.user_id().ok_or(crate::Error::AuthenticationRequired)?.to_owned();
+
+    client.keys_query(&TransactionId::new(), BTreeMap::from([(user_id.clone(), vec![])])).await?;
+    let encryption = client.encryption();
+
+    let device = encryption.get_device(&user_id, &device_id).await?.ok_or(Error::DeviceNotFound)?;
+    if device.ed25519_key().map(|key| key.to_base64()) != Some(device_key) {
+        return Err(Error::KeyMismatch.into());
+    }
+    device.set_local_trust(LocalTrust::Verified).await?;
+
+    if let Some(master_key) = master_key {
+        let identity =
+            encryption.get_user_identity(&user_id).await?.ok_or(Error::DeviceNotFound)?;
+        if identity.master_key().get_first_key().map(|key| key.to_base64()) != Some(master_key) {
+            return Err(Error::KeyMismatch.into());
+        }
+        identity.verify().await.map_err(Error::from)?;
+    }
+
+    Ok(())
+}
+
+/// Check that the homeserver sent by the other device is a Matrix homeserver
+/// that can be reached over HTTPS.
+async fn check_homeserver(client: &Client, homeserver: &Url) -> Result<()> {
+    #[derive(Deserialize)]
+    struct VersionsResponse {
+        #[allow(dead_code)]
+        versions: Vec<String>,
+    }
+
+    if homeserver.scheme() != "https" {
+        return Err(Error::InvalidHomeserver.into());
+    }
+
+    let url = format!("{}/_matrix/client/versions", homeserver.as_str().trim_end_matches('/'));
+    let request = http::Request::get(url).body(Bytes::new()).expect("valid request");
+
+    let response = client.inner.http_client.send_raw(request).await?;
+    if !response.status().is_success()
+        || serde_json::from_slice::<VersionsResponse>(response.body()).is_err()
+    {
+        return Err(Error::InvalidHomeserver.into());
+    }
+
+    Ok(())
+}
+
+/// Request a login token for the new device, as defined in [MSC3882].
+///
+/// [MSC3882]: https://github.com/matrix-org/matrix-spec-proposals/pull/3882
+async fn request_login_token(client: &Client) -> Result<String> {
+    #[derive(Deserialize)]
+    struct LoginTokenResponse {
+        login_token: String,
+    }
+
+    let access_token = client.access_token().ok_or(crate::Error::AuthenticationRequired)?;
+    let url = format!(
+        "{}/_matrix/client/unstable/org.matrix.msc3882/login/token",
+        client.homeserver().await.as_str().trim_end_matches('/')
+    );
+    let request = http::Request::post(url)
+        .header(AUTHORIZATION, format!("Bearer {access_token}"))
+        .header(CONTENT_TYPE, "application/json")
+        .body(Bytes::from_static(b"{}"))
+        .expect("valid request");
+
+    let response = client.inner.http_client.send_raw(request).await?;
+    if !response.status().is_success() {
+        return Err(Error::UnexpectedStatus(response.status()).into());
+    }
+
+    let response: LoginTokenResponse = serde_json::from_slice(response.body())?;
+    Ok(response.login_token)
+}
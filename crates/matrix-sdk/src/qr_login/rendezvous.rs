@@ -0,0 +1,152 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The HTTP rendezvous transport of [MSC3886].
+//!
+//! [MSC3886]: https://github.com/matrix-org/matrix-spec-proposals/pull/3886
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use http::{
+    header::{CONTENT_TYPE, IF_MATCH, IF_NONE_MATCH},
+    Method, StatusCode,
+};
+use tracing::trace;
+use url::Url;
+
+use super::Error;
+use crate::{http_client::sleep, Client, Result};
+
+/// How long to wait before polling the rendezvous session again when it
+/// didn't change.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A rendezvous session on an HTTP rendezvous server.
+///
+/// The session holds a single payload that the two devices replace in turn.
+/// The `ETag` of the payload is used to know whether the other device
+/// replaced it.
+#[derive(Debug)]
+pub(super) struct RendezvousChannel {
+    client: Client,
+    uri: Url,
+    etag: Option<String>,
+}
+
+impl RendezvousChannel {
+    /// Create a new rendezvous session on the given server.
+    pub(super) async fn create(client: Client, rendezvous_server: &Url) -> Result<Self> {
+        let request = http::Request::builder()
+            .method(Method::POST)
+            .uri(rendezvous_server.as_str())
+            .header(CONTENT_TYPE, "application/json")
+            .body(Bytes::from_static(b"{}"))
+            .expect("valid request");
+        let response = client.inner.http_client.send_raw(request).await?;
+
+        if response.status() != StatusCode::CREATED {
+            return Err(Error::UnexpectedStatus(response.status()).into());
+        }
+
+        let location = header(&response, "location")?;
+        let uri = rendezvous_server.join(&location)?;
+        let etag = Some(header(&response, "etag")?);
+
+        Ok(Self { client, uri, etag })
+    }
+
+    /// Connect to the rendezvous session at the given URI.
+    ///
+    /// This receives the current payload of the session, so the next call to
+    /// [`receive()`](Self::receive) waits for a new payload.
+    pub(super) async fn connect(client: Client, uri: Url) -> Result<Self> {
+        let mut channel = Self { client, uri, etag: None };
+        channel.receive().await?;
+        Ok(channel)
+    }
+
+    /// The URI of the rendezvous session.
+    pub(super) fn uri(&self) -> &Url {
+        &self.uri
+    }
+
+    /// Replace the payload of the rendezvous session.
+    pub(super) async fn send(&mut self, body: Vec<u8>) -> Result<()> {
+        let mut request = http::Request::builder()
+            .method(Method::PUT)
+            .uri(self.uri.as_str())
+            .header(CONTENT_TYPE, "application/json");
+        if let Some(etag) = &self.etag {
+            request = request.header(IF_MATCH, etag);
+        }
+        let request = request.body(body.into()).expect("valid request");
+
+        let response = self.client.inner.http_client.send_raw(request).await?;
+        match response.status() {
+            StatusCode::ACCEPTED => {
+                self.etag = Some(header(&response, "etag")?);
+                Ok(())
+            }
+            StatusCode::NOT_FOUND | StatusCode::GONE => Err(Error::Expired.into()),
+            status => Err(Error::UnexpectedStatus(status).into()),
+        }
+    }
+
+    /// Wait for the other device to replace the payload of the rendezvous
+    /// session, and return the new payload.
+    pub(super) async fn receive(&mut self) -> Result<Bytes> {
+        loop {
+            let mut request = http::Request::builder().method(Method::GET).uri(self.uri.as_str());
+            if let Some(etag) = &self.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            let request = request.body(Bytes::new()).expect("valid request");
+
+            let response = self.client.inner.http_client.send_raw(request).await?;
+            match response.status() {
+                StatusCode::OK => {
+                    self.etag = Some(header(&response, "etag")?);
+                    return Ok(response.into_body());
+                }
+                StatusCode::NOT_MODIFIED => {
+                    trace!(uri = %self.uri, "The rendezvous session didn't change, polling again");
+                    sleep(POLL_INTERVAL).await;
+                }
+                StatusCode::NOT_FOUND | StatusCode::GONE => return Err(Error::Expired.into()),
+                status => return Err(Error::UnexpectedStatus(status).into()),
+            }
+        }
+    }
+
+    /// Delete the rendezvous session.
+    pub(super) async fn delete(self) -> Result<()> {
+        let request = http::Request::builder()
+            .method(Method::DELETE)
+            .uri(self.uri.as_str())
+            .body(Bytes::new())
+            .expect("valid request");
+        self.client.inner.http_client.send_raw(request).await?;
+        Ok(())
+    }
+}
+
+fn header(response: &http::Response<Bytes>, name: &'static str) -> Result<String, Error> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(ToOwned::to_owned)
+        .ok_or(Error::MissingHeader(name))
+}
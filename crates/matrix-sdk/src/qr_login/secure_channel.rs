@@ -0,0 +1,261 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The secure channel of [MSC3903], set up over a rendezvous session.
+//!
+//! The device showing the QR code puts its public key in the QR code. The
+//! device scanning it sends its own public key through the rendezvous session,
+//! and both devices derive the same keys from an ECDH key exchange: one
+//! AES-256-GCM key for each direction, and a check code.
+//!
+//! The key sent through the rendezvous session is not authenticated, so the
+//! device showing the QR code must make sure that it is talking to the device
+//! that scanned it by having the user confirm the check code shown on the other
+//! device.
+//!
+//! Each message uses the number of messages sent before it in the same
+//! direction as nonce, so messages that are replayed, reordered or reflected by
+//! the rendezvous server are rejected.
+//!
+//! [MSC3903]: https://github.com/matrix-org/matrix-spec-proposals/pull/3903
+
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Nonce,
+};
+use hkdf::Hkdf;
+use matrix_sdk_qrcode::RENDEZVOUS_ALGORITHM;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::Sha256;
+use vodozemac::{Curve25519PublicKey, Curve25519SecretKey};
+
+use super::{rendezvous::RendezvousChannel, Error};
+use crate::Result;
+
+/// The message sent by the device showing the QR code to confirm that the
+/// secure channel is set up, when the other device speaks first.
+const CONFIRMATION: &[u8] = b"MATRIX_QR_CODE_LOGIN_OK";
+
+const NONCE_SIZE: usize = 12;
+
+/// The unencrypted message with the public key of the device that scanned the
+/// QR code.
+#[derive(Serialize, Deserialize)]
+struct KeyExchange {
+    algorithm: String,
+    key: String,
+}
+
+/// An encrypted message.
+#[derive(Serialize, Deserialize)]
+struct EncryptedMessage {
+    iv: String,
+    ciphertext: String,
+}
+
+/// A secure channel over a rendezvous session.
+pub(super) struct SecureChannel {
+    rendezvous: RendezvousChannel,
+    /// The cipher of the messages sent to the other device.
+    send_cipher: Aes256Gcm,
+    /// The cipher of the messages received from the other device.
+    receive_cipher: Aes256Gcm,
+    /// The number of messages sent to the other device.
+    send_counter: u64,
+    /// The number of messages received from the other device.
+    receive_counter: u64,
+    check_code: String,
+}
+
+impl SecureChannel {
+    /// Set up the secure channel on the device that shows the QR code, by
+    /// waiting for the public key of the other device.
+    ///
+    /// If `confirm` is `true`, a confirmation is sent to the other device so
+    /// it can speak first.
+    pub(super) async fn accept(
+        mut rendezvous: RendezvousChannel,
+        secret_key: Curve25519SecretKey,
+        confirm: bool,
+    ) -> Result<Self> {
+        let message: KeyExchange = serde_json::from_slice(&rendezvous.receive().await?)?;
+        if message.algorithm != RENDEZVOUS_ALGORITHM {
+            return Err(Error::UnsupportedAlgorithm.into());
+        }
+
+        let our_key = Curve25519PublicKey::from(&secret_key);
+        let their_key = Curve25519PublicKey::from_base64(&message.key).map_err(Error::from)?;
+        let keys = ChannelKeys::derive(&secret_key, &their_key, &our_key, &their_key);
+
+        let mut channel = Self {
+            rendezvous,
+            send_cipher: keys.initiator_cipher,
+            receive_cipher: keys.recipient_cipher,
+            send_counter: 0,
+            receive_counter: 0,
+            check_code: keys.check_code,
+        };
+        if confirm {
+            channel.send_bytes(CONFIRMATION).await?;
+        }
+
+        Ok(channel)
+    }
+
+    /// Set up the secure channel on the device that scanned the QR code with
+    /// the given public key, by sending our public key.
+    ///
+    /// If `wait_for_confirmation` is `true`, this waits for the confirmation
+    /// from the other device before returning.
+    pub(super) async fn connect(
+        mut rendezvous: RendezvousChannel,
+        their_key: Curve25519PublicKey,
+        wait_for_confirmation: bool,
+    ) -> Result<Self> {
+        let secret_key = Curve25519SecretKey::new();
+        let our_key = Curve25519PublicKey::from(&secret_key);
+
+        let message =
+            KeyExchange { algorithm: RENDEZVOUS_ALGORITHM.to_owned(), key: our_key.to_base64() };
+        rendezvous.send(serde_json::to_vec(&message)?).await?;
+
+        let keys = ChannelKeys::derive(&secret_key, &their_key, &their_key, &our_key);
+        let mut channel = Self {
+            rendezvous,
+            send_cipher: keys.recipient_cipher,
+            receive_cipher: keys.initiator_cipher,
+            send_counter: 0,
+            receive_counter: 0,
+            check_code: keys.check_code,
+        };
+
+        if wait_for_confirmation && channel.receive_bytes().await? != CONFIRMATION {
+            return Err(Error::InvalidMessage.into());
+        }
+
+        Ok(channel)
+    }
+
+    /// The check code of the channel, which is the same on both devices if
+    /// they are talking to each other.
+    pub(super) fn check_code(&self) -> &str {
+        &self.check_code
+    }
+
+    /// Encrypt and send the given message to the other device.
+    pub(super) async fn send<T: Serialize>(&mut self, message: &T) -> Result<()> {
+        self.send_bytes(&serde_json::to_vec(message)?).await
+    }
+
+    /// Receive and decrypt the next message from the other device.
+    pub(super) async fn receive<T: DeserializeOwned>(&mut self) -> Result<T> {
+        let plaintext = self.receive_bytes().await?;
+        serde_json::from_slice(&plaintext).map_err(|_| Error::InvalidMessage.into())
+    }
+
+    /// Delete the underlying rendezvous session.
+    pub(super) async fn close(self) -> Result<()> {
+        self.rendezvous.delete().await
+    }
+
+    async fn send_bytes(&mut self, plaintext: &[u8]) -> Result<()> {
+        let nonce = nonce(self.send_counter);
+        self.send_counter += 1;
+
+        let ciphertext = self
+            .send_cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .expect("encrypting with AES-GCM should never fail");
+
+        let message =
+            EncryptedMessage { iv: base64::encode(nonce), ciphertext: base64::encode(ciphertext) };
+        self.rendezvous.send(serde_json::to_vec(&message)?).await
+    }
+
+    async fn receive_bytes(&mut self) -> Result<Vec<u8>> {
+        let message: EncryptedMessage = serde_json::from_slice(&self.rendezvous.receive().await?)
+            .map_err(|_| Error::InvalidMessage)?;
+
+        // Messages that don't have the expected nonce were replayed or
+        // reordered.
+        let expected_nonce = nonce(self.receive_counter);
+        let nonce = base64::decode(message.iv).map_err(|_| Error::InvalidMessage)?;
+        let ciphertext = base64::decode(message.ciphertext).map_err(|_| Error::InvalidMessage)?;
+        if nonce != expected_nonce {
+            return Err(Error::InvalidMessage.into());
+        }
+
+        let plaintext = self
+            .receive_cipher
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| Error::Decryption)?;
+        self.receive_counter += 1;
+
+        Ok(plaintext)
+    }
+}
+
+/// The keys of a secure channel.
+struct ChannelKeys {
+    /// The cipher of the messages sent by the device showing the QR code.
+    initiator_cipher: Aes256Gcm,
+    /// The cipher of the messages sent by the device scanning the QR code.
+    recipient_cipher: Aes256Gcm,
+    /// The check code, as four decimal digits.
+    check_code: String,
+}
+
+impl ChannelKeys {
+    /// Derive the keys of the secure channel from the ECDH shared secret and
+    /// the public keys of both devices.
+    fn derive(
+        secret_key: &Curve25519SecretKey,
+        their_key: &Curve25519PublicKey,
+        initiator_key: &Curve25519PublicKey,
+        recipient_key: &Curve25519PublicKey,
+    ) -> Self {
+        let shared_secret = secret_key.diffie_hellman(their_key);
+        let hkdf = Hkdf::<Sha256>::new(None, shared_secret.as_bytes());
+
+        let expand = |purpose: &str, output: &mut [u8]| {
+            let info = format!(
+                "{RENDEZVOUS_ALGORITHM}|{}|{}|{purpose}",
+                initiator_key.to_base64(),
+                recipient_key.to_base64()
+            );
+            hkdf.expand(info.as_bytes(), output).expect("the output has a valid length for HKDF");
+        };
+        let cipher = |purpose: &str| {
+            let mut key = [0u8; 32];
+            expand(purpose, &mut key);
+            Aes256Gcm::new_from_slice(&key).expect("the key has the right length")
+        };
+
+        let mut check_code = [0u8; 2];
+        expand("check_code", &mut check_code);
+
+        Self {
+            initiator_cipher: cipher("initiator"),
+            recipient_cipher: cipher("recipient"),
+            check_code: format!("{:04}", u16::from_be_bytes(check_code) % 10_000),
+        }
+    }
+}
+
+/// The nonce of the message with the given counter.
+fn nonce(counter: u64) -> [u8; NONCE_SIZE] {
+    let mut nonce = [0u8; NONCE_SIZE];
+    nonce[NONCE_SIZE - 8..].copy_from_slice(&counter.to_be_bytes());
+    nonce
+}
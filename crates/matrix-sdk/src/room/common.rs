@@ -13,11 +13,12 @@ use ruma::events::{
 };
 use ruma::{
     api::client::{
-        config::set_global_account_data,
+        context::get_context,
         error::ErrorKind,
         filter::RoomEventFilter,
         membership::{get_member_events, join_room_by_id, leave_room},
         message::get_message_events,
+        relations::get_relating_events_with_rel_type,
         room::get_room_event,
         state::get_state_events_for_key,
         tag::{create_tag, delete_tag},
@@ -25,29 +26,32 @@ use ruma::{
     },
     assign,
     events::{
-        direct::DirectEventContent,
+        relation::RelationType,
         room::{
             encryption::RoomEncryptionEventContent, history_visibility::HistoryVisibility,
-            server_acl::RoomServerAclEventContent, MediaSource,
+            power_levels::RoomPowerLevelsEventContent, server_acl::RoomServerAclEventContent,
+            MediaSource,
         },
+        space::{child::SpaceChildEventContent, parent::SpaceParentEventContent},
         tag::{TagInfo, TagName},
-        AnyRoomAccountDataEvent, AnyStateEvent, AnySyncStateEvent, EmptyStateKey, RedactContent,
-        RedactedStateEventContent, RoomAccountDataEvent, RoomAccountDataEventContent,
-        RoomAccountDataEventType, StateEventContent, StateEventType, StaticEventContent,
-        SyncStateEvent,
+        AnyRoomAccountDataEvent, AnyStateEvent, AnySyncStateEvent, AnyTimelineEvent, EmptyStateKey,
+        RedactContent, RedactedStateEventContent, RoomAccountDataEvent,
+        RoomAccountDataEventContent, RoomAccountDataEventType, StateEventContent, StateEventType,
+        StaticEventContent, SyncStateEvent,
     },
     serde::Raw,
     uint, EventId, MatrixToUri, MatrixUri, OwnedEventId, OwnedServerName, RoomId, UInt, UserId,
 };
 use serde::de::DeserializeOwned;
+use tracing::warn;
 
 #[cfg(feature = "experimental-timeline")]
-use super::timeline::Timeline;
+use super::timeline::{Timeline, TimelineBuilder};
 use super::Joined;
 use crate::{
     event_handler::{EventHandler, EventHandlerHandle, SyncEvent},
     media::{MediaFormat, MediaRequest},
-    room::{Left, RoomMember, RoomType},
+    room::{Left, Room, RoomMember, RoomType, SpaceParent},
     BaseRoom, Client, Error, HttpError, HttpResult, Result,
 };
 
@@ -131,6 +135,45 @@ impl Common {
         self.inner.is_state_fully_synced()
     }
 
+    /// Get the room that this room replaces, if it is known.
+    ///
+    /// When a room is upgraded, the `m.room.create` event of the new room
+    /// points to the old room as its predecessor.
+    pub fn predecessor(&self) -> Option<Room> {
+        let predecessor = self.create_content()?.predecessor?;
+        self.client.get_room(&predecessor.room_id)
+    }
+
+    /// Get the room that replaces this room, if it is known.
+    ///
+    /// When a room is upgraded, an `m.room.tombstone` event is sent in the old
+    /// room that points to the new room as its successor. The successor is
+    /// only returned if it confirms the relationship, i.e. if this room is
+    /// its [`predecessor`](Self::predecessor).
+    pub fn successor(&self) -> Option<Room> {
+        let successor = self.client.get_room(&self.tombstone()?.replacement_room)?;
+        let predecessor = successor.create_content()?.predecessor?;
+        (*predecessor.room_id == *self.room_id()).then_some(successor)
+    }
+
+    /// Whether this room was upgraded and the user has joined the room that
+    /// replaces it.
+    ///
+    /// Such rooms are usually hidden from room lists.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async {
+    /// # let client: matrix_sdk::Client = todo!();
+    /// let rooms: Vec<_> =
+    ///     client.joined_rooms().into_iter().filter(|room| !room.is_replaced()).collect();
+    /// # };
+    /// ```
+    pub fn is_replaced(&self) -> bool {
+        matches!(self.successor(), Some(Room::Joined(_)))
+    }
+
     /// Gets the avatar of this room, if set.
     ///
     /// Returns the avatar.
@@ -195,28 +238,80 @@ impl Common {
     /// # });
     /// ```
     pub async fn messages(&self, options: MessagesOptions) -> Result<Messages> {
+        let mut messages = self.undecrypted_messages(options).await?;
+        let events = messages.chunk.into_iter().map(|event| event.event).collect();
+        messages.chunk = self.try_decrypt_events(events).await;
+
+        Ok(messages)
+    }
+
+    /// Same as [`messages`](Self::messages), but without decrypting the
+    /// events.
+    pub(crate) async fn undecrypted_messages(&self, options: MessagesOptions) -> Result<Messages> {
         let room_id = self.inner.room_id();
         let request = options.into_request(room_id);
         let http_response = self.client.send(request, None).await?;
 
-        #[allow(unused_mut)]
-        let mut response = Messages {
+        Ok(Messages {
             start: http_response.start,
             end: http_response.end,
-            #[cfg(not(feature = "e2e-encryption"))]
             chunk: http_response
                 .chunk
                 .into_iter()
                 .map(|event| TimelineEvent { event, encryption_info: None })
                 .collect(),
-            #[cfg(feature = "e2e-encryption")]
-            chunk: Vec::with_capacity(http_response.chunk.len()),
             state: http_response.state,
-        };
+        })
+    }
 
+    /// Fetch the replies of the thread with the given root event, newest
+    /// first.
+    ///
+    /// The events are not decrypted.
+    #[cfg(feature = "experimental-timeline")]
+    pub(crate) async fn thread_replies(
+        &self,
+        thread_root: &EventId,
+        from: Option<String>,
+        limit: UInt,
+    ) -> Result<Messages> {
+        self.client.ensure_supported(crate::Feature::Relations).await?;
+
+        let request = assign!(
+            get_relating_events_with_rel_type::v1::Request::new(
+                self.room_id().to_owned(),
+                thread_root.to_owned(),
+                RelationType::Thread,
+            ),
+            { from: from.clone(), limit: Some(limit) }
+        );
+        let http_response = self.client.send(request, None).await?;
+        let chunk = http_response
+            .chunk
+            .into_iter()
+            .map(|event| TimelineEvent { event: event.cast(), encryption_info: None })
+            .collect();
+
+        Ok(Messages {
+            start: from.unwrap_or_default(),
+            end: http_response.next_batch,
+            chunk,
+            state: Vec::new(),
+        })
+    }
+
+    /// Decrypt the given events where possible, events that can't be decrypted
+    /// are returned as-is.
+    pub(crate) async fn try_decrypt_events(
+        &self,
+        events: Vec<Raw<AnyTimelineEvent>>,
+    ) -> Vec<TimelineEvent> {
         #[cfg(feature = "e2e-encryption")]
         if let Some(machine) = self.client.olm_machine() {
-            for event in http_response.chunk {
+            let room_id = self.inner.room_id();
+            let mut decrypted_events = Vec::with_capacity(events.len());
+
+            for event in events {
                 let decrypted_event = if let Ok(AnySyncTimelineEvent::MessageLike(
                     AnySyncMessageLikeEvent::RoomEncrypted(SyncMessageLikeEvent::Original(_)),
                 )) = event.deserialize_as::<AnySyncTimelineEvent>()
@@ -230,18 +325,13 @@ impl Common {
                     TimelineEvent { event, encryption_info: None }
                 };
 
-                response.chunk.push(decrypted_event);
+                decrypted_events.push(decrypted_event);
             }
-        } else {
-            response.chunk.extend(
-                http_response
-                    .chunk
-                    .into_iter()
-                    .map(|event| TimelineEvent { event, encryption_info: None }),
-            );
+
+            return decrypted_events;
         }
 
-        Ok(response)
+        events.into_iter().map(|event| TimelineEvent { event, encryption_info: None }).collect()
     }
 
     /// Register a handler for events of a specific type, within this room.
@@ -268,7 +358,73 @@ impl Common {
     /// independent events.
     #[cfg(feature = "experimental-timeline")]
     pub async fn timeline(&self) -> Timeline {
-        Timeline::new(self).await.with_fully_read_tracking().await
+        self.timeline_builder().build().await
+    }
+
+    /// Get a [`TimelineBuilder`] for this room.
+    ///
+    /// This allows configuring the [`Timeline`] before creating it, see the
+    /// builder's methods for the available options.
+    #[cfg(feature = "experimental-timeline")]
+    pub fn timeline_builder(&self) -> TimelineBuilder {
+        TimelineBuilder::new(self)
+    }
+
+    /// Get a [`Timeline`] for the thread with the given root event.
+    ///
+    /// The timeline starts out empty. Use [`Timeline::paginate_backwards`] to
+    /// load the replies of the thread, and finally its root event. New replies
+    /// are added as they are received from sync.
+    ///
+    /// # Arguments
+    ///
+    /// * `root_event_id` - The ID of the thread's root event.
+    #[cfg(feature = "experimental-timeline")]
+    pub fn thread_timeline(&self, root_event_id: &EventId) -> Timeline {
+        Timeline::for_thread(self, root_event_id)
+    }
+
+    /// Get a [`Timeline`] for this room, starting around the event with the
+    /// given ID.
+    ///
+    /// This is useful to open a permalink to an older event, without having
+    /// to paginate back from the live end of the room. Use
+    /// [`Timeline::paginate_backwards`] and [`Timeline::paginate_forwards`] to
+    /// load more events. Once forward pagination reaches the live end of the
+    /// room, live events are added to the timeline like for
+    /// [`timeline`](Self::timeline).
+    ///
+    /// # Arguments
+    ///
+    /// * `event_id` - The ID of the event to focus on.
+    ///
+    /// * `context_size` - The maximum number of events to load around the
+    ///   event, split between events before and after it.
+    #[cfg(feature = "experimental-timeline")]
+    pub async fn timeline_focused_on(
+        &self,
+        event_id: &EventId,
+        context_size: UInt,
+    ) -> Result<Timeline> {
+        let request = assign!(
+            get_context::v3::Request::new(self.room_id().to_owned(), event_id.to_owned()),
+            { limit: context_size }
+        );
+        let response = self.client.send(request, None).await?;
+
+        let mut events: Vec<_> = response.events_before.into_iter().rev().collect();
+        events.extend(response.event);
+        events.extend(response.events_after);
+        let events = self.try_decrypt_events(events).await.into_iter().map(Into::into).collect();
+
+        // The end token is only missing if the homeserver doesn't follow the
+        // spec, treat it like the live end of the room in that case.
+        let timeline = match response.end {
+            Some(end) => Timeline::detached(self, response.start, end, events),
+            None => Timeline::with_events(self, response.start, events),
+        };
+
+        Ok(timeline.with_fully_read_tracking().await)
     }
 
     /// Fetch the event with the given `EventId` in this room.
@@ -401,14 +557,16 @@ impl Common {
     }
 
     fn are_events_visible(&self) -> bool {
-        if let RoomType::Invited = self.inner.room_type() {
-            return matches!(
+        match self.inner.room_type() {
+            RoomType::Invited => matches!(
                 self.inner.history_visibility(),
                 HistoryVisibility::WorldReadable | HistoryVisibility::Invited
-            );
+            ),
+            RoomType::Knocked => {
+                matches!(self.inner.history_visibility(), HistoryVisibility::WorldReadable)
+            }
+            _ => true,
         }
-
-        true
     }
 
     /// Sync the member list with the server.
@@ -781,41 +939,30 @@ impl Common {
     /// # Arguments
     /// * `is_direct` - Whether to mark this room as direct.
     pub async fn set_is_direct(&self, is_direct: bool) -> Result<()> {
-        let user_id =
-            self.client.user_id().ok_or_else(|| Error::from(HttpError::AuthenticationRequired))?;
-
-        let mut content = self
-            .client
-            .account()
-            .account_data::<DirectEventContent>()
-            .await?
-            .map(|c| c.deserialize())
-            .transpose()?
-            .unwrap_or_default();
-
+        let room_members = if is_direct { self.active_members().await? } else { Vec::new() };
         let this_room_id = self.inner.room_id();
 
-        if is_direct {
-            let room_members = self.active_members().await?;
-            for member in room_members {
-                let entry = content.entry(member.user_id().to_owned()).or_default();
-                if !entry.iter().any(|room_id| room_id == this_room_id) {
-                    entry.push(this_room_id.to_owned());
-                }
-            }
-        } else {
-            for (_, list) in content.iter_mut() {
-                list.retain(|room_id| *room_id != this_room_id);
-            }
-
-            // Remove user ids that don't have any room marked as DM
-            content.retain(|_, list| !list.is_empty());
-        }
+        self.client
+            .update_direct_rooms(|content| {
+                if is_direct {
+                    for member in room_members {
+                        let entry = content.entry(member.user_id().to_owned()).or_default();
+                        if !entry.iter().any(|room_id| room_id == this_room_id) {
+                            entry.push(this_room_id.to_owned());
+                        }
+                    }
+                } else {
+                    for (_, list) in content.iter_mut() {
+                        list.retain(|room_id| *room_id != this_room_id);
+                    }
 
-        let request = set_global_account_data::v3::Request::new(user_id.to_owned(), &content)?;
+                    // Remove user ids that don't have any room marked as DM
+                    content.retain(|_, list| !list.is_empty());
+                }
 
-        self.client.send(request, None).await?;
-        Ok(())
+                true
+            })
+            .await
     }
 
     /// Tries to decrypt a room event.
@@ -892,6 +1039,69 @@ impl Common {
             .collect())
     }
 
+    /// Get the spaces this room claims to be part of, from its locally known
+    /// `m.space.parent` state events.
+    ///
+    /// Each parent is validated against the local state of the parent space,
+    /// see [`SpaceParent::is_valid`].
+    pub async fn parent_spaces(&self) -> Result<Vec<SpaceParent>> {
+        let mut parents = Vec::new();
+        for raw in self.get_state_events_static::<SpaceParentEventContent>().await? {
+            let event = match raw.deserialize() {
+                Ok(SyncStateEvent::Original(ev)) => ev,
+                Ok(SyncStateEvent::Redacted(_)) => continue,
+                Err(error) => {
+                    warn!(
+                        room_id = %self.room_id(), ?error,
+                        "Failed to deserialize m.space.parent event"
+                    );
+                    continue;
+                }
+            };
+
+            // Parents without `via` are considered removed.
+            if event.content.via.is_empty() {
+                continue;
+            }
+
+            let is_valid = match self.client.get_room(&event.state_key) {
+                Some(parent) => parent.has_child_or_allows(self.room_id(), &event.sender).await?,
+                None => false,
+            };
+
+            parents.push(SpaceParent {
+                room_id: event.state_key,
+                via: event.content.via,
+                canonical: event.content.canonical,
+                is_valid,
+            });
+        }
+
+        Ok(parents)
+    }
+
+    /// Whether this space has an `m.space.child` event for the given room, or
+    /// the given user is allowed to send one.
+    async fn has_child_or_allows(&self, child_id: &RoomId, user_id: &UserId) -> Result<bool> {
+        let child_event = self
+            .get_state_event_static_for_key::<SpaceChildEventContent, _>(child_id)
+            .await?
+            .and_then(|raw| raw.deserialize().ok());
+        if let Some(SyncStateEvent::Original(ev)) = child_event {
+            if !ev.content.via.is_empty() {
+                return Ok(true);
+            }
+        }
+
+        let power_levels = self
+            .get_state_event_static::<RoomPowerLevelsEventContent>()
+            .await?
+            .and_then(|raw| raw.deserialize().ok());
+        Ok(power_levels.map_or(false, |ev| {
+            ev.power_levels().user_can_send_state(user_id, StateEventType::SpaceChild)
+        }))
+    }
+
     /// Get a `matrix.to` permalink to this room.
     ///
     /// If this room has an alias, we use it. Otherwise, we try to use the
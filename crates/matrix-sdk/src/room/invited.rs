@@ -58,8 +58,19 @@ impl Invited {
     }
 
     /// Accept the invitation.
+    ///
+    /// If the invite is for a DM, the room is added to the `m.direct` account
+    /// data event, so it stays a DM once it is joined. If that fails,
+    /// [`Error::DmNotMarked`] is returned.
     pub async fn accept_invitation(&self) -> Result<Joined> {
-        self.inner.join().await
+        let dm_targets = self.inner.direct_targets();
+        let room = self.inner.join().await?;
+
+        if !dm_targets.is_empty() {
+            self.inner.client.add_direct_room(room.room_id(), dm_targets).await?;
+        }
+
+        Ok(room)
     }
 
     /// The membership details of the (latest) invite for this room.
@@ -19,16 +19,27 @@ use ruma::{
         read_marker::set_read_marker,
         receipt::create_receipt::{self, v3::ReceiptType},
         redact::redact_event,
+        room::upgrade_room,
         state::send_state_event,
         typing::create_typing_event::v3::{Request as TypingRequest, Typing},
     },
     assign,
     events::{
-        room::message::RoomMessageEventContent, EmptyStateKey, MessageLikeEventContent,
-        StateEventContent,
+        poll::{
+            end::{PollEndContent, PollEndEventContent},
+            response::{PollResponseEventContent, SelectionsContent},
+            start::{PollStartContent, PollStartEventContent},
+        },
+        receipt::ReceiptThread,
+        room::{
+            join_rules::{AllowRule, JoinRule, Restricted, RoomJoinRulesEventContent},
+            member::MembershipState,
+            message::RoomMessageEventContent,
+        },
+        EmptyStateKey, MessageLikeEventContent, StateEventContent,
     },
     serde::Raw,
-    EventId, OwnedTransactionId, TransactionId, UserId,
+    EventId, OwnedRoomId, OwnedTransactionId, RoomVersionId, TransactionId, UserId,
 };
 use serde_json::Value;
 use tracing::debug;
@@ -37,8 +48,10 @@ use tracing::instrument;
 
 use super::Left;
 use crate::{
-    attachment::AttachmentConfig, error::HttpResult, room::Common, BaseRoom, Client, Result,
-    RoomType,
+    attachment::AttachmentConfig,
+    error::HttpResult,
+    room::{Common, RoomMember},
+    BaseRoom, Client, Result, RoomType,
 };
 #[cfg(feature = "image-proc")]
 use crate::{
@@ -148,6 +161,46 @@ impl Joined {
         Ok(())
     }
 
+    /// Get the users that are currently requesting to join this room.
+    ///
+    /// These are the members whose membership is `knock`. Their requests can be
+    /// handled with [`accept_knock`](Self::accept_knock) and
+    /// [`deny_knock`](Self::deny_knock).
+    pub async fn knock_requests(&self) -> Result<Vec<RoomMember>> {
+        Ok(self
+            .members()
+            .await?
+            .into_iter()
+            .filter(|member| *member.membership() == MembershipState::Knock)
+            .collect())
+    }
+
+    /// Accept the request of a user to join this room.
+    ///
+    /// This invites the user to the room, so it requires the own user to be
+    /// allowed to invite users.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The `UserId` of the user that knocked on the room.
+    pub async fn accept_knock(&self, user_id: &UserId) -> Result<()> {
+        self.invite_user_by_id(user_id).await
+    }
+
+    /// Deny the request of a user to join this room.
+    ///
+    /// This kicks the user out of the room, so it requires the own user to be
+    /// allowed to kick users.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The `UserId` of the user that knocked on the room.
+    ///
+    /// * `reason` - Optional reason why the request is denied.
+    pub async fn deny_knock(&self, user_id: &UserId, reason: Option<&str>) -> Result<()> {
+        self.kick_user(user_id, reason).await
+    }
+
     /// Activate typing notice for this room.
     ///
     /// The typing notice remains active for 4s. It can be deactivate at any
@@ -247,6 +300,34 @@ impl Joined {
         Ok(())
     }
 
+    /// Send a request to notify this room that the user has read specific
+    /// event, in the given thread.
+    ///
+    /// # Arguments
+    ///
+    /// * `event_id` - The `EventId` specifies the event to set the read receipt
+    ///   on.
+    ///
+    /// * `thread` - The thread the receipt applies to. Use
+    ///   [`ReceiptThread::Main`] for events that are not part of a thread.
+    pub async fn read_receipt_in_thread(
+        &self,
+        event_id: &EventId,
+        thread: ReceiptThread,
+    ) -> Result<()> {
+        let request = assign!(
+            create_receipt::v3::Request::new(
+                self.inner.room_id().to_owned(),
+                ReceiptType::Read,
+                event_id.to_owned(),
+            ),
+            { thread }
+        );
+
+        self.client.send(request, None).await?;
+        Ok(())
+    }
+
     /// Send a request to notify this room that the user has read up to specific
     /// event.
     ///
@@ -623,6 +704,69 @@ impl Joined {
         Ok(response)
     }
 
+    /// Add a message-like event to the client's [`SendQueue`], to be sent to
+    /// this room.
+    ///
+    /// Unlike [`send()`](Self::send), the event is kept in the queue until it
+    /// could be sent, so it isn't lost if the homeserver can't be reached. It
+    /// will be sent again once connectivity returns. Unless the room is
+    /// encrypted, the event is persisted in the state store, so it is also sent
+    /// after the application was restarted.
+    ///
+    /// Returns the transaction ID of the event.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The content of the message event.
+    ///
+    /// * `txn_id` - A locally-unique ID describing a message transaction with
+    ///   the homeserver. If `None`, a new one is created.
+    ///
+    /// [`SendQueue`]: crate::SendQueue
+    pub async fn send_queued(
+        &self,
+        content: impl MessageLikeEventContent,
+        txn_id: Option<&TransactionId>,
+    ) -> Result<OwnedTransactionId> {
+        self.client.send_queue().enqueue(self.room_id(), content, txn_id).await
+    }
+
+    /// Add an attachment to the client's [`SendQueue`], to be uploaded and sent
+    /// to this room.
+    ///
+    /// This is the queued equivalent of
+    /// [`send_attachment()`](Self::send_attachment), see
+    /// [`send_queued()`](Self::send_queued) for details.
+    ///
+    /// Returns the transaction ID of the event containing the attachment.
+    ///
+    /// # Arguments
+    ///
+    /// * `body` - A textual representation of the media that is going to be
+    /// uploaded. Usually the file name.
+    ///
+    /// * `content_type` - The type of the media, this will be used as the
+    /// content-type header.
+    ///
+    /// * `data` - The raw bytes of the media.
+    ///
+    /// * `txn_id` - A locally-unique ID describing a message transaction with
+    ///   the homeserver. If `None`, a new one is created.
+    ///
+    /// [`SendQueue`]: crate::SendQueue
+    pub async fn send_attachment_queued(
+        &self,
+        body: &str,
+        content_type: &Mime,
+        data: Vec<u8>,
+        txn_id: Option<&TransactionId>,
+    ) -> Result<OwnedTransactionId> {
+        self.client
+            .send_queue()
+            .enqueue_attachment(self.room_id(), body, content_type, data, txn_id)
+            .await
+    }
+
     /// Send an attachment to this room.
     ///
     /// This will upload the given data that the reader produces using the
@@ -986,4 +1130,150 @@ impl Joined {
 
         self.client.send(request, None).await
     }
+
+    /// Only allow the members of the given rooms to join this room.
+    ///
+    /// This sets the join rule of this room to `restricted`, which requires
+    /// room version 8 or later. Other users can still be invited.
+    ///
+    /// # Arguments
+    ///
+    /// * `allow` - The rooms, usually spaces, whose members can join this room
+    /// without an invite.
+    pub async fn set_join_rule_restricted(
+        &self,
+        allow: Vec<OwnedRoomId>,
+    ) -> Result<send_state_event::v3::Response> {
+        let join_rule = JoinRule::Restricted(restricted_join_rule(allow));
+        self.send_state_event(RoomJoinRulesEventContent::new(join_rule)).await
+    }
+
+    /// Only allow the members of the given rooms to join this room, and allow
+    /// other users to knock on it.
+    ///
+    /// This sets the join rule of this room to `knock_restricted`, which
+    /// requires room version 10 or later.
+    ///
+    /// # Arguments
+    ///
+    /// * `allow` - The rooms, usually spaces, whose members can join this room
+    /// without an invite.
+    pub async fn set_join_rule_knock_restricted(
+        &self,
+        allow: Vec<OwnedRoomId>,
+    ) -> Result<send_state_event::v3::Response> {
+        let join_rule = JoinRule::KnockRestricted(restricted_join_rule(allow));
+        self.send_state_event(RoomJoinRulesEventContent::new(join_rule)).await
+    }
+
+    /// Upgrade this room to a new room version.
+    ///
+    /// The homeserver creates a new room with the given version, and replaces
+    /// this room with it by sending an `m.room.tombstone` event. The new room
+    /// can be retrieved with [`successor`](Common::successor) once it was
+    /// received from sync.
+    ///
+    /// # Arguments
+    ///
+    /// * `new_version` - The version of the new room.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # futures::executor::block_on(async {
+    /// # let homeserver = url::Url::parse("http://localhost:8080")?;
+    /// # let mut client = matrix_sdk::Client::new(homeserver).await?;
+    /// # let room_id = matrix_sdk::ruma::room_id!("!test:localhost");
+    /// use matrix_sdk::ruma::RoomVersionId;
+    ///
+    /// if let Some(room) = client.get_joined_room(&room_id) {
+    ///     let response = room.upgrade(RoomVersionId::V10).await?;
+    ///     println!("Room upgraded to {}", response.replacement_room);
+    /// }
+    /// # anyhow::Ok(()) });
+    /// ```
+    pub async fn upgrade(
+        &self,
+        new_version: RoomVersionId,
+    ) -> HttpResult<upgrade_room::v3::Response> {
+        let request = upgrade_room::v3::Request::new(self.inner.room_id().to_owned(), new_version);
+        self.client.send(request, None).await
+    }
+
+    /// Start a poll in this room.
+    ///
+    /// Polls are still an unstable feature ([MSC3381]), clients that don't
+    /// support them will only see the fallback text of the question, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `poll_start` - The question, possible answers and kind of the poll.
+    ///
+    /// [MSC3381]: https://github.com/matrix-org/matrix-spec-proposals/pull/3381
+    pub async fn create_poll(
+        &self,
+        poll_start: PollStartContent,
+    ) -> Result<send_message_event::v3::Response> {
+        self.send(PollStartEventContent::new(poll_start), None).await
+    }
+
+    /// Vote in a poll in this room.
+    ///
+    /// Only the latest vote of a user counts, so this replaces any previous
+    /// vote in the same poll.
+    ///
+    /// # Arguments
+    ///
+    /// * `poll_start_id` - The ID of the poll's start event.
+    ///
+    /// * `answers` - The IDs of the selected answers. Only the first
+    /// `max_selections` answers of the poll are taken into account. Sending no
+    /// answers removes the user's previous vote.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # futures::executor::block_on(async {
+    /// # let homeserver = url::Url::parse("http://localhost:8080")?;
+    /// # let mut client = matrix_sdk::Client::new(homeserver).await?;
+    /// # let room_id = matrix_sdk::ruma::room_id!("!test:localhost");
+    /// use matrix_sdk::ruma::event_id;
+    ///
+    /// if let Some(room) = client.get_joined_room(&room_id) {
+    ///     let poll_start_id = event_id!("$xxxxxx:example.org");
+    ///     room.vote_in_poll(&poll_start_id, vec!["answer-1".to_owned()]).await?;
+    /// }
+    /// # anyhow::Ok(()) });
+    /// ```
+    pub async fn vote_in_poll(
+        &self,
+        poll_start_id: &EventId,
+        answers: Vec<String>,
+    ) -> Result<send_message_event::v3::Response> {
+        let content = PollResponseEventContent::new(
+            SelectionsContent::new(answers),
+            poll_start_id.to_owned(),
+        );
+        self.send(content, None).await
+    }
+
+    /// End a poll in this room.
+    ///
+    /// Only the creator of a poll can end it, votes that are sent after that
+    /// are not taken into account anymore.
+    ///
+    /// # Arguments
+    ///
+    /// * `poll_start_id` - The ID of the poll's start event.
+    pub async fn end_poll(
+        &self,
+        poll_start_id: &EventId,
+    ) -> Result<send_message_event::v3::Response> {
+        let content = PollEndEventContent::new(PollEndContent::new(), poll_start_id.to_owned());
+        self.send(content, None).await
+    }
+}
+
+fn restricted_join_rule(allow: Vec<OwnedRoomId>) -> Restricted {
+    Restricted::new(allow.into_iter().map(AllowRule::room_membership).collect())
 }
@@ -0,0 +1,47 @@
+use std::ops::Deref;
+
+use super::Left;
+use crate::{room::Common, BaseRoom, Client, Result, RoomType};
+
+/// A room in the knocked state.
+///
+/// This struct contains all methods specific to a `Room` with type
+/// `RoomType::Knocked`. Operations may fail once the underlying `Room` changes
+/// `RoomType`.
+///
+/// Like for invited rooms, only the stripped state of the room is known until
+/// the knock is accepted, i.e. until the user is invited to the room.
+#[derive(Debug, Clone)]
+pub struct Knocked {
+    pub(crate) inner: Common,
+}
+
+impl Knocked {
+    /// Create a new `room::Knocked` if the underlying `Room` has type
+    /// `RoomType::Knocked`.
+    ///
+    /// # Arguments
+    /// * `client` - The client used to make requests.
+    ///
+    /// * `room` - The underlying room.
+    pub(crate) fn new(client: &Client, room: BaseRoom) -> Option<Self> {
+        if room.room_type() == RoomType::Knocked {
+            Some(Self { inner: Common::new(client.clone(), room) })
+        } else {
+            None
+        }
+    }
+
+    /// Withdraw the request to join this room.
+    pub async fn cancel_knock(&self) -> Result<Left> {
+        self.inner.leave().await
+    }
+}
+
+impl Deref for Knocked {
+    type Target = Common;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
@@ -7,8 +7,10 @@ use crate::RoomType;
 mod common;
 mod invited;
 mod joined;
+mod knocked;
 mod left;
 mod member;
+mod space;
 #[cfg(feature = "experimental-timeline")]
 pub mod timeline;
 
@@ -16,8 +18,10 @@ pub use self::{
     common::{Common, Messages, MessagesOptions},
     invited::Invited,
     joined::Joined,
+    knocked::Knocked,
     left::Left,
     member::RoomMember,
+    space::{Hierarchy, HierarchyOptions, Space, SpaceChild, SpaceParent},
 };
 
 /// An enum that abstracts over the different states a room can be in.
@@ -29,6 +33,8 @@ pub enum Room {
     Left(Left),
     /// The room in the `invited` state.
     Invited(Invited),
+    /// The room in the `knocked` state.
+    Knocked(Knocked),
 }
 
 impl Deref for Room {
@@ -39,6 +45,7 @@ impl Deref for Room {
             Self::Joined(room) => room,
             Self::Left(room) => room,
             Self::Invited(room) => room,
+            Self::Knocked(room) => room,
         }
     }
 }
@@ -49,6 +56,7 @@ impl From<Common> for Room {
             RoomType::Joined => Self::Joined(Joined { inner: room }),
             RoomType::Left => Self::Left(Left { inner: room }),
             RoomType::Invited => Self::Invited(Invited { inner: room }),
+            RoomType::Knocked => Self::Knocked(Knocked { inner: room }),
         }
     }
 }
@@ -60,6 +68,7 @@ impl From<Joined> for Room {
             RoomType::Joined => Self::Joined(Joined { inner: room }),
             RoomType::Left => Self::Left(Left { inner: room }),
             RoomType::Invited => Self::Invited(Invited { inner: room }),
+            RoomType::Knocked => Self::Knocked(Knocked { inner: room }),
         }
     }
 }
@@ -71,6 +80,7 @@ impl From<Left> for Room {
             RoomType::Joined => Self::Joined(Joined { inner: room }),
             RoomType::Left => Self::Left(Left { inner: room }),
             RoomType::Invited => Self::Invited(Invited { inner: room }),
+            RoomType::Knocked => Self::Knocked(Knocked { inner: room }),
         }
     }
 }
@@ -82,6 +92,19 @@ impl From<Invited> for Room {
             RoomType::Joined => Self::Joined(Joined { inner: room }),
             RoomType::Left => Self::Left(Left { inner: room }),
             RoomType::Invited => Self::Invited(Invited { inner: room }),
+            RoomType::Knocked => Self::Knocked(Knocked { inner: room }),
+        }
+    }
+}
+
+impl From<Knocked> for Room {
+    fn from(room: Knocked) -> Self {
+        let room = (*room).clone();
+        match room.room_type() {
+            RoomType::Joined => Self::Joined(Joined { inner: room }),
+            RoomType::Left => Self::Left(Left { inner: room }),
+            RoomType::Invited => Self::Invited(Invited { inner: room }),
+            RoomType::Knocked => Self::Knocked(Knocked { inner: room }),
         }
     }
 }
@@ -0,0 +1,298 @@
+use std::{cmp::Ordering, ops::Deref};
+
+use ruma::{
+    api::client::{
+        space::{get_hierarchy, SpaceHierarchyRoomsChunk},
+        state::send_state_event,
+    },
+    assign,
+    events::{space::child::SpaceChildEventContent, StateEventType, SyncStateEvent},
+    MilliSecondsSinceUnixEpoch, OwnedRoomId, OwnedServerName, RoomId, UInt,
+};
+use serde_json::json;
+use tracing::warn;
+
+use super::Joined;
+use crate::{room::Common, BaseRoom, Client, Result};
+
+/// A space, i.e. a room of type `m.space`.
+///
+/// Spaces group rooms, including other spaces, with `m.space.child` state
+/// events in the space. The rooms can point back to their spaces with
+/// `m.space.parent` state events, see [`Common::parent_spaces`].
+#[derive(Debug, Clone)]
+pub struct Space {
+    pub(crate) inner: Common,
+}
+
+impl Space {
+    /// Create a new `room::Space` if the underlying `Room` is a space.
+    ///
+    /// # Arguments
+    /// * `client` - The client used to make requests.
+    ///
+    /// * `room` - The underlying room.
+    pub(crate) fn new(client: &Client, room: BaseRoom) -> Option<Self> {
+        if room.is_space() {
+            Some(Self { inner: Common::new(client.clone(), room) })
+        } else {
+            None
+        }
+    }
+
+    /// Get the children of this space, from its locally known state.
+    ///
+    /// Children are sorted like the spec recommends: first the ones with an
+    /// `order`, by `order`, then by the time the `m.space.child` event was
+    /// sent, then by room ID.
+    pub async fn children(&self) -> Result<Vec<SpaceChild>> {
+        let mut children = Vec::new();
+        for raw in self.get_state_events_static::<SpaceChildEventContent>().await? {
+            let event = match raw.deserialize() {
+                Ok(SyncStateEvent::Original(ev)) => ev,
+                Ok(SyncStateEvent::Redacted(_)) => continue,
+                Err(error) => {
+                    warn!(
+                        room_id = %self.room_id(), ?error,
+                        "Failed to deserialize m.space.child event"
+                    );
+                    continue;
+                }
+            };
+
+            // Children without `via` are considered removed from the space.
+            if event.content.via.is_empty() {
+                continue;
+            }
+
+            children.push(SpaceChild {
+                room_id: event.state_key,
+                via: event.content.via,
+                order: event.content.order.filter(|order| is_valid_order(order)),
+                suggested: event.content.suggested,
+                origin_server_ts: event.origin_server_ts,
+            });
+        }
+
+        children.sort_by(SpaceChild::cmp_order);
+        Ok(children)
+    }
+
+    /// Get a page of the hierarchy of this space from the homeserver.
+    ///
+    /// Unlike [`children`](Self::children), this also returns the rooms the
+    /// user is not a member of, and the children of child spaces.
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - Pagination and depth options for the request.
+    pub async fn hierarchy(&self, options: HierarchyOptions) -> Result<Hierarchy> {
+        let request = options.into_request(self.room_id());
+        let response = self.client.send(request, None).await?;
+
+        Ok(Hierarchy { rooms: response.rooms, next_batch: response.next_batch })
+    }
+
+    /// Add a room to this space.
+    ///
+    /// This sends an `m.space.child` state event, so it requires the user to
+    /// be allowed to send it in this space.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The ID of the room to add.
+    ///
+    /// * `via` - Servers that can be used to join the room, it must not be
+    /// empty. [`Common::route`] can be used to get them for a known room.
+    ///
+    /// * `suggested` - Whether the room should be shown prominently to the
+    /// members of this space.
+    pub async fn add_child(
+        &self,
+        room_id: &RoomId,
+        via: Vec<OwnedServerName>,
+        suggested: bool,
+    ) -> Result<send_state_event::v3::Response> {
+        let content = assign!(SpaceChildEventContent::new(via), { suggested });
+
+        // If this room isn't actually in joined state, we'll get a server error.
+        let room = Joined { inner: self.inner.clone() };
+        room.send_state_event_for_key(room_id, content).await
+    }
+
+    /// Remove a room from this space.
+    ///
+    /// This replaces the `m.space.child` state event of the room with an
+    /// empty one, so it requires the user to be allowed to send it in this
+    /// space.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The ID of the room to remove.
+    pub async fn remove_child(&self, room_id: &RoomId) -> Result<send_state_event::v3::Response> {
+        let event_type = StateEventType::SpaceChild.to_string();
+
+        // If this room isn't actually in joined state, we'll get a server error.
+        let room = Joined { inner: self.inner.clone() };
+        room.send_state_event_raw(json!({}), &event_type, room_id.as_str()).await
+    }
+}
+
+impl Deref for Space {
+    type Target = Common;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+/// A child of a [`Space`], from an `m.space.child` state event.
+#[derive(Clone, Debug)]
+pub struct SpaceChild {
+    room_id: OwnedRoomId,
+    via: Vec<OwnedServerName>,
+    order: Option<String>,
+    suggested: bool,
+    origin_server_ts: MilliSecondsSinceUnixEpoch,
+}
+
+impl SpaceChild {
+    /// The ID of the child room.
+    pub fn room_id(&self) -> &RoomId {
+        &self.room_id
+    }
+
+    /// Servers that can be used to join the child room.
+    pub fn via(&self) -> &[OwnedServerName] {
+        &self.via
+    }
+
+    /// The string used to order the children of the space, if it is set and
+    /// valid.
+    pub fn order(&self) -> Option<&str> {
+        self.order.as_deref()
+    }
+
+    /// Whether the child room should be shown prominently to the members of
+    /// the space.
+    pub fn is_suggested(&self) -> bool {
+        self.suggested
+    }
+
+    fn cmp_order(&self, other: &Self) -> Ordering {
+        let by_order = match (&self.order, &other.order) {
+            (Some(a), Some(b)) => a.cmp(b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        };
+
+        by_order
+            .then_with(|| self.origin_server_ts.cmp(&other.origin_server_ts))
+            .then_with(|| self.room_id.cmp(&other.room_id))
+    }
+}
+
+/// Whether the given `order` of an `m.space.child` event is valid.
+///
+/// It must consist of at most 50 printable ASCII characters.
+fn is_valid_order(order: &str) -> bool {
+    order.len() <= 50 && order.chars().all(|c| ('\x20'..='\x7E').contains(&c))
+}
+
+/// A parent of a room, from an `m.space.parent` state event.
+#[derive(Clone, Debug)]
+pub struct SpaceParent {
+    pub(crate) room_id: OwnedRoomId,
+    pub(crate) via: Vec<OwnedServerName>,
+    pub(crate) canonical: bool,
+    pub(crate) is_valid: bool,
+}
+
+impl SpaceParent {
+    /// The ID of the parent space.
+    pub fn room_id(&self) -> &RoomId {
+        &self.room_id
+    }
+
+    /// Servers that can be used to join the parent space.
+    pub fn via(&self) -> &[OwnedServerName] {
+        &self.via
+    }
+
+    /// Whether this is the main parent of the room.
+    pub fn is_canonical(&self) -> bool {
+        self.canonical
+    }
+
+    /// Whether the parent space confirms this relationship.
+    ///
+    /// That is the case if the parent space has a corresponding
+    /// `m.space.child` event, or if the sender of the `m.space.parent` event
+    /// is allowed to send one in the parent space.
+    ///
+    /// This is always `false` if the parent space is not known locally.
+    pub fn is_valid(&self) -> bool {
+        self.is_valid
+    }
+}
+
+/// Options for [`hierarchy`][Space::hierarchy].
+///
+/// See that method and
+/// <https://spec.matrix.org/v1.5/client-server-api/#get_matrixclientv1roomsroomidhierarchy>
+/// for details.
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct HierarchyOptions {
+    /// The token to continue the pagination from, as returned in
+    /// [`Hierarchy::next_batch`] by a previous call.
+    pub from: Option<String>,
+
+    /// The maximum number of rooms to return.
+    ///
+    /// The server uses its own default if this is not set.
+    pub limit: Option<UInt>,
+
+    /// The maximum depth in the space tree to explore.
+    ///
+    /// The server uses its own default if this is not set.
+    pub max_depth: Option<UInt>,
+
+    /// Whether to only return the suggested children of spaces.
+    pub suggested_only: bool,
+}
+
+impl HierarchyOptions {
+    /// Creates `HierarchyOptions` to get the first page of the hierarchy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new `HierarchyOptions` from `self` with the `from` field set
+    /// to the given value.
+    pub fn from<'a>(self, from: impl Into<Option<&'a str>>) -> Self {
+        Self { from: from.into().map(ToOwned::to_owned), ..self }
+    }
+
+    fn into_request(self, room_id: &RoomId) -> get_hierarchy::v1::Request {
+        assign!(get_hierarchy::v1::Request::new(room_id.to_owned()), {
+            from: self.from,
+            limit: self.limit,
+            max_depth: self.max_depth,
+            suggested_only: self.suggested_only,
+        })
+    }
+}
+
+/// The result of a [`Space::hierarchy`] call.
+#[derive(Debug)]
+pub struct Hierarchy {
+    /// The rooms of this page, in depth-first order.
+    ///
+    /// The first page starts with the space itself.
+    pub rooms: Vec<SpaceHierarchyRoomsChunk>,
+
+    /// The token to get the next page, if there is one.
+    pub next_batch: Option<String>,
+}
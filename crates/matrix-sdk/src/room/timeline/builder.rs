@@ -0,0 +1,150 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use ruma::{events::AnySyncTimelineEvent, serde::Raw, MilliSecondsSinceUnixEpoch};
+use tracing::warn;
+
+use super::{
+    decrypt_cached_events, inner::TimelineInner, CachedChunk, Timeline, TimelineInnerSettings,
+};
+use crate::room;
+
+/// Builder that allows creating and configuring various parts of a
+/// [`Timeline`].
+///
+/// # Example
+///
+/// ```no_run
+/// # use matrix_sdk::room::Common;
+/// # async {
+/// # let room: Common = todo!();
+/// // Hide state events, and separate events of different days.
+/// let timeline = room
+///     .timeline_builder()
+///     .event_filter(|raw| raw.get_field::<String>("state_key").ok().flatten().is_none())
+///     .day_dividers(true)
+///     .build()
+///     .await;
+/// # };
+/// ```
+#[must_use]
+#[derive(Debug)]
+pub struct TimelineBuilder {
+    room: room::Common,
+    settings: TimelineInnerSettings,
+}
+
+impl TimelineBuilder {
+    pub(super) fn new(room: &room::Common) -> Self {
+        Self { room: room.clone(), settings: TimelineInnerSettings::default() }
+    }
+
+    /// Only show events for which the given predicate returns `true`.
+    ///
+    /// The predicate only decides whether an event gets its own timeline item,
+    /// events that update other items (like edits, reactions and redactions)
+    /// are always applied. Local echoes are always shown.
+    ///
+    /// By default, all events are shown.
+    pub fn event_filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&Raw<AnySyncTimelineEvent>) -> bool + Send + Sync + 'static,
+    {
+        self.settings.event_filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// Whether consecutive membership and profile changes should be collapsed
+    /// into a single [`VirtualTimelineItem::MembershipChanges`] item.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// [`VirtualTimelineItem::MembershipChanges`]: super::VirtualTimelineItem::MembershipChanges
+    pub fn collapse_membership_changes(mut self, collapse: bool) -> Self {
+        self.settings.collapse_membership_changes = collapse;
+        self
+    }
+
+    /// Whether [`VirtualTimelineItem::DayDivider`] items should be added
+    /// between events of different days.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// [`VirtualTimelineItem::DayDivider`]: super::VirtualTimelineItem::DayDivider
+    pub fn day_dividers(mut self, add: bool) -> Self {
+        self.settings.day_dividers = add;
+        self
+    }
+
+    /// Set the offset from UTC, in seconds, of the timezone that decides where
+    /// a day starts for day dividers.
+    ///
+    /// Defaults to `0`, i.e. days start at midnight UTC.
+    pub fn utc_offset(mut self, seconds: i32) -> Self {
+        self.settings.utc_offset = seconds;
+        self
+    }
+
+    /// Set the function used to format the date of day dividers.
+    ///
+    /// The function is called with the timestamp of the first event of the
+    /// day. By default, dates are formatted as `YYYY-MM-DD`, in the timezone
+    /// set with [`utc_offset`](Self::utc_offset).
+    pub fn date_format<F>(mut self, format: F) -> Self
+    where
+        F: Fn(MilliSecondsSinceUnixEpoch) -> String + Send + Sync + 'static,
+    {
+        self.settings.date_format = Some(Arc::new(format));
+        self
+    }
+
+    /// Create a [`Timeline`] with the options set on this builder.
+    ///
+    /// The timeline starts out with the most recent events of the room's
+    /// event cache, if there are any.
+    pub async fn build(self) -> Timeline {
+        let Self { room, settings } = self;
+        let mut inner = TimelineInner::with_settings(settings);
+        inner.load_ignored_users(&room);
+
+        let cached_chunk = match room.client.store().get_last_event_chunk(room.room_id()).await {
+            Ok(chunk) => chunk,
+            Err(error) => {
+                warn!(?error, "Failed to load events from the event cache");
+                None
+            }
+        };
+
+        let timeline = match cached_chunk {
+            Some(chunk) => {
+                let cached = CachedChunk::from(&chunk);
+                let events = decrypt_cached_events(&room, chunk.events).await;
+                for event in &events {
+                    inner.load_event_data(&event.event, &room).await;
+                }
+                inner.add_initial_events(events, room.own_user_id());
+
+                let timeline = Timeline::from_inner(&room, inner, chunk.prev_batch, None);
+                *timeline.cached_chunk.lock().unwrap() = Some(cached);
+                timeline
+            }
+            None => Timeline::from_inner(&room, inner, None, None),
+        };
+
+        timeline.inner.fetch_members_if_needed(&timeline.room).await;
+        timeline.with_fully_read_tracking().await
+    }
+}
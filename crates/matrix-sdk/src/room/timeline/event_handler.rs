@@ -15,31 +15,44 @@
 use std::{collections::HashMap, sync::Arc};
 
 use futures_signals::signal_vec::MutableVecLockMut;
-use indexmap::map::Entry;
+use indexmap::{map::Entry, IndexMap};
 use matrix_sdk_base::deserialized_responses::EncryptionInfo;
 use ruma::{
     events::{
+        poll::{
+            end::PollEndEventContent, response::PollResponseEventContent,
+            start::PollStartEventContent,
+        },
         reaction::ReactionEventContent,
-        relation::{Annotation, Replacement},
+        receipt::{Receipt, ReceiptThread},
+        relation::{Annotation, RelationType, Replacement},
         room::{
             encrypted::{self, RoomEncryptedEventContent},
+            member::MembershipState,
             message::{self, MessageType, RoomMessageEventContent},
             redaction::{
                 OriginalSyncRoomRedactionEvent, RoomRedactionEventContent, SyncRoomRedactionEvent,
             },
         },
         AnyMessageLikeEventContent, AnyStateEventContent, AnySyncMessageLikeEvent,
-        AnySyncTimelineEvent, BundledRelations, MessageLikeEventType, StateEventType,
+        AnySyncTimelineEvent, BundledRelations, EventContent, MessageLikeEventType, StateEventType,
     },
     serde::Raw,
     uint, EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedTransactionId, OwnedUserId,
+    TransactionId, UserId,
 };
-use tracing::{debug, error, info, warn};
+use serde::Deserialize;
+use serde_json::value::RawValue as RawJsonValue;
+use tracing::{debug, error, info, trace, warn};
 
 use super::{
     event_item::{BundledReactions, TimelineDetails},
-    find_event_by_id, find_event_by_txn_id, find_read_marker, EventTimelineItem, Message,
-    TimelineInnerMetadata, TimelineItem, TimelineItemContent, TimelineKey, VirtualTimelineItem,
+    find_event_by_id, find_event_by_txn_id, find_read_marker,
+    polls::{PollPendingEvents, PollResponse},
+    set_event_item, EventSendState, EventTimelineItem, MemberProfileChange, Message, OtherState,
+    PollState, Profile, RepliedToEvent, RoomMembershipChange, ThreadReply, ThreadSummary,
+    TimelineInnerMetadata, TimelineInnerSettings, TimelineItem, TimelineItemContent, TimelineKey,
+    VirtualTimelineItem,
 };
 use crate::events::SyncTimelineEventWithoutContent;
 
@@ -71,19 +84,36 @@ impl Flow {
         }
     }
 
+    fn event_id(&self) -> Option<&EventId> {
+        match self {
+            Flow::Local { .. } => None,
+            Flow::Remote { event_id, .. } => Some(event_id),
+        }
+    }
+
     fn raw_event(&self) -> Option<&Raw<AnySyncTimelineEvent>> {
         match self {
             Flow::Local { .. } => None,
             Flow::Remote { raw_event, .. } => Some(raw_event),
         }
     }
+
+    fn txn_id(&self) -> Option<&TransactionId> {
+        match self {
+            Flow::Local { txn_id } => Some(txn_id),
+            Flow::Remote { txn_id, .. } => txn_id.as_deref(),
+        }
+    }
 }
 
 pub(super) struct TimelineEventMetadata {
     pub(super) sender: OwnedUserId,
+    pub(super) sender_profile: TimelineDetails<Profile>,
     pub(super) is_own_event: bool,
     pub(super) relations: Option<BundledRelations>,
     pub(super) encryption_info: Option<EncryptionInfo>,
+    /// The root of the thread the event is part of, if any.
+    pub(super) thread_root: Option<OwnedEventId>,
 }
 
 #[derive(Clone)]
@@ -96,9 +126,10 @@ pub(super) enum TimelineEventKind {
         redacts: OwnedEventId,
         content: RoomRedactionEventContent,
     },
-    // FIXME: Split further for state keys of different type
     State {
-        _content: AnyStateEventContent,
+        state_key: String,
+        content: AnyStateEventContent,
+        prev_content: Option<AnyStateEventContent>,
     },
     RedactedState, // AnyRedactedStateEventContent
     FailedToParseMessageLike {
@@ -139,8 +170,8 @@ impl TimelineEventKind {
     }
 }
 
-impl From<AnySyncTimelineEvent> for TimelineEventKind {
-    fn from(event: AnySyncTimelineEvent) -> Self {
+impl TimelineEventKind {
+    pub(super) fn from_event(event: AnySyncTimelineEvent, raw: &Raw<AnySyncTimelineEvent>) -> Self {
         match event {
             AnySyncTimelineEvent::MessageLike(AnySyncMessageLikeEvent::RoomRedaction(
                 SyncRoomRedactionEvent::Original(OriginalSyncRoomRedactionEvent {
@@ -154,13 +185,65 @@ impl From<AnySyncTimelineEvent> for TimelineEventKind {
                 None => Self::RedactedMessage,
             },
             AnySyncTimelineEvent::State(ev) => match ev.original_content() {
-                Some(_content) => Self::State { _content },
+                Some(content) => Self::State {
+                    state_key: ev.state_key().to_owned(),
+                    prev_content: prev_state_content(raw, &ev.event_type()),
+                    content,
+                },
                 None => Self::RedactedState,
             },
         }
     }
 }
 
+/// Get the `unsigned.prev_content` of a state event, if any.
+fn prev_state_content(
+    raw: &Raw<AnySyncTimelineEvent>,
+    event_type: &StateEventType,
+) -> Option<AnyStateEventContent> {
+    #[derive(Deserialize)]
+    struct Unsigned {
+        prev_content: Option<Box<RawJsonValue>>,
+    }
+
+    #[derive(Deserialize)]
+    struct StateEventDetails {
+        unsigned: Option<Unsigned>,
+    }
+
+    let prev_content = raw.deserialize_as::<StateEventDetails>().ok()?.unsigned?.prev_content?;
+    match AnyStateEventContent::from_parts(&event_type.to_string(), &prev_content) {
+        Ok(content) => Some(content),
+        Err(error) => {
+            debug!(%event_type, ?error, "Failed to deserialize prev_content of state event");
+            None
+        }
+    }
+}
+
+/// Get the root of the thread the event is part of, if any.
+///
+/// This works for encrypted events too, since `m.relates_to` is not encrypted.
+pub(super) fn thread_root(raw: &Raw<AnySyncTimelineEvent>) -> Option<OwnedEventId> {
+    #[derive(Deserialize)]
+    struct RelatesTo {
+        rel_type: Option<RelationType>,
+        event_id: Option<OwnedEventId>,
+    }
+
+    #[derive(Deserialize)]
+    struct Content {
+        #[serde(rename = "m.relates_to")]
+        relates_to: Option<RelatesTo>,
+    }
+
+    let relates_to = raw.get_field::<Content>("content").ok()??.relates_to?;
+    match relates_to.rel_type? {
+        RelationType::Thread => relates_to.event_id,
+        _ => None,
+    }
+}
+
 pub(super) enum TimelineItemPosition {
     Start,
     End,
@@ -177,8 +260,14 @@ pub(super) struct TimelineEventHandler<'a, 'i> {
     flow: Flow,
     timeline_items: &'a mut MutableVecLockMut<'i, Arc<TimelineItem>>,
     reaction_map: &'a mut HashMap<TimelineKey, (OwnedUserId, Annotation)>,
+    local_edits: &'a mut HashMap<OwnedEventId, (OwnedTransactionId, TimelineItemContent)>,
+    pending_replies: &'a mut HashMap<OwnedEventId, Vec<TimelineKey>>,
+    thread_root: Option<&'a EventId>,
+    settings: &'a TimelineInnerSettings,
     fully_read_event: &'a mut Option<OwnedEventId>,
     fully_read_event_in_timeline: &'a mut bool,
+    read_receipts: &'a HashMap<OwnedUserId, (OwnedEventId, Receipt)>,
+    poll_pending_events: &'a mut PollPendingEvents,
     event_added: bool,
 }
 
@@ -194,8 +283,14 @@ impl<'a, 'i> TimelineEventHandler<'a, 'i> {
             flow,
             timeline_items,
             reaction_map: &mut timeline_meta.reaction_map,
+            local_edits: &mut timeline_meta.local_edits,
+            pending_replies: &mut timeline_meta.pending_replies,
+            thread_root: timeline_meta.thread_root.as_deref(),
+            settings: &timeline_meta.settings,
             fully_read_event: &mut timeline_meta.fully_read_event,
             fully_read_event_in_timeline: &mut timeline_meta.fully_read_event_in_timeline,
+            read_receipts: &timeline_meta.read_receipts,
+            poll_pending_events: &mut timeline_meta.poll_pending_events,
             event_added: false,
         }
     }
@@ -206,6 +301,9 @@ impl<'a, 'i> TimelineEventHandler<'a, 'i> {
                 AnyMessageLikeEventContent::Reaction(c) => self.handle_reaction(c),
                 AnyMessageLikeEventContent::RoomMessage(c) => self.handle_room_message(c),
                 AnyMessageLikeEventContent::RoomEncrypted(c) => self.handle_room_encrypted(c),
+                AnyMessageLikeEventContent::PollStart(c) => self.handle_poll_start(c),
+                AnyMessageLikeEventContent::PollResponse(c) => self.handle_poll_response(c),
+                AnyMessageLikeEventContent::PollEnd(c) => self.handle_poll_end(c),
                 // TODO
                 _ => {}
             },
@@ -215,7 +313,10 @@ impl<'a, 'i> TimelineEventHandler<'a, 'i> {
             TimelineEventKind::Redaction { redacts, content } => {
                 self.handle_redaction(redacts, content)
             }
-            TimelineEventKind::State { .. } | TimelineEventKind::RedactedState => {
+            TimelineEventKind::State { state_key, content, prev_content } => {
+                self.handle_state_event(state_key, content, prev_content);
+            }
+            TimelineEventKind::RedactedState => {
                 // TODO
             }
             TimelineEventKind::FailedToParseMessageLike { event_type, error } => {
@@ -237,7 +338,9 @@ impl<'a, 'i> TimelineEventHandler<'a, 'i> {
                 self.handle_room_message_edit(re);
             }
             _ => {
-                self.add(NewEventTimelineItem::message(content, self.meta.relations.clone()));
+                let relations = self.meta.relations.clone();
+                let item = NewEventTimelineItem::message(content, relations, self.timeline_items);
+                self.add(item);
             }
         }
     }
@@ -267,6 +370,16 @@ impl<'a, 'i> TimelineEventHandler<'a, 'i> {
                     );
                     return None;
                 }
+                TimelineItemContent::Poll(_) => {
+                    info!(%event_id, "Edit event applies to a poll, discarding");
+                    return None;
+                }
+                TimelineItemContent::MembershipChange(_)
+                | TimelineItemContent::ProfileChange(_)
+                | TimelineItemContent::OtherState(_) => {
+                    info!(%event_id, "Edit event applies to a state event, discarding");
+                    return None;
+                }
                 TimelineItemContent::FailedToParseMessageLike { .. }
                 | TimelineItemContent::FailedToParseState { .. } => {
                     info!(
@@ -277,6 +390,18 @@ impl<'a, 'i> TimelineEventHandler<'a, 'i> {
                 }
             };
 
+            match &self.flow {
+                // Remember what to revert to if the edit can't be sent.
+                Flow::Local { txn_id } => {
+                    let revert_to = (txn_id.clone(), item.content.clone());
+                    self.local_edits.insert(event_id.clone(), revert_to);
+                }
+                // A pending local edit must not revert this one if it fails.
+                Flow::Remote { .. } => {
+                    self.local_edits.remove(event_id);
+                }
+            }
+
             let content = TimelineItemContent::Message(Message {
                 msgtype: replacement.new_content,
                 in_reply_to: msg.in_reply_to.clone(),
@@ -289,32 +414,43 @@ impl<'a, 'i> TimelineEventHandler<'a, 'i> {
 
     // Redacted reaction events are no-ops so don't need to be handled
     fn handle_reaction(&mut self, c: ReactionEventContent) {
-        let event_id: &EventId = &c.relates_to.event_id;
+        let key = self.flow.to_key();
 
-        let items = &mut *self.timeline_items;
-        let did_update = maybe_update_timeline_item(items, event_id, "reaction", |item| {
-            // Handling of reactions on redacted events is an open question.
-            // For now, ignore reactions on redacted events like Element does.
-            if let TimelineItemContent::RedactedMessage = item.content {
-                debug!(%event_id, "Ignoring reaction on redacted event");
-                None
-            } else {
-                let mut reactions = item.reactions.clone();
-                let reaction_details =
-                    reactions.bundled.entry(c.relates_to.key.clone()).or_default();
+        // The local echo of our own reaction is already part of the timeline,
+        // only remember the event ID of its remote echo.
+        if let Flow::Remote { txn_id: Some(txn_id), .. } = &self.flow {
+            let local_key = TimelineKey::TransactionId(txn_id.clone());
+            if let Some(reaction) = self.reaction_map.remove(&local_key) {
+                self.reaction_map.insert(key, reaction);
+                return;
+            }
+        }
 
-                reaction_details.count += uint!(1);
-                if let TimelineDetails::Ready(senders) = &mut reaction_details.senders {
-                    senders.push(self.meta.sender.clone());
+        // The remote echo of our own reaction might lack its transaction ID,
+        // match it with a local echo that wasn't assigned an event ID yet.
+        if let Flow::Remote { txn_id: None, .. } = &self.flow {
+            if self.meta.is_own_event {
+                let local_key = self.reaction_map.iter().find_map(|(k, (sender, rel))| {
+                    (matches!(k, TimelineKey::TransactionId(_))
+                        && *sender == self.meta.sender
+                        && rel.event_id == c.relates_to.event_id
+                        && rel.key == c.relates_to.key)
+                        .then(|| k.clone())
+                });
+                if let Some(reaction) = local_key.and_then(|k| self.reaction_map.remove(&k)) {
+                    self.reaction_map.insert(key, reaction);
+                    return;
                 }
-
-                Some(item.with_reactions(reactions))
             }
-        });
+        }
 
-        if did_update {
-            self.reaction_map.insert(self.flow.to_key(), (self.meta.sender.clone(), c.relates_to));
+        if self.reaction_map.contains_key(&key) {
+            debug!(reaction_key = ?key, "Reaction is already part of the timeline");
+            return;
         }
+
+        let sender = self.meta.sender.clone();
+        add_reaction(self.timeline_items, self.reaction_map, key, sender, c.relates_to);
     }
 
     fn handle_room_encrypted(&mut self, c: RoomEncryptedEventContent) {
@@ -329,61 +465,129 @@ impl<'a, 'i> TimelineEventHandler<'a, 'i> {
         }
     }
 
-    // Redacted redactions are no-ops (unfortunately)
-    fn handle_redaction(&mut self, redacts: OwnedEventId, _content: RoomRedactionEventContent) {
-        let mut did_update = false;
+    fn handle_poll_start(&mut self, c: PollStartEventContent) {
+        let mut poll = PollState::new(c.poll_start);
+        if let Some(event_id) = self.flow.event_id() {
+            // Responses and end events might have been received before the
+            // start event, when paginating backwards.
+            self.poll_pending_events.apply(event_id, &self.meta.sender, &mut poll);
+        }
 
-        if let Some((sender, rel)) =
-            self.reaction_map.remove(&TimelineKey::EventId(redacts.clone()))
-        {
-            let items = &mut *self.timeline_items;
-            did_update = maybe_update_timeline_item(items, &rel.event_id, "redaction", |item| {
-                let mut reactions = item.reactions.clone();
+        self.add(NewEventTimelineItem::poll(poll));
+    }
 
-                let Entry::Occupied(mut details_entry) = reactions.bundled.entry(rel.key) else {
-                    return None;
-                };
-                let details = details_entry.get_mut();
-                details.count -= uint!(1);
+    fn handle_poll_response(&mut self, c: PollResponseEventContent) {
+        let poll_start_id = c.relates_to.event_id;
+        let response = PollResponse {
+            sender: self.meta.sender.clone(),
+            timestamp: self.flow.origin_server_ts(),
+            answers: c.poll_response.answers,
+            txn_id: self.flow.txn_id().map(ToOwned::to_owned),
+            event_id: self.flow.event_id().map(ToOwned::to_owned),
+        };
 
-                if details.count == uint!(0) {
-                    details_entry.remove();
-                    return Some(item.with_reactions(reactions));
-                }
+        let Some((idx, item)) = find_event_by_id(self.timeline_items, &poll_start_id) else {
+            trace!(%poll_start_id, "Poll start event not found, keeping response for later");
+            self.poll_pending_events.add_response(poll_start_id, response);
+            return;
+        };
 
-                let TimelineDetails::Ready(senders) = &mut details.senders else {
-                    // FIXME: We probably want to support this somehow in
-                    //        the future, but right now it's not possible.
-                    warn!(
-                        "inconsistent state: shouldn't have a reaction_map entry for a \
-                            timeline item with incomplete reactions"
-                    );
-                    return None;
+        let TimelineItemContent::Poll(poll) = &item.content else {
+            info!(
+                %poll_start_id,
+                "Poll response applies to an event that is not a poll, discarding"
+            );
+            return;
+        };
+
+        let new_item = item.with_content(TimelineItemContent::Poll(poll.with_response(response)));
+        set_event_item(self.timeline_items, idx, new_item);
+    }
+
+    fn handle_poll_end(&mut self, c: PollEndEventContent) {
+        let poll_start_id = c.relates_to.event_id;
+        let timestamp =
+            self.flow.origin_server_ts().unwrap_or_else(MilliSecondsSinceUnixEpoch::now);
+
+        let Some((idx, item)) = find_event_by_id(self.timeline_items, &poll_start_id) else {
+            trace!(%poll_start_id, "Poll start event not found, keeping end event for later");
+            let sender = self.meta.sender.clone();
+            self.poll_pending_events.add_end(poll_start_id, sender, timestamp);
+            return;
+        };
+
+        let TimelineItemContent::Poll(poll) = &item.content else {
+            info!(
+                %poll_start_id,
+                "Poll end event applies to an event that is not a poll, discarding"
+            );
+            return;
+        };
+
+        if self.meta.sender != item.sender() {
+            info!(
+                %poll_start_id, poll_sender = %item.sender(), end_sender = %self.meta.sender,
+                "Poll end event was not sent by the creator of the poll, discarding"
+            );
+            return;
+        }
+
+        let new_item = item.with_content(TimelineItemContent::Poll(poll.ended(timestamp)));
+        set_event_item(self.timeline_items, idx, new_item);
+    }
+
+    fn handle_state_event(
+        &mut self,
+        state_key: String,
+        content: AnyStateEventContent,
+        prev_content: Option<AnyStateEventContent>,
+    ) {
+        let content = match content {
+            AnyStateEventContent::RoomMember(content) => {
+                let user_id = match UserId::parse(state_key) {
+                    Ok(user_id) => user_id,
+                    Err(error) => {
+                        warn!(?error, "Invalid state key for m.room.member event, discarding");
+                        return;
+                    }
+                };
+                let prev_content = match prev_content {
+                    Some(AnyStateEventContent::RoomMember(c)) => Some(c),
+                    _ => None,
                 };
 
-                if let Some(idx) = senders.iter().position(|s| *s == sender) {
-                    senders.remove(idx);
-                } else {
-                    error!(
-                        "inconsistent state: sender from reaction_map not in reaction sender list \
-                         of timeline item"
-                    );
-                    return None;
-                }
+                let prev_membership = prev_content.as_ref().map(|c| &c.membership);
+                if matches!(prev_membership, Some(MembershipState::Join))
+                    && matches!(content.membership, MembershipState::Join)
+                {
+                    let change = MemberProfileChange::new(user_id, &content, prev_content.as_ref());
+                    if change.is_empty() {
+                        debug!(user_id = %change.user_id, "Ignoring no-op m.room.member event");
+                        return;
+                    }
 
-                if u64::from(details.count) != senders.len() as u64 {
-                    error!("inconsistent state: reaction count differs from number of senders");
-                    // Can't make things worse by updating the item, so no early
-                    // return here.
+                    TimelineItemContent::ProfileChange(change)
+                } else {
+                    TimelineItemContent::MembershipChange(RoomMembershipChange::new(
+                        &self.meta.sender,
+                        user_id,
+                        content,
+                        prev_content,
+                    ))
                 }
+            }
+            content => {
+                TimelineItemContent::OtherState(OtherState { state_key, content, prev_content })
+            }
+        };
 
-                Some(item.with_reactions(reactions))
-            });
+        self.add(NewEventTimelineItem::from_content(content));
+    }
 
-            if !did_update {
-                warn!("reaction_map out of sync with timeline items");
-            }
-        }
+    // Redacted redactions are no-ops (unfortunately)
+    fn handle_redaction(&mut self, redacts: OwnedEventId, _content: RoomRedactionEventContent) {
+        let reaction_key = TimelineKey::EventId(redacts.clone());
+        let mut did_update = remove_reaction(self.timeline_items, self.reaction_map, &reaction_key);
 
         // Even if the event being redacted is a reaction (found in
         // `reaction_map`), it can still be present in the timeline items
@@ -398,25 +602,84 @@ impl<'a, 'i> TimelineEventHandler<'a, 'i> {
     }
 
     fn add(&mut self, item: NewEventTimelineItem) {
-        self.event_added = true;
+        if let (Some(filter), Some(raw_event)) =
+            (&self.settings.event_filter, self.flow.raw_event())
+        {
+            if !filter(raw_event) {
+                trace!("Event is hidden by the timeline's event filter");
+                return;
+            }
+        }
 
         let NewEventTimelineItem { content, reactions } = item;
+        let send_state = match &self.flow {
+            Flow::Local { .. } => Some(EventSendState::NotSentYet),
+            Flow::Remote { .. } => None,
+        };
+        let thread_summary = self
+            .meta
+            .relations
+            .as_ref()
+            .and_then(|r| r.thread.as_ref())
+            .map(ThreadSummary::from_bundled);
         let item = EventTimelineItem {
             key: self.flow.to_key(),
-            event_id: None,
+            send_state,
             sender: self.meta.sender.to_owned(),
+            sender_profile: self.meta.sender_profile.clone(),
             content,
             reactions,
+            thread_summary,
+            read_receipts: self.read_receipts_for_new_item(),
             origin_server_ts: self.flow.origin_server_ts(),
             is_own: self.meta.is_own_event,
             encryption_info: self.meta.encryption_info.clone(),
             raw: self.flow.raw_event().cloned(),
         };
 
-        let item = Arc::new(TimelineItem::Event(item));
+        match (self.thread_root, self.meta.thread_root.as_deref()) {
+            // Local echoes of thread replies are shown in the main timeline
+            // while they are being sent.
+            (None, Some(_)) if matches!(self.flow, Flow::Local { .. }) => {}
+            // Thread replies are not part of the main timeline, they only
+            // update the summary of the thread's root event.
+            (None, Some(event_thread_root)) => {
+                let event_thread_root = event_thread_root.to_owned();
+                self.remove_thread_reply_local_echo();
+                self.update_thread_summary(&event_thread_root, &item);
+                return;
+            }
+            // A thread timeline only contains the root event and its replies.
+            (Some(thread_root), event_thread_root)
+                if event_thread_root != Some(thread_root)
+                    && self.flow.event_id() != Some(thread_root) =>
+            {
+                trace!("Event is not part of this thread, discarding");
+                return;
+            }
+            _ => {}
+        }
+
+        self.event_added = true;
+
+        // Remember replies to events that are not part of the timeline yet, to
+        // fill in their details once the replied-to event is added.
+        let in_reply_to = item.content.as_message().and_then(|msg| msg.in_reply_to.as_ref());
+        if let Some(in_reply_to) = in_reply_to.filter(|r| !r.details.is_ready()) {
+            let replies = self.pending_replies.entry(in_reply_to.event_id.clone()).or_default();
+            replies.push(item.key.clone());
+        }
+
+        if let Flow::Remote { event_id, .. } = &self.flow {
+            update_replies_to(self.timeline_items, self.pending_replies, event_id, &item);
+            // The pending events of a poll were applied already if this is its
+            // start event, and are not needed otherwise.
+            self.poll_pending_events.discard(event_id);
+        }
+
         match &self.flow {
             Flow::Local { .. } => {
-                self.timeline_items.push_cloned(item);
+                insert_new_item(self.timeline_items, self.settings, item, false);
             }
             Flow::Remote { txn_id, event_id, position, raw_event, .. } => {
                 if let Some(txn_id) = txn_id {
@@ -425,7 +688,7 @@ impl<'a, 'i> TimelineEventHandler<'a, 'i> {
                     {
                         // TODO: Check whether anything is different about the
                         //       old and new item?
-                        self.timeline_items.set_cloned(idx, item);
+                        set_event_item(self.timeline_items, idx, item);
                         return;
                     } else {
                         warn!(
@@ -447,15 +710,22 @@ impl<'a, 'i> TimelineEventHandler<'a, 'i> {
                     // With /messages and /sync sometimes disagreeing on order
                     // of messages, we might want to change the position in some
                     // circumstances, but for now this should be good enough.
-                    self.timeline_items.set_cloned(idx, item);
+                    set_event_item(self.timeline_items, idx, item);
                     return;
                 }
 
                 match position {
-                    TimelineItemPosition::Start => self.timeline_items.insert_cloned(0, item),
-                    TimelineItemPosition::End => self.timeline_items.push_cloned(item),
+                    TimelineItemPosition::Start => {
+                        insert_new_item(self.timeline_items, self.settings, item, true);
+                    }
+                    TimelineItemPosition::End => {
+                        insert_new_item(self.timeline_items, self.settings, item, false);
+                    }
                     #[cfg(feature = "e2e-encryption")]
-                    TimelineItemPosition::Update(idx) => self.timeline_items.set_cloned(*idx, item),
+                    TimelineItemPosition::Update(idx) => {
+                        let item = Arc::new(TimelineItem::Event(item));
+                        self.timeline_items.set_cloned(*idx, item);
+                    }
                 }
             }
         }
@@ -469,6 +739,114 @@ impl<'a, 'i> TimelineEventHandler<'a, 'i> {
             );
         }
     }
+
+    /// Get the read receipts that point to the event that is added.
+    fn read_receipts_for_new_item(&self) -> IndexMap<OwnedUserId, Receipt> {
+        let Some(event_id) = self.flow.event_id() else { return IndexMap::new() };
+
+        self.read_receipts
+            .iter()
+            .filter(|(_, (receipt_event_id, _))| **receipt_event_id == *event_id)
+            .map(|(user_id, (_, receipt))| (user_id.clone(), receipt.clone()))
+            .collect()
+    }
+
+    /// Remove the local echo of the thread reply whose remote echo is added,
+    /// since the reply is only part of the thread's timeline.
+    fn remove_thread_reply_local_echo(&mut self) {
+        let Flow::Remote { txn_id: Some(txn_id), .. } = &self.flow else { return };
+        let Some((idx, _)) = find_event_by_txn_id(self.timeline_items, txn_id) else { return };
+
+        self.timeline_items.remove(idx);
+        if remove_orphaned_virtual_items(self.timeline_items, self.settings) {
+            *self.fully_read_event_in_timeline = false;
+        }
+    }
+
+    fn update_thread_summary(&mut self, thread_root: &EventId, item: &EventTimelineItem) {
+        // Older replies are already accounted for by the summary bundled with
+        // the root event, and local echoes are accounted for once their remote
+        // echo is received.
+        let Flow::Remote { event_id, position: TimelineItemPosition::End, .. } = &self.flow else {
+            return;
+        };
+
+        let reply = ThreadReply {
+            event_id: event_id.clone(),
+            sender: item.sender.clone(),
+            content: item.content.clone(),
+        };
+        maybe_update_timeline_item(self.timeline_items, thread_root, "thread reply", |root| {
+            let mut summary = root.thread_summary.clone().unwrap_or_default();
+            summary.add_reply(reply).then(|| root.with_thread_summary(Some(summary)))
+        });
+    }
+}
+
+/// Whether a read receipt for the given thread applies to the timeline of the
+/// thread with the given root, or to the main timeline if it is `None`.
+pub(super) fn receipt_applies(thread: &ReceiptThread, thread_root: Option<&EventId>) -> bool {
+    match (thread, thread_root) {
+        (ReceiptThread::Unthreaded, _) | (ReceiptThread::Main, None) => true,
+        (ReceiptThread::Thread(receipt_thread), Some(thread_root)) => {
+            **receipt_thread == *thread_root
+        }
+        _ => false,
+    }
+}
+
+/// Move the read receipt of the given user to the item of the given event.
+///
+/// Receipts for events that are older than the one of the current receipt of
+/// the user are ignored, so that a public and a private read receipt of the
+/// same user don't override each other.
+///
+/// `new_event_position` is the position the event is about to be added at, if
+/// it is not part of the timeline yet.
+pub(super) fn update_read_receipt(
+    items_lock: &mut MutableVecLockMut<'_, Arc<TimelineItem>>,
+    read_receipts: &mut HashMap<OwnedUserId, (OwnedEventId, Receipt)>,
+    user_id: OwnedUserId,
+    event_id: OwnedEventId,
+    receipt: Receipt,
+    new_event_position: Option<&TimelineItemPosition>,
+) {
+    if let Some((old_event_id, old_receipt)) = read_receipts.get(&user_id) {
+        if *old_event_id == event_id {
+            return;
+        }
+
+        // Receipts only move forward in the timeline. The timestamps of the
+        // receipts are only used if the order of the events is not known,
+        // since a public and a private receipt can be sent in any order.
+        let old_idx = find_event_by_id(items_lock, old_event_id).map(|(idx, _)| idx);
+        let new_idx = find_event_by_id(items_lock, &event_id).map(|(idx, _)| idx);
+        let is_older = match (old_idx, new_idx, new_event_position) {
+            (Some(old_idx), Some(new_idx), _) if old_idx != new_idx => new_idx < old_idx,
+            (Some(_), None, Some(TimelineItemPosition::Start)) => true,
+            (Some(_), None, Some(TimelineItemPosition::End)) => false,
+            _ => old_receipt.ts > receipt.ts,
+        };
+        if is_older {
+            return;
+        }
+
+        if let Some((idx, item)) = find_event_by_id(items_lock, old_event_id) {
+            let mut item_receipts = item.read_receipts.clone();
+            item_receipts.remove(&user_id);
+            let item = item.with_read_receipts(item_receipts);
+            set_event_item(items_lock, idx, item);
+        }
+    }
+
+    if let Some((idx, item)) = find_event_by_id(items_lock, &event_id) {
+        let mut item_receipts = item.read_receipts.clone();
+        item_receipts.insert(user_id.clone(), receipt.clone());
+        let item = item.with_read_receipts(item_receipts);
+        set_event_item(items_lock, idx, item);
+    }
+
+    read_receipts.insert(user_id, (event_id, receipt));
 }
 
 pub(crate) fn update_read_marker(
@@ -502,6 +880,283 @@ pub(crate) fn update_read_marker(
     }
 }
 
+/// Insert a new item at the start or the end of the timeline.
+///
+/// Depending on the settings, this also adds a day divider before the item,
+/// and groups it with adjacent membership changes.
+fn insert_new_item(
+    timeline_items: &mut MutableVecLockMut<'_, Arc<TimelineItem>>,
+    settings: &TimelineInnerSettings,
+    item: EventTimelineItem,
+    at_start: bool,
+) {
+    let mut idx = if at_start { 0 } else { timeline_items.len() };
+
+    if let (true, Some(ts)) = (settings.day_dividers, item.origin_server_ts) {
+        let day = settings.day_of(ts);
+        if at_start {
+            let first_day = timeline_items.first().and_then(|it| day_divider_timestamp(it));
+            if first_day.map(|ts| settings.day_of(ts)) == Some(day) {
+                // The divider holds the timestamp of the day's first event.
+                timeline_items.set_cloned(0, settings.day_divider(ts));
+            } else {
+                timeline_items.insert_cloned(0, settings.day_divider(ts));
+            }
+            idx = 1;
+        } else {
+            let last_day = timeline_items.iter().rev().find_map(|it| day_divider_timestamp(it));
+            // Events that arrive out of order are kept in the current day.
+            if last_day.map_or(true, |ts| day > settings.day_of(ts)) {
+                timeline_items.push_cloned(settings.day_divider(ts));
+                idx += 1;
+            }
+        }
+    }
+
+    if settings.collapse_membership_changes && is_membership_change(&item) {
+        let neighbor_idx = if at_start { Some(idx) } else { idx.checked_sub(1) };
+        if let Some(neighbor_idx) = neighbor_idx.filter(|i| *i < timeline_items.len()) {
+            if let Some(mut group) = membership_changes(&timeline_items[neighbor_idx]) {
+                if at_start {
+                    group.insert(0, item);
+                } else {
+                    group.push(item);
+                }
+
+                let group = VirtualTimelineItem::MembershipChanges(group);
+                timeline_items.set_cloned(neighbor_idx, Arc::new(TimelineItem::Virtual(group)));
+                return;
+            }
+        }
+    }
+
+    let item = Arc::new(TimelineItem::Event(item));
+    if idx == timeline_items.len() {
+        timeline_items.push_cloned(item);
+    } else {
+        timeline_items.insert_cloned(idx, item);
+    }
+}
+
+/// Remove the virtual items that lost their meaning after event items were
+/// removed from the timeline: day dividers without events after them, a read
+/// marker without events before it, and membership changes that are now
+/// adjacent but not grouped.
+///
+/// Returns whether the read marker was removed.
+pub(super) fn remove_orphaned_virtual_items(
+    timeline_items: &mut MutableVecLockMut<'_, Arc<TimelineItem>>,
+    settings: &TimelineInnerSettings,
+) -> bool {
+    let mut idx = 0;
+    while idx < timeline_items.len() {
+        if day_divider_timestamp(&timeline_items[idx]).is_some() {
+            let next_item = timeline_items[idx + 1..].iter().find(|it| !is_read_marker(it));
+            if next_item.map_or(true, |it| day_divider_timestamp(it).is_some()) {
+                timeline_items.remove(idx);
+                continue;
+            }
+        }
+        idx += 1;
+    }
+
+    let mut read_marker_removed = false;
+    if let Some(idx) = find_read_marker(timeline_items) {
+        if !timeline_items[..idx].iter().any(|it| contains_events(it)) {
+            timeline_items.remove(idx);
+            read_marker_removed = true;
+        }
+    }
+
+    if settings.collapse_membership_changes {
+        let mut idx = 1;
+        while idx < timeline_items.len() {
+            let (Some(mut group), Some(next_group)) = (
+                membership_changes(&timeline_items[idx - 1]),
+                membership_changes(&timeline_items[idx]),
+            ) else {
+                idx += 1;
+                continue;
+            };
+            group.extend(next_group);
+
+            let group = VirtualTimelineItem::MembershipChanges(group);
+            timeline_items.set_cloned(idx - 1, Arc::new(TimelineItem::Virtual(group)));
+            timeline_items.remove(idx);
+        }
+    }
+
+    read_marker_removed
+}
+
+fn is_read_marker(item: &TimelineItem) -> bool {
+    matches!(item, TimelineItem::Virtual(VirtualTimelineItem::ReadMarker))
+}
+
+/// Whether the item holds at least one event.
+fn contains_events(item: &TimelineItem) -> bool {
+    matches!(
+        item,
+        TimelineItem::Event(_) | TimelineItem::Virtual(VirtualTimelineItem::MembershipChanges(_))
+    )
+}
+
+/// The membership changes held by the item, if it is a group of them or a
+/// single membership change.
+fn membership_changes(item: &TimelineItem) -> Option<Vec<EventTimelineItem>> {
+    match item {
+        TimelineItem::Virtual(VirtualTimelineItem::MembershipChanges(group)) => Some(group.clone()),
+        TimelineItem::Event(ev) if is_membership_change(ev) => Some(vec![ev.clone()]),
+        _ => None,
+    }
+}
+
+fn day_divider_timestamp(item: &TimelineItem) -> Option<MilliSecondsSinceUnixEpoch> {
+    match item.as_virtual()? {
+        VirtualTimelineItem::DayDivider { timestamp, .. } => Some(*timestamp),
+        _ => None,
+    }
+}
+
+fn is_membership_change(item: &EventTimelineItem) -> bool {
+    matches!(
+        item.content,
+        TimelineItemContent::MembershipChange(_) | TimelineItemContent::ProfileChange(_)
+    )
+}
+
+/// Add a reaction to the timeline item it applies to, and remember it in
+/// `reaction_map`.
+///
+/// Returns whether an update happened.
+pub(super) fn add_reaction(
+    timeline_items: &mut MutableVecLockMut<'_, Arc<TimelineItem>>,
+    reaction_map: &mut HashMap<TimelineKey, (OwnedUserId, Annotation)>,
+    key: TimelineKey,
+    sender: OwnedUserId,
+    annotation: Annotation,
+) -> bool {
+    let event_id = &annotation.event_id;
+    let did_update = maybe_update_timeline_item(timeline_items, event_id, "reaction", |item| {
+        // Handling of reactions on redacted events is an open question.
+        // For now, ignore reactions on redacted events like Element does.
+        if let TimelineItemContent::RedactedMessage = item.content {
+            debug!(%event_id, "Ignoring reaction on redacted event");
+            None
+        } else {
+            let mut reactions = item.reactions.clone();
+            let reaction_details = reactions.bundled.entry(annotation.key.clone()).or_default();
+
+            reaction_details.count += uint!(1);
+            if let TimelineDetails::Ready(senders) = &mut reaction_details.senders {
+                senders.push(sender.clone());
+            }
+
+            Some(item.with_reactions(reactions))
+        }
+    });
+
+    if did_update {
+        reaction_map.insert(key, (sender, annotation));
+    }
+
+    did_update
+}
+
+/// Remove the reaction with the given key from `reaction_map` and from the
+/// timeline item it applies to.
+///
+/// Returns whether an update happened.
+pub(super) fn remove_reaction(
+    timeline_items: &mut MutableVecLockMut<'_, Arc<TimelineItem>>,
+    reaction_map: &mut HashMap<TimelineKey, (OwnedUserId, Annotation)>,
+    key: &TimelineKey,
+) -> bool {
+    let Some((sender, rel)) = reaction_map.remove(key) else {
+        return false;
+    };
+
+    let did_update =
+        maybe_update_timeline_item(timeline_items, &rel.event_id, "redaction", |item| {
+            let mut reactions = item.reactions.clone();
+
+            let Entry::Occupied(mut details_entry) = reactions.bundled.entry(rel.key) else {
+                return None;
+            };
+            let details = details_entry.get_mut();
+            details.count -= uint!(1);
+
+            if details.count == uint!(0) {
+                details_entry.remove();
+                return Some(item.with_reactions(reactions));
+            }
+
+            let TimelineDetails::Ready(senders) = &mut details.senders else {
+                // FIXME: We probably want to support this somehow in
+                //        the future, but right now it's not possible.
+                warn!(
+                    "inconsistent state: shouldn't have a reaction_map entry for a \
+                    timeline item with incomplete reactions"
+                );
+                return None;
+            };
+
+            if let Some(idx) = senders.iter().position(|s| *s == sender) {
+                senders.remove(idx);
+            } else {
+                error!(
+                    "inconsistent state: sender from reaction_map not in reaction sender list \
+                 of timeline item"
+                );
+                return None;
+            }
+
+            if u64::from(details.count) != senders.len() as u64 {
+                error!("inconsistent state: reaction count differs from number of senders");
+                // Can't make things worse by updating the item, so no early
+                // return here.
+            }
+
+            Some(item.with_reactions(reactions))
+        });
+
+    if !did_update {
+        warn!("reaction_map out of sync with timeline items");
+    }
+
+    did_update
+}
+
+/// Fill in the reply details of the items replying to `event_id` that don't
+/// have them yet.
+fn update_replies_to(
+    timeline_items: &mut MutableVecLockMut<'_, Arc<TimelineItem>>,
+    pending_replies: &mut HashMap<OwnedEventId, Vec<TimelineKey>>,
+    event_id: &EventId,
+    replied_to: &EventTimelineItem,
+) {
+    let Some(reply_keys) = pending_replies.remove(event_id) else { return };
+
+    let details = TimelineDetails::Ready(Box::new(RepliedToEvent::from(replied_to)));
+    for key in reply_keys {
+        // The reply might have been removed from the timeline since.
+        let reply = match &key {
+            TimelineKey::EventId(reply_id) => find_event_by_id(timeline_items, reply_id),
+            TimelineKey::TransactionId(txn_id) => find_event_by_txn_id(timeline_items, txn_id),
+        };
+        let Some((idx, event_item)) = reply else { continue };
+        let Some(msg) = event_item.content.as_message() else { continue };
+        if msg.in_reply_to.as_ref().map_or(true, |r| r.details.is_ready()) {
+            continue;
+        }
+
+        trace!(%event_id, "Filling in reply details");
+        let content = TimelineItemContent::Message(msg.with_in_reply_to_details(details.clone()));
+        let new_item = event_item.with_content(content);
+        set_event_item(timeline_items, idx, new_item);
+    }
+}
+
 /// Returns whether an update happened
 fn maybe_update_timeline_item(
     timeline_items: &mut MutableVecLockMut<'_, Arc<TimelineItem>>,
@@ -511,7 +1166,7 @@ fn maybe_update_timeline_item(
 ) -> bool {
     if let Some((idx, item)) = find_event_by_id(timeline_items, event_id) {
         if let Some(new_item) = update(item) {
-            timeline_items.set_cloned(idx, Arc::new(TimelineItem::Event(new_item)));
+            set_event_item(timeline_items, idx, new_item);
             return true;
         }
     } else {
@@ -539,16 +1194,13 @@ struct NewEventTimelineItem {
 impl NewEventTimelineItem {
     // These constructors could also be `From` implementations, but that would
     // allow users to call them directly, which should not be supported
-    fn message(c: RoomMessageEventContent, relations: Option<BundledRelations>) -> Self {
+    fn message(
+        c: RoomMessageEventContent,
+        relations: Option<BundledRelations>,
+        timeline_items: &[Arc<TimelineItem>],
+    ) -> Self {
         let edited = relations.as_ref().map_or(false, |r| r.replace.is_some());
-        let content = TimelineItemContent::Message(Message {
-            msgtype: c.msgtype,
-            in_reply_to: c.relates_to.and_then(|rel| match rel {
-                message::Relation::Reply { in_reply_to } => Some(in_reply_to.event_id),
-                _ => None,
-            }),
-            edited,
-        });
+        let content = TimelineItemContent::Message(Message::new(c, edited, timeline_items));
 
         let reactions =
             relations.and_then(|r| r.annotation).map(BundledReactions::from).unwrap_or_default();
@@ -560,6 +1212,10 @@ impl NewEventTimelineItem {
         Self::from_content(TimelineItemContent::UnableToDecrypt(content.into()))
     }
 
+    fn poll(poll: PollState) -> Self {
+        Self::from_content(TimelineItemContent::Poll(poll))
+    }
+
     fn redacted_message() -> Self {
         Self::from_content(TimelineItemContent::RedactedMessage)
     }
@@ -18,18 +18,26 @@ use indexmap::IndexMap;
 use matrix_sdk_base::deserialized_responses::EncryptionInfo;
 use ruma::{
     events::{
+        receipt::Receipt,
         relation::{AnnotationChunk, AnnotationType},
         room::{
             encrypted::{EncryptedEventScheme, MegolmV1AesSha2Content, RoomEncryptedEventContent},
-            message::MessageType,
+            member::{MembershipState, RoomMemberEventContent},
+            message::{self, MessageType, RoomMessageEventContent},
         },
-        AnySyncTimelineEvent, MessageLikeEventType, StateEventType,
+        AnyMessageLikeEventContent, AnyStateEventContent, AnySyncTimelineEvent, BundledThread,
+        MessageLikeEventType, StateEventType,
     },
     serde::Raw,
-    uint, EventId, MilliSecondsSinceUnixEpoch, OwnedDeviceId, OwnedEventId, OwnedTransactionId,
-    OwnedUserId, TransactionId, UInt, UserId,
+    uint, EventId, MilliSecondsSinceUnixEpoch, OwnedDeviceId, OwnedEventId, OwnedMxcUri,
+    OwnedTransactionId, OwnedUserId, TransactionId, UInt, UserId,
 };
 
+use tracing::debug;
+
+use super::{find_event_by_id, PollState, TimelineItem};
+use crate::room::RoomMember;
+
 /// An item in the timeline that represents at least one event.
 ///
 /// There is always one main event that gives the `EventTimelineItem` its
@@ -38,13 +46,16 @@ use ruma::{
 #[derive(Clone)]
 pub struct EventTimelineItem {
     pub(super) key: TimelineKey,
-    // If this item is a local echo that has been acknowledged by the server
-    // but not remote-echoed yet, this field holds the event ID from the send
-    // response.
-    pub(super) event_id: Option<OwnedEventId>,
+    // If this item is a local echo that hasn't been remote-echoed yet, this
+    // field holds its send state, including the event ID from the send
+    // response once the server has acknowledged it.
+    pub(super) send_state: Option<EventSendState>,
     pub(super) sender: OwnedUserId,
+    pub(super) sender_profile: TimelineDetails<Profile>,
     pub(super) content: TimelineItemContent,
     pub(super) reactions: BundledReactions,
+    pub(super) thread_summary: Option<ThreadSummary>,
+    pub(super) read_receipts: IndexMap<OwnedUserId, Receipt>,
     pub(super) origin_server_ts: Option<MilliSecondsSinceUnixEpoch>,
     pub(super) is_own: bool,
     pub(super) encryption_info: Option<EncryptionInfo>,
@@ -56,10 +67,13 @@ impl fmt::Debug for EventTimelineItem {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("EventTimelineItem")
             .field("key", &self.key)
-            .field("event_id", &self.event_id)
+            .field("send_state", &self.send_state)
             .field("sender", &self.sender)
+            .field("sender_profile", &self.sender_profile)
             .field("content", &self.content)
             .field("reactions", &self.reactions)
+            .field("thread_summary", &self.thread_summary)
+            .field("read_receipts", &self.read_receipts)
             .field("origin_server_ts", &self.origin_server_ts)
             .field("is_own", &self.is_own)
             .field("encryption_info", &self.encryption_info)
@@ -99,16 +113,36 @@ impl EventTimelineItem {
     /// of the send request that created the event.
     pub fn event_id(&self) -> Option<&EventId> {
         match &self.key {
-            TimelineKey::TransactionId(_) => self.event_id.as_deref(),
+            TimelineKey::TransactionId(_) => match &self.send_state {
+                Some(EventSendState::Sent { event_id }) => Some(event_id),
+                _ => None,
+            },
             TimelineKey::EventId(id) => Some(id),
         }
     }
 
+    /// Get the send state of this item.
+    ///
+    /// Returns `None` if this item is not a local echo, i.e. it was received
+    /// from the server.
+    pub fn send_state(&self) -> Option<&EventSendState> {
+        self.send_state.as_ref()
+    }
+
     /// Get the sender of this item.
     pub fn sender(&self) -> &UserId {
         &self.sender
     }
 
+    /// Get the profile of the sender of this item.
+    ///
+    /// This is the profile the sender had at the time of the event, as far as
+    /// it is known. If the sender's membership isn't available locally, it is
+    /// fetched from the server and the item is updated once it is available.
+    pub fn sender_profile(&self) -> &TimelineDetails<Profile> {
+        &self.sender_profile
+    }
+
     /// Get the content of this item.
     pub fn content(&self) -> &TimelineItemContent {
         &self.content
@@ -121,6 +155,25 @@ impl EventTimelineItem {
         &self.reactions.bundled
     }
 
+    /// Get the summary of the thread this item is the root of, if any.
+    ///
+    /// This is only available in the main timeline of a room, thread replies
+    /// are only part of the thread's own timeline (see
+    /// [`Common::thread_timeline`](crate::room::Common::thread_timeline)).
+    pub fn thread_summary(&self) -> Option<&ThreadSummary> {
+        self.thread_summary.as_ref()
+    }
+
+    /// Get the read receipts of this item.
+    ///
+    /// The keys are the IDs of the users whose latest read receipt in this
+    /// timeline points to this item. Unthreaded receipts apply to every
+    /// timeline, threaded receipts only to the timeline of their thread, or to
+    /// the main timeline of the room.
+    pub fn read_receipts(&self) -> &IndexMap<OwnedUserId, Receipt> {
+        &self.read_receipts
+    }
+
     /// Get the origin server timestamp of this item.
     ///
     /// Returns `None` if this event hasn't been echoed back by the server yet.
@@ -157,14 +210,29 @@ impl EventTimelineItem {
             // FIXME: Change when we support state events
             content: TimelineItemContent::RedactedMessage,
             reactions: BundledReactions::default(),
-            ..self(key, event_id, sender, origin_server_ts, is_own, encryption_info, raw)
+            ..self(
+                key,
+                send_state,
+                sender,
+                sender_profile,
+                thread_summary,
+                read_receipts,
+                origin_server_ts,
+                is_own,
+                encryption_info,
+                raw,
+            )
         })
     }
 
-    pub(super) fn with_event_id(&self, event_id: Option<OwnedEventId>) -> Self {
+    #[rustfmt::skip]
+    pub(super) fn with_send_state(&self, send_state: Option<EventSendState>) -> Self {
         build!(Self {
-            event_id,
-            ..self(key, sender, content, reactions, origin_server_ts, is_own, encryption_info, raw,)
+            send_state,
+            ..self(
+                key, sender, sender_profile, content, reactions, thread_summary,
+                read_receipts, origin_server_ts, is_own, encryption_info, raw,
+            )
         })
     }
 
@@ -173,7 +241,8 @@ impl EventTimelineItem {
         build!(Self {
             content,
             ..self(
-                key, event_id, sender, reactions, origin_server_ts, is_own, encryption_info, raw,
+                key, send_state, sender, sender_profile, reactions, thread_summary,
+                read_receipts, origin_server_ts, is_own, encryption_info, raw,
             )
         })
     }
@@ -183,12 +252,74 @@ impl EventTimelineItem {
         build!(Self {
             reactions,
             ..self(
-                key, event_id, sender, content, origin_server_ts, is_own, encryption_info, raw,
+                key, send_state, sender, sender_profile, content, thread_summary,
+                read_receipts, origin_server_ts, is_own, encryption_info, raw,
+            )
+        })
+    }
+
+    #[rustfmt::skip]
+    pub(super) fn with_thread_summary(&self, thread_summary: Option<ThreadSummary>) -> Self {
+        build!(Self {
+            thread_summary,
+            ..self(
+                key, send_state, sender, sender_profile, content, reactions,
+                read_receipts, origin_server_ts, is_own, encryption_info, raw,
+            )
+        })
+    }
+
+    #[rustfmt::skip]
+    pub(super) fn with_sender_profile(&self, sender_profile: TimelineDetails<Profile>) -> Self {
+        build!(Self {
+            sender_profile,
+            ..self(
+                key, send_state, sender, content, reactions, thread_summary, read_receipts,
+                origin_server_ts, is_own, encryption_info, raw,
+            )
+        })
+    }
+
+    #[rustfmt::skip]
+    pub(super) fn with_read_receipts(
+        &self,
+        read_receipts: IndexMap<OwnedUserId, Receipt>,
+    ) -> Self {
+        build!(Self {
+            read_receipts,
+            ..self(
+                key, send_state, sender, sender_profile, content, reactions, thread_summary,
+                origin_server_ts, is_own, encryption_info, raw,
             )
         })
     }
 }
 
+/// The send state of a local echo, i.e. an [`EventTimelineItem`] for an event
+/// that was created locally.
+#[derive(Clone, Debug)]
+pub enum EventSendState {
+    /// The local event has not been sent yet.
+    NotSentYet,
+
+    /// Sending has failed.
+    ///
+    /// The event can be sent again with
+    /// [`Timeline::retry_send`](super::Timeline::retry_send), or removed from
+    /// the timeline with [`Timeline::cancel_send`](super::Timeline::cancel_send).
+    SendingFailed {
+        /// Details about how sending the event failed.
+        error: Arc<crate::Error>,
+    },
+
+    /// The local event has been sent successfully, but the remote echo has not
+    /// been received yet.
+    Sent {
+        /// The ID of the event assigned by the server.
+        event_id: OwnedEventId,
+    },
+}
+
 /// A unique identifier for a timeline item.
 ///
 /// This identifier is used to find the item in the timeline in order to update
@@ -235,6 +366,15 @@ pub enum TimelineDetails<T> {
 
     /// The details are available.
     Ready(T),
+
+    /// An error occurred when fetching the details.
+    Error(Arc<crate::Error>),
+}
+
+impl<T> TimelineDetails<T> {
+    pub(super) fn is_ready(&self) -> bool {
+        matches!(self, Self::Ready(_))
+    }
 }
 
 /// The content of an [`EventTimelineItem`].
@@ -249,6 +389,9 @@ pub enum TimelineItemContent {
     /// An `m.room.encrypted` event that could not be decrypted.
     UnableToDecrypt(EncryptedMessage),
 
+    /// A poll, with the responses and end event that apply to it.
+    Poll(PollState),
+
     /// A message-like event that failed to deserialize.
     FailedToParseMessageLike {
         /// The event `type`.
@@ -258,6 +401,16 @@ pub enum TimelineItemContent {
         error: Arc<serde_json::Error>,
     },
 
+    /// An `m.room.member` event that changes the membership of a user.
+    MembershipChange(RoomMembershipChange),
+
+    /// An `m.room.member` event that changes the display name or avatar of a
+    /// joined user, without changing their membership.
+    ProfileChange(MemberProfileChange),
+
+    /// Any other state event.
+    OtherState(OtherState),
+
     /// A state event that failed to deserialize.
     FailedToParseState {
         /// The event `type`.
@@ -289,20 +442,79 @@ impl TimelineItemContent {
             _ => None,
         }
     }
+
+    /// If `self` is of the [`Poll`][Self::Poll] variant, return the inner
+    /// [`PollState`].
+    pub fn as_poll(&self) -> Option<&PollState> {
+        match self {
+            Self::Poll(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Whether this content stems from a state event.
+    pub fn is_state(&self) -> bool {
+        matches!(
+            self,
+            Self::MembershipChange(_)
+                | Self::ProfileChange(_)
+                | Self::OtherState(_)
+                | Self::FailedToParseState { .. }
+        )
+    }
+
+    /// Build the content for an event that is displayed outside of its own
+    /// timeline item, like a replied-to event or the latest reply of a thread.
+    ///
+    /// Returns `None` if the event is not supported in this context.
+    pub(super) fn from_standalone_event(event: &AnySyncTimelineEvent) -> Option<Self> {
+        let AnySyncTimelineEvent::MessageLike(ev) = event else { return None };
+        let edited = event.relations().map_or(false, |r| r.replace.is_some());
+
+        match ev.original_content() {
+            Some(AnyMessageLikeEventContent::RoomMessage(c)) => {
+                Some(Self::Message(Message::new(c, edited, &[])))
+            }
+            Some(AnyMessageLikeEventContent::RoomEncrypted(c)) => {
+                Some(Self::UnableToDecrypt(c.into()))
+            }
+            Some(AnyMessageLikeEventContent::PollStart(c)) => {
+                Some(Self::Poll(PollState::new(c.poll_start)))
+            }
+            Some(_) => None,
+            None => Some(Self::RedactedMessage),
+        }
+    }
 }
 
 /// An `m.room.message` event or extensible event, including edits.
 #[derive(Clone, Debug)]
 pub struct Message {
     pub(super) msgtype: MessageType,
-    // TODO: Add everything required to display the replied-to event, plus a
-    // 'loading' state that is entered at first, until the user requests the
-    // reply to be loaded.
-    pub(super) in_reply_to: Option<OwnedEventId>,
+    pub(super) in_reply_to: Option<InReplyToDetails>,
     pub(super) edited: bool,
 }
 
 impl Message {
+    /// Construct a `Message` from the content of an `m.room.message` event.
+    ///
+    /// If the message is a reply and the replied-to event is found in
+    /// `timeline_items`, the reply details are filled in right away.
+    pub(super) fn new(
+        c: RoomMessageEventContent,
+        edited: bool,
+        timeline_items: &[Arc<TimelineItem>],
+    ) -> Self {
+        let in_reply_to = c.relates_to.and_then(|rel| match rel {
+            message::Relation::Reply { in_reply_to } => {
+                Some(InReplyToDetails::new(in_reply_to.event_id, timeline_items))
+            }
+            _ => None,
+        });
+
+        Self { msgtype: c.msgtype, in_reply_to, edited }
+    }
+
     /// Get the `msgtype`-specific data of this message.
     pub fn msgtype(&self) -> &MessageType {
         &self.msgtype
@@ -315,15 +527,442 @@ impl Message {
         self.msgtype.body()
     }
 
-    /// Get the event ID of the event this message is replying to, if any.
-    pub fn in_reply_to(&self) -> Option<&EventId> {
-        self.in_reply_to.as_deref()
+    /// Get the event this message is replying to, if any.
+    pub fn in_reply_to(&self) -> Option<&InReplyToDetails> {
+        self.in_reply_to.as_ref()
     }
 
     /// Get the edit state of this message (has been edited: `true` / `false`).
     pub fn is_edited(&self) -> bool {
         self.edited
     }
+
+    pub(super) fn with_in_reply_to_details(
+        &self,
+        details: TimelineDetails<Box<RepliedToEvent>>,
+    ) -> Self {
+        let mut msg = self.clone();
+        if let Some(in_reply_to) = &mut msg.in_reply_to {
+            in_reply_to.details = details;
+        }
+        msg
+    }
+}
+
+/// Details about an event being replied to.
+#[derive(Clone, Debug)]
+pub struct InReplyToDetails {
+    /// The ID of the event.
+    pub event_id: OwnedEventId,
+
+    /// The details of the event.
+    ///
+    /// Use [`Timeline::fetch_details_for_event`] to fetch the data if it is
+    /// unavailable.
+    ///
+    /// [`Timeline::fetch_details_for_event`]: super::Timeline::fetch_details_for_event
+    pub details: TimelineDetails<Box<RepliedToEvent>>,
+}
+
+impl InReplyToDetails {
+    fn new(event_id: OwnedEventId, timeline_items: &[Arc<TimelineItem>]) -> Self {
+        let details = match find_event_by_id(timeline_items, &event_id) {
+            Some((_, item)) => TimelineDetails::Ready(Box::new(RepliedToEvent::from(item))),
+            None => TimelineDetails::Unavailable,
+        };
+
+        Self { event_id, details }
+    }
+}
+
+/// An event that is replied to.
+#[derive(Clone, Debug)]
+pub struct RepliedToEvent {
+    pub(super) content: TimelineItemContent,
+    pub(super) sender: OwnedUserId,
+    pub(super) sender_profile: TimelineDetails<Profile>,
+}
+
+impl RepliedToEvent {
+    /// Get the content of the event.
+    pub fn content(&self) -> &TimelineItemContent {
+        &self.content
+    }
+
+    /// Get the sender of the event.
+    pub fn sender(&self) -> &UserId {
+        &self.sender
+    }
+
+    /// Get the profile of the sender.
+    pub fn sender_profile(&self) -> &TimelineDetails<Profile> {
+        &self.sender_profile
+    }
+}
+
+impl From<&EventTimelineItem> for RepliedToEvent {
+    fn from(item: &EventTimelineItem) -> Self {
+        let content = match &item.content {
+            // Only the directly replied-to event is of interest, don't keep a
+            // whole chain of replies around.
+            TimelineItemContent::Message(msg) => TimelineItemContent::Message(
+                msg.with_in_reply_to_details(TimelineDetails::Unavailable),
+            ),
+            content => content.clone(),
+        };
+
+        Self { content, sender: item.sender.clone(), sender_profile: item.sender_profile.clone() }
+    }
+}
+
+/// The display name and avatar URL of a room member.
+#[derive(Clone, Debug)]
+pub struct Profile {
+    /// The display name, if set.
+    pub display_name: Option<String>,
+
+    /// Whether the display name is ambiguous.
+    ///
+    /// Note that in rooms with lazy-loading enabled, this could be `false` even
+    /// though the display name is actually ambiguous if not all member events
+    /// have been seen yet.
+    pub display_name_ambiguous: bool,
+
+    /// The avatar URL, if set.
+    pub avatar_url: Option<OwnedMxcUri>,
+}
+
+impl From<&RoomMember> for Profile {
+    fn from(member: &RoomMember) -> Self {
+        Self {
+            display_name: member.display_name().map(ToOwned::to_owned),
+            display_name_ambiguous: member.name_ambiguous(),
+            avatar_url: member.avatar_url().map(ToOwned::to_owned),
+        }
+    }
+}
+
+/// A summary of a thread, attached to the thread's root event.
+#[derive(Clone, Debug, Default)]
+pub struct ThreadSummary {
+    pub(super) num_replies: u64,
+    pub(super) latest_reply: Option<Box<ThreadReply>>,
+    pub(super) participants: Vec<OwnedUserId>,
+}
+
+impl ThreadSummary {
+    /// Create a `ThreadSummary` from the thread relation bundled with the
+    /// thread's root event.
+    pub(super) fn from_bundled(thread: &BundledThread) -> Self {
+        let latest_reply =
+            match thread.latest_event.cast_ref::<AnySyncTimelineEvent>().deserialize() {
+                Ok(event) => ThreadReply::from_event(&event).map(Box::new),
+                Err(error) => {
+                    debug!(?error, "Failed to deserialize latest event of bundled thread");
+                    None
+                }
+            };
+        let participants = latest_reply.iter().map(|reply| reply.sender.clone()).collect();
+
+        Self { num_replies: thread.count.into(), latest_reply, participants }
+    }
+
+    /// Add a new reply to the summary.
+    ///
+    /// Returns `false` if the reply was already the latest reply.
+    pub(super) fn add_reply(&mut self, reply: ThreadReply) -> bool {
+        if self.latest_reply.as_ref().map_or(false, |latest| latest.event_id == reply.event_id) {
+            return false;
+        }
+
+        self.num_replies += 1;
+        if !self.participants.contains(&reply.sender) {
+            self.participants.push(reply.sender.clone());
+        }
+        self.latest_reply = Some(Box::new(reply));
+
+        true
+    }
+
+    /// The number of replies in the thread.
+    pub fn num_replies(&self) -> u64 {
+        self.num_replies
+    }
+
+    /// The latest reply in the thread, if known.
+    pub fn latest_reply(&self) -> Option<&ThreadReply> {
+        self.latest_reply.as_deref()
+    }
+
+    /// The users that are known to have replied in the thread, in the order in
+    /// which they were first seen.
+    ///
+    /// When the summary was received from the homeserver, this only contains
+    /// the sender of the latest reply until more replies are received.
+    pub fn participants(&self) -> &[OwnedUserId] {
+        &self.participants
+    }
+}
+
+/// A reply in a thread, as displayed in a [`ThreadSummary`].
+#[derive(Clone, Debug)]
+pub struct ThreadReply {
+    pub(super) event_id: OwnedEventId,
+    pub(super) sender: OwnedUserId,
+    pub(super) content: TimelineItemContent,
+}
+
+impl ThreadReply {
+    pub(super) fn from_event(event: &AnySyncTimelineEvent) -> Option<Self> {
+        Some(Self {
+            event_id: event.event_id().to_owned(),
+            sender: event.sender().to_owned(),
+            content: TimelineItemContent::from_standalone_event(event)?,
+        })
+    }
+
+    /// Get the ID of the event.
+    pub fn event_id(&self) -> &EventId {
+        &self.event_id
+    }
+
+    /// Get the sender of the event.
+    pub fn sender(&self) -> &UserId {
+        &self.sender
+    }
+
+    /// Get the content of the event.
+    pub fn content(&self) -> &TimelineItemContent {
+        &self.content
+    }
+}
+
+/// An `m.room.member` event that changes the membership of a user.
+#[derive(Clone, Debug)]
+pub struct RoomMembershipChange {
+    pub(super) user_id: OwnedUserId,
+    pub(super) content: RoomMemberEventContent,
+    pub(super) prev_content: Option<RoomMemberEventContent>,
+    pub(super) change: MembershipChange,
+}
+
+impl RoomMembershipChange {
+    pub(super) fn new(
+        sender: &UserId,
+        user_id: OwnedUserId,
+        content: RoomMemberEventContent,
+        prev_content: Option<RoomMemberEventContent>,
+    ) -> Self {
+        let prev_membership = prev_content.as_ref().map(|c| &c.membership);
+        let change = MembershipChange::new(prev_membership, &content.membership, sender, &user_id);
+        Self { user_id, content, prev_content, change }
+    }
+
+    /// The ID of the user whose membership changed.
+    pub fn user_id(&self) -> &UserId {
+        &self.user_id
+    }
+
+    /// The content of the `m.room.member` event.
+    pub fn content(&self) -> &RoomMemberEventContent {
+        &self.content
+    }
+
+    /// The content of the previous `m.room.member` event of the same user, if
+    /// it was included in the event's `unsigned.prev_content`.
+    pub fn prev_content(&self) -> Option<&RoomMemberEventContent> {
+        self.prev_content.as_ref()
+    }
+
+    /// What changed about the user's membership.
+    pub fn change(&self) -> &MembershipChange {
+        &self.change
+    }
+}
+
+/// The kind of membership change described by an `m.room.member` event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MembershipChange {
+    /// No change.
+    None,
+
+    /// Must never happen.
+    Error,
+
+    /// User joined the room.
+    Joined,
+
+    /// User left the room.
+    Left,
+
+    /// User was banned.
+    Banned,
+
+    /// User was unbanned.
+    Unbanned,
+
+    /// User was kicked.
+    Kicked,
+
+    /// User was invited.
+    Invited,
+
+    /// User was kicked and banned.
+    KickedAndBanned,
+
+    /// User accepted the invite.
+    InvitationAccepted,
+
+    /// User rejected the invite.
+    InvitationRejected,
+
+    /// User had their invite revoked.
+    InvitationRevoked,
+
+    /// User knocked.
+    Knocked,
+
+    /// User had their knock accepted.
+    KnockAccepted,
+
+    /// User retracted their knock.
+    KnockRetracted,
+
+    /// User had their knock denied.
+    KnockDenied,
+
+    /// Not implemented.
+    NotImplemented,
+}
+
+impl MembershipChange {
+    /// Compute the membership change from the previous and current membership
+    /// of `user_id`, as described in the spec for `m.room.member`.
+    ///
+    /// A missing previous membership is treated like `leave`.
+    fn new(
+        prev: Option<&MembershipState>,
+        new: &MembershipState,
+        sender: &UserId,
+        user_id: &UserId,
+    ) -> Self {
+        use MembershipState as St;
+
+        let by_self = sender == user_id;
+        let prev = prev.unwrap_or(&St::Leave);
+
+        match (prev, new) {
+            (St::Ban, St::Ban)
+            | (St::Invite, St::Invite)
+            | (St::Join, St::Join)
+            | (St::Knock, St::Knock)
+            | (St::Leave, St::Leave) => Self::None,
+            (St::Ban, St::Leave) => Self::Unbanned,
+            (St::Ban, _) => Self::Error,
+            (St::Join, St::Ban) => Self::KickedAndBanned,
+            (_, St::Ban) => Self::Banned,
+            (St::Invite, St::Join) => Self::InvitationAccepted,
+            (St::Invite, St::Leave) if by_self => Self::InvitationRejected,
+            (St::Invite, St::Leave) => Self::InvitationRevoked,
+            (St::Join, St::Leave) if by_self => Self::Left,
+            (St::Join, St::Leave) => Self::Kicked,
+            (St::Knock, St::Invite) => Self::KnockAccepted,
+            (St::Knock, St::Leave) if by_self => Self::KnockRetracted,
+            (St::Knock, St::Leave) => Self::KnockDenied,
+            (_, St::Join) => Self::Joined,
+            (_, St::Invite) => Self::Invited,
+            (_, St::Knock) => Self::Knocked,
+            _ => Self::NotImplemented,
+        }
+    }
+}
+
+/// An `m.room.member` event that changes the profile of a joined user.
+#[derive(Clone, Debug)]
+pub struct MemberProfileChange {
+    pub(super) user_id: OwnedUserId,
+    pub(super) displayname_change: Option<Change<Option<String>>>,
+    pub(super) avatar_url_change: Option<Change<Option<OwnedMxcUri>>>,
+}
+
+impl MemberProfileChange {
+    pub(super) fn new(
+        user_id: OwnedUserId,
+        content: &RoomMemberEventContent,
+        prev_content: Option<&RoomMemberEventContent>,
+    ) -> Self {
+        let prev_displayname = prev_content.and_then(|c| c.displayname.clone());
+        let prev_avatar_url = prev_content.and_then(|c| c.avatar_url.clone());
+
+        Self {
+            user_id,
+            displayname_change: Change::new(prev_displayname, content.displayname.clone()),
+            avatar_url_change: Change::new(prev_avatar_url, content.avatar_url.clone()),
+        }
+    }
+
+    /// The ID of the user whose profile changed.
+    pub fn user_id(&self) -> &UserId {
+        &self.user_id
+    }
+
+    /// The display name change, if any.
+    pub fn displayname_change(&self) -> Option<&Change<Option<String>>> {
+        self.displayname_change.as_ref()
+    }
+
+    /// The avatar URL change, if any.
+    pub fn avatar_url_change(&self) -> Option<&Change<Option<OwnedMxcUri>>> {
+        self.avatar_url_change.as_ref()
+    }
+
+    /// Whether neither the display name nor the avatar URL changed.
+    pub(super) fn is_empty(&self) -> bool {
+        self.displayname_change.is_none() && self.avatar_url_change.is_none()
+    }
+}
+
+/// A change of a value, from `old` to `new`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change<T> {
+    /// The old value.
+    pub old: T,
+
+    /// The new value.
+    pub new: T,
+}
+
+impl<T: PartialEq> Change<T> {
+    fn new(old: T, new: T) -> Option<Self> {
+        (old != new).then_some(Self { old, new })
+    }
+}
+
+/// A state event that doesn't have a more specific representation in the
+/// timeline, like `m.room.name` or `m.room.power_levels`.
+#[derive(Clone, Debug)]
+pub struct OtherState {
+    pub(super) state_key: String,
+    pub(super) content: AnyStateEventContent,
+    pub(super) prev_content: Option<AnyStateEventContent>,
+}
+
+impl OtherState {
+    /// The state key of the event.
+    pub fn state_key(&self) -> &str {
+        &self.state_key
+    }
+
+    /// The content of the event.
+    pub fn content(&self) -> &AnyStateEventContent {
+        &self.content
+    }
+
+    /// The content of the previous state event with the same type and state
+    /// key, if it was included in the event's `unsigned.prev_content`.
+    pub fn prev_content(&self) -> Option<&AnyStateEventContent> {
+        self.prev_content.as_ref()
+    }
 }
 
 /// Metadata about an `m.room.encrypted` event that could not be decrypted.
@@ -1,4 +1,7 @@
-use std::{collections::BTreeSet, sync::Arc};
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Arc,
+};
 
 use futures_signals::signal_vec::{MutableVec, MutableVecLockMut};
 use matrix_sdk_base::{
@@ -7,20 +10,34 @@ use matrix_sdk_base::{
     locks::Mutex,
 };
 use ruma::{
-    events::{fully_read::FullyReadEvent, AnyMessageLikeEventContent, AnySyncTimelineEvent},
+    events::{
+        fully_read::FullyReadEvent,
+        poll::response::PollResponseEventContent,
+        receipt::{Receipt, ReceiptEventContent, ReceiptType},
+        relation::Annotation,
+        room::{
+            member::{OriginalSyncRoomMemberEvent, RoomMemberEventContent, SyncRoomMemberEvent},
+            message::{self, RoomMessageEventContent},
+            redaction::RoomRedactionEventContent,
+        },
+        AnyMessageLikeEventContent, AnySyncTimelineEvent,
+    },
     serde::Raw,
-    OwnedEventId, OwnedTransactionId, RoomId, TransactionId, UserId,
+    EventId, OwnedEventId, OwnedTransactionId, OwnedUserId, RoomId, TransactionId, UserId,
 };
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
 
 use super::{
     event_handler::{
-        update_read_marker, Flow, TimelineEventHandler, TimelineEventKind, TimelineEventMetadata,
-        TimelineItemPosition,
+        add_reaction, receipt_applies, remove_orphaned_virtual_items, remove_reaction, thread_root,
+        update_read_marker, update_read_receipt, Flow, TimelineEventHandler, TimelineEventKind,
+        TimelineEventMetadata, TimelineItemPosition,
     },
-    find_event_by_txn_id, TimelineInnerMetadata, TimelineItem, TimelineKey,
+    event_items, find_event_by_id, find_event_by_txn_id, set_event_item, Error, EventSendState,
+    EventTimelineItem, Profile, RepliedToEvent, TimelineDetails, TimelineInnerMetadata,
+    TimelineInnerSettings, TimelineItem, TimelineItemContent, TimelineKey, VirtualTimelineItem,
 };
-use crate::events::SyncTimelineEventWithoutContent;
+use crate::{events::SyncTimelineEventWithoutContent, room};
 
 #[derive(Debug, Default)]
 pub(super) struct TimelineInner {
@@ -29,6 +46,24 @@ pub(super) struct TimelineInner {
 }
 
 impl TimelineInner {
+    pub(super) fn with_settings(settings: TimelineInnerSettings) -> Self {
+        let metadata = TimelineInnerMetadata { settings, ..Default::default() };
+        Self { items: Default::default(), metadata: Mutex::new(metadata) }
+    }
+
+    pub(super) fn for_thread(thread_root: OwnedEventId) -> Self {
+        let metadata =
+            TimelineInnerMetadata { thread_root: Some(thread_root), ..Default::default() };
+        Self { items: Default::default(), metadata: Mutex::new(metadata) }
+    }
+
+    /// Start out with the users currently ignored by the user of the client,
+    /// so the initial events of the timeline are filtered too.
+    pub(super) fn load_ignored_users(&mut self, room: &room::Common) {
+        self.metadata.get_mut().ignored_users =
+            room.client.base_client().ignored_users().get_cloned();
+    }
+
     pub(super) fn add_initial_events(
         &mut self,
         events: Vec<SyncTimelineEvent>,
@@ -43,6 +78,7 @@ impl TimelineInner {
                 own_user_id,
                 event.encryption_info,
                 TimelineItemPosition::End,
+                None,
                 timeline_items,
                 timeline_meta,
             );
@@ -56,11 +92,32 @@ impl TimelineInner {
         own_user_id: &UserId,
     ) {
         let mut timeline_meta = self.metadata.lock().await;
+        if timeline_meta.detached {
+            // The remote echo of an event sent from this timeline replaces its
+            // local echo, which is already part of the timeline.
+            let txn_id =
+                raw.deserialize().ok().and_then(|ev| ev.transaction_id().map(ToOwned::to_owned));
+            let is_remote_echo = txn_id.map_or(false, |txn_id| {
+                find_event_by_txn_id(&self.items.lock_ref(), &txn_id).is_some()
+            });
+
+            if !is_remote_echo {
+                // There's a gap between the end of the timeline and the live
+                // event, unless a forward pagination request that reaches the
+                // live end is ongoing.
+                if let Some(events) = &mut timeline_meta.detached_live_events {
+                    events.push(SyncTimelineEvent { event: raw, encryption_info });
+                }
+                return;
+            }
+        }
+
         handle_remote_event(
             raw,
             own_user_id,
             encryption_info,
             TimelineItemPosition::End,
+            None,
             &mut self.items.lock_mut(),
             &mut timeline_meta,
         );
@@ -72,16 +129,108 @@ impl TimelineInner {
         content: AnyMessageLikeEventContent,
         own_user_id: &UserId,
     ) {
+        let thread_root = match &content {
+            AnyMessageLikeEventContent::RoomMessage(RoomMessageEventContent {
+                relates_to: Some(message::Relation::Thread(thread)),
+                ..
+            }) => Some(thread.event_id.clone()),
+            _ => None,
+        };
+        let mut timeline_meta = self.metadata.lock().await;
         let event_meta = TimelineEventMetadata {
             sender: own_user_id.to_owned(),
+            sender_profile: sender_profile_at_end(&timeline_meta, own_user_id),
             is_own_event: true,
             relations: None,
             // FIXME: Should we supply something here for encrypted rooms?
             encryption_info: None,
+            thread_root,
+        };
+
+        let flow = Flow::Local { txn_id: txn_id.clone() };
+        let kind = TimelineEventKind::Message { content: content.clone() };
+
+        timeline_meta.local_echo_contents.insert(txn_id, content);
+
+        let mut timeline_items = self.items.lock_mut();
+        TimelineEventHandler::new(event_meta, flow, &mut timeline_items, &mut timeline_meta)
+            .handle_event(kind);
+    }
+
+    /// Apply an edit that was created locally to the timeline.
+    pub(super) async fn handle_local_edit(
+        &self,
+        txn_id: OwnedTransactionId,
+        content: RoomMessageEventContent,
+        own_user_id: &UserId,
+    ) {
+        let event_meta = TimelineEventMetadata {
+            sender: own_user_id.to_owned(),
+            // No new item is created for this event.
+            sender_profile: TimelineDetails::Unavailable,
+            is_own_event: true,
+            relations: None,
+            encryption_info: None,
+            thread_root: None,
+        };
+        let flow = Flow::Local { txn_id };
+        let kind = TimelineEventKind::Message {
+            content: AnyMessageLikeEventContent::RoomMessage(content),
+        };
+
+        let mut timeline_meta = self.metadata.lock().await;
+        let mut timeline_items = self.items.lock_mut();
+        TimelineEventHandler::new(event_meta, flow, &mut timeline_items, &mut timeline_meta)
+            .handle_event(kind);
+    }
+
+    /// Apply a redaction that was sent successfully to the timeline, without
+    /// waiting for its remote echo.
+    pub(super) async fn handle_local_redaction(
+        &self,
+        txn_id: OwnedTransactionId,
+        redacts: OwnedEventId,
+        own_user_id: &UserId,
+    ) {
+        let event_meta = TimelineEventMetadata {
+            sender: own_user_id.to_owned(),
+            // No new item is created for this event.
+            sender_profile: TimelineDetails::Unavailable,
+            is_own_event: true,
+            relations: None,
+            encryption_info: None,
+            thread_root: None,
         };
+        let flow = Flow::Local { txn_id };
+        let kind =
+            TimelineEventKind::Redaction { redacts, content: RoomRedactionEventContent::new() };
+
+        let mut timeline_meta = self.metadata.lock().await;
+        let mut timeline_items = self.items.lock_mut();
+        TimelineEventHandler::new(event_meta, flow, &mut timeline_items, &mut timeline_meta)
+            .handle_event(kind);
+    }
 
+    /// Apply a poll response that was created locally to the timeline.
+    pub(super) async fn handle_local_poll_response(
+        &self,
+        txn_id: OwnedTransactionId,
+        content: PollResponseEventContent,
+        own_user_id: &UserId,
+    ) {
+        let event_meta = TimelineEventMetadata {
+            sender: own_user_id.to_owned(),
+            // No new item is created for this event.
+            sender_profile: TimelineDetails::Unavailable,
+            is_own_event: true,
+            relations: None,
+            encryption_info: None,
+            thread_root: None,
+        };
         let flow = Flow::Local { txn_id };
-        let kind = TimelineEventKind::Message { content };
+        let kind = TimelineEventKind::Message {
+            content: AnyMessageLikeEventContent::PollResponse(content),
+        };
 
         let mut timeline_meta = self.metadata.lock().await;
         let mut timeline_items = self.items.lock_mut();
@@ -89,9 +238,166 @@ impl TimelineInner {
             .handle_event(kind);
     }
 
+    /// Remove the local echo of a poll response that couldn't be sent from
+    /// the poll with the given start event.
+    pub(super) fn remove_local_poll_response(
+        &self,
+        poll_start_id: &EventId,
+        txn_id: &TransactionId,
+    ) {
+        let mut lock = self.items.lock_mut();
+        let Some((idx, item)) = find_event_by_id(&lock, poll_start_id) else {
+            debug!(%poll_start_id, "Timeline item not found, can't remove poll response");
+            return;
+        };
+        let TimelineItemContent::Poll(poll) = &item.content else {
+            warn!(%poll_start_id, "Timeline item is not a poll, can't remove poll response");
+            return;
+        };
+
+        let item =
+            item.with_content(TimelineItemContent::Poll(poll.without_local_response(txn_id)));
+        set_event_item(&mut lock, idx, item);
+    }
+
+    /// Set the event ID of the local echo of a poll response that was sent, so
+    /// its remote echo replaces it even if it has no transaction ID.
+    pub(super) fn mark_local_poll_response_sent(
+        &self,
+        poll_start_id: &EventId,
+        txn_id: &TransactionId,
+        event_id: &EventId,
+    ) {
+        let mut lock = self.items.lock_mut();
+        let Some((idx, item)) = find_event_by_id(&lock, poll_start_id) else {
+            debug!(%poll_start_id, "Timeline item not found, can't update poll response");
+            return;
+        };
+        let TimelineItemContent::Poll(poll) = &item.content else {
+            warn!(%poll_start_id, "Timeline item is not a poll, can't update poll response");
+            return;
+        };
+
+        let poll = poll.with_local_response_sent(txn_id, event_id);
+        let item = item.with_content(TimelineItemContent::Poll(poll));
+        set_event_item(&mut lock, idx, item);
+    }
+
+    /// Drop the responses and end events of polls whose start event was not
+    /// found, once the start of the room was reached.
+    pub(super) async fn clear_poll_pending_events(&self) {
+        self.metadata.lock().await.poll_pending_events.clear();
+    }
+
+    /// Revert the local edit with the given transaction ID of the item with
+    /// the given event ID, because it couldn't be sent.
+    ///
+    /// Nothing is reverted if the item was edited again since, locally or
+    /// remotely, so as not to bring back outdated content.
+    pub(super) async fn revert_local_edit(&self, event_id: &EventId, txn_id: &TransactionId) {
+        let mut metadata_lock = self.metadata.lock().await;
+        let Some(content) = take_local_edit(&mut metadata_lock, event_id, txn_id) else {
+            debug!(%event_id, %txn_id, "Item was edited again, not reverting edit");
+            return;
+        };
+
+        let mut lock = self.items.lock_mut();
+        let Some((idx, item)) = find_event_by_id(&lock, event_id) else {
+            debug!(%event_id, "Timeline item not found, can't revert edit");
+            return;
+        };
+
+        let item = item.with_content(content);
+        set_event_item(&mut lock, idx, item);
+    }
+
+    /// Forget the content to revert to for the local edit with the given
+    /// transaction ID, once it was sent successfully.
+    pub(super) async fn forget_local_edit(&self, event_id: &EventId, txn_id: &TransactionId) {
+        let mut metadata_lock = self.metadata.lock().await;
+        take_local_edit(&mut metadata_lock, event_id, txn_id);
+    }
+
+    /// Get the key of the user's own reaction with the given annotation, if
+    /// any.
+    pub(super) async fn own_reaction(
+        &self,
+        annotation: &Annotation,
+        own_user_id: &UserId,
+    ) -> Option<TimelineKey> {
+        let metadata_lock = self.metadata.lock().await;
+        metadata_lock.reaction_map.iter().find_map(|(key, (sender, rel))| {
+            (sender == own_user_id
+                && rel.event_id == annotation.event_id
+                && rel.key == annotation.key)
+                .then(|| key.clone())
+        })
+    }
+
+    pub(super) async fn add_reaction(
+        &self,
+        key: TimelineKey,
+        sender: &UserId,
+        annotation: Annotation,
+    ) {
+        let mut metadata_lock = self.metadata.lock().await;
+        let mut items_lock = self.items.lock_mut();
+        add_reaction(
+            &mut items_lock,
+            &mut metadata_lock.reaction_map,
+            key,
+            sender.to_owned(),
+            annotation,
+        );
+    }
+
+    pub(super) async fn remove_reaction(&self, key: &TimelineKey) {
+        let mut metadata_lock = self.metadata.lock().await;
+        let mut items_lock = self.items.lock_mut();
+        remove_reaction(&mut items_lock, &mut metadata_lock.reaction_map, key);
+    }
+
+    /// Remember the event ID of a local reaction that was sent successfully,
+    /// so it can be redacted before its remote echo is received.
+    pub(super) async fn set_reaction_event_id(
+        &self,
+        txn_id: &TransactionId,
+        event_id: OwnedEventId,
+    ) {
+        let mut metadata_lock = self.metadata.lock().await;
+        let reaction_map = &mut metadata_lock.reaction_map;
+
+        // If the remote echo was received already, the reaction is keyed by
+        // its event ID already.
+        if let Some(reaction) = reaction_map.remove(&TimelineKey::TransactionId(txn_id.to_owned()))
+        {
+            reaction_map.insert(TimelineKey::EventId(event_id), reaction);
+        }
+    }
+
+    /// Set the users ignored by the current user, and remove the items of the
+    /// non-state events they sent.
+    pub(super) async fn set_ignored_users(&self, ignored_users: Vec<OwnedUserId>) {
+        let mut metadata_lock = self.metadata.lock().await;
+        let mut items_lock = self.items.lock_mut();
+        items_lock.retain(|item| {
+            item.as_event().map_or(true, |event| {
+                event.content().is_state() || !ignored_users.iter().any(|u| **u == *event.sender())
+            })
+        });
+
+        if remove_orphaned_virtual_items(&mut items_lock, &metadata_lock.settings) {
+            metadata_lock.fully_read_event_in_timeline = false;
+        }
+        metadata_lock.ignored_users = ignored_users;
+    }
+
+    /// Add a back-paginated event to the start of the timeline, with the
+    /// profile its sender had at the time of the event.
     pub(super) async fn handle_back_paginated_event(
         &self,
         event: TimelineEvent,
+        sender_profile: TimelineDetails<Profile>,
         own_user_id: &UserId,
     ) {
         let mut metadata_lock = self.metadata.lock().await;
@@ -100,22 +406,574 @@ impl TimelineInner {
             own_user_id,
             event.encryption_info,
             TimelineItemPosition::Start,
+            Some(sender_profile),
             &mut self.items.lock_mut(),
             &mut metadata_lock,
         );
     }
 
-    pub(super) fn add_event_id(&self, txn_id: &TransactionId, event_id: OwnedEventId) {
+    pub(super) async fn handle_forward_paginated_event(
+        &self,
+        event: TimelineEvent,
+        own_user_id: &UserId,
+    ) {
+        let mut metadata_lock = self.metadata.lock().await;
+        handle_remote_event(
+            event.event.cast(),
+            own_user_id,
+            event.encryption_info,
+            TimelineItemPosition::End,
+            None,
+            &mut self.items.lock_mut(),
+            &mut metadata_lock,
+        );
+    }
+
+    /// Load the data from the store that is needed to add the given event to
+    /// the timeline: the profile of its sender and its read receipts.
+    pub(super) async fn load_event_data(
+        &self,
+        raw: &Raw<AnySyncTimelineEvent>,
+        room: &room::Common,
+    ) {
+        self.load_sender_profile(raw, room).await;
+        self.load_read_receipts([raw], TimelineItemPosition::End, room).await;
+    }
+
+    /// Update the profiles at the end of the timeline for the given event,
+    /// which is about to be added there.
+    ///
+    /// The profile of a sender is loaded from the store the first time they
+    /// are seen, and follows the `m.room.member` events of the timeline from
+    /// then on, so each event gets the profile its sender had at the time.
+    async fn load_sender_profile(&self, raw: &Raw<AnySyncTimelineEvent>, room: &room::Common) {
+        if let Ok(Some(event_type)) = raw.get_field::<String>("type") {
+            if event_type == "m.room.member" {
+                if let Ok(SyncRoomMemberEvent::Original(event)) = raw.deserialize_as() {
+                    self.handle_member_event(&event, room).await;
+                }
+            }
+        }
+
+        let Ok(Some(sender)) = raw.get_field::<OwnedUserId>("sender") else { return };
+        self.load_sender_profile_at_end(&sender, room).await;
+    }
+
+    /// Get the profile of the given user at the end of the timeline, loading
+    /// it from the store if it isn't known yet.
+    async fn load_sender_profile_at_end(
+        &self,
+        sender: &UserId,
+        room: &room::Common,
+    ) -> Option<Profile> {
+        if let Some(profile) = self.metadata.lock().await.sender_profiles.get(sender) {
+            return profile.clone();
+        }
+
+        let profile = match room.get_member_no_sync(sender).await {
+            Ok(member) => member.map(|member| Profile::from(&member)),
+            Err(error) => {
+                warn!(?error, %sender, "Failed to load the sender's membership from the store");
+                return None;
+            }
+        };
+
+        let mut metadata_lock = self.metadata.lock().await;
+        if profile.is_none() {
+            metadata_lock.sender_profile_missing = true;
+        }
+        metadata_lock.store_sender_profiles.insert(sender.to_owned());
+        metadata_lock.sender_profiles.insert(sender.to_owned(), profile.clone());
+
+        profile
+    }
+
+    /// Update the profile of a member at the end of the timeline with their
+    /// `m.room.member` event.
+    async fn handle_member_event(&self, event: &OriginalSyncRoomMemberEvent, room: &room::Common) {
+        let profile = member_profile(room, &event.content).await;
+        let prev_profile = match &event.unsigned.prev_content {
+            Some(prev_content) => Some(member_profile(room, prev_content).await),
+            None => None,
+        };
+
+        let mut metadata_lock = self.metadata.lock().await;
+        let metadata = &mut *metadata_lock;
+        let user_id = &event.state_key;
+
+        if metadata.store_sender_profiles.remove(user_id) {
+            // The profile was loaded from the store, which already includes this
+            // event if it was received in the same sync response. The events
+            // since the previous `m.room.member` event were sent with the
+            // profile from before this one.
+            let sender_profile =
+                prev_profile.clone().map_or(TimelineDetails::Unavailable, TimelineDetails::Ready);
+            set_sender_profile_since_member_event(
+                &mut self.items.lock_mut(),
+                user_id,
+                sender_profile,
+            );
+        }
+
+        // Other members that have the previous or the new display name may
+        // have become ambiguous, or stopped being so.
+        let display_names = [
+            event.content.displayname.as_deref(),
+            prev_profile.as_ref().and_then(|profile| profile.display_name.as_deref()),
+        ];
+        let store_sender_profiles = &mut metadata.store_sender_profiles;
+        metadata.sender_profiles.retain(|other_user_id, other_profile| {
+            let display_name = other_profile.as_ref().and_then(|p| p.display_name.as_deref());
+            let keep = display_name.map_or(true, |name| !display_names.contains(&Some(name)));
+            if !keep {
+                store_sender_profiles.remove(other_user_id);
+            }
+            keep
+        });
+
+        metadata.sender_profiles.insert(user_id.clone(), Some(profile));
+    }
+
+    /// Get the profile of the sender of each of the given back-paginated
+    /// events, which are in reverse chronological order, at the time of the
+    /// event.
+    ///
+    /// `state` holds the profiles of room members as of the first, most
+    /// recent, event. The earlier profiles are found by going back through
+    /// the `m.room.member` events. Senders that are not part of `state` get
+    /// their current profile from the store.
+    pub(super) async fn paginated_sender_profiles<'a>(
+        &self,
+        events: impl IntoIterator<Item = &'a Raw<AnySyncTimelineEvent>>,
+        state: HashMap<OwnedUserId, Profile>,
+        room: &room::Common,
+    ) -> Vec<TimelineDetails<Profile>> {
+        let mut profiles: HashMap<_, _> =
+            state.into_iter().map(|(user_id, profile)| (user_id, Some(profile))).collect();
+        let mut sender_profiles = Vec::new();
+
+        for raw in events {
+            let profile = match raw.get_field::<OwnedUserId>("sender") {
+                Ok(Some(sender)) => match profiles.get(&sender) {
+                    Some(profile) => profile.clone(),
+                    None => {
+                        let profile = match room.get_member_no_sync(&sender).await {
+                            Ok(member) => member.map(|member| Profile::from(&member)),
+                            Err(error) => {
+                                warn!(?error, %sender, "Failed to load the sender's membership");
+                                None
+                            }
+                        };
+                        if profile.is_none() {
+                            self.metadata.lock().await.sender_profile_missing = true;
+                        }
+                        profiles.insert(sender, profile.clone());
+                        profile
+                    }
+                },
+                _ => None,
+            };
+            sender_profiles
+                .push(profile.map_or(TimelineDetails::Unavailable, TimelineDetails::Ready));
+
+            // Before their `m.room.member` event, a member had the profile of
+            // its previous content, if any.
+            let Ok(Some(event_type)) = raw.get_field::<String>("type") else { continue };
+            if event_type != "m.room.member" {
+                continue;
+            }
+            let Ok(SyncRoomMemberEvent::Original(event)) = raw.deserialize_as() else { continue };
+            let prev_profile = match &event.unsigned.prev_content {
+                Some(prev_content) => Some(member_profile(room, prev_content).await),
+                None => None,
+            };
+            profiles.insert(event.state_key, prev_profile);
+        }
+
+        sender_profiles
+    }
+
+    /// Fetch the members of the room in the background if the profile of a
+    /// sender couldn't be found in the store, which happens when room members
+    /// are lazy-loaded.
+    pub(super) async fn fetch_members_if_needed(self: &Arc<Self>, room: &room::Common) {
+        {
+            let mut metadata_lock = self.metadata.lock().await;
+            if !metadata_lock.sender_profile_missing || room.are_members_synced() {
+                return;
+            }
+            metadata_lock.sender_profile_missing = false;
+        }
+
+        let inner = self.clone();
+        let room = room.clone();
+        matrix_sdk_common::executor::spawn(async move {
+            // The items are only updated if the members could be fetched, it
+            // can be retried with `Timeline::fetch_members` otherwise.
+            match room.sync_members().await {
+                Ok(_) => inner.fetch_sender_profiles(&room).await,
+                Err(error) => warn!(?error, "Failed to fetch the members of the room"),
+            }
+        });
+    }
+
+    /// Load the read receipts of the given events from the store, before they
+    /// are added to the timeline at the given position.
+    ///
+    /// The events must be in the order in which they are added.
+    pub(super) async fn load_read_receipts<'a>(
+        &self,
+        events: impl IntoIterator<Item = &'a Raw<AnySyncTimelineEvent>>,
+        position: TimelineItemPosition,
+        room: &room::Common,
+    ) {
+        let mut event_ids: Vec<OwnedEventId> = events
+            .into_iter()
+            .filter_map(|raw| raw.get_field::<OwnedEventId>("event_id").ok().flatten())
+            .collect();
+        if event_ids.is_empty() {
+            return;
+        }
+
+        // Order the events from the oldest to the most recent.
+        if matches!(position, TimelineItemPosition::Start) {
+            event_ids.reverse();
+        }
+
+        let store = room.client.store();
+        let own_user_id = room.own_user_id();
+        let thread_root = self.metadata.lock().await.thread_root.clone();
+
+        // Only the latest receipt of each user is kept, so if a user has
+        // receipts for several of the events, the one of the most recent
+        // event wins.
+        let mut receipts = HashMap::new();
+        for event_id in &event_ids {
+            let event_receipts = match store
+                .get_event_room_receipt_events(room.room_id(), ReceiptType::Read, event_id)
+                .await
+            {
+                Ok(receipts) => receipts,
+                Err(error) => {
+                    warn!(?error, %event_id, "Failed to load read receipts from the store");
+                    continue;
+                }
+            };
+
+            for (user_id, receipt) in event_receipts {
+                if is_timeline_receipt(
+                    &ReceiptType::Read,
+                    &user_id,
+                    &receipt,
+                    own_user_id,
+                    thread_root.as_deref(),
+                ) {
+                    receipts.insert(user_id, (event_id.clone(), receipt));
+                }
+            }
+        }
+
+        // Private receipts are only visible for the own user, so that single
+        // receipt is loaded instead of looking it up for every event.
+        match store
+            .get_user_room_receipt_event(room.room_id(), ReceiptType::ReadPrivate, own_user_id)
+            .await
+        {
+            Ok(Some((event_id, receipt)))
+                if receipt_applies(&receipt.thread, thread_root.as_deref()) =>
+            {
+                let chunk_idx = |id: &EventId| event_ids.iter().position(|e| e == id);
+                if let Some(idx) = chunk_idx(&event_id) {
+                    let is_more_recent = match receipts.get(own_user_id) {
+                        Some((public_event_id, _)) => chunk_idx(public_event_id) < Some(idx),
+                        None => true,
+                    };
+                    if is_more_recent {
+                        receipts.insert(own_user_id.to_owned(), (event_id, receipt));
+                    }
+                }
+            }
+            Ok(_) => {}
+            Err(error) => {
+                warn!(?error, "Failed to load the private read receipt from the store");
+            }
+        }
+
+        let mut metadata_lock = self.metadata.lock().await;
+        let metadata = &mut *metadata_lock;
+        let mut items_lock = self.items.lock_mut();
+
+        for (user_id, (event_id, receipt)) in receipts {
+            update_read_receipt(
+                &mut items_lock,
+                &mut metadata.read_receipts,
+                user_id,
+                event_id,
+                receipt,
+                Some(&position),
+            );
+        }
+    }
+
+    /// Move the read receipts of the timeline's items according to a new
+    /// `m.receipt` event.
+    pub(super) async fn handle_read_receipts(
+        &self,
+        content: ReceiptEventContent,
+        own_user_id: &UserId,
+    ) {
+        let mut metadata_lock = self.metadata.lock().await;
+        let metadata = &mut *metadata_lock;
+        let mut items_lock = self.items.lock_mut();
+
+        for (event_id, receipts) in content.0 {
+            for (receipt_type, receipts) in receipts {
+                for (user_id, receipt) in receipts {
+                    if is_timeline_receipt(
+                        &receipt_type,
+                        &user_id,
+                        &receipt,
+                        own_user_id,
+                        metadata.thread_root.as_deref(),
+                    ) {
+                        update_read_receipt(
+                            &mut items_lock,
+                            &mut metadata.read_receipts,
+                            user_id,
+                            event_id.clone(),
+                            receipt,
+                            None,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Fetch the profiles of the senders of items whose profile isn't
+    /// available, requesting the members of the room from the server if
+    /// necessary.
+    pub(super) async fn fetch_sender_profiles(&self, room: &room::Common) {
+        let senders: BTreeSet<_> = event_items(&self.items.lock_ref())
+            .filter_map(|(_, event_item)| {
+                matches!(event_item.sender_profile, TimelineDetails::Unavailable)
+                    .then(|| event_item.sender.clone())
+            })
+            .collect();
+
+        if senders.is_empty() {
+            return;
+        }
+
+        let pending = senders.iter().map(|sender| (sender.clone(), TimelineDetails::Pending));
+        self.set_sender_profiles(&pending.collect());
+
+        let mut sender_profiles = HashMap::new();
+        for sender in senders {
+            // Only the first call requests the members from the server.
+            let sender_profile = match room.get_member(&sender).await {
+                Ok(Some(member)) => {
+                    let profile = Profile::from(&member);
+                    let mut metadata_lock = self.metadata.lock().await;
+                    metadata_lock.store_sender_profiles.insert(sender.clone());
+                    metadata_lock.sender_profiles.insert(sender.clone(), Some(profile.clone()));
+                    TimelineDetails::Ready(profile)
+                }
+                Ok(None) => {
+                    debug!(%sender, "Sender is not a member of the room");
+                    TimelineDetails::Unavailable
+                }
+                Err(e) => {
+                    warn!(%sender, "Failed to fetch the sender's membership: {e}");
+                    TimelineDetails::Error(Arc::new(e))
+                }
+            };
+
+            sender_profiles.insert(sender, sender_profile);
+        }
+
+        // Members that were missing from the store may be known now.
+        self.metadata.lock().await.sender_profiles.retain(|_, profile| profile.is_some());
+        self.set_sender_profiles(&sender_profiles);
+    }
+
+    /// Set the profile of the items of the given senders whose profile isn't
+    /// known yet.
+    fn set_sender_profiles(
+        &self,
+        sender_profiles: &HashMap<OwnedUserId, TimelineDetails<Profile>>,
+    ) {
+        let mut lock = self.items.lock_mut();
+
+        // Events within collapsed membership changes are updated too.
+        let new_items: Vec<_> = event_items(&lock)
+            .filter(|(_, event_item)| {
+                matches!(
+                    event_item.sender_profile,
+                    TimelineDetails::Unavailable | TimelineDetails::Pending
+                )
+            })
+            .filter_map(|(idx, event_item)| {
+                let sender_profile = sender_profiles.get(&event_item.sender)?;
+                Some((idx, event_item.with_sender_profile(sender_profile.clone())))
+            })
+            .collect();
+
+        for (idx, item) in new_items {
+            set_event_item(&mut lock, idx, item);
+        }
+    }
+
+    /// Keep the live events that are received from now on while the timeline
+    /// is detached, dropping the ones that were kept before.
+    pub(super) async fn keep_detached_live_events(&self) {
+        self.metadata.lock().await.detached_live_events = Some(Vec::new());
+    }
+
+    /// Start adding live events to the timeline.
+    ///
+    /// The live events that were kept since the last call to
+    /// [`keep_detached_live_events`](Self::keep_detached_live_events) are
+    /// added, except the ones with an ID in `paginated_event_ids`.
+    pub(super) async fn attach(
+        &self,
+        paginated_event_ids: &BTreeSet<OwnedEventId>,
+        own_user_id: &UserId,
+    ) {
+        let mut timeline_meta = self.metadata.lock().await;
+        timeline_meta.detached = false;
+
+        let events = timeline_meta.detached_live_events.take().unwrap_or_default();
+        for event in events {
+            if event.event_id().map_or(false, |id| paginated_event_ids.contains(&id)) {
+                continue;
+            }
+
+            handle_remote_event(
+                event.event,
+                own_user_id,
+                event.encryption_info,
+                TimelineItemPosition::End,
+                None,
+                &mut self.items.lock_mut(),
+                &mut timeline_meta,
+            );
+        }
+    }
+
+    pub(super) async fn update_event_send_state(
+        &self,
+        txn_id: &TransactionId,
+        send_state: EventSendState,
+    ) {
+        let mut metadata_lock = self.metadata.lock().await;
+        if let EventSendState::Sent { .. } = send_state {
+            // The event won't be sent again, so there is no need to keep its
+            // content around.
+            metadata_lock.local_echo_contents.remove(txn_id);
+        }
+
         let mut lock = self.items.lock_mut();
-        if let Some((idx, item)) = find_event_by_txn_id(&lock, txn_id) {
-            if item.event_id.as_ref().map_or(false, |ev_id| *ev_id != event_id) {
+        let Some((idx, item)) = find_event_by_txn_id(&lock, txn_id) else {
+            warn!(%txn_id, "Timeline item not found, can't update send state");
+            return;
+        };
+
+        if let EventSendState::Sent { event_id } = &send_state {
+            if item.event_id().map_or(false, |ev_id| ev_id != event_id) {
                 error!("remote echo and send-event response disagree on the event ID");
             }
+        }
+
+        let new_item = item.with_send_state(Some(send_state));
+        set_event_item(&mut lock, idx, new_item);
+    }
+
+    /// Mark the local echo with the given transaction ID as being sent again,
+    /// and return its content.
+    pub(super) async fn prepare_retry(
+        &self,
+        txn_id: &TransactionId,
+    ) -> Result<AnyMessageLikeEventContent, Error> {
+        let metadata_lock = self.metadata.lock().await;
+        let mut lock = self.items.lock_mut();
+
+        let (idx, item) = find_event_by_txn_id(&lock, txn_id).ok_or(Error::TransactionNotFound)?;
+        let Some(EventSendState::SendingFailed { .. }) = item.send_state else {
+            return Err(Error::InvalidSendState);
+        };
+        let content =
+            metadata_lock.local_echo_contents.get(txn_id).ok_or(Error::TransactionNotFound)?;
+
+        let item = item.with_send_state(Some(EventSendState::NotSentYet));
+        set_event_item(&mut lock, idx, item);
+
+        Ok(content.clone())
+    }
+
+    /// Remove the local echo with the given transaction ID from the timeline.
+    pub(super) async fn discard_local_echo(&self, txn_id: &TransactionId) -> Result<(), Error> {
+        let mut metadata_lock = self.metadata.lock().await;
+        let mut lock = self.items.lock_mut();
+
+        let (idx, item) = find_event_by_txn_id(&lock, txn_id).ok_or(Error::TransactionNotFound)?;
+        let Some(EventSendState::SendingFailed { .. }) = item.send_state else {
+            return Err(Error::InvalidSendState);
+        };
+
+        lock.remove(idx);
+        if remove_orphaned_virtual_items(&mut lock, &metadata_lock.settings) {
+            metadata_lock.fully_read_event_in_timeline = false;
+        }
+        metadata_lock.local_echo_contents.remove(txn_id);
+
+        Ok(())
+    }
+
+    /// Mark the reply details of the message with the given event ID as
+    /// pending, and return the ID of the replied-to event.
+    ///
+    /// Returns `Ok(None)` if the event is not a reply, or if its reply details
+    /// are already available or being fetched.
+    pub(super) fn set_reply_details_pending(
+        &self,
+        event_id: &EventId,
+    ) -> Result<Option<OwnedEventId>, Error> {
+        let mut lock = self.items.lock_mut();
 
-            lock.set_cloned(idx, Arc::new(TimelineItem::Event(item.with_event_id(Some(event_id)))));
-        } else {
-            warn!(%txn_id, "Timeline item not found, can't add event ID");
+        let (idx, item) =
+            find_event_by_id(&lock, event_id).ok_or(Error::RemoteEventNotInTimeline)?;
+        let Some(msg) = item.content.as_message() else { return Ok(None) };
+        let Some(in_reply_to) = &msg.in_reply_to else { return Ok(None) };
+        if let TimelineDetails::Ready(_) | TimelineDetails::Pending = in_reply_to.details {
+            return Ok(None);
         }
+
+        let replied_to_id = in_reply_to.event_id.clone();
+        let msg = msg.with_in_reply_to_details(TimelineDetails::Pending);
+        let item = item.with_content(TimelineItemContent::Message(msg));
+        set_event_item(&mut lock, idx, item);
+
+        Ok(Some(replied_to_id))
+    }
+
+    /// Set the reply details of the message with the given event ID.
+    pub(super) fn set_reply_details(
+        &self,
+        event_id: &EventId,
+        details: TimelineDetails<Box<RepliedToEvent>>,
+    ) {
+        let mut lock = self.items.lock_mut();
+
+        let Some((idx, item)) = find_event_by_id(&lock, event_id) else {
+            debug!(%event_id, "Timeline item not found, discarding reply details");
+            return;
+        };
+        let Some(msg) = item.content.as_message() else { return };
+
+        let msg = msg.with_in_reply_to_details(details);
+        let item = item.with_content(TimelineItemContent::Message(msg));
+        set_event_item(&mut lock, idx, item);
     }
 
     pub(super) async fn handle_fully_read(&self, raw: Raw<FullyReadEvent>) {
@@ -158,12 +1016,14 @@ impl TimelineInner {
     ) {
         use super::EncryptedMessage;
 
+        // Lock the metadata first, so no other event is handled until the
+        // decrypted events are added.
+        let mut metadata_lock = self.metadata.lock().await;
         let utds_for_session: Vec<_> = self
             .items
             .lock_ref()
             .iter()
-            .enumerate()
-            .filter_map(|(idx, item)| {
+            .filter_map(|item| {
                 let event_item = &item.as_event()?;
                 let utd = event_item.content.as_unable_to_decrypt()?;
 
@@ -180,7 +1040,7 @@ impl TimelineInner {
                             return None;
                         };
 
-                        Some((idx, event_id.to_owned(), session_id.to_owned(), raw))
+                        Some((event_id.to_owned(), session_id.to_owned(), raw))
                     }
                     EncryptedMessage::MegolmV1AesSha2 { .. }
                     | EncryptedMessage::OlmV1Curve25519AesSha2 { .. }
@@ -189,12 +1049,7 @@ impl TimelineInner {
             })
             .collect();
 
-        if utds_for_session.is_empty() {
-            return;
-        }
-
-        let mut metadata_lock = self.metadata.lock().await;
-        for (idx, event_id, session_id, utd) in utds_for_session.iter().rev() {
+        for (event_id, session_id, utd) in utds_for_session.iter().rev() {
             let event = match olm_machine.decrypt_room_event(utd.cast_ref(), room_id).await {
                 Ok(ev) => ev,
                 Err(e) => {
@@ -212,11 +1067,24 @@ impl TimelineInner {
             // this mutex every iteration because holding it across `.await`
             // makes the future `!Send`, which makes it not event-handler-safe.
             let mut items_lock = self.items.lock_mut();
+
+            // Items can still be updated without locking the metadata, so the
+            // item is looked up again after decrypting the event.
+            let Some((idx, item)) = find_event_by_id(&items_lock, event_id) else {
+                debug!(%event_id, "Decrypted event is not in the timeline anymore");
+                continue;
+            };
+            if item.content.as_unable_to_decrypt().is_none() {
+                debug!(%event_id, "Decrypted event was already updated in the timeline");
+                continue;
+            }
+
             handle_remote_event(
                 event.event.cast(),
                 own_user_id,
                 event.encryption_info,
-                TimelineItemPosition::Update(*idx),
+                TimelineItemPosition::Update(idx),
+                None,
                 &mut items_lock,
                 &mut metadata_lock,
             );
@@ -224,11 +1092,17 @@ impl TimelineInner {
     }
 }
 
+/// Handle a remote event.
+///
+/// `sender_profile` is the profile of the sender at the time of the event, if
+/// it is known already. Otherwise, it is the profile at the end of the timeline
+/// or the one of the item that is updated.
 fn handle_remote_event(
     raw: Raw<AnySyncTimelineEvent>,
     own_user_id: &UserId,
     encryption_info: Option<EncryptionInfo>,
     position: TimelineItemPosition,
+    sender_profile: Option<TimelineDetails<Profile>>,
     timeline_items: &mut MutableVecLockMut<'_, Arc<TimelineItem>>,
     timeline_meta: &mut TimelineInnerMetadata,
 ) {
@@ -240,7 +1114,7 @@ fn handle_remote_event(
                 event.origin_server_ts(),
                 event.transaction_id().map(ToOwned::to_owned),
                 event.relations().cloned(),
-                event.into(),
+                TimelineEventKind::from_event(event, &raw),
             ),
             Err(e) => match raw.deserialize_as::<SyncTimelineEventWithoutContent>() {
                 Ok(event) => (
@@ -258,10 +1132,164 @@ fn handle_remote_event(
             },
         };
 
+    // State events still apply to the room, so they are shown even when
+    // their sender is ignored.
+    let is_state = matches!(
+        event_kind,
+        TimelineEventKind::State { .. }
+            | TimelineEventKind::RedactedState
+            | TimelineEventKind::FailedToParseState { .. }
+    );
+    if !is_state && timeline_meta.ignored_users.contains(&sender) {
+        debug!(%event_id, %sender, "Ignoring event from ignored user");
+        return;
+    }
+
+    let sender_profile = match (sender_profile, position) {
+        (Some(sender_profile), _) => sender_profile,
+        (None, TimelineItemPosition::Start | TimelineItemPosition::End) => {
+            sender_profile_at_end(timeline_meta, &sender)
+        }
+        // Keep the profile of the item that is updated.
+        #[cfg(feature = "e2e-encryption")]
+        (None, TimelineItemPosition::Update(idx)) => timeline_items[idx]
+            .as_event()
+            .map_or(TimelineDetails::Unavailable, |item| item.sender_profile.clone()),
+    };
+
     let is_own_event = sender == own_user_id;
-    let event_meta = TimelineEventMetadata { sender, is_own_event, relations, encryption_info };
+    let thread_root = thread_root(&raw);
+    let event_meta = TimelineEventMetadata {
+        sender,
+        sender_profile,
+        is_own_event,
+        relations,
+        encryption_info,
+        thread_root,
+    };
     let flow = Flow::Remote { event_id, origin_server_ts, raw_event: raw, txn_id, position };
 
     TimelineEventHandler::new(event_meta, flow, timeline_items, timeline_meta)
         .handle_event(event_kind)
 }
+
+/// Get the profile of the sender of an event at the end of the timeline, if it
+/// is known already.
+fn sender_profile_at_end(
+    timeline_meta: &TimelineInnerMetadata,
+    sender: &UserId,
+) -> TimelineDetails<Profile> {
+    match timeline_meta.sender_profiles.get(sender) {
+        Some(Some(profile)) => TimelineDetails::Ready(profile.clone()),
+        _ => TimelineDetails::Unavailable,
+    }
+}
+
+/// Take the content to revert to for the local edit with the given transaction
+/// ID, if it is still the latest edit of the item with the given event ID.
+fn take_local_edit(
+    timeline_meta: &mut TimelineInnerMetadata,
+    event_id: &EventId,
+    txn_id: &TransactionId,
+) -> Option<TimelineItemContent> {
+    match timeline_meta.local_edits.get(event_id) {
+        Some((edit_txn_id, _)) if edit_txn_id == txn_id => {
+            timeline_meta.local_edits.remove(event_id).map(|(_, content)| content)
+        }
+        _ => None,
+    }
+}
+
+/// Get the profile of a room member from the content of their `m.room.member`
+/// event.
+///
+/// Whether the display name is ambiguous is determined from the current state
+/// of the room.
+pub(super) async fn member_profile(
+    room: &room::Common,
+    content: &RoomMemberEventContent,
+) -> Profile {
+    let display_name_ambiguous = match &content.displayname {
+        Some(display_name) => room
+            .client
+            .store()
+            .get_users_with_display_name(room.room_id(), display_name)
+            .await
+            .map_or(false, |users| users.len() > 1),
+        None => false,
+    };
+
+    Profile {
+        display_name: content.displayname.clone(),
+        display_name_ambiguous,
+        avatar_url: content.avatar_url.clone(),
+    }
+}
+
+/// Set the profile of the items of the given user that were added after their
+/// last `m.room.member` event.
+fn set_sender_profile_since_member_event(
+    timeline_items: &mut MutableVecLockMut<'_, Arc<TimelineItem>>,
+    user_id: &UserId,
+    sender_profile: TimelineDetails<Profile>,
+) {
+    let is_member_event = |item: &EventTimelineItem| match &item.content {
+        TimelineItemContent::MembershipChange(change) => change.user_id() == user_id,
+        TimelineItemContent::ProfileChange(change) => change.user_id() == user_id,
+        _ => false,
+    };
+
+    for idx in (0..timeline_items.len()).rev() {
+        match &*timeline_items[idx] {
+            TimelineItem::Event(item) => {
+                if is_member_event(item) {
+                    break;
+                }
+                if *item.sender == *user_id {
+                    let item = item.with_sender_profile(sender_profile.clone());
+                    set_event_item(timeline_items, idx, item);
+                }
+            }
+            TimelineItem::Virtual(VirtualTimelineItem::MembershipChanges(group)) => {
+                if group.iter().any(is_member_event) {
+                    break;
+                }
+                if group.iter().any(|item| *item.sender == *user_id) {
+                    let group = group
+                        .iter()
+                        .map(|item| {
+                            if *item.sender == *user_id {
+                                item.with_sender_profile(sender_profile.clone())
+                            } else {
+                                item.clone()
+                            }
+                        })
+                        .collect();
+                    let group = VirtualTimelineItem::MembershipChanges(group);
+                    timeline_items.set_cloned(idx, Arc::new(TimelineItem::Virtual(group)));
+                }
+            }
+            TimelineItem::Virtual(_) => {}
+        }
+    }
+}
+
+/// Whether the given read receipt is shown in the timeline of the thread with
+/// the given root, or in the main timeline if it is `None`.
+fn is_timeline_receipt(
+    receipt_type: &ReceiptType,
+    user_id: &UserId,
+    receipt: &Receipt,
+    own_user_id: &UserId,
+    thread_root: Option<&EventId>,
+) -> bool {
+    let visible = match receipt_type {
+        ReceiptType::Read => true,
+        // Private read receipts are only ever sent to their own user, but
+        // better be safe.
+        ReceiptType::ReadPrivate => user_id == own_user_id,
+        _ => false,
+    };
+
+    visible && receipt_applies(&receipt.thread, thread_root)
+}
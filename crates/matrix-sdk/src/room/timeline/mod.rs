@@ -17,19 +17,41 @@
 //! See [`Timeline`] for details.
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap},
+    fmt,
     sync::{Arc, Mutex as StdMutex},
 };
 
+use dashmap::DashMap;
 use futures_core::Stream;
-use futures_signals::signal_vec::{SignalVec, SignalVecExt, VecDiff};
-use matrix_sdk_base::deserialized_responses::{EncryptionInfo, SyncTimelineEvent};
+use futures_signals::{
+    signal::SignalExt,
+    signal_vec::{MutableVecLockMut, SignalVec, SignalVecExt, VecDiff},
+};
+use futures_util::future::{abortable, AbortHandle};
+use matrix_sdk_base::{
+    deserialized_responses::{EncryptionInfo, SyncTimelineEvent, TimelineEvent},
+    locks::Mutex,
+    store::{EventChunk, StateStoreExt},
+};
 use ruma::{
+    api::client::filter::{LazyLoadOptions, RoomEventFilter},
     assign,
-    events::{fully_read::FullyReadEventContent, relation::Annotation, AnyMessageLikeEventContent},
-    EventId, OwnedEventId, OwnedUserId, TransactionId, UInt,
+    events::{
+        fully_read::FullyReadEventContent,
+        poll::response::{PollResponseEventContent, SelectionsContent},
+        reaction::ReactionEventContent,
+        receipt::{Receipt, ReceiptThread, SyncReceiptEvent},
+        relation::{Annotation, Replacement, Thread},
+        room::message::{self, MessageType, RoomMessageEventContent},
+        AnyMessageLikeEventContent, AnyStateEvent, AnySyncTimelineEvent,
+    },
+    serde::Raw,
+    EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedTransactionId, OwnedUserId,
+    TransactionId, UInt,
 };
-use tracing::{error, instrument};
+use thiserror::Error;
+use tracing::{debug, error, instrument, warn};
 
 use super::{Joined, Room};
 use crate::{
@@ -38,21 +60,31 @@ use crate::{
     Result,
 };
 
+mod builder;
 mod event_handler;
 mod event_item;
 mod inner;
+mod polls;
 #[cfg(test)]
 mod tests;
 mod virtual_item;
 
-use self::inner::TimelineInner;
 pub use self::{
+    builder::TimelineBuilder,
     event_item::{
-        EncryptedMessage, EventTimelineItem, Message, PaginationOutcome, ReactionDetails,
+        Change, EncryptedMessage, EventSendState, EventTimelineItem, InReplyToDetails,
+        MemberProfileChange, MembershipChange, Message, OtherState, PaginationOutcome, Profile,
+        ReactionDetails, RepliedToEvent, RoomMembershipChange, ThreadReply, ThreadSummary,
         TimelineDetails, TimelineItemContent, TimelineKey,
     },
+    polls::PollState,
     virtual_item::VirtualTimelineItem,
 };
+use self::{
+    event_handler::{thread_root, TimelineItemPosition},
+    inner::{member_profile, TimelineInner},
+    polls::PollPendingEvents,
+};
 
 /// A high-level view into a regular¹ room's contents.
 ///
@@ -63,12 +95,50 @@ pub use self::{
 pub struct Timeline {
     inner: Arc<TimelineInner>,
     room: room::Common,
+    thread_root: Option<OwnedEventId>,
     start_token: StdMutex<Option<String>>,
-    _end_token: StdMutex<Option<String>>,
+    end_token: StdMutex<Option<String>>,
+    // The room backwards pagination happens in, which is a predecessor of
+    // `room` once its start was reached
+    pagination_room: StdMutex<room::Common>,
+    // The oldest chunk of the room's event cache that was loaded into the
+    // timeline, if any
+    cached_chunk: StdMutex<Option<CachedChunk>>,
+    // Locks that serialize toggling the same reaction, by the ID of the
+    // reacted-to event and the key of the reaction
+    reaction_toggle_locks: DashMap<(OwnedEventId, String), Arc<Mutex<()>>>,
     _timeline_event_handler_guard: EventHandlerDropGuard,
+    _read_receipts_handler_guard: EventHandlerDropGuard,
     _fully_read_handler_guard: Option<EventHandlerDropGuard>,
+    // Task that removes the events of users when they get ignored
+    ignored_users_task: AbortHandle,
+    // Task that retries decrypting events when room keys are received
     #[cfg(feature = "e2e-encryption")]
-    _room_key_handler_guard: EventHandlerDropGuard,
+    room_keys_task: Option<AbortHandle>,
+}
+
+/// The position of a [`Timeline`] in the event cache of its room.
+#[derive(Clone, Debug)]
+struct CachedChunk {
+    id: u64,
+    previous: Option<u64>,
+    has_gap: bool,
+}
+
+impl From<&EventChunk> for CachedChunk {
+    fn from(chunk: &EventChunk) -> Self {
+        Self { id: chunk.id, previous: chunk.previous, has_gap: chunk.has_gap }
+    }
+}
+
+/// Decrypt the events of a chunk of the event cache where possible, since they
+/// are cached in their encrypted form.
+async fn decrypt_cached_events(
+    room: &room::Common,
+    events: Vec<SyncTimelineEvent>,
+) -> Vec<SyncTimelineEvent> {
+    let events = events.into_iter().map(|event| event.event.cast()).collect();
+    room.try_decrypt_events(events).await.into_iter().map(Into::into).collect()
 }
 
 /// Non-signalling parts of `TimelineInner`.
@@ -76,23 +146,151 @@ pub struct Timeline {
 struct TimelineInnerMetadata {
     // Reaction event / txn ID => sender and reaction data
     reaction_map: HashMap<TimelineKey, (OwnedUserId, Annotation)>,
+    // Event ID of an edited item => txn ID of the local edit that is still
+    // being sent and the content to revert to if it fails
+    local_edits: HashMap<OwnedEventId, (OwnedTransactionId, TimelineItemContent)>,
+    // Replied-to event ID => keys of the items replying to it whose reply
+    // details are not available yet
+    pending_replies: HashMap<OwnedEventId, Vec<TimelineKey>>,
     fully_read_event: Option<OwnedEventId>,
     fully_read_event_in_timeline: bool,
+    // The root of the thread, if this is the timeline of a thread
+    thread_root: Option<OwnedEventId>,
+    // Whether the timeline doesn't reach the live end of the room, in which
+    // case live events are not added to it
+    detached: bool,
+    // Live events received while the timeline is detached and a forward
+    // pagination request is ongoing, if it is
+    detached_live_events: Option<Vec<SyncTimelineEvent>>,
+    // Content of local echoes that haven't been sent successfully yet, so they
+    // can be sent again
+    local_echo_contents: HashMap<OwnedTransactionId, AnyMessageLikeEventContent>,
+    // The latest read receipt of each user that applies to this timeline, and
+    // the event it points to
+    read_receipts: HashMap<OwnedUserId, (OwnedEventId, Receipt)>,
+    // Profiles of the senders of events at the end of the timeline, `None` for
+    // senders that are not known to be members of the room
+    sender_profiles: HashMap<OwnedUserId, Option<Profile>>,
+    // Senders whose profile in `sender_profiles` was loaded from the store,
+    // rather than from an `m.room.member` event of the timeline
+    store_sender_profiles: BTreeSet<OwnedUserId>,
+    // Whether the profile of a sender was missing from the store since the
+    // last time the members of the room were fetched
+    sender_profile_missing: bool,
+    // Responses and end events of polls whose start event is not part of the
+    // timeline (yet)
+    poll_pending_events: PollPendingEvents,
+    // Users ignored by the current user, whose events are left out
+    ignored_users: Vec<OwnedUserId>,
+    settings: TimelineInnerSettings,
 }
 
-impl Timeline {
-    pub(super) async fn new(room: &room::Common) -> Self {
-        Self::with_events(room, None, Vec::new())
+type EventFilterFn = dyn Fn(&Raw<AnySyncTimelineEvent>) -> bool + Send + Sync;
+type DateFormatFn = dyn Fn(MilliSecondsSinceUnixEpoch) -> String + Send + Sync;
+
+/// Options set through [`TimelineBuilder`].
+#[derive(Clone, Default)]
+struct TimelineInnerSettings {
+    event_filter: Option<Arc<EventFilterFn>>,
+    collapse_membership_changes: bool,
+    day_dividers: bool,
+    utc_offset: i32,
+    date_format: Option<Arc<DateFormatFn>>,
+}
+
+impl TimelineInnerSettings {
+    /// Get the number of days between the unix epoch and the day of the given
+    /// timestamp, in the configured timezone.
+    fn day_of(&self, ts: MilliSecondsSinceUnixEpoch) -> i64 {
+        let secs = i64::from(ts.get()) / 1000 + i64::from(self.utc_offset);
+        secs.div_euclid(86_400)
     }
 
+    fn day_divider(&self, timestamp: MilliSecondsSinceUnixEpoch) -> Arc<TimelineItem> {
+        let date = match &self.date_format {
+            Some(format) => format(timestamp),
+            None => format_date(self.day_of(timestamp)),
+        };
+
+        Arc::new(TimelineItem::Virtual(VirtualTimelineItem::DayDivider { timestamp, date }))
+    }
+}
+
+impl fmt::Debug for TimelineInnerSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimelineInnerSettings")
+            .field("event_filter", &self.event_filter.as_ref().map(|_| ".."))
+            .field("collapse_membership_changes", &self.collapse_membership_changes)
+            .field("day_dividers", &self.day_dividers)
+            .field("utc_offset", &self.utc_offset)
+            .field("date_format", &self.date_format.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+/// Format the day with the given number of days since the unix epoch as
+/// `YYYY-MM-DD`.
+fn format_date(days: i64) -> String {
+    // Howard Hinnant's `civil_from_days` algorithm.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+impl Timeline {
     pub(crate) fn with_events(
         room: &room::Common,
         prev_token: Option<String>,
         events: Vec<SyncTimelineEvent>,
     ) -> Self {
         let mut inner = TimelineInner::default();
+        inner.load_ignored_users(room);
         inner.add_initial_events(events, room.own_user_id());
 
+        Self::from_inner(room, inner, prev_token, None)
+    }
+
+    pub(super) fn for_thread(room: &room::Common, thread_root: &EventId) -> Self {
+        let mut inner = TimelineInner::for_thread(thread_root.to_owned());
+        inner.load_ignored_users(room);
+        Self::from_inner(room, inner, None, None)
+    }
+
+    /// Create a timeline that doesn't reach the live end of the room yet.
+    ///
+    /// Live events are only added once [`paginate_forwards`] catches up with
+    /// the live end.
+    ///
+    /// [`paginate_forwards`]: Self::paginate_forwards
+    pub(super) fn detached(
+        room: &room::Common,
+        prev_token: Option<String>,
+        next_token: String,
+        events: Vec<SyncTimelineEvent>,
+    ) -> Self {
+        let mut inner = TimelineInner::default();
+        inner.metadata.get_mut().detached = true;
+        inner.load_ignored_users(room);
+        inner.add_initial_events(events, room.own_user_id());
+
+        Self::from_inner(room, inner, prev_token, Some(next_token))
+    }
+
+    pub(super) fn from_inner(
+        room: &room::Common,
+        mut inner: TimelineInner,
+        prev_token: Option<String>,
+        next_token: Option<String>,
+    ) -> Self {
+        let thread_root = inner.metadata.get_mut().thread_root.clone();
         let inner = Arc::new(inner);
 
         let timeline_event_handle = room.add_event_handler({
@@ -100,68 +298,95 @@ impl Timeline {
             move |event, encryption_info: Option<EncryptionInfo>, room: Room| {
                 let inner = inner.clone();
                 async move {
+                    inner.load_event_data(&event, &room).await;
                     inner.handle_live_event(event, encryption_info, room.own_user_id()).await;
+                    inner.fetch_members_if_needed(&room).await;
                 }
             }
         });
         let _timeline_event_handler_guard =
             room.client.event_handler_drop_guard(timeline_event_handle);
 
-        // Not using room.add_event_handler here because RoomKey events are
-        // to-device events that are not received in the context of a room.
-        #[cfg(feature = "e2e-encryption")]
-        let room_id = room.room_id().to_owned();
-        #[cfg(feature = "e2e-encryption")]
-        let room_key_handle = room.client.add_event_handler({
-            use std::iter;
-
-            use ruma::events::room_key::ToDeviceRoomKeyEvent;
-
-            use crate::Client;
+        let read_receipts_handle = room.add_event_handler({
+            let inner = inner.clone();
+            move |event: SyncReceiptEvent, room: Room| {
+                let inner = inner.clone();
+                async move {
+                    inner.handle_read_receipts(event.content, room.own_user_id()).await;
+                }
+            }
+        });
+        let _read_receipts_handler_guard =
+            room.client.event_handler_drop_guard(read_receipts_handle);
 
+        let (task, ignored_users_task) = abortable({
             let inner = inner.clone();
-            move |event: ToDeviceRoomKeyEvent, client: Client| {
+            room.client.base_client().ignored_users().signal_cloned().for_each(move |users| {
                 let inner = inner.clone();
-                let room_id = room_id.clone();
                 async move {
-                    if event.content.room_id != room_id {
-                        return;
-                    }
+                    inner.set_ignored_users(users).await;
+                }
+            })
+        });
+        matrix_sdk_common::executor::spawn(task);
 
-                    let Some(olm_machine) = client.olm_machine() else {
-                        error!("The olm machine isn't yet available");
-                        return;
-                    };
+        // Retry decrypting events of this room whenever new room keys for it
+        // are received or imported.
+        #[cfg(feature = "e2e-encryption")]
+        let room_keys_task = room.client.olm_machine().map(|olm_machine| {
+            use futures_util::StreamExt;
+            use matrix_sdk_common::executor::spawn;
+
+            let mut room_keys_stream = Box::pin(olm_machine.room_keys_received_stream());
+            let inner = inner.clone();
+            let room = room.clone();
+
+            let (task, abort_handle) = abortable(async move {
+                while let Some(room_keys) = room_keys_stream.next().await {
+                    let session_ids: BTreeSet<_> = room_keys
+                        .iter()
+                        .filter(|info| *info.room_id == *room.room_id())
+                        .map(|info| info.session_id.as_str())
+                        .collect();
+                    if session_ids.is_empty() {
+                        continue;
+                    }
 
-                    let session_id = event.content.session_id;
-                    let Some(own_user_id) = client.user_id() else {
-                        error!("The user's own ID isn't available");
-                        return;
+                    let Some(olm_machine) = room.client.olm_machine() else {
+                        error!("The olm machine isn't available anymore");
+                        break;
                     };
 
                     inner
                         .retry_event_decryption(
-                            &room_id,
+                            room.room_id(),
                             olm_machine,
-                            iter::once(session_id.as_str()).collect(),
-                            own_user_id,
+                            session_ids,
+                            room.own_user_id(),
                         )
                         .await;
                 }
-            }
+            });
+            spawn(task);
+
+            abort_handle
         });
-        #[cfg(feature = "e2e-encryption")]
-        let _room_key_handler_guard = room.client.event_handler_drop_guard(room_key_handle);
 
         Timeline {
             inner,
             room: room.clone(),
+            thread_root,
             start_token: StdMutex::new(prev_token),
-            _end_token: StdMutex::new(None),
+            end_token: StdMutex::new(next_token),
+            pagination_room: StdMutex::new(room.clone()),
+            cached_chunk: StdMutex::new(None),
+            reaction_toggle_locks: DashMap::new(),
             _timeline_event_handler_guard,
+            _read_receipts_handler_guard,
             _fully_read_handler_guard: None,
+            ignored_users_task,
             #[cfg(feature = "e2e-encryption")]
-            _room_key_handler_guard,
+            room_keys_task,
         }
     }
 
@@ -196,31 +421,342 @@ impl Timeline {
     }
 
     /// Add more events to the start of the timeline.
+    ///
+    /// Once the start of the room is reached, pagination continues in the room
+    /// it replaces, if that room is known, see
+    /// [`Common::predecessor`](room::Common::predecessor).
+    ///
+    /// For the timeline of a thread, this loads older replies of the thread,
+    /// and the thread's root event once the start of the thread is reached.
     #[instrument(skip(self), fields(room_id = %self.room.room_id()))]
     pub async fn paginate_backwards(&self, limit: UInt) -> Result<PaginationOutcome> {
+        if let Some(outcome) = self.paginate_backwards_in_cache().await {
+            return Ok(outcome);
+        }
+
         let start = self.start_token.lock().unwrap().clone();
+        if start.is_none() {
+            // Without a token, the position of the requested events relative to
+            // the event cache is unknown.
+            *self.cached_chunk.lock().unwrap() = None;
+        }
+        let room = self.pagination_room.lock().unwrap().clone();
+        let mut messages = match &self.thread_root {
+            Some(thread_root) => room.thread_replies(thread_root, start, limit).await?,
+            None => {
+                room.undecrypted_messages(assign!(MessagesOptions::backward(), {
+                    from: start,
+                    limit,
+                    filter: assign!(RoomEventFilter::default(), {
+                        lazy_load_options: LazyLoadOptions::Enabled {
+                            include_redundant_members: false,
+                        },
+                    }),
+                }))
+                .await?
+            }
+        };
+
+        let mut outcome = PaginationOutcome { more_messages: messages.end.is_some() };
+        *self.start_token.lock().unwrap() = messages.end.clone();
+
+        let cached_chunk = self.cached_chunk.lock().unwrap().clone();
+        if let Some(cached_chunk) = cached_chunk {
+            let events = messages.chunk.iter().cloned().map(Into::into).collect();
+            let store = self.room.client.store();
+            match store
+                .prepend_timeline_events(self.room.room_id(), cached_chunk.id, events, messages.end)
+                .await
+            {
+                Ok(Some(chunk)) => {
+                    // The gap to the previous chunk may have been closed, in
+                    // which case the next pagination continues in the cache.
+                    outcome.more_messages |= chunk.previous.is_some();
+                    *self.cached_chunk.lock().unwrap() = Some((&chunk).into());
+                }
+                Ok(None) => *self.cached_chunk.lock().unwrap() = None,
+                Err(error) => {
+                    warn!(?error, "Failed to add paginated events to the event cache");
+                    *self.cached_chunk.lock().unwrap() = None;
+                }
+            }
+        }
+
+        let events = messages.chunk.into_iter().map(|event| event.event).collect();
+        messages.chunk = room.try_decrypt_events(events).await;
+
+        let state_profiles = self.sender_profiles_from_state(&messages.state).await;
+        let raw_events = messages.chunk.iter().map(|room_ev| room_ev.event.cast_ref());
+        self.inner.load_read_receipts(raw_events.clone(), TimelineItemPosition::Start, &room).await;
+        let sender_profiles =
+            self.inner.paginated_sender_profiles(raw_events, state_profiles, &room).await;
+
+        let own_user_id = self.room.own_user_id();
+        for (room_ev, sender_profile) in messages.chunk.into_iter().zip(sender_profiles) {
+            self.inner.handle_back_paginated_event(room_ev, sender_profile, own_user_id).await;
+        }
+
+        let start_reached = !outcome.more_messages;
+        match (&self.thread_root, outcome.more_messages) {
+            (Some(thread_root), false) => {
+                let root_ev = room.event(thread_root).await?;
+                let raw_event = root_ev.event.cast_ref();
+                let sender_profile = self
+                    .inner
+                    .paginated_sender_profiles([raw_event], HashMap::new(), &room)
+                    .await
+                    .remove(0);
+                self.inner
+                    .load_read_receipts([raw_event], TimelineItemPosition::Start, &room)
+                    .await;
+                self.inner.handle_back_paginated_event(root_ev, sender_profile, own_user_id).await;
+            }
+            (None, false) => outcome.more_messages = self.continue_in_predecessor(&room),
+            _ => {}
+        }
+
+        if start_reached {
+            self.inner.clear_poll_pending_events().await;
+        }
+
+        self.inner.fetch_members_if_needed(&self.room).await;
+        Ok(outcome)
+    }
+
+    /// Add the events of the previous chunk of the room's event cache to the
+    /// start of the timeline, if there is no gap before it.
+    ///
+    /// Returns `None` if the events have to be requested from the server.
+    async fn paginate_backwards_in_cache(&self) -> Option<PaginationOutcome> {
+        let cached_chunk = self.cached_chunk.lock().unwrap().clone()?;
+        if cached_chunk.has_gap {
+            return None;
+        }
+
+        let Some(previous_id) = cached_chunk.previous else {
+            // This is the start of the room.
+            self.inner.clear_poll_pending_events().await;
+            let more_messages = self.continue_in_predecessor(&self.room);
+            return Some(PaginationOutcome { more_messages });
+        };
+
+        let store = self.room.client.store();
+        let chunk = match store.get_event_chunk(self.room.room_id(), previous_id).await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => {
+                warn!(previous_id, "Previous chunk is missing from the event cache");
+                *self.cached_chunk.lock().unwrap() = None;
+                return None;
+            }
+            Err(error) => {
+                warn!(?error, "Failed to load events from the event cache");
+                *self.cached_chunk.lock().unwrap() = None;
+                return None;
+            }
+        };
+
+        *self.cached_chunk.lock().unwrap() = Some((&chunk).into());
+        *self.start_token.lock().unwrap() = chunk.prev_batch.clone();
+        let start_reached = chunk.is_start_of_timeline();
+        let more_messages = !start_reached || self.continue_in_predecessor(&self.room);
+        let outcome = PaginationOutcome { more_messages };
+
+        let own_user_id = self.room.own_user_id();
+        let events = decrypt_cached_events(&self.room, chunk.events).await;
+        // The member state at the time of cached events is not stored, the
+        // profiles are only updated with their `m.room.member` events.
+        let raw_events = events.iter().rev().map(|event| &event.event);
+        let position = TimelineItemPosition::Start;
+        self.inner.load_read_receipts(raw_events.clone(), position, &self.room).await;
+        let sender_profiles =
+            self.inner.paginated_sender_profiles(raw_events, HashMap::new(), &self.room).await;
+
+        for (event, sender_profile) in events.into_iter().rev().zip(sender_profiles) {
+            let event =
+                TimelineEvent { event: event.event.cast(), encryption_info: event.encryption_info };
+            self.inner.handle_back_paginated_event(event, sender_profile, own_user_id).await;
+        }
+
+        if start_reached {
+            self.inner.clear_poll_pending_events().await;
+        }
+
+        self.inner.fetch_members_if_needed(&self.room).await;
+        Some(outcome)
+    }
+
+    /// Continue backwards pagination in the predecessor of the given room,
+    /// once the start of the room was reached.
+    ///
+    /// Returns whether there is a known predecessor to paginate in.
+    fn continue_in_predecessor(&self, room: &room::Common) -> bool {
+        if self.thread_root.is_some() {
+            return false;
+        }
+
+        let Some(predecessor) = room.predecessor() else { return false };
+        debug!(
+            predecessor_id = %predecessor.room_id(),
+            "Reached the start of the room, continuing in its predecessor"
+        );
+
+        *self.pagination_room.lock().unwrap() = (*predecessor).clone();
+        *self.start_token.lock().unwrap() = None;
+        *self.cached_chunk.lock().unwrap() = None;
+
+        true
+    }
+
+    /// Get the profiles of room members from the `m.room.member` events of the
+    /// given state, like the one returned along with paginated events.
+    async fn sender_profiles_from_state(
+        &self,
+        state: &[Raw<AnyStateEvent>],
+    ) -> HashMap<OwnedUserId, Profile> {
+        let mut profiles = HashMap::new();
+
+        for event in state {
+            let Ok(AnyStateEvent::RoomMember(event)) = event.deserialize() else { continue };
+            let Some(event) = event.as_original() else { continue };
+
+            let profile = member_profile(&self.room, &event.content).await;
+            profiles.insert(event.state_key.clone(), profile);
+        }
+
+        profiles
+    }
+
+    /// Add more events to the end of the timeline.
+    ///
+    /// This is only useful for timelines that don't start at the live end of
+    /// the room, like the ones created by
+    /// [`Common::timeline_focused_on`](room::Common::timeline_focused_on).
+    /// Once the live end of the room is reached, live events are added to the
+    /// timeline as they are received from sync, and `more_messages` is `false`
+    /// in the returned [`PaginationOutcome`].
+    #[instrument(skip(self), fields(room_id = %self.room.room_id()))]
+    pub async fn paginate_forwards(&self, limit: UInt) -> Result<PaginationOutcome> {
+        let Some(end) = self.end_token.lock().unwrap().clone() else {
+            return Ok(PaginationOutcome { more_messages: false });
+        };
+
+        // Live events that are received during the request are added to the
+        // timeline if it reaches the live end, those that the response also
+        // contains are skipped.
+        self.inner.keep_detached_live_events().await;
+
         let messages = self
             .room
-            .messages(assign!(MessagesOptions::backward(), {
-                from: start,
+            .messages(assign!(MessagesOptions::forward(), {
+                from: Some(end),
                 limit,
             }))
             .await?;
 
-        let outcome = PaginationOutcome { more_messages: messages.end.is_some() };
-        *self.start_token.lock().unwrap() = messages.end;
-
         let own_user_id = self.room.own_user_id();
+        let reached_live_end = messages.end.is_none() || messages.chunk.is_empty();
+        let mut event_ids = BTreeSet::new();
         for room_ev in messages.chunk {
-            self.inner.handle_back_paginated_event(room_ev, own_user_id).await;
+            if let Ok(Some(event_id)) = room_ev.event.get_field::<OwnedEventId>("event_id") {
+                event_ids.insert(event_id);
+            }
+
+            self.inner.load_event_data(room_ev.event.cast_ref(), &self.room).await;
+            self.inner.handle_forward_paginated_event(room_ev, own_user_id).await;
+        }
+
+        let outcome = PaginationOutcome { more_messages: !reached_live_end };
+        if reached_live_end {
+            *self.end_token.lock().unwrap() = None;
+            self.inner.attach(&event_ids, own_user_id).await;
+        } else {
+            *self.end_token.lock().unwrap() = messages.end;
         }
 
         Ok(outcome)
     }
 
+    /// Get the root of the thread, if this is the timeline of a thread.
+    pub fn thread_root(&self) -> Option<&EventId> {
+        self.thread_root.as_deref()
+    }
+
+    /// Fetch the details of the event with the given ID that are not part of
+    /// the event itself.
+    ///
+    /// Currently, this loads the event a message is replying to, if it isn't
+    /// already available, and updates the message's
+    /// [`InReplyToDetails`] in the timeline. The update is
+    /// published through the timeline's [`signal`](Self::signal) like any
+    /// other change.
+    ///
+    /// Returns an error if the event with the given ID is not in the timeline.
+    /// Errors that occur when loading the replied-to event are stored in
+    /// [`TimelineDetails::Error`] instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `event_id` - The ID of the event in the timeline.
+    #[instrument(skip(self), fields(room_id = %self.room.room_id()))]
+    pub async fn fetch_details_for_event(&self, event_id: &EventId) -> Result<()> {
+        let Some(replied_to_id) = self.inner.set_reply_details_pending(event_id)? else {
+            return Ok(());
+        };
+
+        let details = match self.fetch_replied_to_event(&replied_to_id).await {
+            Ok(replied_to) => TimelineDetails::Ready(Box::new(replied_to)),
+            Err(e) => {
+                warn!(%replied_to_id, "Failed to fetch replied-to event: {e}");
+                TimelineDetails::Error(Arc::new(e))
+            }
+        };
+        self.inner.set_reply_details(event_id, details);
+
+        Ok(())
+    }
+
+    async fn fetch_replied_to_event(&self, event_id: &EventId) -> Result<RepliedToEvent> {
+        let event = self.room.event(event_id).await?;
+        let raw = event.event.cast_ref::<AnySyncTimelineEvent>();
+
+        let sender = raw.deserialize()?.sender().to_owned();
+        let sender_profile = match self.room.get_member(&sender).await? {
+            Some(member) => TimelineDetails::Ready(Profile::from(&member)),
+            None => TimelineDetails::Unavailable,
+        };
+
+        // Build the item of the event in a timeline of its own, so it has the
+        // same content as if it was part of this timeline. Thread replies are
+        // only added to the timeline of their thread.
+        let inner = match thread_root(raw) {
+            Some(thread_root) => TimelineInner::for_thread(thread_root),
+            None => TimelineInner::default(),
+        };
+        inner.handle_back_paginated_event(event, sender_profile, self.room.own_user_id()).await;
+
+        let items = inner.items.lock_ref();
+        let item = event_items(&items).next().ok_or(Error::UnsupportedEvent)?.1;
+        Ok(RepliedToEvent::from(item))
+    }
+
+    /// Fetch the members of the room from the server if necessary, and update
+    /// the [`sender_profile`](EventTimelineItem::sender_profile) of items
+    /// whose sender's profile wasn't available yet.
+    ///
+    /// With lazy-loading of room members, the senders of older events are
+    /// often not known locally. The members are fetched automatically the
+    /// first time a sender is missing from the store, this can be used to try
+    /// again if that failed.
+    pub async fn fetch_members(&self) {
+        self.inner.fetch_sender_profiles(&self.room).await;
+    }
+
     /// Retry decryption of previously un-decryptable events given a list of
     /// session IDs whose keys have been imported.
     ///
+    /// This is done automatically whenever room keys are received or imported
+    /// while the timeline exists, so it is usually not necessary to call this.
+    ///
     /// # Example
     ///
     /// ```no_run
@@ -290,6 +826,15 @@ impl Timeline {
     /// If the encryption feature is enabled, this method will transparently
     /// encrypt the room message if the room is encrypted.
     ///
+    /// In the timeline of a thread, room messages that don't have a relation
+    /// yet are sent as replies in the thread.
+    ///
+    /// If sending the message fails, the local echo is kept in the timeline
+    /// with an [`EventSendState::SendingFailed`] send state, and can be sent
+    /// again with [`retry_send`](Self::retry_send) or discarded with
+    /// [`cancel_send`](Self::cancel_send). The error is returned as
+    /// [`Error::SendingFailed`] as well.
+    ///
     /// # Arguments
     ///
     /// * `content` - The content of the message event.
@@ -314,20 +859,348 @@ impl Timeline {
         content: AnyMessageLikeEventContent,
         txn_id: Option<&TransactionId>,
     ) -> Result<()> {
+        let content = match (&self.thread_root, content) {
+            (Some(thread_root), AnyMessageLikeEventContent::RoomMessage(mut c))
+                if c.relates_to.is_none() =>
+            {
+                let latest_event_id = self
+                    .latest_event()
+                    .and_then(|item| item.event_id().map(ToOwned::to_owned))
+                    .unwrap_or_else(|| thread_root.clone());
+                c.relates_to = Some(message::Relation::Thread(Thread::plain(
+                    thread_root.clone(),
+                    latest_event_id,
+                )));
+                AnyMessageLikeEventContent::RoomMessage(c)
+            }
+            (_, content) => content,
+        };
+
         let txn_id = txn_id.map_or_else(TransactionId::new, ToOwned::to_owned);
         self.inner
             .handle_local_event(txn_id.clone(), content.clone(), self.room.own_user_id())
             .await;
 
+        self.send_local_echo(txn_id, content).await
+    }
+
+    /// Send a read receipt for the given event.
+    ///
+    /// In the timeline of a thread, this sends a threaded read receipt for
+    /// that thread, otherwise a read receipt for the main timeline of the room.
+    ///
+    /// # Arguments
+    ///
+    /// * `event_id` - The ID of the event in the timeline.
+    #[instrument(skip(self), fields(room_id = %self.room.room_id()))]
+    pub async fn send_read_receipt(&self, event_id: &EventId) -> Result<()> {
+        let thread = match &self.thread_root {
+            Some(thread_root) => ReceiptThread::Thread(thread_root.clone()),
+            None => ReceiptThread::Main,
+        };
+
         // If this room isn't actually in joined state, we'll get a server error.
-        // Not ideal, but works for now.
         let room = Joined { inner: self.room.clone() };
+        room.read_receipt_in_thread(event_id, thread).await
+    }
 
-        let response = room.send(content, Some(&txn_id)).await?;
-        self.inner.add_event_id(&txn_id, response.event_id);
+    /// Send a local echo that previously failed to be sent again.
+    ///
+    /// Returns an error if there is no local echo with the given transaction
+    /// ID in the timeline, if it is not in the
+    /// [`EventSendState::SendingFailed`] state, or if sending it fails again.
+    ///
+    /// # Arguments
+    ///
+    /// * `txn_id` - The transaction ID of the local echo.
+    #[instrument(skip(self), fields(room_id = %self.room.room_id()))]
+    pub async fn retry_send(&self, txn_id: &TransactionId) -> Result<()> {
+        let content = self.inner.prepare_retry(txn_id).await?;
+        self.send_local_echo(txn_id.to_owned(), content).await
+    }
 
+    /// Remove a local echo that failed to be sent from the timeline.
+    ///
+    /// Returns an error if there is no local echo with the given transaction
+    /// ID in the timeline, or if it is not in the
+    /// [`EventSendState::SendingFailed`] state.
+    ///
+    /// # Arguments
+    ///
+    /// * `txn_id` - The transaction ID of the local echo.
+    #[instrument(skip(self), fields(room_id = %self.room.room_id()))]
+    pub async fn cancel_send(&self, txn_id: &TransactionId) -> Result<()> {
+        self.inner.discard_local_echo(txn_id).await?;
         Ok(())
     }
+
+    /// Edit a message in the timeline.
+    ///
+    /// The edit is applied to the timeline right away, and reverted if it
+    /// can't be sent.
+    ///
+    /// Returns an error if the event with the given ID is not in the timeline,
+    /// or if it can't be edited by the user (see
+    /// [`EventTimelineItem::is_editable`]).
+    ///
+    /// # Arguments
+    ///
+    /// * `event_id` - The ID of the message to edit.
+    ///
+    /// * `new_content` - The new content of the message.
+    #[instrument(skip(self, new_content), fields(room_id = %self.room.room_id()))]
+    pub async fn edit(&self, event_id: &EventId, new_content: MessageType) -> Result<()> {
+        {
+            let items = self.inner.items.lock_ref();
+            let (_, item) =
+                find_event_by_id(&items, event_id).ok_or(Error::RemoteEventNotInTimeline)?;
+            if !item.is_editable() {
+                return Err(Error::EventNotEditable.into());
+            }
+        }
+
+        let mut content = RoomMessageEventContent::new(new_content.clone());
+        content.relates_to = Some(message::Relation::Replacement(Replacement::new(
+            event_id.to_owned(),
+            new_content,
+        )));
+
+        let txn_id = TransactionId::new();
+        let own_user_id = self.room.own_user_id();
+        self.inner.handle_local_edit(txn_id.clone(), content.clone(), own_user_id).await;
+
+        // If this room isn't actually in joined state, we'll get a server error.
+        let room = Joined { inner: self.room.clone() };
+        if let Err(error) = room.send(content, Some(&txn_id)).await {
+            warn!(%event_id, "Failed to send edit, reverting it: {error}");
+            self.inner.revert_local_edit(event_id, &txn_id).await;
+            return Err(error);
+        }
+
+        self.inner.forget_local_edit(event_id, &txn_id).await;
+        Ok(())
+    }
+
+    /// Redact an event in the timeline.
+    ///
+    /// Once the redaction was sent successfully, it is applied to the timeline
+    /// without waiting for the remote echo.
+    ///
+    /// # Arguments
+    ///
+    /// * `event_id` - The ID of the event to redact.
+    ///
+    /// * `reason` - The reason for the event being redacted.
+    #[instrument(skip(self), fields(room_id = %self.room.room_id()))]
+    pub async fn redact(&self, event_id: &EventId, reason: Option<&str>) -> Result<()> {
+        let txn_id = TransactionId::new();
+
+        // If this room isn't actually in joined state, we'll get a server error.
+        let room = Joined { inner: self.room.clone() };
+        room.redact(event_id, reason, Some(txn_id.clone())).await?;
+
+        self.inner
+            .handle_local_redaction(txn_id, event_id.to_owned(), self.room.own_user_id())
+            .await;
+
+        Ok(())
+    }
+
+    /// Vote in a poll in the timeline.
+    ///
+    /// The vote is applied to the timeline right away, and reverted if it
+    /// can't be sent.
+    ///
+    /// Returns an error if the event with the given ID is not in the timeline,
+    /// or if it is not a poll.
+    ///
+    /// # Arguments
+    ///
+    /// * `poll_start_id` - The ID of the poll's start event.
+    ///
+    /// * `answers` - The IDs of the selected answers. Sending no answers
+    ///   removes the user's previous vote.
+    #[instrument(skip(self, answers), fields(room_id = %self.room.room_id()))]
+    pub async fn vote_in_poll(&self, poll_start_id: &EventId, answers: Vec<String>) -> Result<()> {
+        {
+            let items = self.inner.items.lock_ref();
+            let (_, item) =
+                find_event_by_id(&items, poll_start_id).ok_or(Error::RemoteEventNotInTimeline)?;
+            if item.content.as_poll().is_none() {
+                return Err(Error::EventNotAPoll.into());
+            }
+        }
+
+        let content = PollResponseEventContent::new(
+            SelectionsContent::new(answers),
+            poll_start_id.to_owned(),
+        );
+
+        let txn_id = TransactionId::new();
+        let own_user_id = self.room.own_user_id();
+        self.inner.handle_local_poll_response(txn_id.clone(), content.clone(), own_user_id).await;
+
+        // If this room isn't actually in joined state, we'll get a server error.
+        let room = Joined { inner: self.room.clone() };
+        match room.send(content, Some(&txn_id)).await {
+            Ok(response) => {
+                let event_id = &response.event_id;
+                self.inner.mark_local_poll_response_sent(poll_start_id, &txn_id, event_id);
+                Ok(())
+            }
+            Err(error) => {
+                warn!(%poll_start_id, "Failed to send poll response, reverting it: {error}");
+                self.inner.remove_local_poll_response(poll_start_id, &txn_id);
+                Err(error)
+            }
+        }
+    }
+
+    /// Add a reaction to an event in the timeline, or remove it if the user
+    /// has already reacted with the same key.
+    ///
+    /// The change is applied to the timeline right away, and reverted if it
+    /// can't be sent. Removing a reaction redacts the user's existing reaction
+    /// event.
+    ///
+    /// Toggling the same reaction again waits for the previous toggle to
+    /// complete. Returns an error if the user's reaction with the same key is
+    /// still being sent.
+    ///
+    /// # Arguments
+    ///
+    /// * `annotation` - The event to react to and the key of the reaction,
+    ///   usually an emoji.
+    #[instrument(skip(self), fields(room_id = %self.room.room_id()))]
+    pub async fn toggle_reaction(&self, annotation: &Annotation) -> Result<()> {
+        let lock_key = (annotation.event_id.clone(), annotation.key.clone());
+        let mutex = self.reaction_toggle_locks.entry(lock_key.clone()).or_default().clone();
+
+        let result = {
+            let _guard = mutex.lock().await;
+            self.toggle_reaction_inner(annotation).await
+        };
+
+        // Clean up the lock if no other toggle of the same reaction is waiting
+        // for it.
+        drop(mutex);
+        self.reaction_toggle_locks.remove_if(&lock_key, |_, mutex| Arc::strong_count(mutex) == 1);
+
+        result
+    }
+
+    async fn toggle_reaction_inner(&self, annotation: &Annotation) -> Result<()> {
+        let own_user_id = self.room.own_user_id();
+        // If this room isn't actually in joined state, we'll get a server error.
+        let room = Joined { inner: self.room.clone() };
+
+        match self.inner.own_reaction(annotation, own_user_id).await {
+            Some(TimelineKey::EventId(reaction_id)) => {
+                let key = TimelineKey::EventId(reaction_id.clone());
+                self.inner.remove_reaction(&key).await;
+
+                if let Err(error) = room.redact(&reaction_id, None, None).await {
+                    warn!(%reaction_id, "Failed to redact reaction, restoring it: {error}");
+                    self.inner.add_reaction(key, own_user_id, annotation.clone()).await;
+                    return Err(error.into());
+                }
+            }
+            Some(TimelineKey::TransactionId(_)) => return Err(Error::ReactionPending.into()),
+            None => {
+                let txn_id = TransactionId::new();
+                let key = TimelineKey::TransactionId(txn_id.clone());
+                self.inner.add_reaction(key.clone(), own_user_id, annotation.clone()).await;
+
+                let content = ReactionEventContent::new(annotation.clone());
+                match room.send(content, Some(&txn_id)).await {
+                    Ok(response) => {
+                        self.inner.set_reaction_event_id(&txn_id, response.event_id).await;
+                    }
+                    Err(error) => {
+                        warn!(%txn_id, "Failed to send reaction, removing it: {error}");
+                        self.inner.remove_reaction(&key).await;
+                        return Err(error);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn send_local_echo(
+        &self,
+        txn_id: OwnedTransactionId,
+        content: AnyMessageLikeEventContent,
+    ) -> Result<()> {
+        // If this room isn't actually in joined state, we'll get a server error.
+        // Not ideal, but works for now.
+        let room = Joined { inner: self.room.clone() };
+
+        let (send_state, result) = match room.send(content, Some(&txn_id)).await {
+            Ok(response) => (EventSendState::Sent { event_id: response.event_id }, Ok(())),
+            Err(error) => {
+                warn!(%txn_id, "Failed to send event: {error}");
+                let error = Arc::new(error);
+                let send_state = EventSendState::SendingFailed { error: error.clone() };
+                (send_state, Err(Error::SendingFailed(error).into()))
+            }
+        };
+
+        self.inner.update_event_send_state(&txn_id, send_state).await;
+        result
+    }
+}
+
+impl Drop for Timeline {
+    fn drop(&mut self) {
+        self.ignored_users_task.abort();
+
+        #[cfg(feature = "e2e-encryption")]
+        if let Some(room_keys_task) = &self.room_keys_task {
+            room_keys_task.abort();
+        }
+    }
+}
+
+/// Errors specific to the [`Timeline`].
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// There is no local echo with the given transaction ID in the timeline.
+    #[error("No local echo with the given transaction ID in the timeline")]
+    TransactionNotFound,
+
+    /// The local echo is not in a send state that allows the operation.
+    #[error("The local echo is not in a send state that allows this operation")]
+    InvalidSendState,
+
+    /// There is no event with the given ID in the timeline.
+    #[error("No event with the given ID in the timeline")]
+    RemoteEventNotInTimeline,
+
+    /// The event can't be edited by the user.
+    #[error("The event can't be edited")]
+    EventNotEditable,
+
+    /// The event is not a poll.
+    #[error("The event is not a poll")]
+    EventNotAPoll,
+
+    /// The user's reaction with the same key is still being sent.
+    #[error("A reaction with the same key is still being sent")]
+    ReactionPending,
+
+    /// The event is of a type that isn't displayed in the timeline.
+    #[error("Unsupported event type")]
+    UnsupportedEvent,
+
+    /// Sending the event failed.
+    ///
+    /// Its local echo stays in the timeline with the same error, in the
+    /// [`EventSendState::SendingFailed`] state.
+    #[error("Failed to send the event: {0}")]
+    SendingFailed(#[source] Arc<crate::Error>),
 }
 
 /// A single entry in timeline.
@@ -360,26 +1233,65 @@ impl TimelineItem {
     }
 }
 
+/// Iterate over the event items of the timeline along with the index of the
+/// timeline item they are part of.
+///
+/// Events that are collapsed into a
+/// [`VirtualTimelineItem::MembershipChanges`] item are yielded with the index
+/// of the group.
+fn event_items(
+    lock: &[Arc<TimelineItem>],
+) -> impl DoubleEndedIterator<Item = (usize, &EventTimelineItem)> {
+    lock.iter().enumerate().flat_map(|(idx, item)| {
+        let events: &[EventTimelineItem] = match &**item {
+            TimelineItem::Event(ev) => std::slice::from_ref(ev),
+            TimelineItem::Virtual(VirtualTimelineItem::MembershipChanges(group)) => group,
+            TimelineItem::Virtual(_) => &[],
+        };
+        events.iter().map(move |ev| (idx, ev))
+    })
+}
+
 // FIXME: Put an upper bound on timeline size or add a separate map to look up
 // the index of a timeline item by its key, to avoid large linear scans.
 fn find_event_by_id<'a>(
     lock: &'a [Arc<TimelineItem>],
     event_id: &EventId,
 ) -> Option<(usize, &'a EventTimelineItem)> {
-    lock.iter()
-        .enumerate()
-        .filter_map(|(idx, item)| Some((idx, item.as_event()?)))
-        .rfind(|(_, it)| it.event_id() == Some(event_id))
+    event_items(lock).rfind(|(_, it)| it.event_id() == Some(event_id))
 }
 
 fn find_event_by_txn_id<'a>(
     lock: &'a [Arc<TimelineItem>],
     txn_id: &TransactionId,
 ) -> Option<(usize, &'a EventTimelineItem)> {
-    lock.iter()
-        .enumerate()
-        .filter_map(|(idx, item)| Some((idx, item.as_event()?)))
-        .rfind(|(_, it)| it.key == *txn_id)
+    event_items(lock).rfind(|(_, it)| it.key == *txn_id)
+}
+
+/// Replace the event at the given index, as returned by [`find_event_by_id`]
+/// or [`find_event_by_txn_id`], with a new version of it.
+///
+/// If the index is the one of a group of membership changes, only the event
+/// with the same key is replaced within the group.
+fn set_event_item(
+    lock: &mut MutableVecLockMut<'_, Arc<TimelineItem>>,
+    idx: usize,
+    item: EventTimelineItem,
+) {
+    let new_item = match &*lock[idx] {
+        TimelineItem::Virtual(VirtualTimelineItem::MembershipChanges(group)) => {
+            let mut group = group.clone();
+            let Some(grouped) = group.iter_mut().find(|it| it.key == item.key) else {
+                error!(key = ?item.key, "Event not found in membership changes group");
+                return;
+            };
+            *grouped = item;
+            TimelineItem::Virtual(VirtualTimelineItem::MembershipChanges(group))
+        }
+        _ => TimelineItem::Event(item),
+    };
+
+    lock.set_cloned(idx, Arc::new(new_item));
 }
 
 fn find_read_marker(lock: &[Arc<TimelineItem>]) -> Option<usize> {
@@ -0,0 +1,288 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Polls ([MSC3381]) in the timeline.
+//!
+//! [MSC3381]: https://github.com/matrix-org/matrix-spec-proposals/pull/3381
+
+use std::collections::{btree_map::Entry, BTreeMap};
+
+use indexmap::IndexMap;
+use ruma::{
+    events::poll::start::{PollAnswer, PollKind, PollStartContent},
+    EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedTransactionId, OwnedUserId,
+    TransactionId, UInt, UserId,
+};
+
+/// The state of a poll, including the responses and the end event that were
+/// received for it.
+#[derive(Clone, Debug)]
+pub struct PollState {
+    start: PollStartContent,
+    responses: Vec<PollResponse>,
+    end_ts: Option<MilliSecondsSinceUnixEpoch>,
+}
+
+impl PollState {
+    pub(super) fn new(start: PollStartContent) -> Self {
+        Self { start, responses: Vec::new(), end_ts: None }
+    }
+
+    /// Get the question of the poll, in plain text.
+    pub fn question(&self) -> Option<&str> {
+        self.start.question.find_plain()
+    }
+
+    /// Get the possible answers of the poll.
+    pub fn answers(&self) -> &[PollAnswer] {
+        &self.start.answers
+    }
+
+    /// Get the kind of the poll.
+    ///
+    /// The results of undisclosed polls should only be shown once the poll
+    /// has ended.
+    pub fn kind(&self) -> &PollKind {
+        &self.start.kind
+    }
+
+    /// Get the maximum number of answers a user can select.
+    pub fn max_selections(&self) -> UInt {
+        self.start.max_selections
+    }
+
+    /// Get the content of the poll's start event.
+    pub fn start_content(&self) -> &PollStartContent {
+        &self.start
+    }
+
+    /// Whether the poll has ended.
+    pub fn is_ended(&self) -> bool {
+        self.end_ts.is_some()
+    }
+
+    /// Get the time at which the poll ended, if it did.
+    pub fn end_timestamp(&self) -> Option<MilliSecondsSinceUnixEpoch> {
+        self.end_ts
+    }
+
+    /// Tally the votes of the poll.
+    ///
+    /// Returns the users that voted for each answer, by answer ID, in the order
+    /// of the poll's answers.
+    ///
+    /// Only the latest response of each user that was sent before the poll
+    /// ended counts. Responses are truncated to the first
+    /// [`max_selections`](Self::max_selections) answers, and responses that
+    /// don't select any answer or that select an unknown answer are spoiled:
+    /// they only cancel the previous vote of the user.
+    pub fn votes(&self) -> IndexMap<String, Vec<OwnedUserId>> {
+        let mut latest_responses: BTreeMap<&UserId, &PollResponse> = BTreeMap::new();
+        for response in &self.responses {
+            if let Some(end_ts) = self.end_ts {
+                // Local echoes can't have been sent before the poll ended.
+                if response.timestamp.map_or(true, |timestamp| timestamp > end_ts) {
+                    continue;
+                }
+            }
+
+            match latest_responses.entry(&response.sender) {
+                Entry::Vacant(entry) => {
+                    entry.insert(response);
+                }
+                Entry::Occupied(mut entry) => {
+                    // Local echoes are more recent than any remote response.
+                    let is_latest = match (response.timestamp, entry.get().timestamp) {
+                        (Some(timestamp), Some(latest_timestamp)) => timestamp >= latest_timestamp,
+                        (None, _) => true,
+                        (Some(_), None) => false,
+                    };
+                    if is_latest {
+                        entry.insert(response);
+                    }
+                }
+            }
+        }
+
+        let mut votes: IndexMap<_, _> =
+            self.start.answers.iter().map(|answer| (answer.id.clone(), Vec::new())).collect();
+        let max_selections =
+            usize::try_from(u64::from(self.start.max_selections)).unwrap_or(usize::MAX);
+
+        for (sender, response) in latest_responses {
+            let answers = &response.answers[..response.answers.len().min(max_selections)];
+            if answers.is_empty() || answers.iter().any(|id| !votes.contains_key(id)) {
+                continue;
+            }
+
+            for id in answers {
+                let Some(voters) = votes.get_mut(id) else { continue };
+                if !voters.iter().any(|voter| **voter == *sender) {
+                    voters.push(sender.to_owned());
+                }
+            }
+        }
+
+        votes
+    }
+
+    pub(super) fn with_response(&self, response: PollResponse) -> Self {
+        let mut state = self.clone();
+        state.add_response(response);
+        state
+    }
+
+    /// Remove the local echo of a response with the given transaction ID.
+    pub(super) fn without_local_response(&self, txn_id: &TransactionId) -> Self {
+        let mut state = self.clone();
+        state.responses.retain(|response| response.txn_id.as_deref() != Some(txn_id));
+        state
+    }
+
+    /// Set the event ID of the local echo of a response with the given
+    /// transaction ID, once it was sent.
+    ///
+    /// The local echo is removed if its remote echo was already received.
+    pub(super) fn with_local_response_sent(
+        &self,
+        txn_id: &TransactionId,
+        event_id: &EventId,
+    ) -> Self {
+        let mut state = self.clone();
+        if state.responses.iter().any(|r| r.event_id.as_deref() == Some(event_id)) {
+            state.responses.retain(|r| r.txn_id.as_deref() != Some(txn_id));
+        } else if let Some(response) =
+            state.responses.iter_mut().find(|r| r.txn_id.as_deref() == Some(txn_id))
+        {
+            response.event_id = Some(event_id.to_owned());
+        }
+        state
+    }
+
+    pub(super) fn ended(&self, timestamp: MilliSecondsSinceUnixEpoch) -> Self {
+        let mut state = self.clone();
+        state.end(timestamp);
+        state
+    }
+
+    fn add_response(&mut self, response: PollResponse) {
+        // The remote echo of a response replaces its local echo, which is
+        // matched by event ID if the remote echo has no transaction ID.
+        self.responses.retain(|r| {
+            let same_txn_id = response.txn_id.is_some() && r.txn_id == response.txn_id;
+            let same_event_id = response.event_id.is_some() && r.event_id == response.event_id;
+            !same_txn_id && !same_event_id
+        });
+
+        self.responses.push(response);
+    }
+
+    fn end(&mut self, timestamp: MilliSecondsSinceUnixEpoch) {
+        // Only the first end event counts.
+        if self.end_ts.map_or(true, |end_ts| timestamp < end_ts) {
+            self.end_ts = Some(timestamp);
+        }
+    }
+}
+
+/// A response to a poll.
+#[derive(Clone, Debug)]
+pub(super) struct PollResponse {
+    pub(super) sender: OwnedUserId,
+    // The timestamp of the response, `None` for local echoes
+    pub(super) timestamp: Option<MilliSecondsSinceUnixEpoch>,
+    pub(super) answers: Vec<String>,
+    // The transaction ID of an own response, to match local and remote echoes
+    pub(super) txn_id: Option<OwnedTransactionId>,
+    // The ID of the response event, set on local echoes once they are sent
+    pub(super) event_id: Option<OwnedEventId>,
+}
+
+/// The maximum number of polls whose start event is not part of the timeline
+/// for which responses and end events are kept.
+const MAX_PENDING_POLLS: usize = 100;
+
+/// Responses and end events of polls whose start event is not part of the
+/// timeline yet.
+///
+/// When paginating backwards, these are received before the start event. The
+/// events of the polls that were seen first are dropped once there are more
+/// than [`MAX_PENDING_POLLS`] polls.
+#[derive(Debug, Default)]
+pub(super) struct PollPendingEvents {
+    // Poll start event ID => pending events, in the order the polls were seen
+    polls: IndexMap<OwnedEventId, PendingPollEvents>,
+}
+
+#[derive(Debug, Default)]
+struct PendingPollEvents {
+    responses: Vec<PollResponse>,
+    ends: Vec<(OwnedUserId, MilliSecondsSinceUnixEpoch)>,
+}
+
+impl PollPendingEvents {
+    pub(super) fn add_response(&mut self, poll_start_id: OwnedEventId, response: PollResponse) {
+        self.poll_entry(poll_start_id).responses.push(response);
+    }
+
+    pub(super) fn add_end(
+        &mut self,
+        poll_start_id: OwnedEventId,
+        sender: OwnedUserId,
+        timestamp: MilliSecondsSinceUnixEpoch,
+    ) {
+        self.poll_entry(poll_start_id).ends.push((sender, timestamp));
+    }
+
+    /// Apply the pending events of the poll with the given start event, that
+    /// was sent by the given user.
+    pub(super) fn apply(
+        &mut self,
+        poll_start_id: &EventId,
+        sender: &UserId,
+        state: &mut PollState,
+    ) {
+        let Some(pending) = self.polls.shift_remove(poll_start_id) else { return };
+
+        for response in pending.responses {
+            state.add_response(response);
+        }
+
+        for (end_sender, timestamp) in pending.ends {
+            if *end_sender == *sender {
+                state.end(timestamp);
+            }
+        }
+    }
+
+    /// Drop the pending events of the poll with the given start event, because
+    /// the event was added to the timeline but is not a poll.
+    pub(super) fn discard(&mut self, poll_start_id: &EventId) {
+        self.polls.shift_remove(poll_start_id);
+    }
+
+    /// Drop all the pending events, because the start of the room was reached
+    /// and their start events will never be added to the timeline.
+    pub(super) fn clear(&mut self) {
+        self.polls.clear();
+    }
+
+    fn poll_entry(&mut self, poll_start_id: OwnedEventId) -> &mut PendingPollEvents {
+        if !self.polls.contains_key(&poll_start_id) && self.polls.len() >= MAX_PENDING_POLLS {
+            self.polls.shift_remove_index(0);
+        }
+
+        self.polls.entry(poll_start_id).or_default()
+    }
+}
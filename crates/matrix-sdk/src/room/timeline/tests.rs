@@ -14,21 +14,25 @@
 
 //! Unit tests (based on private methods) for the timeline API.
 
-use std::sync::{
-    atomic::{AtomicU32, Ordering::SeqCst},
-    Arc,
+use std::{
+    collections::BTreeSet,
+    sync::{
+        atomic::{AtomicU32, Ordering::SeqCst},
+        Arc,
+    },
 };
 
 use assert_matches::assert_matches;
 use futures_core::Stream;
 use futures_signals::signal_vec::{SignalVecExt, VecDiff};
-use futures_util::StreamExt;
-use matrix_sdk_base::crypto::OlmMachine;
+use futures_util::{FutureExt, StreamExt};
+use matrix_sdk_base::{crypto::OlmMachine, deserialized_responses::TimelineEvent};
 use matrix_sdk_test::async_test;
 use once_cell::sync::Lazy;
 use ruma::{
     assign, event_id,
     events::{
+        poll::response::{PollResponseEventContent, SelectionsContent},
         reaction::ReactionEventContent,
         relation::{Annotation, Replacement},
         room::{
@@ -38,8 +42,8 @@ use ruma::{
             message::{self, MessageType, RoomMessageEventContent},
             redaction::OriginalSyncRoomRedactionEvent,
         },
-        AnyMessageLikeEventContent, MessageLikeEventContent, MessageLikeEventType,
-        OriginalSyncMessageLikeEvent, StateEventType,
+        AnyMessageLikeEventContent, AnyStateEventContent, MessageLikeEventContent,
+        MessageLikeEventType, OriginalSyncMessageLikeEvent, StateEventType,
     },
     room_id,
     serde::Raw,
@@ -49,7 +53,8 @@ use ruma::{
 use serde_json::{json, Value as JsonValue};
 
 use super::{
-    EncryptedMessage, TimelineInner, TimelineItem, TimelineItemContent, TimelineKey,
+    Change, EncryptedMessage, Error, EventSendState, MembershipChange, TimelineDetails,
+    TimelineInner, TimelineInnerSettings, TimelineItem, TimelineItemContent, TimelineKey,
     VirtualTimelineItem,
 };
 
@@ -340,6 +345,121 @@ async fn invalid_event_content() {
     assert_eq!(*state_key, "@alice:example.org");
 }
 
+#[async_test]
+async fn membership_and_profile_changes() {
+    let timeline = TestTimeline::new(&ALICE);
+    let mut stream = timeline.stream();
+
+    timeline
+        .handle_live_custom_event(json!({
+            "content": {
+                "membership": "join",
+                "displayname": "Bob",
+            },
+            "event_id": "$ev0",
+            "origin_server_ts": 1,
+            "sender": &*BOB,
+            "state_key": &*BOB,
+            "type": "m.room.member",
+            "unsigned": {
+                "prev_content": { "membership": "invite" },
+            },
+        }))
+        .await;
+
+    let item = assert_matches!(stream.next().await, Some(VecDiff::Push { value }) => value);
+    let membership = assert_matches!(
+        item.as_event().unwrap().content(),
+        TimelineItemContent::MembershipChange(membership) => membership
+    );
+    assert_eq!(membership.user_id(), *BOB);
+    assert_eq!(*membership.change(), MembershipChange::InvitationAccepted);
+
+    timeline
+        .handle_live_custom_event(json!({
+            "content": {
+                "membership": "join",
+                "displayname": "Robert",
+            },
+            "event_id": "$ev1",
+            "origin_server_ts": 2,
+            "sender": &*BOB,
+            "state_key": &*BOB,
+            "type": "m.room.member",
+            "unsigned": {
+                "prev_content": {
+                    "membership": "join",
+                    "displayname": "Bob",
+                },
+            },
+        }))
+        .await;
+
+    let item = assert_matches!(stream.next().await, Some(VecDiff::Push { value }) => value);
+    let profile = assert_matches!(
+        item.as_event().unwrap().content(),
+        TimelineItemContent::ProfileChange(profile) => profile
+    );
+    assert_eq!(
+        profile.displayname_change(),
+        Some(&Change { old: Some("Bob".to_owned()), new: Some("Robert".to_owned()) })
+    );
+    assert_matches!(profile.avatar_url_change(), None);
+
+    timeline
+        .handle_live_custom_event(json!({
+            "content": { "membership": "leave" },
+            "event_id": "$ev2",
+            "origin_server_ts": 3,
+            "sender": &*ALICE,
+            "state_key": &*BOB,
+            "type": "m.room.member",
+            "unsigned": {
+                "prev_content": { "membership": "join" },
+            },
+        }))
+        .await;
+
+    let item = assert_matches!(stream.next().await, Some(VecDiff::Push { value }) => value);
+    let membership = assert_matches!(
+        item.as_event().unwrap().content(),
+        TimelineItemContent::MembershipChange(membership) => membership
+    );
+    assert_eq!(*membership.change(), MembershipChange::Kicked);
+}
+
+#[async_test]
+async fn other_state() {
+    let timeline = TestTimeline::new(&ALICE);
+    let mut stream = timeline.stream();
+
+    timeline
+        .handle_live_custom_event(json!({
+            "content": { "topic": "New topic" },
+            "event_id": "$ev0",
+            "origin_server_ts": 1,
+            "sender": &*BOB,
+            "state_key": "",
+            "type": "m.room.topic",
+            "unsigned": {
+                "prev_content": { "topic": "Old topic" },
+            },
+        }))
+        .await;
+
+    let item = assert_matches!(stream.next().await, Some(VecDiff::Push { value }) => value);
+    let state = assert_matches!(
+        item.as_event().unwrap().content(),
+        TimelineItemContent::OtherState(state) => state
+    );
+    assert_eq!(state.state_key(), "");
+    let topic = assert_matches!(state.content(), AnyStateEventContent::RoomTopic(c) => c);
+    assert_eq!(topic.topic, "New topic");
+    let prev_topic =
+        assert_matches!(state.prev_content(), Some(AnyStateEventContent::RoomTopic(c)) => c);
+    assert_eq!(prev_topic.topic, "Old topic");
+}
+
 #[async_test]
 async fn invalid_event() {
     let timeline = TestTimeline::new(&ALICE);
@@ -377,7 +497,10 @@ async fn remote_echo_without_txn_id() {
 
     // That has an event ID assigned already (from the response to sending it)…
     let event_id = event_id!("$W6mZSLWMmfuQQ9jhZWeTxFIM");
-    timeline.inner.add_event_id(&txn_id, event_id.to_owned());
+    timeline
+        .inner
+        .update_event_send_state(&txn_id, EventSendState::Sent { event_id: event_id.to_owned() })
+        .await;
 
     let item =
         assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { value, index: 0 }) => value);
@@ -402,14 +525,811 @@ async fn remote_echo_without_txn_id() {
     assert_matches!(item.as_event().unwrap().key(), TimelineKey::EventId(_));
 }
 
+#[async_test]
+async fn local_echo_send_state() {
+    let timeline = TestTimeline::new(&ALICE);
+    let mut stream = timeline.stream();
+
+    let txn_id = timeline
+        .handle_local_event(AnyMessageLikeEventContent::RoomMessage(
+            RoomMessageEventContent::text_plain("echo"),
+        ))
+        .await;
+
+    let item = assert_matches!(stream.next().await, Some(VecDiff::Push { value }) => value);
+    assert_matches!(item.as_event().unwrap().send_state(), Some(EventSendState::NotSentYet));
+
+    // Can't retry or cancel an event that is still being sent.
+    assert_matches!(timeline.inner.prepare_retry(&txn_id).await, Err(Error::InvalidSendState));
+    assert_matches!(timeline.inner.discard_local_echo(&txn_id).await, Err(Error::InvalidSendState));
+
+    let error = Arc::new(crate::Error::InconsistentState);
+    timeline.inner.update_event_send_state(&txn_id, EventSendState::SendingFailed { error }).await;
+    let item =
+        assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { value, index: 0 }) => value);
+    assert_matches!(
+        item.as_event().unwrap().send_state(),
+        Some(EventSendState::SendingFailed { .. })
+    );
+
+    let content = timeline.inner.prepare_retry(&txn_id).await.unwrap();
+    assert_matches!(content, AnyMessageLikeEventContent::RoomMessage(_));
+    let item =
+        assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { value, index: 0 }) => value);
+    assert_matches!(item.as_event().unwrap().send_state(), Some(EventSendState::NotSentYet));
+
+    let error = Arc::new(crate::Error::InconsistentState);
+    timeline.inner.update_event_send_state(&txn_id, EventSendState::SendingFailed { error }).await;
+    assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, .. }));
+
+    timeline.inner.discard_local_echo(&txn_id).await.unwrap();
+    assert_matches!(stream.next().await, Some(VecDiff::RemoveAt { index: 0 }));
+    assert_eq!(timeline.inner.items.lock_ref().len(), 0);
+
+    assert_matches!(
+        timeline.inner.discard_local_echo(&txn_id).await,
+        Err(Error::TransactionNotFound)
+    );
+}
+
+#[async_test]
+async fn reply_details() {
+    let timeline = TestTimeline::new(&ALICE);
+    let mut stream = timeline.stream();
+
+    let original_id = event_id!("$original");
+    let reply_json = |event_id: &str| {
+        json!({
+            "content": {
+                "body": "> <@alice:server.name> hi\n\nhello",
+                "msgtype": "m.text",
+                "m.relates_to": {
+                    "m.in_reply_to": { "event_id": original_id },
+                },
+            },
+            "sender": &*BOB,
+            "event_id": event_id,
+            "origin_server_ts": 10,
+            "type": "m.room.message",
+        })
+    };
+
+    // A reply to an event that is not in the timeline…
+    let reply_id = event_id!("$reply");
+    timeline.handle_live_custom_event(reply_json(reply_id.as_str())).await;
+    let item = assert_matches!(stream.next().await, Some(VecDiff::Push { value }) => value);
+    let msg = item.as_event().unwrap().content().as_message().unwrap();
+    let in_reply_to = msg.in_reply_to().unwrap();
+    assert_eq!(in_reply_to.event_id, original_id);
+    assert_matches!(in_reply_to.details, TimelineDetails::Unavailable);
+
+    // …can have its details marked as pending…
+    let replied_to_id = timeline.inner.set_reply_details_pending(reply_id).unwrap();
+    assert_eq!(replied_to_id.as_deref(), Some(original_id));
+    let item =
+        assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, value }) => value);
+    let msg = item.as_event().unwrap().content().as_message().unwrap();
+    assert_matches!(msg.in_reply_to().unwrap().details, TimelineDetails::Pending);
+
+    // …but not twice.
+    assert_matches!(timeline.inner.set_reply_details_pending(reply_id), Ok(None));
+    assert_matches!(
+        timeline.inner.set_reply_details_pending(event_id!("$unknown")),
+        Err(Error::RemoteEventNotInTimeline)
+    );
+
+    // When the replied-to event is added to the timeline, the details are
+    // filled in.
+    timeline
+        .handle_live_custom_event(json!({
+            "content": {
+                "body": "hi",
+                "msgtype": "m.text",
+            },
+            "sender": &*ALICE,
+            "event_id": original_id,
+            "origin_server_ts": 11,
+            "type": "m.room.message",
+        }))
+        .await;
+    let item =
+        assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, value }) => value);
+    let msg = item.as_event().unwrap().content().as_message().unwrap();
+    let replied_to =
+        assert_matches!(&msg.in_reply_to().unwrap().details, TimelineDetails::Ready(ev) => ev);
+    assert_eq!(replied_to.sender(), *ALICE);
+    assert_eq!(replied_to.content().as_message().unwrap().body(), "hi");
+    assert_matches!(stream.next().await, Some(VecDiff::Push { .. }));
+
+    // A reply to an event that is already in the timeline gets its details
+    // right away.
+    timeline.handle_live_custom_event(reply_json("$reply2")).await;
+    let item = assert_matches!(stream.next().await, Some(VecDiff::Push { value }) => value);
+    let msg = item.as_event().unwrap().content().as_message().unwrap();
+    assert_matches!(msg.in_reply_to().unwrap().details, TimelineDetails::Ready(_));
+}
+
+#[async_test]
+async fn thread_summary() {
+    let timeline = TestTimeline::new(&ALICE);
+    let mut stream = timeline.stream();
+
+    let root_id = event_id!("$root");
+    timeline.handle_live_custom_event(text_event(&ALICE, root_id, "root", None)).await;
+    assert_matches!(stream.next().await, Some(VecDiff::Push { .. }));
+
+    // Thread replies are not added to the main timeline, but update the root.
+    let reply = text_event(&BOB, event_id!("$reply1"), "reply", Some(root_id));
+    timeline.handle_live_custom_event(reply.clone()).await;
+    let item =
+        assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, value }) => value);
+    let summary = item.as_event().unwrap().thread_summary().unwrap();
+    assert_eq!(summary.num_replies(), 1);
+    assert_eq!(summary.participants(), [BOB.to_owned()]);
+    let latest_reply = summary.latest_reply().unwrap();
+    assert_eq!(latest_reply.event_id(), event_id!("$reply1"));
+    assert_eq!(latest_reply.content().as_message().unwrap().body(), "reply");
+
+    // The same reply isn't counted twice.
+    timeline.handle_live_custom_event(reply).await;
+
+    timeline
+        .handle_live_custom_event(text_event(&ALICE, event_id!("$reply2"), "re", Some(root_id)))
+        .await;
+    let item =
+        assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, value }) => value);
+    let summary = item.as_event().unwrap().thread_summary().unwrap();
+    assert_eq!(summary.num_replies(), 2);
+    assert_eq!(summary.participants(), [BOB.to_owned(), ALICE.to_owned()]);
+    assert_eq!(timeline.inner.items.lock_ref().len(), 1);
+}
+
+#[async_test]
+async fn thread_reply_local_echo() {
+    let timeline = TestTimeline::new(&ALICE);
+    let mut stream = timeline.stream();
+
+    let root_id = event_id!("$root");
+    timeline.handle_live_custom_event(text_event(&ALICE, root_id, "root", None)).await;
+    assert_matches!(stream.next().await, Some(VecDiff::Push { .. }));
+
+    // The local echo of a thread reply is shown while it is being sent…
+    let mut content = RoomMessageEventContent::text_plain("reply");
+    content.relates_to = Some(message::Relation::Thread(message::Thread::plain(
+        root_id.to_owned(),
+        root_id.to_owned(),
+    )));
+    let txn_id =
+        timeline.handle_local_event(AnyMessageLikeEventContent::RoomMessage(content)).await;
+    let item = assert_matches!(stream.next().await, Some(VecDiff::Push { value }) => value);
+    assert_matches!(item.as_event().unwrap().send_state(), Some(EventSendState::NotSentYet));
+
+    // …and replaced by the thread summary once the remote echo is received.
+    let mut event = text_event(&ALICE, event_id!("$reply"), "reply", Some(root_id));
+    event["unsigned"] = json!({ "transaction_id": txn_id });
+    timeline.handle_live_custom_event(event).await;
+    assert_matches!(stream.next().await, Some(VecDiff::RemoveAt { index: 1 }));
+    let item =
+        assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, value }) => value);
+    assert_eq!(item.as_event().unwrap().thread_summary().unwrap().num_replies(), 1);
+    assert_eq!(timeline.inner.items.lock_ref().len(), 1);
+}
+
+#[async_test]
+async fn thread_timeline() {
+    let root_id = event_id!("$root");
+    let timeline = TestTimeline::with_inner(&ALICE, TimelineInner::for_thread(root_id.to_owned()));
+    let mut stream = timeline.stream();
+
+    timeline.handle_live_custom_event(text_event(&ALICE, root_id, "root", None)).await;
+    assert_matches!(stream.next().await, Some(VecDiff::Push { .. }));
+
+    // Events that are not part of the thread are discarded.
+    timeline.handle_live_custom_event(text_event(&BOB, event_id!("$main"), "hi", None)).await;
+    let other_root = event_id!("$other_root");
+    timeline
+        .handle_live_custom_event(text_event(&BOB, event_id!("$other"), "hi", Some(other_root)))
+        .await;
+
+    timeline
+        .handle_live_custom_event(text_event(&BOB, event_id!("$reply"), "reply", Some(root_id)))
+        .await;
+    let item = assert_matches!(stream.next().await, Some(VecDiff::Push { value }) => value);
+    assert_eq!(item.as_event().unwrap().event_id(), Some(event_id!("$reply")));
+    assert_eq!(timeline.inner.items.lock_ref().len(), 2);
+}
+
+#[async_test]
+async fn local_reaction() {
+    let timeline = TestTimeline::new(&ALICE);
+    let mut stream = timeline.stream();
+
+    let msg_id = event_id!("$msg");
+    timeline.handle_live_custom_event(text_event(&BOB, msg_id, "hi", None)).await;
+    assert_matches!(stream.next().await, Some(VecDiff::Push { .. }));
+
+    // The local echo of a reaction is added right away…
+    let annotation = Annotation::new(msg_id.to_owned(), "👍".to_owned());
+    let txn_id = TransactionId::new();
+    let local_key = TimelineKey::TransactionId(txn_id.clone());
+    timeline.inner.add_reaction(local_key.clone(), &ALICE, annotation.clone()).await;
+    let item =
+        assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, value }) => value);
+    assert_eq!(item.as_event().unwrap().reactions().get("👍").unwrap().count, uint!(1));
+    assert_eq!(timeline.inner.own_reaction(&annotation, &ALICE).await, Some(local_key));
+
+    // …and its remote echo isn't counted twice.
+    let reaction_id = event_id!("$reaction");
+    timeline.inner.set_reaction_event_id(&txn_id, reaction_id.to_owned()).await;
+    timeline
+        .handle_live_custom_event(json!({
+            "content": {
+                "m.relates_to": {
+                    "rel_type": "m.annotation",
+                    "event_id": msg_id,
+                    "key": "👍",
+                },
+            },
+            "sender": &*ALICE,
+            "event_id": reaction_id,
+            "origin_server_ts": next_server_ts(),
+            "type": "m.reaction",
+        }))
+        .await;
+    let remote_key = TimelineKey::EventId(reaction_id.to_owned());
+    assert_eq!(timeline.inner.own_reaction(&annotation, &ALICE).await, Some(remote_key.clone()));
+
+    timeline.inner.remove_reaction(&remote_key).await;
+    let item =
+        assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, value }) => value);
+    assert_eq!(item.as_event().unwrap().reactions().len(), 0);
+    assert_eq!(timeline.inner.own_reaction(&annotation, &ALICE).await, None);
+}
+
+#[async_test]
+async fn local_reaction_remote_echo_without_txn_id() {
+    let timeline = TestTimeline::new(&ALICE);
+    let mut stream = timeline.stream();
+
+    let msg_id = event_id!("$msg");
+    timeline.handle_live_custom_event(text_event(&BOB, msg_id, "hi", None)).await;
+    assert_matches!(stream.next().await, Some(VecDiff::Push { .. }));
+
+    let annotation = Annotation::new(msg_id.to_owned(), "👍".to_owned());
+    let txn_id = TransactionId::new();
+    let local_key = TimelineKey::TransactionId(txn_id.clone());
+    timeline.inner.add_reaction(local_key, &ALICE, annotation.clone()).await;
+    assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, .. }));
+
+    // The remote echo is received before the response to the send request,
+    // without a transaction ID.
+    let reaction_id = event_id!("$reaction");
+    timeline
+        .handle_live_custom_event(json!({
+            "content": {
+                "m.relates_to": {
+                    "rel_type": "m.annotation",
+                    "event_id": msg_id,
+                    "key": "👍",
+                },
+            },
+            "sender": &*ALICE,
+            "event_id": reaction_id,
+            "origin_server_ts": next_server_ts(),
+            "type": "m.reaction",
+        }))
+        .await;
+    timeline.inner.set_reaction_event_id(&txn_id, reaction_id.to_owned()).await;
+
+    let remote_key = TimelineKey::EventId(reaction_id.to_owned());
+    assert_eq!(timeline.inner.own_reaction(&annotation, &ALICE).await, Some(remote_key));
+    let items = timeline.inner.items.lock_ref();
+    let reactions = items[0].as_event().unwrap().reactions();
+    assert_eq!(reactions.get("👍").unwrap().count, uint!(1));
+}
+
+#[async_test]
+async fn local_edit() {
+    let timeline = TestTimeline::new(&ALICE);
+    let mut stream = timeline.stream();
+
+    let msg_id = event_id!("$msg");
+    timeline.handle_live_custom_event(text_event(&ALICE, msg_id, "hi", None)).await;
+    assert_matches!(stream.next().await, Some(VecDiff::Push { .. }));
+
+    let edit_content = |body: &str| {
+        let new_content = MessageType::text_plain(body);
+        let mut content = RoomMessageEventContent::new(new_content.clone());
+        content.relates_to =
+            Some(message::Relation::Replacement(Replacement::new(msg_id.to_owned(), new_content)));
+        content
+    };
+
+    let txn_id = TransactionId::new();
+    timeline.inner.handle_local_edit(txn_id.clone(), edit_content("hello"), &ALICE).await;
+
+    let item =
+        assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, value }) => value);
+    let msg = item.as_event().unwrap().content().as_message().unwrap();
+    assert_eq!(msg.body(), "hello");
+    assert!(msg.is_edited());
+
+    // Reverting the edit restores the original content.
+    timeline.inner.revert_local_edit(msg_id, &txn_id).await;
+    let item =
+        assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, value }) => value);
+    let msg = item.as_event().unwrap().content().as_message().unwrap();
+    assert_eq!(msg.body(), "hi");
+    assert!(!msg.is_edited());
+
+    // An edit is not reverted once the message was edited again.
+    let txn_id = TransactionId::new();
+    timeline.inner.handle_local_edit(txn_id.clone(), edit_content("hello"), &ALICE).await;
+    assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, .. }));
+    timeline.inner.handle_local_edit(TransactionId::new(), edit_content("hey"), &ALICE).await;
+    assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, .. }));
+
+    timeline.inner.revert_local_edit(msg_id, &txn_id).await;
+    let items = timeline.inner.items.lock_ref();
+    let msg = items[0].as_event().unwrap().content().as_message().unwrap();
+    assert_eq!(msg.body(), "hey");
+}
+
+#[async_test]
+async fn detached_timeline() {
+    let mut inner = TimelineInner::default();
+    inner.metadata.get_mut().detached = true;
+    let timeline = TestTimeline::with_inner(&ALICE, inner);
+    let mut stream = timeline.stream();
+
+    // Live events are not added while there is a gap to the live end…
+    timeline.handle_live_custom_event(text_event(&BOB, event_id!("$live1"), "hi", None)).await;
+    assert_eq!(timeline.inner.items.lock_ref().len(), 0);
+
+    // …but they are once the timeline caught up with it.
+    timeline.inner.attach(&BTreeSet::new(), &ALICE).await;
+    timeline.handle_live_custom_event(text_event(&BOB, event_id!("$live2"), "hi", None)).await;
+    let item = assert_matches!(stream.next().await, Some(VecDiff::Push { value }) => value);
+    assert_eq!(item.as_event().unwrap().event_id(), Some(event_id!("$live2")));
+}
+
+#[async_test]
+async fn detached_timeline_keeps_live_events_during_pagination() {
+    let mut inner = TimelineInner::default();
+    inner.metadata.get_mut().detached = true;
+    let timeline = TestTimeline::with_inner(&ALICE, inner);
+    let mut stream = timeline.stream();
+
+    // Live events that are received while forward pagination reaches the live
+    // end are kept…
+    timeline.inner.keep_detached_live_events().await;
+    timeline.handle_live_custom_event(text_event(&BOB, event_id!("$live1"), "hi", None)).await;
+    timeline.handle_live_custom_event(text_event(&BOB, event_id!("$live2"), "hi", None)).await;
+    assert_eq!(timeline.inner.items.lock_ref().len(), 0);
+
+    // …and added when the timeline is attached, unless the pagination response
+    // already contained them.
+    let paginated_event_ids = BTreeSet::from([event_id!("$live1").to_owned()]);
+    timeline.inner.attach(&paginated_event_ids, &ALICE).await;
+    let item = assert_matches!(stream.next().await, Some(VecDiff::Push { value }) => value);
+    assert_eq!(item.as_event().unwrap().event_id(), Some(event_id!("$live2")));
+    assert_eq!(timeline.inner.items.lock_ref().len(), 1);
+}
+
+#[async_test]
+async fn detached_timeline_remote_echo() {
+    let mut inner = TimelineInner::default();
+    inner.metadata.get_mut().detached = true;
+    let timeline = TestTimeline::with_inner(&ALICE, inner);
+    let mut stream = timeline.stream();
+
+    let txn_id = timeline
+        .handle_local_event(AnyMessageLikeEventContent::RoomMessage(
+            RoomMessageEventContent::text_plain("hi"),
+        ))
+        .await;
+    let _local_echo = assert_matches!(stream.next().await, Some(VecDiff::Push { value }) => value);
+
+    // The remote echo replaces the local echo even though the timeline doesn't
+    // reach the live end.
+    let mut event = text_event(&ALICE, event_id!("$remote"), "hi", None);
+    event["unsigned"] = json!({ "transaction_id": txn_id });
+    timeline.handle_live_custom_event(event).await;
+    let item =
+        assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, value }) => value);
+    assert_eq!(item.as_event().unwrap().event_id(), Some(event_id!("$remote")));
+}
+
+#[async_test]
+async fn day_dividers() {
+    let settings = TimelineInnerSettings { day_dividers: true, ..Default::default() };
+    let timeline = TestTimeline::with_inner(&ALICE, TimelineInner::with_settings(settings));
+    let mut stream = timeline.stream();
+
+    // 2022-01-08, 23:00 UTC
+    let first_ts: u64 = 19_000 * 86_400_000 + 23 * 3_600_000;
+    let mut first = text_event(&BOB, event_id!("$ev0"), "hi", None);
+    first["origin_server_ts"] = first_ts.into();
+    timeline.handle_live_custom_event(first).await;
+
+    let divider = assert_matches!(stream.next().await, Some(VecDiff::Push { value }) => value);
+    let date = assert_matches!(
+        divider.as_virtual(),
+        Some(VirtualTimelineItem::DayDivider { date, .. }) => date
+    );
+    assert_eq!(date, "2022-01-08");
+    assert_matches!(stream.next().await, Some(VecDiff::Push { value }) => value);
+
+    // Two hours later, the next day has started.
+    let mut second = text_event(&BOB, event_id!("$ev1"), "hello", None);
+    second["origin_server_ts"] = (first_ts + 2 * 3_600_000).into();
+    timeline.handle_live_custom_event(second).await;
+
+    let divider = assert_matches!(stream.next().await, Some(VecDiff::Push { value }) => value);
+    let date = assert_matches!(
+        divider.as_virtual(),
+        Some(VirtualTimelineItem::DayDivider { date, .. }) => date
+    );
+    assert_eq!(date, "2022-01-09");
+    let item = assert_matches!(stream.next().await, Some(VecDiff::Push { value }) => value);
+    assert_eq!(item.as_event().unwrap().event_id(), Some(event_id!("$ev1")));
+}
+
+#[async_test]
+async fn collapse_membership_changes() {
+    let settings =
+        TimelineInnerSettings { collapse_membership_changes: true, ..Default::default() };
+    let timeline = TestTimeline::with_inner(&ALICE, TimelineInner::with_settings(settings));
+    let mut stream = timeline.stream();
+
+    timeline.handle_live_custom_event(member_event("$ev0", &ALICE)).await;
+    let item = assert_matches!(stream.next().await, Some(VecDiff::Push { value }) => value);
+    assert_matches!(item.as_event().unwrap().content(), TimelineItemContent::MembershipChange(_));
+
+    // The second membership change replaces the first one with a group.
+    timeline.handle_live_custom_event(member_event("$ev1", &BOB)).await;
+    let item = assert_matches!(
+        stream.next().await,
+        Some(VecDiff::UpdateAt { index: 0, value }) => value
+    );
+    let group = assert_matches!(
+        item.as_virtual(),
+        Some(VirtualTimelineItem::MembershipChanges(group)) => group
+    );
+    assert_eq!(group.len(), 2);
+    assert_eq!(group[1].sender(), *BOB);
+
+    // A message ends the group.
+    timeline.handle_live_custom_event(text_event(&BOB, event_id!("$ev2"), "hi", None)).await;
+    assert_matches!(stream.next().await, Some(VecDiff::Push { value }) => value);
+    timeline.handle_live_custom_event(member_event("$ev3", &BOB)).await;
+    let item = assert_matches!(stream.next().await, Some(VecDiff::Push { value }) => value);
+    assert_matches!(item.as_event().unwrap().content(), TimelineItemContent::MembershipChange(_));
+}
+
+#[async_test]
+async fn redact_grouped_membership_change() {
+    let settings =
+        TimelineInnerSettings { collapse_membership_changes: true, ..Default::default() };
+    let timeline = TestTimeline::with_inner(&ALICE, TimelineInner::with_settings(settings));
+    let mut stream = timeline.stream();
+
+    timeline.handle_live_custom_event(member_event("$ev0", &ALICE)).await;
+    assert_matches!(stream.next().await, Some(VecDiff::Push { .. }));
+    timeline.handle_live_custom_event(member_event("$ev1", &BOB)).await;
+    assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, .. }));
+
+    // The redaction applies to the event within the group.
+    timeline.handle_live_redaction(&BOB, event_id!("$ev1")).await;
+    let item = assert_matches!(
+        stream.next().await,
+        Some(VecDiff::UpdateAt { index: 0, value }) => value
+    );
+    let group = assert_matches!(
+        item.as_virtual(),
+        Some(VirtualTimelineItem::MembershipChanges(group)) => group
+    );
+    assert_eq!(group.len(), 2);
+    assert_matches!(group[0].content(), TimelineItemContent::MembershipChange(_));
+    assert_matches!(group[1].content(), TimelineItemContent::RedactedMessage);
+    assert_eq!(timeline.inner.items.lock_ref().len(), 1);
+}
+
+#[async_test]
+async fn paginate_membership_changes_twice() {
+    let settings =
+        TimelineInnerSettings { collapse_membership_changes: true, ..Default::default() };
+    let timeline = TestTimeline::with_inner(&ALICE, TimelineInner::with_settings(settings));
+
+    let events = [member_event("$ev1", &BOB), member_event("$ev0", &ALICE)];
+    for event in &events {
+        timeline.handle_back_paginated_custom_event(event.clone()).await;
+    }
+    // Paginating over the same events again must not duplicate them.
+    for event in &events {
+        timeline.handle_back_paginated_custom_event(event.clone()).await;
+    }
+
+    let items = timeline.inner.items.lock_ref();
+    assert_eq!(items.len(), 1);
+    let group = assert_matches!(
+        items[0].as_virtual(),
+        Some(VirtualTimelineItem::MembershipChanges(group)) => group
+    );
+    assert_eq!(group.len(), 2);
+    assert_eq!(group[0].event_id(), Some(event_id!("$ev0")));
+    assert_eq!(group[1].event_id(), Some(event_id!("$ev1")));
+}
+
+#[async_test]
+async fn event_filter() {
+    let settings = TimelineInnerSettings {
+        event_filter: Some(Arc::new(|raw| {
+            raw.get_field::<String>("state_key").ok().flatten().is_none()
+        })),
+        ..Default::default()
+    };
+    let timeline = TestTimeline::with_inner(&ALICE, TimelineInner::with_settings(settings));
+
+    timeline
+        .handle_live_custom_event(json!({
+            "content": { "membership": "join" },
+            "event_id": "$ev0",
+            "origin_server_ts": next_server_ts(),
+            "sender": &*BOB,
+            "state_key": &*BOB,
+            "type": "m.room.member",
+        }))
+        .await;
+    assert_eq!(timeline.inner.items.lock_ref().len(), 0);
+
+    timeline.handle_live_custom_event(text_event(&BOB, event_id!("$ev1"), "hi", None)).await;
+    let items = timeline.inner.items.lock_ref();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].as_event().unwrap().event_id(), Some(event_id!("$ev1")));
+}
+
+#[async_test]
+async fn ignored_users() {
+    let timeline = TestTimeline::new(&ALICE);
+
+    timeline.handle_live_message_event(&ALICE, RoomMessageEventContent::text_plain("hi")).await;
+    timeline.handle_live_message_event(&BOB, RoomMessageEventContent::text_plain("spam")).await;
+    assert_eq!(timeline.inner.items.lock_ref().len(), 2);
+
+    // Items from users that get ignored are removed.
+    timeline.inner.set_ignored_users(vec![BOB.to_owned()]).await;
+    {
+        let items = timeline.inner.items.lock_ref();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].as_event().unwrap().sender(), *ALICE);
+    }
+
+    // New events from ignored users are left out.
+    timeline.handle_live_message_event(&BOB, RoomMessageEventContent::text_plain("more")).await;
+    assert_eq!(timeline.inner.items.lock_ref().len(), 1);
+
+    timeline.inner.set_ignored_users(Vec::new()).await;
+    timeline.handle_live_message_event(&BOB, RoomMessageEventContent::text_plain("sorry")).await;
+    assert_eq!(timeline.inner.items.lock_ref().len(), 2);
+}
+
+#[async_test]
+async fn ignored_users_state_events() {
+    let timeline = TestTimeline::new(&ALICE);
+    timeline.inner.set_ignored_users(vec![BOB.to_owned()]).await;
+
+    // Membership changes of ignored users still affect the room.
+    timeline.handle_live_custom_event(member_event("$ev0", &BOB)).await;
+    timeline.handle_live_custom_event(text_event(&BOB, event_id!("$ev1"), "spam", None)).await;
+
+    let items = timeline.inner.items.lock_ref();
+    assert_eq!(items.len(), 1);
+    assert_matches!(
+        items[0].as_event().unwrap().content(),
+        TimelineItemContent::MembershipChange(_)
+    );
+}
+
+#[async_test]
+async fn ignored_users_virtual_items() {
+    let settings = TimelineInnerSettings {
+        day_dividers: true,
+        collapse_membership_changes: true,
+        ..Default::default()
+    };
+    let timeline = TestTimeline::with_inner(&ALICE, TimelineInner::with_settings(settings));
+
+    timeline.handle_live_custom_event(member_event("$ev0", &ALICE)).await;
+    timeline.handle_live_custom_event(text_event(&BOB, event_id!("$ev1"), "spam", None)).await;
+    timeline.handle_live_custom_event(member_event("$ev2", &BOB)).await;
+    let mut next_day = text_event(&BOB, event_id!("$ev3"), "more spam", None);
+    next_day["origin_server_ts"] = (2 * 86_400_000_u64).into();
+    timeline.handle_live_custom_event(next_day).await;
+    assert_eq!(timeline.inner.items.lock_ref().len(), 6);
+
+    // The membership changes around the removed message are grouped, and the
+    // day divider of the next day goes away with its only event.
+    timeline.inner.set_ignored_users(vec![BOB.to_owned()]).await;
+    let items = timeline.inner.items.lock_ref();
+    assert_eq!(items.len(), 2);
+    assert_matches!(items[0].as_virtual(), Some(VirtualTimelineItem::DayDivider { .. }));
+    let group = assert_matches!(
+        items[1].as_virtual(),
+        Some(VirtualTimelineItem::MembershipChanges(group)) => group
+    );
+    assert_eq!(group.len(), 2);
+}
+
 struct TestTimeline {
     own_user_id: OwnedUserId,
     inner: TimelineInner,
 }
 
+#[async_test]
+async fn read_receipts() {
+    let timeline = TestTimeline::new(&ALICE);
+    let mut stream = timeline.stream();
+
+    let first_id = event_id!("$first");
+    let second_id = event_id!("$second");
+    timeline.handle_live_custom_event(text_event(&BOB, first_id, "hi", None)).await;
+    assert_matches!(stream.next().await, Some(VecDiff::Push { .. }));
+    timeline.handle_live_custom_event(text_event(&BOB, second_id, "ho", None)).await;
+    assert_matches!(stream.next().await, Some(VecDiff::Push { .. }));
+
+    timeline
+        .handle_read_receipts(json!({
+            first_id.as_str(): { "m.read": { BOB.as_str(): { "ts": 1 } } },
+        }))
+        .await;
+    let item =
+        assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, value }) => value);
+    assert!(item.as_event().unwrap().read_receipts().contains_key(*BOB));
+
+    // A newer receipt moves the user to the other item.
+    timeline
+        .handle_read_receipts(json!({
+            second_id.as_str(): { "m.read": { BOB.as_str(): { "ts": 2 } } },
+        }))
+        .await;
+    let item =
+        assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, value }) => value);
+    assert!(item.as_event().unwrap().read_receipts().is_empty());
+    let item =
+        assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 1, value }) => value);
+    assert!(item.as_event().unwrap().read_receipts().contains_key(*BOB));
+
+    // Receipts of other threads and private receipts of other users are ignored.
+    timeline
+        .handle_read_receipts(json!({
+            first_id.as_str(): {
+                "m.read": { BOB.as_str(): { "ts": 3, "thread_id": "$thread_root" } },
+                "m.read.private": { BOB.as_str(): { "ts": 3 } },
+            },
+        }))
+        .await;
+    assert!(stream.next().now_or_never().is_none());
+
+    // The own private receipt is used.
+    timeline
+        .handle_read_receipts(json!({
+            second_id.as_str(): { "m.read.private": { ALICE.as_str(): { "ts": 4 } } },
+        }))
+        .await;
+    let item =
+        assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 1, value }) => value);
+    let read_receipts = item.as_event().unwrap().read_receipts();
+    assert_eq!(read_receipts.len(), 2);
+    assert!(read_receipts.contains_key(*ALICE));
+
+    // A more recent public receipt for an older event doesn't move the own
+    // receipt backwards.
+    timeline
+        .handle_read_receipts(json!({
+            first_id.as_str(): { "m.read": { ALICE.as_str(): { "ts": 5 } } },
+        }))
+        .await;
+    assert!(stream.next().now_or_never().is_none());
+}
+
+#[async_test]
+async fn poll() {
+    let timeline = TestTimeline::new(&ALICE);
+    let mut stream = timeline.stream();
+
+    // A response received before the start event is applied once it arrives.
+    let poll_id = event_id!("$poll");
+    timeline.handle_live_custom_event(poll_response_event(&BOB, poll_id, &["pizza"])).await;
+    timeline.handle_live_custom_event(poll_start_event(&ALICE, poll_id)).await;
+    let item = assert_matches!(stream.next().await, Some(VecDiff::Push { value }) => value);
+    let poll = item.as_event().unwrap().content().as_poll().unwrap().clone();
+    assert_eq!(poll.question(), Some("Pizza or pasta?"));
+    assert_eq!(poll.answers().len(), 2);
+    assert_eq!(poll.votes()["pizza"], [BOB.to_owned()]);
+
+    // Only the latest response of a user counts.
+    timeline.handle_live_custom_event(poll_response_event(&BOB, poll_id, &["pasta"])).await;
+    let item =
+        assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, value }) => value);
+    let votes = item.as_event().unwrap().content().as_poll().unwrap().votes();
+    assert!(votes["pizza"].is_empty());
+    assert_eq!(votes["pasta"], [BOB.to_owned()]);
+
+    // A response with an unknown answer spoils the previous vote.
+    timeline.handle_live_custom_event(poll_response_event(&ALICE, poll_id, &["pizza"])).await;
+    let item =
+        assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, value }) => value);
+    let votes = item.as_event().unwrap().content().as_poll().unwrap().votes();
+    assert_eq!(votes["pizza"], [ALICE.to_owned()]);
+
+    timeline.handle_live_custom_event(poll_response_event(&ALICE, poll_id, &["salad"])).await;
+    let item =
+        assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, value }) => value);
+    let votes = item.as_event().unwrap().content().as_poll().unwrap().votes();
+    assert!(votes["pizza"].is_empty());
+
+    // Only the creator of the poll can end it.
+    timeline.handle_live_custom_event(poll_end_event(&BOB, poll_id)).await;
+    timeline.handle_live_custom_event(poll_end_event(&ALICE, poll_id)).await;
+    let item =
+        assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, value }) => value);
+    assert!(item.as_event().unwrap().content().as_poll().unwrap().is_ended());
+
+    // Responses sent after the end of the poll are ignored.
+    timeline.handle_live_custom_event(poll_response_event(&BOB, poll_id, &["pizza"])).await;
+    let item =
+        assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, value }) => value);
+    let votes = item.as_event().unwrap().content().as_poll().unwrap().votes();
+    assert!(votes["pizza"].is_empty());
+    assert_eq!(votes["pasta"], [BOB.to_owned()]);
+}
+
+#[async_test]
+async fn poll_local_vote() {
+    let timeline = TestTimeline::new(&ALICE);
+    let mut stream = timeline.stream();
+
+    let poll_id = event_id!("$poll");
+    timeline.handle_live_custom_event(poll_start_event(&ALICE, poll_id)).await;
+    assert_matches!(stream.next().await, Some(VecDiff::Push { .. }));
+
+    let content = PollResponseEventContent::new(
+        SelectionsContent::new(vec!["pasta".to_owned()]),
+        poll_id.to_owned(),
+    );
+    let txn_id = TransactionId::new();
+    timeline.inner.handle_local_poll_response(txn_id.clone(), content, &ALICE).await;
+    let item =
+        assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, value }) => value);
+    let votes = item.as_event().unwrap().content().as_poll().unwrap().votes();
+    assert_eq!(votes["pasta"], [ALICE.to_owned()]);
+
+    // The remote echo replaces the local echo even without a transaction ID,
+    // so the vote counts with its server timestamp once the poll ended.
+    let vote_id = event_id!("$vote");
+    timeline.inner.mark_local_poll_response_sent(poll_id, &txn_id, vote_id);
+    assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, .. }));
+    let mut event = poll_response_event(&ALICE, poll_id, &["pasta"]);
+    event["event_id"] = json!(vote_id);
+    timeline.handle_live_custom_event(event).await;
+    assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, .. }));
+
+    timeline.handle_live_custom_event(poll_end_event(&ALICE, poll_id)).await;
+    let item =
+        assert_matches!(stream.next().await, Some(VecDiff::UpdateAt { index: 0, value }) => value);
+    let poll = item.as_event().unwrap().content().as_poll().unwrap().clone();
+    assert!(poll.is_ended());
+    assert_eq!(poll.votes()["pasta"], [ALICE.to_owned()]);
+}
+
 impl TestTimeline {
     fn new(own_user_id: &UserId) -> Self {
-        Self { own_user_id: own_user_id.to_owned(), inner: Default::default() }
+        Self::with_inner(own_user_id, Default::default())
+    }
+
+    fn with_inner(own_user_id: &UserId, inner: TimelineInner) -> Self {
+        Self { own_user_id: own_user_id.to_owned(), inner }
     }
 
     fn stream(&self) -> impl Stream<Item = VecDiff<Arc<TimelineItem>>> {
@@ -436,6 +1356,14 @@ impl TestTimeline {
         self.inner.handle_live_event(raw, None, &self.own_user_id).await;
     }
 
+    async fn handle_back_paginated_custom_event(&self, event: JsonValue) {
+        let event =
+            TimelineEvent { event: Raw::new(&event).unwrap().cast(), encryption_info: None };
+        self.inner
+            .handle_back_paginated_event(event, TimelineDetails::Unavailable, &self.own_user_id)
+            .await;
+    }
+
     async fn handle_live_redaction(&self, sender: &UserId, redacts: &EventId) {
         let ev = OriginalSyncRoomRedactionEvent {
             content: Default::default(),
@@ -449,6 +1377,11 @@ impl TestTimeline {
         self.inner.handle_live_event(raw, None, &self.own_user_id).await;
     }
 
+    async fn handle_read_receipts(&self, content: JsonValue) {
+        let content = serde_json::from_value(content).unwrap();
+        self.inner.handle_read_receipts(content, &self.own_user_id).await;
+    }
+
     async fn handle_local_event(&self, content: AnyMessageLikeEventContent) -> OwnedTransactionId {
         let txn_id = TransactionId::new();
         self.inner.handle_local_event(txn_id.clone(), content, &self.own_user_id).await;
@@ -456,6 +1389,89 @@ impl TestTimeline {
     }
 }
 
+fn text_event(
+    sender: &UserId,
+    event_id: &EventId,
+    body: &str,
+    thread_root: Option<&EventId>,
+) -> JsonValue {
+    let mut content = json!({
+        "body": body,
+        "msgtype": "m.text",
+    });
+    if let Some(thread_root) = thread_root {
+        content["m.relates_to"] = json!({
+            "rel_type": "m.thread",
+            "event_id": thread_root,
+        });
+    }
+
+    json!({
+        "content": content,
+        "sender": sender,
+        "event_id": event_id,
+        "origin_server_ts": next_server_ts(),
+        "type": "m.room.message",
+    })
+}
+
+fn member_event(event_id: &str, user_id: &UserId) -> JsonValue {
+    json!({
+        "content": { "membership": "join" },
+        "event_id": event_id,
+        "origin_server_ts": next_server_ts(),
+        "sender": user_id,
+        "state_key": user_id,
+        "type": "m.room.member",
+    })
+}
+
+fn poll_start_event(sender: &UserId, event_id: &EventId) -> JsonValue {
+    json!({
+        "content": {
+            "org.matrix.msc3381.poll.start": {
+                "question": { "org.matrix.msc1767.text": "Pizza or pasta?" },
+                "kind": "org.matrix.msc3381.poll.disclosed",
+                "max_selections": 1,
+                "answers": [
+                    { "id": "pizza", "org.matrix.msc1767.text": "Pizza" },
+                    { "id": "pasta", "org.matrix.msc1767.text": "Pasta" },
+                ],
+            },
+        },
+        "sender": sender,
+        "event_id": event_id,
+        "origin_server_ts": next_server_ts(),
+        "type": "org.matrix.msc3381.poll.start",
+    })
+}
+
+fn poll_response_event(sender: &UserId, poll_start_id: &EventId, answers: &[&str]) -> JsonValue {
+    json!({
+        "content": {
+            "org.matrix.msc3381.poll.response": { "answers": answers },
+            "m.relates_to": { "rel_type": "m.reference", "event_id": poll_start_id },
+        },
+        "sender": sender,
+        "event_id": EventId::new(server_name!("dummy.server")),
+        "origin_server_ts": next_server_ts(),
+        "type": "org.matrix.msc3381.poll.response",
+    })
+}
+
+fn poll_end_event(sender: &UserId, poll_start_id: &EventId) -> JsonValue {
+    json!({
+        "content": {
+            "org.matrix.msc3381.poll.end": {},
+            "m.relates_to": { "rel_type": "m.reference", "event_id": poll_start_id },
+        },
+        "sender": sender,
+        "event_id": EventId::new(server_name!("dummy.server")),
+        "origin_server_ts": next_server_ts(),
+        "type": "org.matrix.msc3381.poll.end",
+    })
+}
+
 fn next_server_ts() -> MilliSecondsSinceUnixEpoch {
     static NEXT_TS: AtomicU32 = AtomicU32::new(0);
     MilliSecondsSinceUnixEpoch(NEXT_TS.fetch_add(1, SeqCst).into())
@@ -12,11 +12,25 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use ruma::MilliSecondsSinceUnixEpoch;
+
+use super::EventTimelineItem;
+
 /// A [`TimelineItem`](super::TimelineItem) that doesn't correspond to an event.
 #[derive(Clone, Debug)]
 pub enum VirtualTimelineItem {
     /// A divider between messages of two days.
-    DayDivider,
+    DayDivider {
+        /// The timestamp of the first event of the day.
+        timestamp: MilliSecondsSinceUnixEpoch,
+        /// The formatted date of the day.
+        date: String,
+    },
     /// The user's own read marker.
     ReadMarker,
+    /// Consecutive membership and profile changes, collapsed into one item.
+    ///
+    /// Only created if enabled through
+    /// [`TimelineBuilder::collapse_membership_changes`][super::TimelineBuilder::collapse_membership_changes].
+    MembershipChanges(Vec<EventTimelineItem>),
 }
@@ -0,0 +1,624 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A persistent queue of events to send to rooms.
+//!
+//! See [`SendQueue`] for details.
+
+use std::collections::VecDeque;
+
+use futures_core::Stream;
+use futures_signals::signal::{Mutable, Signal, SignalExt};
+use matrix_sdk_common::{
+    executor::spawn,
+    locks::{Mutex, MutexGuard},
+};
+use mime::Mime;
+use ruma::{
+    api::{client::error::ErrorKind, error::FromHttpResponseError},
+    events::MessageLikeEventContent,
+    OwnedRoomId, OwnedTransactionId, RoomId, TransactionId,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{debug, error, instrument, warn};
+
+use crate::{attachment::AttachmentConfig, room, Client, Error, HttpError, Result, RumaApiError};
+
+/// The key under which the transaction IDs of the persisted events are stored
+/// in the state store.
+///
+/// Each event is stored under its own key, derived from this one, so that
+/// changing an event doesn't require writing the whole queue again.
+const SEND_QUEUE_KEY: &str = "matrix-sdk.send_queue";
+
+/// An event waiting in the [`SendQueue`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QueuedEvent {
+    /// The room the event will be sent to.
+    pub room_id: OwnedRoomId,
+
+    /// The transaction ID used to send the event.
+    pub txn_id: OwnedTransactionId,
+
+    /// The type of the event.
+    pub event_type: String,
+
+    /// The content of the event.
+    ///
+    /// This is `null` for attachments, whose content is only known once the
+    /// media was uploaded.
+    pub content: Value,
+
+    /// The attachment to upload and send, if this event is an attachment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attachment: Option<QueuedAttachment>,
+
+    /// The reason why the event couldn't be sent, if the homeserver rejected
+    /// it.
+    ///
+    /// Events that failed to be sent stay in the queue without blocking the
+    /// events after them, until they are sent again with
+    /// [`SendQueue::retry`] or removed with [`SendQueue::cancel`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// An attachment waiting in the [`SendQueue`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QueuedAttachment {
+    /// A textual representation of the media, usually the file name.
+    pub body: String,
+
+    /// The content type of the media.
+    pub content_type: String,
+}
+
+/// The state of the [`SendQueue`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SendQueueState {
+    /// No events are waiting to be sent.
+    #[default]
+    Idle,
+
+    /// Queued events are being sent.
+    Sending {
+        /// The number of events waiting to be sent.
+        pending: usize,
+    },
+
+    /// Sending the queued events failed because the homeserver couldn't be
+    /// reached. They will be sent again after the next successful sync.
+    Offline {
+        /// The number of events waiting to be sent.
+        pending: usize,
+    },
+
+    /// No events are waiting to be sent, but some events were rejected by the
+    /// homeserver.
+    ///
+    /// These events can be found with [`SendQueue::pending_events`].
+    Failed {
+        /// The number of events that failed to be sent.
+        failed: usize,
+    },
+}
+
+#[derive(Default)]
+pub(crate) struct SendQueueInner {
+    /// The queued events, or `None` if they haven't been loaded from the store
+    /// yet.
+    entries: Mutex<Option<VecDeque<QueueEntry>>>,
+    /// Lock making sure the queue is only flushed by one task at a time.
+    flush_lock: Mutex<()>,
+    state: Mutable<SendQueueState>,
+}
+
+struct QueueEntry {
+    event: QueuedEvent,
+    /// Whether the event is saved in the state store.
+    persisted: bool,
+    /// The data of the attachment, if it is only kept in memory.
+    data: Option<Vec<u8>>,
+}
+
+/// A persistent, per-client queue of message-like events to send to rooms.
+///
+/// Events added to the queue are saved in the state store before they are
+/// sent, and are sent in the order they were added. If the homeserver can't be
+/// reached, the remaining events stay in the queue until connectivity returns,
+/// which is detected by the next successful sync. Because the queue is
+/// persisted, it also survives restarts of the application.
+///
+/// Events for encrypted rooms are persisted in plaintext, which the store
+/// encrypts with its store cipher, like the sled and IndexedDB stores do when
+/// they are opened with a passphrase. If the store has no store cipher, events
+/// for encrypted rooms, or rooms whose encryption state isn't known yet, are
+/// only kept in memory so their plaintext never reaches the disk. They are
+/// lost if the application is restarted before they could be sent.
+#[derive(Debug, Clone)]
+pub struct SendQueue {
+    client: Client,
+}
+
+impl SendQueue {
+    pub(crate) fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// Add a message-like event to the queue, and start sending the queue in
+    /// the background.
+    ///
+    /// Returns the transaction ID of the event, which can be used to recognize
+    /// the remote echo of the event or to remove it from the queue with
+    /// [`cancel`](Self::cancel).
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The ID of the joined room the event should be sent to.
+    ///
+    /// * `content` - The content of the event.
+    ///
+    /// * `txn_id` - A locally-unique ID describing a message transaction with
+    ///   the homeserver. If `None`, a new one is created.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::{Client, ruma::room_id};
+    /// # use url::Url;
+    /// # async {
+    /// # let homeserver = Url::parse("http://localhost:8080")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// use matrix_sdk::ruma::events::room::message::RoomMessageEventContent;
+    ///
+    /// let content = RoomMessageEventContent::text_plain("Hello world");
+    /// let room_id = room_id!("!test:localhost");
+    ///
+    /// client.send_queue().enqueue(room_id, content, None).await?;
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub async fn enqueue(
+        &self,
+        room_id: &RoomId,
+        content: impl MessageLikeEventContent,
+        txn_id: Option<&TransactionId>,
+    ) -> Result<OwnedTransactionId> {
+        let event_type = content.event_type().to_string();
+        let content = serde_json::to_value(&content)?;
+
+        self.enqueue_raw(room_id, content, &event_type, txn_id).await
+    }
+
+    /// Add a message-like event to the queue from a json `Value`, and start
+    /// sending the queue in the background.
+    ///
+    /// This method is equivalent to [`enqueue`](Self::enqueue) but allows
+    /// queueing custom JSON payloads.
+    pub async fn enqueue_raw(
+        &self,
+        room_id: &RoomId,
+        content: Value,
+        event_type: &str,
+        txn_id: Option<&TransactionId>,
+    ) -> Result<OwnedTransactionId> {
+        let txn_id = txn_id.map_or_else(TransactionId::new, ToOwned::to_owned);
+        let event = QueuedEvent {
+            room_id: room_id.to_owned(),
+            txn_id: txn_id.clone(),
+            event_type: event_type.to_owned(),
+            content,
+            attachment: None,
+            error: None,
+        };
+
+        self.push(event, None).await?;
+        Ok(txn_id)
+    }
+
+    /// Add an attachment to the queue, and start sending the queue in the
+    /// background.
+    ///
+    /// The media is uploaded when the attachment is sent, like with
+    /// [`Joined::send_attachment`](crate::room::Joined::send_attachment).
+    ///
+    /// Returns the transaction ID of the event containing the attachment.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The ID of the joined room the attachment should be sent
+    ///   to.
+    ///
+    /// * `body` - A textual representation of the media that is going to be
+    ///   uploaded. Usually the file name.
+    ///
+    /// * `content_type` - The type of the media, this will be used as the
+    ///   content-type header.
+    ///
+    /// * `data` - The raw bytes of the media.
+    ///
+    /// * `txn_id` - A locally-unique ID describing a message transaction with
+    ///   the homeserver. If `None`, a new one is created.
+    pub async fn enqueue_attachment(
+        &self,
+        room_id: &RoomId,
+        body: &str,
+        content_type: &Mime,
+        data: Vec<u8>,
+        txn_id: Option<&TransactionId>,
+    ) -> Result<OwnedTransactionId> {
+        let txn_id = txn_id.map_or_else(TransactionId::new, ToOwned::to_owned);
+        let event = QueuedEvent {
+            room_id: room_id.to_owned(),
+            txn_id: txn_id.clone(),
+            event_type: "m.room.message".to_owned(),
+            content: Value::Null,
+            attachment: Some(QueuedAttachment {
+                body: body.to_owned(),
+                content_type: content_type.to_string(),
+            }),
+            error: None,
+        };
+
+        self.push(event, Some(data)).await?;
+        Ok(txn_id)
+    }
+
+    /// Remove an event from the queue.
+    ///
+    /// Returns `false` if there was no event with the given transaction ID in
+    /// the queue.
+    ///
+    /// Note that an event that is currently being sent might still arrive in
+    /// the room.
+    pub async fn cancel(&self, txn_id: &TransactionId) -> Result<bool> {
+        let mut entries_lock = self.lock_entries().await?;
+        let entries = entries_lock.as_mut().expect("send queue was loaded");
+
+        let Some(entry) = remove_entry(entries, txn_id) else { return Ok(false) };
+        if entry.persisted {
+            self.remove_persisted(&entry.event).await?;
+            self.save_index(entries).await?;
+        }
+        self.update_state_after_cancel(entries);
+
+        Ok(true)
+    }
+
+    /// Send an event that was rejected by the homeserver again.
+    ///
+    /// Returns `false` if there was no event that failed to be sent with the
+    /// given transaction ID in the queue.
+    pub async fn retry(&self, txn_id: &TransactionId) -> Result<bool> {
+        {
+            let mut entries_lock = self.lock_entries().await?;
+            let entries = entries_lock.as_mut().expect("send queue was loaded");
+
+            let entry = entries.iter_mut().find(|entry| entry.event.txn_id == txn_id);
+            let Some(entry) = entry.filter(|entry| entry.event.error.is_some()) else {
+                return Ok(false);
+            };
+
+            entry.event.error = None;
+            if entry.persisted {
+                self.save_event(&entry.event).await?;
+            }
+        }
+
+        self.flush_in_background();
+        Ok(true)
+    }
+
+    /// Get the events that are waiting to be sent, in order.
+    ///
+    /// This includes the events that were rejected by the homeserver, see
+    /// [`QueuedEvent::error`].
+    pub async fn pending_events(&self) -> Result<Vec<QueuedEvent>> {
+        let entries = self.lock_entries().await?;
+        let entries = entries.as_ref().expect("send queue was loaded");
+        Ok(entries.iter().map(|entry| entry.event.clone()).collect())
+    }
+
+    /// Get the current state of the queue.
+    pub fn state(&self) -> SendQueueState {
+        self.client.inner.send_queue.state.get()
+    }
+
+    /// Get a signal of the state of the queue.
+    ///
+    /// This can be used to show an "offline, will send later" status.
+    pub fn state_signal(&self) -> impl Signal<Item = SendQueueState> {
+        self.client.inner.send_queue.state.signal()
+    }
+
+    /// Get a stream of the state of the queue.
+    ///
+    /// This is a convenience shorthand for `queue.state_signal().to_stream()`.
+    pub fn state_stream(&self) -> impl Stream<Item = SendQueueState> {
+        self.state_signal().to_stream()
+    }
+
+    /// Try to send all the events in the queue, in order.
+    ///
+    /// This is started automatically in the background after every successful
+    /// sync and whenever an event is added to the queue, but can also be
+    /// called manually, e.g. when the application knows that connectivity has
+    /// returned.
+    ///
+    /// Sending stops at the first event that can't be sent because the
+    /// homeserver couldn't be reached. Events that are rejected by the
+    /// homeserver, or whose room isn't joined anymore, are kept in the queue
+    /// with an [`error`](QueuedEvent::error) and skipped.
+    #[instrument(skip(self))]
+    pub async fn flush(&self) -> Result<()> {
+        let inner = &self.client.inner.send_queue;
+        let _guard = inner.flush_lock.lock().await;
+
+        loop {
+            let (event, data, pending) = {
+                let entries = self.lock_entries().await?;
+                let entries = entries.as_ref().expect("send queue was loaded");
+
+                let pending = entries.iter().filter(|entry| entry.event.error.is_none()).count();
+                let Some(entry) = entries.iter().find(|entry| entry.event.error.is_none()) else {
+                    inner.state.set(idle_state(entries));
+                    return Ok(());
+                };
+
+                (entry.event.clone(), entry.data.clone(), pending)
+            };
+
+            inner.state.set(SendQueueState::Sending { pending });
+
+            let QueuedEvent { room_id, txn_id, .. } = &event;
+            let Some(room) = self.client.get_joined_room(room_id) else {
+                warn!(%room_id, %txn_id, "Room of queued event is not joined");
+                self.set_failed(txn_id, "The room is not joined".to_owned()).await?;
+                continue;
+            };
+
+            match self.send_event(&room, &event, data).await {
+                Ok(()) => {
+                    debug!(%room_id, %txn_id, "Sent queued event");
+                    self.remove_sent(txn_id).await?;
+                }
+                Err(error) if is_transient_error(&error) => {
+                    debug!(%room_id, %txn_id, "Homeserver unreachable, keeping queued events");
+                    inner.state.set(SendQueueState::Offline { pending });
+                    return Ok(());
+                }
+                Err(error) => {
+                    warn!(%room_id, %txn_id, "Failed to send queued event: {error}");
+                    self.set_failed(txn_id, error.to_string()).await?;
+                }
+            }
+        }
+    }
+
+    /// Try to send all the events in the queue from a background task.
+    pub(crate) fn flush_in_background(&self) {
+        let queue = self.clone();
+        spawn(async move {
+            if let Err(e) = queue.flush().await {
+                error!(error = ?e, "Error while sending queued events");
+            }
+        });
+    }
+
+    async fn send_event(
+        &self,
+        room: &room::Joined,
+        event: &QueuedEvent,
+        data: Option<Vec<u8>>,
+    ) -> Result<()> {
+        let QueuedEvent { txn_id, event_type, content, attachment, .. } = event;
+
+        let Some(attachment) = attachment else {
+            room.send_raw(content.clone(), event_type, Some(txn_id)).await?;
+            return Ok(());
+        };
+
+        let data =
+            match data {
+                Some(data) => data,
+                None => self.client.store().get_custom_value(&data_key(txn_id)).await?.ok_or_else(
+                    || Error::UnknownError("Missing data of queued attachment".into()),
+                )?,
+            };
+        let content_type: Mime = attachment
+            .content_type
+            .parse()
+            .map_err(|_| Error::UnknownError("Invalid content type of queued attachment".into()))?;
+
+        let config = AttachmentConfig::new().txn_id(txn_id);
+        room.send_attachment(&attachment.body, &content_type, data, config).await?;
+
+        Ok(())
+    }
+
+    /// Add an event to the queue, persisting it if the store encrypts it or if
+    /// its room is known not to be encrypted.
+    async fn push(&self, event: QueuedEvent, data: Option<Vec<u8>>) -> Result<()> {
+        let persisted =
+            self.client.store().has_store_cipher()
+                || self.client.base_client().get_room(&event.room_id).map_or(false, |room| {
+                    room.is_encryption_state_synced() && !room.is_encrypted()
+                });
+
+        {
+            let mut entries_lock = self.lock_entries().await?;
+            let entries = entries_lock.as_mut().expect("send queue was loaded");
+
+            let data = if persisted {
+                if let Some(data) = data {
+                    self.client.store().set_custom_value(&data_key(&event.txn_id), data).await?;
+                }
+                self.save_event(&event).await?;
+                None
+            } else {
+                data
+            };
+
+            entries.push_back(QueueEntry { event, persisted, data });
+            if persisted {
+                self.save_index(entries).await?;
+            }
+        }
+
+        self.flush_in_background();
+        Ok(())
+    }
+
+    /// Remove an event that was sent successfully from the queue.
+    async fn remove_sent(&self, txn_id: &TransactionId) -> Result<()> {
+        let mut entries_lock = self.lock_entries().await?;
+        let entries = entries_lock.as_mut().expect("send queue was loaded");
+
+        // The event might have been cancelled while it was being sent.
+        if let Some(entry) = remove_entry(entries, txn_id) {
+            if entry.persisted {
+                self.remove_persisted(&entry.event).await?;
+                self.save_index(entries).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Mark an event as rejected by the homeserver.
+    async fn set_failed(&self, txn_id: &TransactionId, error: String) -> Result<()> {
+        let mut entries_lock = self.lock_entries().await?;
+        let entries = entries_lock.as_mut().expect("send queue was loaded");
+
+        if let Some(entry) = entries.iter_mut().find(|entry| entry.event.txn_id == txn_id) {
+            entry.event.error = Some(error);
+            if entry.persisted {
+                self.save_event(&entry.event).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Update the state of the queue after an event was cancelled.
+    fn update_state_after_cancel(&self, entries: &VecDeque<QueueEntry>) {
+        let state = &self.client.inner.send_queue.state;
+        let pending = entries.iter().filter(|entry| entry.event.error.is_none()).count();
+
+        match state.get() {
+            // The flush will update the state.
+            SendQueueState::Sending { .. } => {}
+            SendQueueState::Offline { .. } if pending > 0 => {
+                state.set(SendQueueState::Offline { pending });
+            }
+            _ if pending == 0 => state.set(idle_state(entries)),
+            _ => {}
+        }
+    }
+
+    /// Save the transaction IDs of the persisted events, in order.
+    async fn save_index(&self, entries: &VecDeque<QueueEntry>) -> Result<()> {
+        let txn_ids: Vec<_> = entries
+            .iter()
+            .filter(|entry| entry.persisted)
+            .map(|entry| &entry.event.txn_id)
+            .collect();
+        let key = SEND_QUEUE_KEY.as_bytes();
+        self.client.store().set_custom_value(key, serde_json::to_vec(&txn_ids)?).await?;
+        Ok(())
+    }
+
+    async fn save_event(&self, event: &QueuedEvent) -> Result<()> {
+        let value = serde_json::to_vec(event)?;
+        self.client.store().set_custom_value(&event_key(&event.txn_id), value).await?;
+        Ok(())
+    }
+
+    async fn remove_persisted(&self, event: &QueuedEvent) -> Result<()> {
+        let store = self.client.store();
+        store.remove_custom_value(&event_key(&event.txn_id)).await?;
+        if event.attachment.is_some() {
+            store.remove_custom_value(&data_key(&event.txn_id)).await?;
+        }
+        Ok(())
+    }
+
+    /// Lock the queued events, loading them from the store first if necessary.
+    ///
+    /// The returned guard always contains `Some(_)`.
+    async fn lock_entries(&self) -> Result<MutexGuard<'_, Option<VecDeque<QueueEntry>>>> {
+        let mut entries_lock = self.client.inner.send_queue.entries.lock().await;
+
+        if entries_lock.is_none() {
+            let store = self.client.store();
+            let txn_ids: Vec<OwnedTransactionId> =
+                match store.get_custom_value(SEND_QUEUE_KEY.as_bytes()).await? {
+                    Some(bytes) => serde_json::from_slice(&bytes)?,
+                    None => Vec::new(),
+                };
+
+            let mut entries = VecDeque::with_capacity(txn_ids.len());
+            for txn_id in txn_ids {
+                let Some(bytes) = store.get_custom_value(&event_key(&txn_id)).await? else {
+                    warn!(%txn_id, "Queued event is missing from the store");
+                    continue;
+                };
+                let event = serde_json::from_slice(&bytes)?;
+                entries.push_back(QueueEntry { event, persisted: true, data: None });
+            }
+
+            *entries_lock = Some(entries);
+        }
+
+        Ok(entries_lock)
+    }
+}
+
+fn event_key(txn_id: &TransactionId) -> Vec<u8> {
+    format!("{SEND_QUEUE_KEY}.{txn_id}").into_bytes()
+}
+
+fn data_key(txn_id: &TransactionId) -> Vec<u8> {
+    format!("{SEND_QUEUE_KEY}.{txn_id}.data").into_bytes()
+}
+
+/// The state of the queue once all the events that can be sent were sent.
+fn idle_state(entries: &VecDeque<QueueEntry>) -> SendQueueState {
+    match entries.iter().filter(|entry| entry.event.error.is_some()).count() {
+        0 => SendQueueState::Idle,
+        failed => SendQueueState::Failed { failed },
+    }
+}
+
+fn remove_entry(entries: &mut VecDeque<QueueEntry>, txn_id: &TransactionId) -> Option<QueueEntry> {
+    let idx = entries.iter().position(|entry| entry.event.txn_id == txn_id)?;
+    entries.remove(idx)
+}
+
+/// Whether the given error means that the request could succeed if it was
+/// sent again later.
+fn is_transient_error(error: &Error) -> bool {
+    let Error::Http(error) = error else {
+        return false;
+    };
+
+    match error {
+        HttpError::Reqwest(_) => true,
+        HttpError::Api(FromHttpResponseError::Server(RumaApiError::ClientApi(e))) => {
+            e.status_code.is_server_error()
+                || matches!(error.client_api_error_kind(), Some(ErrorKind::LimitExceeded { .. }))
+        }
+        _ => false,
+    }
+}
@@ -45,7 +45,7 @@ use url::Url;
 
 #[cfg(feature = "experimental-timeline")]
 use crate::room::timeline::{EventTimelineItem, Timeline};
-use crate::{Client, HttpError, Result, RumaApiError};
+use crate::{Client, Feature, HttpError, Result, RumaApiError};
 
 /// Internal representation of errors in Sliding Sync
 #[derive(Error, Debug)]
@@ -733,6 +733,11 @@ impl SlidingSync {
     pub async fn stream<'a>(
         &self,
     ) -> Result<impl Stream<Item = Result<UpdateSummary, crate::Error>> + '_> {
+        // A separate proxy is expected to support sliding sync.
+        if self.homeserver.is_none() {
+            self.client.ensure_supported(Feature::SlidingSync).await?;
+        }
+
         let views = self.views.lock_ref().to_vec();
         let extensions = self.extensions.clone();
         let client = self.client.clone();
@@ -4,7 +4,8 @@ use std::{collections::BTreeMap, time::Duration};
 
 pub use matrix_sdk_base::sync::*;
 use matrix_sdk_base::{
-    deserialized_responses::AmbiguityChanges, instant::Instant,
+    deserialized_responses::{AmbiguityChanges, EncryptionInfo, SyncTimelineEvent},
+    instant::Instant,
     sync::SyncResponse as BaseSyncResponse,
 };
 use ruma::{
@@ -12,14 +13,16 @@ use ruma::{
         push::get_notifications::v3::Notification,
         sync::sync_events::{self, v3::Presence, DeviceLists},
     },
-    events::{AnyGlobalAccountDataEvent, AnyToDeviceEvent},
+    events::{
+        presence::PresenceEvent, AnyGlobalAccountDataEvent, AnySyncTimelineEvent, AnyToDeviceEvent,
+    },
     serde::Raw,
-    DeviceKeyAlgorithm, OwnedRoomId,
+    DeviceKeyAlgorithm, OwnedEventId, OwnedRoomId, RoomId,
 };
 use serde::{Deserialize, Serialize};
 use tracing::{debug, error, warn};
 
-use crate::{event_handler::HandlerKind, Client, Result};
+use crate::{event_handler::HandlerKind, Client, NotificationItem, Result};
 
 /// The processed response of a `/sync` request.
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
@@ -87,6 +90,25 @@ impl Client {
         Ok(response)
     }
 
+    fn update_presence_observers(&self, events: &[Raw<PresenceEvent>]) {
+        // Drop the observers whose signals were all dropped.
+        self.inner.presence_observers.retain(|_, (_, handle)| handle.strong_count() > 0);
+
+        for raw in events {
+            let event = match raw.deserialize() {
+                Ok(event) => event,
+                Err(error) => {
+                    warn!(?error, "Failed to deserialize presence event");
+                    continue;
+                }
+            };
+
+            if let Some(observer) = self.inner.presence_observers.get(&event.sender) {
+                observer.0.set(Some(event.content));
+            }
+        }
+    }
+
     #[tracing::instrument(skip(self, response))]
     pub(crate) async fn handle_sync_response(&self, response: &BaseSyncResponse) -> Result<()> {
         let BaseSyncResponse {
@@ -103,6 +125,7 @@ impl Client {
         let now = Instant::now();
         self.handle_sync_events(HandlerKind::GlobalAccountData, &None, account_data).await?;
         self.handle_sync_events(HandlerKind::Presence, &None, &presence.events).await?;
+        self.update_presence_observers(&presence.events);
         self.handle_sync_events(HandlerKind::ToDevice, &None, to_device_events).await?;
 
         for (room_id, room_info) in &rooms.join {
@@ -153,6 +176,21 @@ impl Client {
             .await?;
         }
 
+        for (room_id, room_info) in &rooms.knock {
+            let room = self.get_room(room_id);
+            if room.is_none() {
+                error!(%room_id, "Can't call event handler, room not found");
+                continue;
+            }
+
+            self.handle_sync_events(
+                HandlerKind::StrippedState,
+                &room,
+                &room_info.knock_state.events,
+            )
+            .await?;
+        }
+
         debug!("Ran event handlers in {:?}", now.elapsed());
 
         let now = Instant::now();
@@ -180,6 +218,27 @@ impl Client {
 
         debug!("Ran notification handlers in {:?}", now.elapsed());
 
+        if self.has_notification_listeners() {
+            for (room_id, room_notifications) in notifications {
+                let Some(room) = self.get_room(room_id) else {
+                    warn!(%room_id, "Can't resolve notifications, room not found");
+                    continue;
+                };
+
+                for notification in room_notifications {
+                    let event = SyncTimelineEvent {
+                        event: notification.event.clone(),
+                        encryption_info: encryption_info(rooms, room_id, &notification.event),
+                    };
+                    let actions = notification.actions.clone();
+                    match NotificationItem::from_sync(&room, &event, actions).await {
+                        Ok(item) => self.broadcast_notification(item),
+                        Err(error) => warn!(%room_id, ?error, "Failed to resolve notification"),
+                    }
+                }
+            }
+        }
+
         Ok(())
     }
 
@@ -224,3 +283,25 @@ impl Client {
         *last_sync_time = Some(now);
     }
 }
+
+/// Find the encryption info of the given event in the timeline of its room in
+/// a sync response.
+///
+/// Returns `None` if the event wasn't encrypted or isn't in the timeline.
+fn encryption_info(
+    rooms: &Rooms,
+    room_id: &RoomId,
+    event: &Raw<AnySyncTimelineEvent>,
+) -> Option<EncryptionInfo> {
+    let event_id = event.get_field::<OwnedEventId>("event_id").ok().flatten()?;
+    let timeline = match rooms.join.get(room_id) {
+        Some(room) => &room.timeline,
+        None => &rooms.leave.get(room_id)?.timeline,
+    };
+
+    timeline
+        .events
+        .iter()
+        .find(|e| e.event_id().as_ref() == Some(&event_id))
+        .and_then(|e| e.encryption_info.clone())
+}
@@ -1,11 +1,27 @@
-use std::{collections::BTreeMap, str::FromStr, time::Duration};
+use std::{
+    collections::BTreeMap,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use assert_matches::assert_matches;
+use futures::{FutureExt, StreamExt};
+use futures_signals::signal::SignalExt;
 
 use matrix_sdk::{
-    config::SyncSettings,
+    config::{RequestConfig, SyncSettings},
     media::{MediaFormat, MediaRequest, MediaThumbnailSize},
-    RumaApiError, Session,
+    Client, Error, Feature, RateLimitClass, RumaApiError, Session,
+};
+use matrix_sdk_test::{
+    async_test, test_json, EventBuilder, GlobalAccountDataTestEvent, InvitedRoomBuilder,
+    JoinedRoomBuilder, LeftRoomBuilder, PresenceTestEvent, StateTestEvent, StrippedStateTestEvent,
+    TimelineTestEvent,
 };
-use matrix_sdk_test::{async_test, test_json};
 use ruma::{
     api::client::{
         self as client_api,
@@ -15,19 +31,31 @@ use ruma::{
             get_public_rooms_filtered::{self, v3::Request as PublicRoomsFilterRequest},
         },
         media::get_content_thumbnail::v3::Method,
+        message::send_message_event,
+        profile::get_display_name,
+        redact::redact_event,
         session::get_login_types::v3::LoginType,
         uiaa,
     },
     assign, device_id,
     directory::Filter,
-    events::room::{message::ImageMessageEventContent, ImageInfo, MediaSource},
-    mxc_uri, room_id, uint, user_id,
+    event_id,
+    events::room::{
+        member::OriginalSyncRoomMemberEvent,
+        message::{
+            ImageMessageEventContent, OriginalSyncRoomMessageEvent, RoomMessageEventContent,
+        },
+        ImageInfo, MediaSource,
+    },
+    mxc_uri,
+    presence::PresenceState,
+    room_id, uint, user_id, TransactionId,
 };
 use serde_json::{from_value as from_json_value, json, to_value as to_json_value};
 use url::Url;
 use wiremock::{
-    matchers::{header, method, path, path_regex},
-    Mock, ResponseTemplate,
+    matchers::{body_json, body_partial_json, header, method, path, path_regex, query_param},
+    Mock, MockServer, ResponseTemplate,
 };
 
 use crate::{logged_in_client, mock_sync, no_retry_test_client};
@@ -428,6 +456,43 @@ async fn join_room_by_id_or_alias() {
     );
 }
 
+#[async_test]
+async fn knock() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"/knock/"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_partial_json(json!({ "reason": "Let me in" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::ROOM_ID))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let room_id = room_id!("!testroom:example.org");
+
+    let room = client.knock(room_id.into(), Some("Let me in"), &[]).await.unwrap();
+    assert_eq!(room.room_id(), room_id);
+    assert!(client.get_knocked_room(room_id).is_some());
+    assert_eq!(client.knocked_rooms().len(), 1);
+
+    // The knock is accepted.
+    let mut ev_builder = EventBuilder::new();
+    ev_builder.add_invited_room(InvitedRoomBuilder::new(room_id).add_state_event(
+        StrippedStateTestEvent::Custom(json!({
+            "content": { "membership": "invite" },
+            "sender": "@moderator:example.org",
+            "state_key": "@example:localhost",
+            "type": "m.room.member",
+        })),
+    ));
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    assert!(client.get_knocked_room(room_id).is_none());
+    assert!(client.get_invited_room(room_id).is_some());
+}
+
 #[async_test]
 async fn room_search_all() {
     let (client, server) = no_retry_test_client().await;
@@ -630,3 +695,600 @@ fn serialize_session() {
         })
     );
 }
+
+#[async_test]
+async fn get_presence() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/presence/.*/status"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "presence": "unavailable",
+            "last_active_ago": 420845,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let presence = client.get_presence(user_id!("@alice:localhost")).await.unwrap();
+    assert_eq!(presence.presence, PresenceState::Unavailable);
+    assert_eq!(presence.last_active_ago, Some(uint!(420845)));
+
+    let mut ev_builder = EventBuilder::new();
+    ev_builder.add_presence_event(PresenceTestEvent::Presence);
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    // The presence received from sync doesn't need a request.
+    let presence = client.get_presence(user_id!("@example:localhost")).await.unwrap();
+    assert_eq!(presence.presence, PresenceState::Online);
+    assert_eq!(presence.status_msg.as_deref(), Some("Making cupcakes"));
+}
+
+#[async_test]
+async fn presence_signal() {
+    let (client, server) = logged_in_client().await;
+
+    let signal = client.presence_signal(user_id!("@example:localhost")).await.unwrap();
+    let mut presence_stream = signal.to_stream();
+    assert!(presence_stream.next().await.unwrap().is_none());
+
+    let mut ev_builder = EventBuilder::new();
+    ev_builder.add_presence_event(PresenceTestEvent::Presence);
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    let presence = presence_stream.next().await.unwrap().unwrap();
+    assert_eq!(presence.presence, PresenceState::Online);
+    assert_eq!(presence.status_msg.as_deref(), Some("Making cupcakes"));
+}
+
+#[async_test]
+async fn set_presence_and_idle() {
+    let (client, server) = logged_in_client().await;
+
+    let mut ev_builder = EventBuilder::new();
+    ev_builder.add_presence_event(PresenceTestEvent::Presence);
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let sync_token = client.sync_once(SyncSettings::default()).await.unwrap().next_batch;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/presence/.*/status"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_partial_json(json!({ "presence": "online", "status_msg": "Back soon" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .named("set_presence")
+        .mount(&server)
+        .await;
+
+    // The status message from sync is kept.
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/presence/.*/status"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_partial_json(json!({
+            "presence": "unavailable",
+            "status_msg": "Making cupcakes",
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .named("set_idle")
+        .mount(&server)
+        .await;
+
+    // Syncing doesn't set the user back online while idle.
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/sync"))
+        .and(query_param("set_presence", "unavailable"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::SYNC))
+        .expect(1)
+        .named("idle_sync")
+        .mount(&server)
+        .await;
+
+    client.account().set_presence(PresenceState::Online, Some("Back soon")).await.unwrap();
+
+    assert!(!client.account().is_idle());
+    client.account().set_idle(true).await.unwrap();
+    // Already idle, this is a no-op.
+    client.account().set_idle(true).await.unwrap();
+    assert!(client.account().is_idle());
+
+    client.sync_once(SyncSettings::default().token(sync_token)).await.unwrap();
+}
+
+#[async_test]
+async fn set_idle_failure() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/presence/.*/status"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(500).set_body_json(json!({
+            "errcode": "M_UNKNOWN",
+            "error": "Internal server error",
+        })))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    // The user is not considered idle if the presence couldn't be set, so it
+    // can be retried.
+    client.account().set_idle(true).await.unwrap_err();
+    assert!(!client.account().is_idle());
+    client.account().set_idle(true).await.unwrap_err();
+}
+
+#[async_test]
+async fn ignored_users() {
+    let (client, server) = logged_in_client().await;
+    let spammer = user_id!("@spammer:localhost");
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/account_data/m.ignored_user_list"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_partial_json(json!({ "ignored_users": { spammer.as_str(): {} } })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client.account().ignore_user(spammer).await.unwrap();
+    // The list is only updated from sync.
+    assert!(client.account().ignored_users().is_empty());
+
+    let message_received = Arc::new(AtomicBool::new(false));
+    client.add_event_handler({
+        let message_received = message_received.clone();
+        move |_: OriginalSyncRoomMessageEvent| {
+            let message_received = message_received.clone();
+            async move { message_received.store(true, Ordering::SeqCst) }
+        }
+    });
+
+    // State events of ignored users still apply to the room.
+    let member_received = Arc::new(AtomicBool::new(false));
+    client.add_event_handler({
+        let member_received = member_received.clone();
+        move |_: OriginalSyncRoomMemberEvent| {
+            let member_received = member_received.clone();
+            async move { member_received.store(true, Ordering::SeqCst) }
+        }
+    });
+
+    let mut ev_builder = EventBuilder::new();
+    ev_builder
+        .add_global_account_data_event(GlobalAccountDataTestEvent::Custom(json!({
+            "content": { "ignored_users": { spammer.as_str(): {} } },
+            "type": "m.ignored_user_list",
+        })))
+        .add_joined_room(
+            JoinedRoomBuilder::default()
+                .add_timeline_event(TimelineTestEvent::Custom(json!({
+                    "content": { "body": "Buy my stuff", "msgtype": "m.text" },
+                    "event_id": "$spam:localhost",
+                    "origin_server_ts": 152037280,
+                    "sender": spammer,
+                    "type": "m.room.message",
+                })))
+                .add_timeline_event(TimelineTestEvent::Custom(json!({
+                    "content": { "membership": "leave" },
+                    "event_id": "$leave:localhost",
+                    "origin_server_ts": 152037290,
+                    "sender": spammer,
+                    "state_key": spammer,
+                    "type": "m.room.member",
+                }))),
+        )
+        .add_invited_room(InvitedRoomBuilder::new(room_id!("!spam:localhost")).add_state_event(
+            StrippedStateTestEvent::Custom(json!({
+                "content": { "membership": "invite" },
+                "sender": spammer,
+                "state_key": "@example:localhost",
+                "type": "m.room.member",
+            })),
+        ));
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    assert_eq!(client.account().ignored_users(), [spammer.to_owned()]);
+    assert!(!message_received.load(Ordering::SeqCst));
+    assert!(member_received.load(Ordering::SeqCst));
+    assert!(client.invited_rooms().is_empty());
+}
+
+#[async_test]
+async fn create_dm() {
+    let (client, server) = logged_in_client().await;
+    let bob = user_id!("@bob:localhost");
+    let room_id = room_id!("!dm:localhost");
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/createRoom"))
+        .and(body_partial_json(json!({
+            "invite": [bob],
+            "is_direct": true,
+            "initial_state": [{ "type": "m.room.encryption", "state_key": "" }],
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "room_id": room_id })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/account_data/m.direct"))
+        .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+            "errcode": "M_NOT_FOUND",
+            "error": "Account data not found",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/account_data/m.direct"))
+        .and(body_json(json!({ bob.as_str(): [room_id] })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    assert!(client.get_dm_room(&[bob.to_owned()]).is_none());
+
+    let room = client.create_dm(&[bob.to_owned()], true).await.unwrap();
+    assert_eq!(room.room_id(), room_id);
+
+    // The room is marked as a DM right away.
+    assert_matches!(
+        client.get_dm_room(&[bob.to_owned()]),
+        Some(matrix_sdk::room::Room::Joined(room)) if room.room_id() == room_id
+    );
+}
+
+#[async_test]
+async fn create_dm_not_marked() {
+    let (client, server) = logged_in_client().await;
+    let bob = user_id!("@bob:localhost");
+    let room_id = room_id!("!dm:localhost");
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/createRoom"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "room_id": room_id })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/account_data/m.direct"))
+        .respond_with(ResponseTemplate::new(500).set_body_json(json!({
+            "errcode": "M_UNKNOWN",
+            "error": "Internal server error",
+        })))
+        .mount(&server)
+        .await;
+
+    // The room is still created, and reported with the error.
+    assert_matches!(
+        client.create_dm(&[bob.to_owned()], false).await,
+        Err(Error::DmNotMarked { room_id: id, .. }) if id == room_id
+    );
+    assert!(client.get_joined_room(room_id).is_some());
+    assert!(client.get_dm_room(&[bob.to_owned()]).is_none());
+}
+
+#[async_test]
+async fn accept_dm_invitation() {
+    let (client, server) = logged_in_client().await;
+    let bob = user_id!("@bob:localhost");
+    let room_id = room_id!("!dm:localhost");
+
+    let mut ev_builder = EventBuilder::new();
+    ev_builder.add_invited_room(InvitedRoomBuilder::new(room_id).add_state_event(
+        StrippedStateTestEvent::Custom(json!({
+            "content": {
+                "is_direct": true,
+                "membership": "invite",
+            },
+            "sender": bob,
+            "state_key": "@example:localhost",
+            "type": "m.room.member",
+        })),
+    ));
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/join"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "room_id": room_id })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/account_data/m.direct"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/account_data/m.direct"))
+        .and(body_json(json!({ bob.as_str(): [room_id] })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let room = client.get_invited_room(room_id).unwrap();
+    room.accept_invitation().await.unwrap();
+
+    // The room stays a DM once it is joined.
+    assert_matches!(
+        client.get_dm_room(&[bob.to_owned()]),
+        Some(matrix_sdk::room::Room::Joined(room)) if room.room_id() == room_id
+    );
+}
+
+#[async_test]
+async fn repair_dm_rooms() {
+    let (client, server) = logged_in_client().await;
+    let bob = user_id!("@bob:localhost");
+    let carol = user_id!("@carol:localhost");
+    let room_id = room_id!("!dm:localhost");
+    let left_room_id = room_id!("!left:localhost");
+
+    let mut ev_builder = EventBuilder::new();
+    ev_builder
+        .add_joined_room(JoinedRoomBuilder::new(room_id))
+        .add_left_room(LeftRoomBuilder::new(left_room_id));
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/account_data/m.direct"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            bob.as_str(): [room_id, room_id, left_room_id],
+            carol.as_str(): [left_room_id],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/account_data/m.direct"))
+        .and(body_json(json!({ bob.as_str(): [room_id] })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client.repair_dm_rooms().await.unwrap();
+
+    assert!(client.get_dm_room(&[bob.to_owned()]).is_some());
+    assert!(client.get_dm_room(&[carol.to_owned()]).is_none());
+}
+
+#[async_test]
+async fn notifications() {
+    let (client, server) = logged_in_client().await;
+    let room_id = room_id!("!notifications:localhost");
+    let mut notifications = client.notifications();
+
+    let message = |event_id: &str| {
+        json!({
+            "content": { "body": "Hello example", "msgtype": "m.text" },
+            "event_id": event_id,
+            "origin_server_ts": 152037280,
+            "sender": "@bob:localhost",
+            "type": "m.room.message",
+        })
+    };
+
+    let mut ev_builder = EventBuilder::new();
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_state_event(StateTestEvent::Member)
+            .add_state_event(StateTestEvent::PowerLevels)
+            .add_state_event(StateTestEvent::Custom(json!({
+                "content": {
+                    "avatar_url": "mxc://localhost/bob",
+                    "displayname": "Bob",
+                    "membership": "join",
+                },
+                "event_id": "$bob_join:localhost",
+                "origin_server_ts": 151800140,
+                "sender": "@bob:localhost",
+                "state_key": "@bob:localhost",
+                "type": "m.room.member",
+            })))
+            .add_timeline_event(TimelineTestEvent::Custom(message("$sync:localhost"))),
+    );
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    let item = notifications.next().now_or_never().unwrap().unwrap();
+    assert_eq!(item.room_id, room_id);
+    assert_eq!(
+        item.event.event.get_field::<String>("event_id").unwrap().unwrap(),
+        "$sync:localhost"
+    );
+    assert_eq!(item.sender.as_deref(), Some(user_id!("@bob:localhost")));
+    assert_eq!(item.sender_display_name.as_deref(), Some("Bob"));
+    assert_eq!(item.sender_avatar_url.as_deref(), Some(mxc_uri!("mxc://localhost/bob")));
+    assert!(!item.is_direct);
+    // The message contains the display name of the user.
+    assert!(item.is_highlighted());
+    assert!(notifications.next().now_or_never().is_none());
+
+    let mut event = message("$push:localhost");
+    event["room_id"] = room_id.as_str().into();
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/event/"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(event))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let item = client
+        .get_notification(room_id, event_id!("$push:localhost"))
+        .await
+        .unwrap()
+        .expect("the event should trigger a notification");
+    assert_eq!(
+        item.event.event.get_field::<String>("event_id").unwrap().unwrap(),
+        "$push:localhost"
+    );
+    assert_eq!(item.sender_display_name.as_deref(), Some("Bob"));
+    assert!(item.is_highlighted());
+}
+
+#[async_test]
+async fn rate_limit() {
+    let (client, server) = logged_in_client().await;
+    let user_id = user_id!("@example:localhost");
+    let mut rate_limit_stream = client.rate_limit_signal().to_stream();
+    assert!(rate_limit_stream.next().await.unwrap().is_none());
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/profile/.*/displayname"))
+        .respond_with(ResponseTemplate::new(429).set_body_json(json!({
+            "errcode": "M_LIMIT_EXCEEDED",
+            "error": "Too many requests",
+            "retry_after_ms": 200,
+        })))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/profile/.*/displayname"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "displayname": "Ex" })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let start = Instant::now();
+    let request = get_display_name::v3::Request::new(user_id.to_owned());
+    let response = client.send(request, Some(RequestConfig::new().retry_limit(3))).await.unwrap();
+    assert_eq!(response.displayname.as_deref(), Some("Ex"));
+    assert!(start.elapsed() >= Duration::from_millis(200));
+
+    // The rate limit is forgotten once it is over.
+    assert!(rate_limit_stream.next().await.unwrap().is_none());
+}
+
+#[async_test]
+async fn rate_limit_class() {
+    let (client, server) = logged_in_client().await;
+    let room_id = room_id!("!test:localhost");
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .respond_with(ResponseTemplate::new(429).set_body_json(json!({
+            "errcode": "M_LIMIT_EXCEEDED",
+            "error": "Too many requests",
+            "retry_after_ms": 200,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/redact/.*"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "event_id": "$redaction" })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let content = RoomMessageEventContent::text_plain("Hello");
+    let request =
+        send_message_event::v3::Request::new(room_id.to_owned(), TransactionId::new(), &content)
+            .unwrap();
+    client.send(request, None).await.unwrap_err();
+
+    let rate_limit = client.rate_limit_signal().to_stream().next().await.unwrap().unwrap();
+    assert!(rate_limit.request_type.contains("send_message_event"));
+    assert_eq!(rate_limit.class, RateLimitClass::Message);
+    assert_eq!(rate_limit.retry_after, Some(Duration::from_millis(200)));
+
+    // Other requests of the same class are held back until the end of the
+    // rate limit.
+    let start = Instant::now();
+    let request = redact_event::v3::Request::new(
+        room_id.to_owned(),
+        event_id!("$event").to_owned(),
+        TransactionId::new(),
+    );
+    client.send(request, None).await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(150));
+    assert!(client.rate_limit_signal().to_stream().next().await.unwrap().is_none());
+}
+
+#[async_test]
+async fn server_versions_and_capabilities() {
+    let server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/versions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "versions": ["r0.6.1", "v1.4"],
+            "unstable_features": { "org.matrix.msc3575": true },
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/(r0|v3)/capabilities"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "capabilities": { "m.change_password": { "enabled": false } },
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    // The versions are only requested when they are first needed.
+    let client = Client::builder().homeserver_url(server.uri()).build().await.unwrap();
+    client
+        .restore_session(Session {
+            access_token: "1234".to_owned(),
+            refresh_token: None,
+            user_id: user_id!("@example:localhost").to_owned(),
+            device_id: device_id!("DEVICEID").to_owned(),
+        })
+        .await
+        .unwrap();
+
+    assert!(client.supports(Feature::Threads).await.unwrap());
+    assert!(client.supports(Feature::SlidingSync).await.unwrap());
+    assert!(!client.supports(Feature::ChangePassword).await.unwrap());
+
+    // The capabilities are cached, the password change fails without a request.
+    let error = client.account().change_password("hunter2", None).await.unwrap_err();
+    assert_matches!(error, Error::UnsupportedFeature(Feature::ChangePassword));
+}
+
+#[async_test]
+async fn preset_server_versions() {
+    let server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/versions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "versions": ["r0.6.1", "v1.4"],
+            "unstable_features": { "org.matrix.msc3575": true },
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = Client::builder()
+        .homeserver_url(server.uri())
+        .server_versions([ruma::api::MatrixVersion::V1_4])
+        .build()
+        .await
+        .unwrap();
+
+    // The preset versions are enough to know that these are supported…
+    assert!(client.supports(Feature::Threads).await.unwrap());
+    assert!(client.supports(Feature::Relations).await.unwrap());
+
+    // …but the unstable features have to be requested.
+    assert!(client.supports(Feature::SlidingSync).await.unwrap());
+    assert!(client.supports(Feature::SlidingSync).await.unwrap());
+}
@@ -11,6 +11,11 @@ use wiremock::{
 };
 
 mod client;
+mod notification_settings;
+#[cfg(feature = "experimental-oidc")]
+mod oidc;
+#[cfg(feature = "experimental-qr-login")]
+mod qr_login;
 mod refresh_token;
 mod room;
 
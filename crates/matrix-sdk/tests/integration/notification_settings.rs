@@ -0,0 +1,98 @@
+use matrix_sdk::{notification_settings::RoomNotificationMode, ruma::api::client::push::RuleKind};
+use matrix_sdk_test::async_test;
+use ruma::room_id;
+use serde_json::json;
+use wiremock::{
+    matchers::{body_partial_json, header, method, path_regex},
+    Mock, ResponseTemplate,
+};
+
+use crate::logged_in_client;
+
+#[async_test]
+async fn room_notification_mode() {
+    let (client, server) = logged_in_client().await;
+    let settings = client.notification_settings();
+    let room_id = room_id!("!room:localhost");
+
+    assert_eq!(settings.room_notification_mode(room_id).await.unwrap(), None);
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/pushrules/global/room/.*room.*localhost$"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_partial_json(json!({ "actions": ["dont_notify"] })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .named("set_room_rule")
+        .mount(&server)
+        .await;
+
+    settings
+        .set_room_notification_mode(room_id, RoomNotificationMode::MentionsAndKeywordsOnly)
+        .await
+        .unwrap();
+    assert_eq!(
+        settings.room_notification_mode(room_id).await.unwrap(),
+        Some(RoomNotificationMode::MentionsAndKeywordsOnly)
+    );
+
+    Mock::given(method("DELETE"))
+        .and(path_regex(r"^/_matrix/client/r0/pushrules/global/room/.*room.*localhost$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .named("delete_room_rule")
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/pushrules/global/override/.*room.*localhost$"))
+        .and(body_partial_json(json!({
+            "actions": ["dont_notify"],
+            "conditions": [{ "kind": "event_match", "key": "room_id", "pattern": room_id }],
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .named("set_override_rule")
+        .mount(&server)
+        .await;
+
+    settings.set_room_notification_mode(room_id, RoomNotificationMode::Mute).await.unwrap();
+    assert_eq!(
+        settings.room_notification_mode(room_id).await.unwrap(),
+        Some(RoomNotificationMode::Mute)
+    );
+    let ruleset = settings.push_rules().await.unwrap();
+    assert!(ruleset.room.get(room_id.as_str()).is_none());
+    // The new rule comes right after the master rule.
+    assert_eq!(ruleset.override_.get_index(1).unwrap().rule_id, room_id.as_str());
+}
+
+#[async_test]
+async fn keywords() {
+    let (client, server) = logged_in_client().await;
+    let settings = client.notification_settings();
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/pushrules/global/content/cupcake$"))
+        .and(body_partial_json(json!({ "pattern": "cupcake" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/pushrules/global/content/cupcake/enabled"))
+        .and(body_partial_json(json!({ "enabled": false })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    assert!(settings.keywords().await.unwrap().is_empty());
+    settings.add_keyword("cupcake").await.unwrap();
+    assert_eq!(settings.keywords().await.unwrap(), ["cupcake"]);
+
+    settings.set_push_rule_enabled(RuleKind::Content, "cupcake", false).await.unwrap();
+    let ruleset = settings.push_rules().await.unwrap();
+    let rule = ruleset.content.get_index(0).unwrap();
+    assert_eq!(rule.rule_id, "cupcake");
+    assert!(!rule.enabled);
+}
@@ -0,0 +1,302 @@
+use assert_matches::assert_matches;
+use futures::StreamExt;
+use futures_signals::signal::SignalExt;
+use matrix_sdk::{
+    config::RequestConfig,
+    oidc::{self, ApplicationType, ClientMetadata},
+    Client, Error,
+};
+use matrix_sdk_test::async_test;
+use ruma::{api::MatrixVersion, device_id, user_id};
+use serde_json::json;
+use url::Url;
+use wiremock::{
+    matchers::{body_partial_json, body_string_contains, header, method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+/// Mount a mock OpenID Connect Provider under `/oidc` on the given server.
+async fn mock_provider(server: &MockServer) {
+    let issuer = format!("{}/oidc/", server.uri());
+
+    Mock::given(method("GET"))
+        .and(path("/oidc/.well-known/openid-configuration"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{issuer}authorize"),
+            "token_endpoint": format!("{issuer}token"),
+            "registration_endpoint": format!("{issuer}register"),
+            "revocation_endpoint": format!("{issuer}revoke"),
+            "account_management_uri": format!("{}/account", server.uri()),
+        })))
+        .expect(1)
+        .mount(server)
+        .await;
+}
+
+#[async_test]
+async fn oidc_login() {
+    let server = MockServer::start().await;
+    mock_provider(&server).await;
+
+    let client = Client::builder()
+        .homeserver_url(server.uri())
+        .server_versions([MatrixVersion::V1_0])
+        .authentication_issuer(Url::parse(&format!("{}/oidc/", server.uri())).unwrap())
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+    let oidc = client.oidc();
+    let redirect_uri = Url::parse("http://127.0.0.1:1234/callback").unwrap();
+
+    // Register the client.
+    Mock::given(method("POST"))
+        .and(path("/oidc/register"))
+        .and(body_partial_json(json!({
+            "application_type": "native",
+            "redirect_uris": [redirect_uri],
+            "client_name": "Test client",
+            "token_endpoint_auth_method": "none",
+        })))
+        .respond_with(ResponseTemplate::new(201).set_body_json(json!({
+            "client_id": "client1",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut metadata = ClientMetadata::new(ApplicationType::Native, vec![redirect_uri.clone()]);
+    metadata.client_name = Some("Test client".to_owned());
+    let registered_client = oidc.register_client(&metadata).await.unwrap();
+    assert_eq!(registered_client.client_id, "client1");
+    assert_eq!(oidc.client_id().as_deref(), Some("client1"));
+
+    // Start the authorization code flow.
+    let data = oidc.login_url(&redirect_uri, Some(device_id!("DEVICEID"))).await.unwrap();
+    assert_eq!(data.url.path(), "/oidc/authorize");
+
+    let query = |key: &str| {
+        data.url.query_pairs().find(|(k, _)| k == key).map(|(_, value)| value.into_owned())
+    };
+    assert_eq!(query("response_type").as_deref(), Some("code"));
+    assert_eq!(query("client_id").as_deref(), Some("client1"));
+    assert_eq!(query("redirect_uri").as_deref(), Some(redirect_uri.as_str()));
+    assert_eq!(query("state").as_deref(), Some(data.state.as_str()));
+    assert_eq!(query("code_challenge_method").as_deref(), Some("S256"));
+    assert!(query("code_challenge").is_some());
+    let scope = query("scope").unwrap();
+    assert!(scope.contains("urn:matrix:org.matrix.msc2967.client:api:*"));
+    assert!(scope.contains("urn:matrix:org.matrix.msc2967.client:device:DEVICEID"));
+
+    // Finish the login with the callback.
+    Mock::given(method("POST"))
+        .and(path("/oidc/token"))
+        .and(body_string_contains("grant_type=authorization_code"))
+        .and(body_string_contains("code=authcode"))
+        .and(body_string_contains("code_verifier="))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "1234",
+            "refresh_token": "abcd",
+            "token_type": "Bearer",
+            "expires_in": 300,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/account/whoami"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "user_id": "@example:localhost",
+        })))
+        .mount(&server)
+        .await;
+
+    let mut callback_url = redirect_uri.clone();
+    callback_url
+        .query_pairs_mut()
+        .append_pair("code", "authcode")
+        .append_pair("state", &data.state);
+    oidc.finish_login(&callback_url).await.unwrap();
+
+    let session = client.session().unwrap();
+    assert_eq!(session.user_id, user_id!("@example:localhost"));
+    assert_eq!(session.device_id, device_id!("DEVICEID"));
+    assert_eq!(session.access_token, "1234");
+    assert_eq!(session.refresh_token.as_deref(), Some("abcd"));
+
+    // The state can't be used twice.
+    oidc.finish_login(&callback_url).await.unwrap_err();
+
+    // Refresh the access token with the Provider.
+    Mock::given(method("POST"))
+        .and(path("/oidc/token"))
+        .and(body_string_contains("grant_type=refresh_token"))
+        .and(body_string_contains("refresh_token=abcd"))
+        .and(body_string_contains("client_id=client1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "5678",
+            "refresh_token": "efgh",
+            "token_type": "Bearer",
+            "expires_in": 300,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut tokens_stream = client.session_tokens_signal().to_stream();
+    let tokens = tokens_stream.next().await.flatten().unwrap();
+    assert_eq!(tokens.access_token, "1234");
+
+    client.refresh_access_token().await.unwrap();
+
+    let tokens = tokens_stream.next().await.flatten().unwrap();
+    assert_eq!(tokens.access_token, "5678");
+    assert_eq!(tokens.refresh_token.as_deref(), Some("efgh"));
+
+    // Discover the account management URL.
+    let account_management_url = oidc.account_management_url().await.unwrap().unwrap();
+    assert_eq!(account_management_url.as_str(), format!("{}/account", server.uri()));
+
+    // Log out by revoking both tokens.
+    Mock::given(method("POST"))
+        .and(path("/oidc/revoke"))
+        .and(body_string_contains("token=efgh"))
+        .and(body_string_contains("token_type_hint=refresh_token"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/oidc/revoke"))
+        .and(body_string_contains("token=5678"))
+        .and(body_string_contains("token_type_hint=access_token"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    oidc.logout().await.unwrap();
+}
+
+#[async_test]
+async fn oidc_login_error() {
+    let server = MockServer::start().await;
+    mock_provider(&server).await;
+
+    let client = Client::builder()
+        .homeserver_url(server.uri())
+        .server_versions([MatrixVersion::V1_0])
+        .authentication_issuer(Url::parse(&format!("{}/oidc/", server.uri())).unwrap())
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+    let oidc = client.oidc();
+    let redirect_uri = Url::parse("http://127.0.0.1:1234/callback").unwrap();
+
+    // Logging in requires a registered client.
+    assert_matches!(
+        oidc.login_url(&redirect_uri, None).await,
+        Err(Error::Oidc(oidc::Error::NotRegistered))
+    );
+
+    oidc.restore_registered_client("client1".to_owned());
+    let data = oidc.login_url(&redirect_uri, None).await.unwrap();
+
+    let mut callback_url = redirect_uri.clone();
+    callback_url
+        .query_pairs_mut()
+        .append_pair("error", "access_denied")
+        .append_pair("state", &data.state);
+
+    assert_matches!(
+        oidc.finish_login(&callback_url).await,
+        Err(Error::Oidc(oidc::Error::Provider(error)))
+            if error.error == "access_denied"
+    );
+    assert!(!client.logged_in());
+}
+
+#[async_test]
+async fn oidc_issuer_mismatch() {
+    let server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path("/oidc/.well-known/openid-configuration"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "issuer": "https://evil.example.org/",
+            "authorization_endpoint": "https://evil.example.org/authorize",
+            "token_endpoint": "https://evil.example.org/token",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = Client::builder()
+        .homeserver_url(server.uri())
+        .server_versions([MatrixVersion::V1_0])
+        .authentication_issuer(Url::parse(&format!("{}/oidc/", server.uri())).unwrap())
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+
+    assert_matches!(
+        client.oidc().provider_metadata().await,
+        Err(Error::Oidc(oidc::Error::IssuerMismatch { got, .. }))
+            if got.as_str() == "https://evil.example.org/"
+    );
+}
+
+#[async_test]
+async fn oidc_login_device_id_mismatch() {
+    let server = MockServer::start().await;
+    mock_provider(&server).await;
+
+    let client = Client::builder()
+        .homeserver_url(server.uri())
+        .server_versions([MatrixVersion::V1_0])
+        .authentication_issuer(Url::parse(&format!("{}/oidc/", server.uri())).unwrap())
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+    let oidc = client.oidc();
+    let redirect_uri = Url::parse("http://127.0.0.1:1234/callback").unwrap();
+
+    oidc.restore_registered_client("client1".to_owned());
+    let data = oidc.login_url(&redirect_uri, Some(device_id!("DEVICEID"))).await.unwrap();
+
+    Mock::given(method("POST"))
+        .and(path("/oidc/token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "1234",
+            "token_type": "Bearer",
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/account/whoami"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "user_id": "@example:localhost",
+            "device_id": "OTHERDEVICE",
+        })))
+        .mount(&server)
+        .await;
+
+    let mut callback_url = redirect_uri.clone();
+    callback_url
+        .query_pairs_mut()
+        .append_pair("code", "authcode")
+        .append_pair("state", &data.state);
+
+    assert_matches!(
+        oidc.finish_login(&callback_url).await,
+        Err(Error::Oidc(oidc::Error::DeviceIdMismatch { expected, got }))
+            if expected == device_id!("DEVICEID") && got == device_id!("OTHERDEVICE")
+    );
+    assert!(!client.logged_in());
+}
@@ -0,0 +1,311 @@
+use std::sync::{Arc, Mutex};
+
+use assert_matches::assert_matches;
+use futures_channel::oneshot;
+use matrix_sdk::{config::RequestConfig, executor::spawn, qr_login, Client, Error};
+use matrix_sdk_test::async_test;
+use ruma::{api::MatrixVersion, device_id, user_id};
+use serde_json::{json, Map, Value as JsonValue};
+use url::Url;
+use wiremock::{
+    matchers::{body_partial_json, method, path, path_regex},
+    Mock, MockServer, Request, Respond, ResponseTemplate,
+};
+
+use crate::{logged_in_client, mock_sync, no_retry_test_client};
+
+/// A local stand-in for an HTTP rendezvous server, with a single session.
+#[derive(Default)]
+struct RendezvousServer {
+    /// The ETag and the payload of the session, if it was created.
+    session: Mutex<Option<(u32, Vec<u8>)>>,
+}
+
+impl Respond for RendezvousServer {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let header = |name: &str| {
+            request
+                .headers
+                .iter()
+                .find(|(header, _)| header.as_str().eq_ignore_ascii_case(name))
+                .map(|(_, values)| values.last().as_str().to_owned())
+        };
+        let mut session = self.session.lock().unwrap();
+
+        match (request.method.to_string().as_str(), session.as_mut()) {
+            ("POST", _) => {
+                *session = Some((0, b"{}".to_vec()));
+                ResponseTemplate::new(201)
+                    .insert_header("location", "/rendezvous/session")
+                    .insert_header("etag", "0")
+            }
+            ("GET", Some((etag, payload))) => {
+                if header("if-none-match") == Some(etag.to_string()) {
+                    ResponseTemplate::new(304)
+                } else {
+                    ResponseTemplate::new(200)
+                        .insert_header("etag", etag.to_string().as_str())
+                        .set_body_bytes(payload.clone())
+                }
+            }
+            ("PUT", Some((etag, payload))) => {
+                if header("if-match").map_or(false, |value| value != etag.to_string()) {
+                    return ResponseTemplate::new(412);
+                }
+
+                *etag += 1;
+                *payload = request.body.clone();
+                ResponseTemplate::new(202).insert_header("etag", etag.to_string().as_str())
+            }
+            ("DELETE", Some(_)) => {
+                *session = None;
+                ResponseTemplate::new(204)
+            }
+            _ => ResponseTemplate::new(404),
+        }
+    }
+}
+
+/// A local stand-in for the key endpoints of the homeserver, for a single user.
+#[derive(Clone, Default)]
+struct KeyServer {
+    state: Arc<Mutex<KeyServerState>>,
+}
+
+#[derive(Default)]
+struct KeyServerState {
+    /// The device keys, by device ID.
+    device_keys: Map<String, JsonValue>,
+    /// The cross-signing keys, by field name in the query response.
+    cross_signing_keys: Map<String, JsonValue>,
+}
+
+impl KeyServer {
+    /// Get the signatures of the keys of the device with the given ID.
+    fn device_signatures(&self, device_id: &str) -> Map<String, JsonValue> {
+        let state = self.state.lock().unwrap();
+        state.device_keys[device_id]["signatures"]["@example:localhost"]
+            .as_object()
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+impl Respond for KeyServer {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let body: JsonValue = serde_json::from_slice(&request.body).unwrap_or_default();
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        let path = request.url.path();
+
+        let response = if path.ends_with("/keys/upload") {
+            if let Some(device_keys) = body.get("device_keys") {
+                let device_id = device_keys["device_id"].as_str().unwrap().to_owned();
+                state.device_keys.insert(device_id, device_keys.clone());
+            }
+            let count = body["one_time_keys"].as_object().map_or(0, |keys| keys.len());
+            json!({ "one_time_key_counts": { "signed_curve25519": count } })
+        } else if path.ends_with("/keys/device_signing/upload") {
+            for (name, field) in [
+                ("master_key", "master_keys"),
+                ("self_signing_key", "self_signing_keys"),
+                ("user_signing_key", "user_signing_keys"),
+            ] {
+                if let Some(key) = body.get(name) {
+                    state.cross_signing_keys.insert(field.to_owned(), key.clone());
+                }
+            }
+            json!({})
+        } else if path.ends_with("/keys/signatures/upload") {
+            for signed in body.as_object().into_iter().flat_map(|users| users.values()) {
+                for (key_id, object) in signed.as_object().into_iter().flatten() {
+                    let master_key_id = format!("ed25519:{key_id}");
+                    let target = match state.device_keys.get_mut(key_id) {
+                        Some(device_keys) => device_keys,
+                        None => match state.cross_signing_keys.get_mut("master_keys") {
+                            Some(master_key)
+                                if master_key["keys"].get(&master_key_id).is_some() =>
+                            {
+                                master_key
+                            }
+                            _ => continue,
+                        },
+                    };
+                    merge_signatures(target, object);
+                }
+            }
+            json!({ "failures": {} })
+        } else if path.ends_with("/keys/query") {
+            let mut response = json!({
+                "device_keys": { "@example:localhost": state.device_keys },
+                "failures": {},
+            });
+            for (field, key) in &state.cross_signing_keys {
+                response[field] = json!({ "@example:localhost": key });
+            }
+            response
+        } else {
+            return ResponseTemplate::new(404);
+        };
+
+        ResponseTemplate::new(200).set_body_json(response)
+    }
+}
+
+/// Add the signatures of `signed` to the ones of `target`.
+fn merge_signatures(target: &mut JsonValue, signed: &JsonValue) {
+    for (user_id, signatures) in signed["signatures"].as_object().into_iter().flatten() {
+        for (key_id, signature) in signatures.as_object().into_iter().flatten() {
+            target["signatures"][user_id][key_id] = signature.clone();
+        }
+    }
+}
+
+#[async_test]
+async fn qr_login_approved() {
+    let (existing_client, server) = logged_in_client().await;
+    let new_client = Client::builder()
+        .homeserver_url(server.uri())
+        .server_versions([MatrixVersion::V1_0])
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+    let user_id = user_id!("@example:localhost");
+
+    Mock::given(path_regex("^/rendezvous"))
+        .respond_with(RendezvousServer::default())
+        .mount(&server)
+        .await;
+
+    let key_server = KeyServer::default();
+    Mock::given(path_regex(r"^/_matrix/client/.*/keys/"))
+        .respond_with(key_server.clone())
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/unstable/org.matrix.msc3882/login/token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "login_token": "LOGIN_TOKEN",
+            "expires_in_ms": 120_000,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/login"))
+        .and(body_partial_json(json!({ "type": "m.login.token", "token": "LOGIN_TOKEN" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "user_id": user_id,
+            "access_token": "1234",
+            "device_id": "NEWDEVICE",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    // The device that is already logged in uploads its keys and sets up
+    // cross-signing.
+    mock_sync(&server, json!({ "next_batch": "s1" }), None).await;
+    existing_client.sync_once(Default::default()).await.unwrap();
+    existing_client.encryption().bootstrap_cross_signing(None).await.unwrap();
+
+    // The device that is already logged in shows the QR code.
+    let rendezvous_server = Url::parse(&format!("{}/rendezvous", server.uri())).unwrap();
+    let (mut session, data) = existing_client.qr_login().start(&rendezvous_server).await.unwrap();
+
+    // The new device scans it, shows the check code and logs in.
+    let (check_code_sender, check_code_receiver) = oneshot::channel();
+    let new_device = spawn(async move {
+        let mut session = new_client.qr_login().scan(&data).await?;
+        check_code_sender.send(session.check_code().await?).unwrap();
+        session.login().await?;
+        Ok::<_, Error>(new_client)
+    });
+
+    let check_code = session.check_code().await.unwrap();
+    let new_device_check_code = check_code_receiver.await.unwrap();
+    assert_eq!(check_code, new_device_check_code);
+
+    // The user enters the check code shown on the new device.
+    assert_matches!(
+        session.confirm_check_code("not the check code").await,
+        Err(Error::QrLogin(qr_login::Error::CheckCodeMismatch))
+    );
+    session.confirm_check_code(&new_device_check_code).await.unwrap();
+
+    let device_id = session.approve().await.unwrap();
+    assert_eq!(device_id.as_str(), "NEWDEVICE");
+    let new_client = new_device.await.unwrap().unwrap();
+
+    // The new device was cross-signed by the device that is already logged in.
+    let signatures = key_server.device_signatures("NEWDEVICE");
+    assert!(signatures.contains_key("ed25519:NEWDEVICE"));
+    assert_eq!(signatures.len(), 2);
+
+    // The new device trusts the device that is already logged in and the
+    // cross-signing identity.
+    let encryption = new_client.encryption();
+    let device = encryption.get_device(user_id, device_id!("DEVICEID")).await.unwrap().unwrap();
+    assert!(device.is_locally_trusted());
+    assert!(encryption.get_user_identity(user_id).await.unwrap().unwrap().is_verified());
+}
+
+#[async_test]
+async fn qr_login_requires_check_code() {
+    let (existing_client, server) = logged_in_client().await;
+    let (new_client, _new_server) = no_retry_test_client().await;
+
+    Mock::given(path_regex("^/rendezvous"))
+        .respond_with(RendezvousServer::default())
+        .mount(&server)
+        .await;
+
+    let rendezvous_server = Url::parse(&format!("{}/rendezvous", server.uri())).unwrap();
+    let (session, data) = existing_client.qr_login().start(&rendezvous_server).await.unwrap();
+
+    let _new_device = spawn(async move {
+        let session = new_client.qr_login().scan(&data).await?;
+        session.login().await
+    });
+
+    // The check code must be confirmed before approving the login.
+    assert_matches!(
+        session.approve().await,
+        Err(Error::QrLogin(qr_login::Error::CheckCodeNotConfirmed))
+    );
+}
+
+#[async_test]
+async fn qr_login_declined() {
+    let (existing_client, server) = logged_in_client().await;
+    let (new_client, _new_server) = no_retry_test_client().await;
+
+    Mock::given(path_regex("^/rendezvous"))
+        .respond_with(RendezvousServer::default())
+        .mount(&server)
+        .await;
+
+    // The device that is already logged in shows the QR code.
+    let rendezvous_server = Url::parse(&format!("{}/rendezvous", server.uri())).unwrap();
+    let (session, data) = existing_client.qr_login().start(&rendezvous_server).await.unwrap();
+    assert_eq!(data.intent, qr_login::LoginIntent::Reconcile);
+
+    // A device that is already logged in can't scan it.
+    assert_matches!(
+        existing_client.qr_login().scan(&data).await,
+        Err(Error::QrLogin(qr_login::Error::InvalidIntent))
+    );
+
+    // The new device scans it and tries to log in.
+    let new_device = spawn(async move {
+        let session = new_client.qr_login().scan(&data).await?;
+        session.login().await
+    });
+
+    session.decline().await.unwrap();
+
+    assert_matches!(new_device.await.unwrap(), Err(Error::QrLogin(qr_login::Error::Declined)));
+}
@@ -2,7 +2,8 @@ use std::time::Duration;
 
 use matrix_sdk::{config::SyncSettings, room::RoomMember, DisplayName};
 use matrix_sdk_test::{
-    async_test, bulk_room_members, test_json, EventBuilder, JoinedRoomBuilder, TimelineTestEvent,
+    async_test, bulk_room_members, test_json, EventBuilder, JoinedRoomBuilder, StateTestEvent,
+    TimelineTestEvent,
 };
 use ruma::{
     event_id,
@@ -492,3 +493,59 @@ async fn room_event_permalink() {
         "matrix:roomid/test_room:127.0.0.1/e/15139375512JaHAW?via=notarealhs&via=localhost"
     );
 }
+
+#[async_test]
+async fn room_upgrade() {
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let old_room_id = room_id!("!old:example.org");
+    let new_room_id = room_id!("!new:example.org");
+
+    let mut ev_builder = EventBuilder::new();
+    ev_builder
+        .add_joined_room(JoinedRoomBuilder::new(old_room_id).add_state_event(
+            StateTestEvent::Custom(json!({
+                "content": {
+                    "body": "This room has been replaced",
+                    "replacement_room": new_room_id,
+                },
+                "event_id": "$tombstone:example.org",
+                "origin_server_ts": 152037280,
+                "sender": "@example:localhost",
+                "state_key": "",
+                "type": "m.room.tombstone",
+            })),
+        ))
+        .add_joined_room(JoinedRoomBuilder::new(new_room_id).add_state_event(
+            StateTestEvent::Custom(json!({
+                "content": {
+                    "creator": "@example:localhost",
+                    "predecessor": {
+                        "event_id": "$tombstone:example.org",
+                        "room_id": old_room_id,
+                    },
+                    "room_version": "9",
+                },
+                "event_id": "$create:example.org",
+                "origin_server_ts": 152037290,
+                "sender": "@example:localhost",
+                "state_key": "",
+                "type": "m.room.create",
+            })),
+        ));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(sync_settings).await.unwrap();
+
+    let old_room = client.get_room(old_room_id).unwrap();
+    let new_room = client.get_room(new_room_id).unwrap();
+
+    assert_eq!(old_room.successor().unwrap().room_id(), new_room_id);
+    assert!(old_room.predecessor().is_none());
+    assert!(old_room.is_replaced());
+
+    assert_eq!(new_room.predecessor().unwrap().room_id(), old_room_id);
+    assert!(new_room.successor().is_none());
+    assert!(!new_room.is_replaced());
+}
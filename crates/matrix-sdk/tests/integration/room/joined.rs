@@ -1,17 +1,19 @@
 use std::time::Duration;
 
+use futures::{future, StreamExt};
 use matrix_sdk::{
     attachment::{
         AttachmentConfig, AttachmentInfo, BaseImageInfo, BaseThumbnailInfo, BaseVideoInfo,
         Thumbnail,
     },
     config::SyncSettings,
+    SendQueue, SendQueueState,
 };
 use matrix_sdk_test::{async_test, test_json};
 use ruma::{
     api::client::membership::Invite3pidInit, assign, event_id,
-    events::room::message::RoomMessageEventContent, mxc_uri, thirdparty, uint, user_id,
-    TransactionId,
+    events::room::message::RoomMessageEventContent, mxc_uri, room_id, thirdparty, uint, user_id,
+    RoomVersionId, TransactionId,
 };
 use serde_json::json;
 use wiremock::{
@@ -144,6 +146,83 @@ async fn kick_user() {
     room.kick_user(user, None).await.unwrap();
 }
 
+#[async_test]
+async fn accept_and_deny_knock() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/invite$"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_partial_json(json!({ "user_id": "@alice:localhost" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/kick$"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_partial_json(json!({ "user_id": "@mallory:localhost", "reason": "Spam" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let _response = client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_joined_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+
+    room.accept_knock(user_id!("@alice:localhost")).await.unwrap();
+    room.deny_knock(user_id!("@mallory:localhost"), Some("Spam")).await.unwrap();
+}
+
+#[async_test]
+async fn set_join_rule_restricted() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.room.join_rules"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_partial_json(json!({
+            "join_rule": "restricted",
+            "allow": [{ "type": "m.room_membership", "room_id": "!space:localhost" }],
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
+        .named("restricted")
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.room.join_rules"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_partial_json(json!({
+            "join_rule": "knock_restricted",
+            "allow": [{ "type": "m.room_membership", "room_id": "!space:localhost" }],
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
+        .named("knock_restricted")
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let _response = client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_joined_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+    let space_id = room_id!("!space:localhost");
+
+    room.set_join_rule_restricted(vec![space_id.to_owned()]).await.unwrap();
+    room.set_join_rule_knock_restricted(vec![space_id.to_owned()]).await.unwrap();
+}
+
 #[async_test]
 async fn read_receipt() {
     let (client, server) = logged_in_client().await;
@@ -271,6 +350,155 @@ async fn room_message_send() {
     assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
 }
 
+#[async_test]
+async fn room_message_send_queued() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(502).set_body_json(json!({
+            "errcode": "M_UNKNOWN",
+            "error": "Bad gateway",
+        })))
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    mock_encryption_state(&server, false).await;
+
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+
+    let room = client.get_joined_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+
+    // The homeserver can't be reached, so the event stays in the queue.
+    let content = RoomMessageEventContent::text_plain("Hello world");
+    let txn_id = room.send_queued(content, None).await.unwrap();
+
+    let send_queue = client.send_queue();
+    wait_for_send_queue_state(&send_queue, SendQueueState::Offline { pending: 1 }).await;
+    let pending = send_queue.pending_events().await.unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].txn_id, txn_id);
+
+    server.reset().await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    mock_encryption_state(&server, false).await;
+
+    // A successful sync flushes the queue.
+    let _response = client.sync_once(sync_settings).await.unwrap();
+
+    wait_for_send_queue_state(&send_queue, SendQueueState::Idle).await;
+    assert!(send_queue.pending_events().await.unwrap().is_empty());
+}
+
+#[async_test]
+async fn room_message_send_queued_rejected() {
+    let (client, server) = logged_in_client().await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    mock_encryption_state(&server, false).await;
+
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+    let _response = client.sync_once(sync_settings).await.unwrap();
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(403).set_body_json(json!({
+            "errcode": "M_FORBIDDEN",
+            "error": "You are not allowed to send messages",
+        })))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+
+    let room = client.get_joined_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+    let content = RoomMessageEventContent::text_plain("Hello world");
+    let txn_id = room.send_queued(content, None).await.unwrap();
+
+    // The rejected event stays in the queue, with an error.
+    let send_queue = client.send_queue();
+    wait_for_send_queue_state(&send_queue, SendQueueState::Failed { failed: 1 }).await;
+    let pending = send_queue.pending_events().await.unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].txn_id, txn_id);
+    assert!(pending[0].error.is_some());
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    assert!(send_queue.retry(&txn_id).await.unwrap());
+    wait_for_send_queue_state(&send_queue, SendQueueState::Idle).await;
+    assert!(send_queue.pending_events().await.unwrap().is_empty());
+}
+
+#[async_test]
+async fn room_attachment_send_queued() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_partial_json(json!({
+            "body": "image",
+            "url": "mxc://example.com/AQwafuaFswefuhsfAFAgsw",
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/media/r0/upload"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(header("content-type", "image/jpeg"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+          "content_uri": "mxc://example.com/AQwafuaFswefuhsfAFAgsw"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    mock_encryption_state(&server, false).await;
+
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+    let _response = client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_joined_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+    room.send_attachment_queued("image", &mime::IMAGE_JPEG, b"Hello world".to_vec(), None)
+        .await
+        .unwrap();
+
+    let send_queue = client.send_queue();
+    wait_for_send_queue_state(&send_queue, SendQueueState::Idle).await;
+    assert!(send_queue.pending_events().await.unwrap().is_empty());
+}
+
+/// Wait until the send queue, which is sent in the background, reaches the
+/// given state.
+async fn wait_for_send_queue_state(send_queue: &SendQueue, state: SendQueueState) {
+    let mut stream = Box::pin(send_queue.state_stream().filter(|s| future::ready(*s == state)));
+    assert_eq!(stream.next().await, Some(state));
+}
+
 #[async_test]
 async fn room_attachment_send() {
     let (client, server) = logged_in_client().await;
@@ -518,3 +746,29 @@ async fn room_redact() {
 
     assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
 }
+
+#[async_test]
+async fn upgrade() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/upgrade$"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_partial_json(json!({ "new_version": "10" })))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "replacement_room": "!new:example.org" })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+    let _response = client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_joined_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+    let response = room.upgrade(RoomVersionId::V10).await.unwrap();
+    assert_eq!(response.replacement_room.as_str(), "!new:example.org");
+}
@@ -1,4 +1,5 @@
 mod common;
 mod joined;
 mod left;
+mod space;
 mod timeline;
@@ -0,0 +1,201 @@
+use std::{
+    sync::atomic::{AtomicU32, Ordering::SeqCst},
+    time::Duration,
+};
+
+use matrix_sdk::{config::SyncSettings, room::HierarchyOptions};
+use matrix_sdk_test::{async_test, EventBuilder, JoinedRoomBuilder, StateTestEvent};
+use ruma::{room_id, server_name, RoomId};
+use serde_json::{json, Value as JsonValue};
+use wiremock::{
+    matchers::{body_partial_json, header, method, path_regex, query_param},
+    Mock, ResponseTemplate,
+};
+
+use crate::{logged_in_client, mock_sync};
+
+fn state_event(
+    event_type: &str,
+    state_key: &str,
+    sender: &str,
+    content: JsonValue,
+) -> StateTestEvent {
+    static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+    let id = NEXT_ID.fetch_add(1, SeqCst);
+
+    StateTestEvent::Custom(json!({
+        "content": content,
+        "event_id": format!("$state{id}:example.org"),
+        "origin_server_ts": 151957878 + u64::from(id),
+        "sender": sender,
+        "state_key": state_key,
+        "type": event_type,
+    }))
+}
+
+fn space_room(room_id: &RoomId, children: &[&RoomId]) -> JoinedRoomBuilder {
+    let create = state_event(
+        "m.room.create",
+        "",
+        "@admin:example.org",
+        json!({ "creator": "@admin:example.org", "room_version": "9", "type": "m.space" }),
+    );
+    let power_levels = state_event(
+        "m.room.power_levels",
+        "",
+        "@admin:example.org",
+        json!({ "users": { "@admin:example.org": 100 }, "state_default": 50 }),
+    );
+    let children = children.iter().map(|child_id| {
+        state_event(
+            "m.space.child",
+            child_id.as_str(),
+            "@admin:example.org",
+            json!({ "via": ["example.org"] }),
+        )
+        .into_raw_event()
+    });
+
+    JoinedRoomBuilder::new(room_id)
+        .add_state_event(create)
+        .add_state_event(power_levels)
+        .add_state_bulk(children)
+}
+
+#[async_test]
+async fn children_and_parents() {
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let space_id = room_id!("!space:example.org");
+    let subspace_id = room_id!("!subspace:example.org");
+    let room_id = room_id!("!room:example.org");
+    let other_room_id = room_id!("!other_room:example.org");
+    let unknown_room_id = room_id!("!unknown:example.org");
+
+    let mut ev_builder = EventBuilder::new();
+    ev_builder
+        .add_joined_room(space_room(space_id, &[room_id, subspace_id, unknown_room_id]))
+        // The subspace points back to the space, which must not loop forever.
+        .add_joined_room(space_room(subspace_id, &[other_room_id, space_id]))
+        .add_joined_room(
+            JoinedRoomBuilder::new(room_id)
+                .add_state_event(state_event(
+                    "m.space.parent",
+                    space_id.as_str(),
+                    "@admin:example.org",
+                    json!({ "via": ["example.org"], "canonical": true }),
+                ))
+                // Nobody with enough power in the subspace confirms this.
+                .add_state_event(state_event(
+                    "m.space.parent",
+                    subspace_id.as_str(),
+                    "@mallory:example.org",
+                    json!({ "via": ["example.org"] }),
+                )),
+        )
+        .add_joined_room(JoinedRoomBuilder::new(other_room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(sync_settings).await.unwrap();
+
+    assert!(client.get_space(room_id).is_none());
+    let space = client.get_space(space_id).unwrap();
+
+    let children = space.children().await.unwrap();
+    let child_ids: Vec<_> = children.iter().map(|child| child.room_id()).collect();
+    assert_eq!(child_ids, [room_id, subspace_id, unknown_room_id]);
+    assert_eq!(children[0].via(), [server_name!("example.org").to_owned()]);
+
+    let rooms = client.rooms_in_space(space_id).await.unwrap();
+    let room_ids: Vec<_> = rooms.iter().map(|room| room.room_id()).collect();
+    assert_eq!(room_ids, [room_id, subspace_id, other_room_id]);
+
+    let room = client.get_room(room_id).unwrap();
+    let parents = room.parent_spaces().await.unwrap();
+    assert_eq!(parents.len(), 2);
+    let parent = parents.iter().find(|p| p.room_id() == space_id).unwrap();
+    assert!(parent.is_canonical());
+    assert!(parent.is_valid());
+    let parent = parents.iter().find(|p| p.room_id() == subspace_id).unwrap();
+    assert!(!parent.is_canonical());
+    assert!(!parent.is_valid());
+}
+
+#[async_test]
+async fn hierarchy() {
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let space_id = room_id!("!space:example.org");
+    let mut ev_builder = EventBuilder::new();
+    ev_builder.add_joined_room(space_room(space_id, &[]));
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(sync_settings).await.unwrap();
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/v1/rooms/.*/hierarchy"))
+        .and(query_param("from", "page_2"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "rooms": [{
+                "room_id": "!room:example.org",
+                "num_joined_members": 4,
+                "world_readable": false,
+                "guest_can_join": false,
+                "children_state": [],
+            }],
+            "next_batch": "page_3",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let space = client.get_space(space_id).unwrap();
+    let hierarchy = space.hierarchy(HierarchyOptions::new().from("page_2")).await.unwrap();
+    assert_eq!(hierarchy.rooms.len(), 1);
+    assert_eq!(hierarchy.rooms[0].room_id.as_str(), "!room:example.org");
+    assert_eq!(hierarchy.next_batch.as_deref(), Some("page_3"));
+}
+
+#[async_test]
+async fn add_and_remove_child() {
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let space_id = room_id!("!space:example.org");
+    let mut ev_builder = EventBuilder::new();
+    ev_builder.add_joined_room(space_room(space_id, &[]));
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(sync_settings).await.unwrap();
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.space.child/.*"))
+        .and(body_partial_json(json!({ "via": ["example.org"], "suggested": true })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "event_id": "$add" })))
+        .expect(1)
+        .named("add_child")
+        .mount(&server)
+        .await;
+
+    let space = client.get_space(space_id).unwrap();
+    let child_id = room_id!("!room:example.org");
+    let response = space
+        .add_child(child_id, vec![server_name!("example.org").to_owned()], true)
+        .await
+        .unwrap();
+    assert_eq!(response.event_id.as_str(), "$add");
+
+    server.reset().await;
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.space.child/.*"))
+        .and(body_partial_json(json!({})))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "event_id": "$remove" })))
+        .expect(1)
+        .named("remove_child")
+        .mount(&server)
+        .await;
+
+    let response = space.remove_child(child_id).await.unwrap();
+    assert_eq!(response.event_id.as_str(), "$remove");
+}
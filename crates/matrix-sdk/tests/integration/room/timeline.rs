@@ -4,25 +4,31 @@ use std::{sync::Arc, time::Duration};
 
 use assert_matches::assert_matches;
 use futures_signals::signal_vec::{SignalVecExt, VecDiff};
-use futures_util::StreamExt;
+use futures_util::{future, StreamExt};
 use matrix_sdk::{
     config::SyncSettings,
-    room::timeline::{TimelineDetails, TimelineItemContent, TimelineKey, VirtualTimelineItem},
+    room::timeline::{
+        Error as TimelineError, EventSendState, TimelineDetails, TimelineItem, TimelineItemContent,
+        TimelineKey, VirtualTimelineItem,
+    },
     ruma::MilliSecondsSinceUnixEpoch,
 };
 use matrix_sdk_common::executor::spawn;
 use matrix_sdk_test::{
     async_test, test_json, EventBuilder, JoinedRoomBuilder, RoomAccountDataTestEvent,
-    TimelineTestEvent,
+    StateTestEvent, TimelineTestEvent,
 };
 use ruma::{
     event_id,
-    events::room::message::{MessageType, RoomMessageEventContent},
+    events::{
+        relation::Annotation,
+        room::message::{MessageType, RoomMessageEventContent},
+    },
     room_id, uint, user_id, TransactionId,
 };
 use serde_json::json;
 use wiremock::{
-    matchers::{header, method, path_regex},
+    matchers::{header, method, path_regex, query_param},
     Mock, ResponseTemplate,
 };
 
@@ -232,7 +238,7 @@ async fn echo() {
 }
 
 #[async_test]
-async fn back_pagination() {
+async fn send_failure() {
     let room_id = room_id!("!a98sd12bjh:example.org");
     let (client, server) = logged_in_client().await;
     let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
@@ -245,45 +251,51 @@ async fn back_pagination() {
     server.reset().await;
 
     let room = client.get_room(room_id).unwrap();
-    let timeline = Arc::new(room.timeline().await);
+    let timeline = room.timeline().await;
     let mut timeline_stream = timeline.signal().to_stream();
+    let txn_id: &TransactionId = "my-txn-id".into();
 
-    Mock::given(method("GET"))
-        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+    mock_encryption_state(&server, false).await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
         .and(header("authorization", "Bearer 1234"))
-        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::ROOM_MESSAGES_BATCH_1))
-        .expect(1)
-        .named("messages_batch_1")
+        .respond_with(ResponseTemplate::new(500).set_body_json(&json!({
+            "errcode": "M_UNKNOWN",
+            "error": "Internal server error",
+        })))
         .mount(&server)
         .await;
 
-    timeline.paginate_backwards(uint!(10)).await.unwrap();
+    // The error is returned, and the local echo stays in the timeline.
+    let result =
+        timeline.send(RoomMessageEventContent::text_plain("Hello").into(), Some(txn_id)).await;
+    assert_matches!(result, Err(matrix_sdk::Error::Timeline(TimelineError::SendingFailed(_))));
 
-    let message = assert_matches!(
+    assert_matches!(timeline_stream.next().await, Some(VecDiff::Push { .. }));
+    let item = assert_matches!(
         timeline_stream.next().await,
-        Some(VecDiff::Push { value }) => value
+        Some(VecDiff::UpdateAt { index: 0, value }) => value
     );
-    let msg = assert_matches!(
-        message.as_event().unwrap().content(),
-        TimelineItemContent::Message(msg) => msg
+    assert_matches!(
+        item.as_event().unwrap().send_state(),
+        Some(EventSendState::SendingFailed { .. })
     );
-    let text = assert_matches!(msg.msgtype(), MessageType::Text(text) => text);
-    assert_eq!(text.body, "hello world");
 
-    let message = assert_matches!(
-        timeline_stream.next().await,
-        Some(VecDiff::InsertAt { index: 0, value }) => value
-    );
-    let msg = assert_matches!(
-        message.as_event().unwrap().content(),
-        TimelineItemContent::Message(msg) => msg
+    // Retrying fails the same way.
+    assert_matches!(
+        timeline.retry_send(txn_id).await,
+        Err(matrix_sdk::Error::Timeline(TimelineError::SendingFailed(_)))
     );
-    let text = assert_matches!(msg.msgtype(), MessageType::Text(text) => text);
-    assert_eq!(text.body, "the world is big");
+    assert_matches!(timeline_stream.next().await, Some(VecDiff::UpdateAt { index: 0, .. }));
+    assert_matches!(timeline_stream.next().await, Some(VecDiff::UpdateAt { index: 0, .. }));
+
+    timeline.cancel_send(txn_id).await.unwrap();
+    assert_matches!(timeline_stream.next().await, Some(VecDiff::RemoveAt { index: 0 }));
 }
 
 #[async_test]
-async fn reaction() {
+async fn local_edit() {
     let room_id = room_id!("!a98sd12bjh:example.org");
     let (client, server) = logged_in_client().await;
     let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
@@ -299,83 +311,102 @@ async fn reaction() {
     let timeline = room.timeline().await;
     let mut timeline_stream = timeline.signal().to_stream();
 
-    ev_builder.add_joined_room(
-        JoinedRoomBuilder::new(room_id)
-            .add_timeline_event(TimelineTestEvent::Custom(json!({
-                "content": {
-                    "body": "hello",
-                    "msgtype": "m.text",
-                },
-                "event_id": "$TTvQUp1e17qkw41rBSjpZ",
-                "origin_server_ts": 152037280,
-                "sender": "@alice:example.org",
-                "type": "m.room.message",
-            })))
-            .add_timeline_event(TimelineTestEvent::Custom(json!({
-                "content": {
-                    "m.relates_to": {
-                        "event_id": "$TTvQUp1e17qkw41rBSjpZ",
-                        "key": "👍",
-                        "rel_type": "m.annotation",
-                    },
-                },
-                "event_id": "$031IXQRi27504",
-                "origin_server_ts": 152038300,
-                "sender": "@bob:example.org",
-                "type": "m.reaction",
-            }))),
-    );
+    let event_id = event_id!("$msda7m:localhost");
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_timeline_event(
+        TimelineTestEvent::Custom(json!({
+            "content": {
+                "body": "hello",
+                "msgtype": "m.text",
+            },
+            "event_id": event_id,
+            "origin_server_ts": 152037280,
+            "sender": "@example:localhost",
+            "type": "m.room.message",
+        })),
+    ));
 
     mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
     let _response = client.sync_once(sync_settings.clone()).await.unwrap();
     server.reset().await;
 
-    let message =
-        assert_matches!(timeline_stream.next().await, Some(VecDiff::Push { value }) => value);
-    assert_matches!(message.as_event().unwrap().content(), TimelineItemContent::Message(_));
+    assert_matches!(timeline_stream.next().await, Some(VecDiff::Push { .. }));
 
-    let updated_message = assert_matches!(
+    mock_encryption_state(&server, false).await;
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(&json!({ "event_id": "$msda7m2:localhost" })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    // The edit is applied right away.
+    timeline.edit(event_id, MessageType::text_plain("hi")).await.unwrap();
+
+    let edit = assert_matches!(
         timeline_stream.next().await,
         Some(VecDiff::UpdateAt { index: 0, value }) => value
     );
-    let event_item = updated_message.as_event().unwrap();
-    let msg = assert_matches!(event_item.content(), TimelineItemContent::Message(msg) => msg);
-    assert!(!msg.is_edited());
-    assert_eq!(event_item.reactions().len(), 1);
-    let details = &event_item.reactions()["👍"];
-    assert_eq!(details.count, uint!(1));
-    let senders = assert_matches!(&details.senders, TimelineDetails::Ready(s) => s);
-    assert_eq!(*senders, vec![user_id!("@bob:example.org").to_owned()]);
+    let edited = edit.as_event().unwrap().content().as_message().unwrap();
+    assert_eq!(edited.body(), "hi");
+    assert!(edited.is_edited());
 
-    // TODO: After adding raw timeline items, check for one here
+    server.reset().await;
+    mock_encryption_state(&server, false).await;
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(500).set_body_json(&json!({
+            "errcode": "M_UNKNOWN",
+            "error": "Internal server error",
+        })))
+        .mount(&server)
+        .await;
+
+    // An edit that can't be sent is reverted.
+    timeline.edit(event_id, MessageType::text_plain("hey")).await.unwrap_err();
+
+    let edit = assert_matches!(
+        timeline_stream.next().await,
+        Some(VecDiff::UpdateAt { index: 0, value }) => value
+    );
+    assert_eq!(edit.as_event().unwrap().content().as_message().unwrap().body(), "hey");
+    let reverted = assert_matches!(
+        timeline_stream.next().await,
+        Some(VecDiff::UpdateAt { index: 0, value }) => value
+    );
+    let msg = reverted.as_event().unwrap().content().as_message().unwrap();
+    assert_eq!(msg.body(), "hi");
+    assert!(msg.is_edited());
 
+    // Events of other users can't be edited.
     ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_timeline_event(
         TimelineTestEvent::Custom(json!({
-            "content": {},
-            "redacts": "$031IXQRi27504",
-            "event_id": "$N6eUCBc3vu58PL8TobGaVQzM",
+            "content": {
+                "body": "hello",
+                "msgtype": "m.text",
+            },
+            "event_id": "$7at8sd:localhost",
+            "origin_server_ts": 152038280,
             "sender": "@bob:example.org",
-            "origin_server_ts": 152037280,
-            "type": "m.room.redaction",
+            "type": "m.room.message",
         })),
     ));
 
     mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
     let _response = client.sync_once(sync_settings.clone()).await.unwrap();
-    server.reset().await;
 
-    let updated_message = assert_matches!(
-        timeline_stream.next().await,
-        Some(VecDiff::UpdateAt { index: 0, value }) => value
+    assert_matches!(timeline_stream.next().await, Some(VecDiff::Push { .. }));
+    assert_matches!(
+        timeline.edit(event_id!("$7at8sd:localhost"), MessageType::text_plain("hi")).await,
+        Err(matrix_sdk::Error::Timeline(TimelineError::EventNotEditable))
     );
-    let event_item = updated_message.as_event().unwrap();
-    let msg = assert_matches!(event_item.content(), TimelineItemContent::Message(msg) => msg);
-    assert!(!msg.is_edited());
-    assert_eq!(event_item.reactions().len(), 0);
 }
 
 #[async_test]
-async fn redacted_message() {
+async fn local_redaction() {
     let room_id = room_id!("!a98sd12bjh:example.org");
     let (client, server) = logged_in_client().await;
     let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
@@ -391,48 +422,49 @@ async fn redacted_message() {
     let timeline = room.timeline().await;
     let mut timeline_stream = timeline.signal().to_stream();
 
-    ev_builder.add_joined_room(
-        JoinedRoomBuilder::new(room_id)
-            .add_timeline_event(TimelineTestEvent::Custom(json!({
-                "content": {},
-                "event_id": "$eeG0HA0FAZ37wP8kXlNkxx3I",
-                "origin_server_ts": 152035910,
-                "sender": "@alice:example.org",
-                "type": "m.room.message",
-                "unsigned": {
-                    "redacted_because": {
-                        "content": {},
-                        "redacts": "$eeG0HA0FAZ37wP8kXlNkxx3I",
-                        "event_id": "$N6eUCBc3vu58PL8TobGaVQzM",
-                        "sender": "@alice:example.org",
-                        "origin_server_ts": 152037280,
-                        "type": "m.room.redaction",
-                    },
-                },
-            })))
-            .add_timeline_event(TimelineTestEvent::Custom(json!({
-                "content": {},
-                "redacts": "$eeG0HA0FAZ37wP8kXlNkxx3I",
-                "event_id": "$N6eUCBc3vu58PL8TobGaVQzM",
-                "sender": "@alice:example.org",
-                "origin_server_ts": 152037280,
-                "type": "m.room.redaction",
-            }))),
-    );
+    let event_id = event_id!("$msda7m:localhost");
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_timeline_event(
+        TimelineTestEvent::Custom(json!({
+            "content": {
+                "body": "hello",
+                "msgtype": "m.text",
+            },
+            "event_id": event_id,
+            "origin_server_ts": 152037280,
+            "sender": "@example:localhost",
+            "type": "m.room.message",
+        })),
+    ));
 
     mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
     let _response = client.sync_once(sync_settings.clone()).await.unwrap();
     server.reset().await;
 
-    let first =
-        assert_matches!(timeline_stream.next().await, Some(VecDiff::Push { value }) => value);
-    assert_matches!(first.as_event().unwrap().content(), TimelineItemContent::RedactedMessage);
+    assert_matches!(timeline_stream.next().await, Some(VecDiff::Push { .. }));
 
-    // TODO: After adding raw timeline items, check for one here
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/redact/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(&json!({ "event_id": "$redaction:localhost" })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    // The redaction is applied without waiting for its remote echo.
+    timeline.redact(event_id, Some("spam")).await.unwrap();
+
+    let redacted = assert_matches!(
+        timeline_stream.next().await,
+        Some(VecDiff::UpdateAt { index: 0, value }) => value
+    );
+    assert_matches!(redacted.as_event().unwrap().content(), TimelineItemContent::RedactedMessage);
 }
 
 #[async_test]
-async fn read_marker() {
+async fn toggle_reaction() {
     let room_id = room_id!("!a98sd12bjh:example.org");
     let (client, server) = logged_in_client().await;
     let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
@@ -448,13 +480,14 @@ async fn read_marker() {
     let timeline = room.timeline().await;
     let mut timeline_stream = timeline.signal().to_stream();
 
+    let event_id = event_id!("$TTvQUp1e17qkw41rBSjpZ");
     ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_timeline_event(
         TimelineTestEvent::Custom(json!({
             "content": {
                 "body": "hello",
                 "msgtype": "m.text",
             },
-            "event_id": "$someplace:example.org",
+            "event_id": event_id,
             "origin_server_ts": 152037280,
             "sender": "@alice:example.org",
             "type": "m.room.message",
@@ -465,19 +498,858 @@ async fn read_marker() {
     let _response = client.sync_once(sync_settings.clone()).await.unwrap();
     server.reset().await;
 
-    let message =
-        assert_matches!(timeline_stream.next().await, Some(VecDiff::Push { value }) => value);
-    assert_matches!(message.as_event().unwrap().content(), TimelineItemContent::Message(_));
+    assert_matches!(timeline_stream.next().await, Some(VecDiff::Push { .. }));
 
-    ev_builder.add_joined_room(
-        JoinedRoomBuilder::new(room_id).add_account_data(RoomAccountDataTestEvent::FullyRead),
+    mock_encryption_state(&server, false).await;
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/m\.reaction/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(&json!({ "event_id": "$031IXQRi27504" })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/redact/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(&json!({ "event_id": "$redaction:localhost" })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    // Toggling the same reaction twice at once adds it, then removes it again
+    // once it was sent.
+    let annotation = Annotation::new(event_id.to_owned(), "👍".to_owned());
+    let (added, removed) =
+        future::join(timeline.toggle_reaction(&annotation), timeline.toggle_reaction(&annotation))
+            .await;
+    added.unwrap();
+    removed.unwrap();
+
+    let message = assert_matches!(
+        timeline_stream.next().await,
+        Some(VecDiff::UpdateAt { index: 0, value }) => value
+    );
+    let details = &message.as_event().unwrap().reactions()["👍"];
+    assert_eq!(details.count, uint!(1));
+    let senders = assert_matches!(&details.senders, TimelineDetails::Ready(s) => s);
+    assert_eq!(*senders, vec![user_id!("@example:localhost").to_owned()]);
+
+    let message = assert_matches!(
+        timeline_stream.next().await,
+        Some(VecDiff::UpdateAt { index: 0, value }) => value
     );
+    assert_eq!(message.as_event().unwrap().reactions().len(), 0);
+}
+
+#[async_test]
+async fn reply_to_state_event() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = EventBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
 
     mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
     let _response = client.sync_once(sync_settings.clone()).await.unwrap();
     server.reset().await;
 
-    let marker =
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room.timeline().await;
+    let mut timeline_stream = timeline.signal().to_stream();
+
+    let reply_id = event_id!("$reply:localhost");
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_timeline_event(
+        TimelineTestEvent::Custom(json!({
+            "content": {
+                "body": "Nice topic!",
+                "msgtype": "m.text",
+                "m.relates_to": {
+                    "m.in_reply_to": {
+                        "event_id": "$topic:localhost",
+                    },
+                },
+            },
+            "event_id": reply_id,
+            "origin_server_ts": 152038280,
+            "sender": "@bob:example.org",
+            "type": "m.room.message",
+        })),
+    ));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let reply =
         assert_matches!(timeline_stream.next().await, Some(VecDiff::Push { value }) => value);
-    assert_matches!(marker.as_virtual().unwrap(), VirtualTimelineItem::ReadMarker);
+    let msg = reply.as_event().unwrap().content().as_message().unwrap();
+    assert_matches!(msg.in_reply_to().unwrap().details, TimelineDetails::Unavailable);
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/event/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "content": {
+                "topic": "Cats",
+            },
+            "event_id": "$topic:localhost",
+            "origin_server_ts": 152037280,
+            "room_id": room_id,
+            "sender": "@alice:example.org",
+            "state_key": "",
+            "type": "m.room.topic",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    // The replied-to state event is resolved like any other event.
+    timeline.fetch_details_for_event(reply_id).await.unwrap();
+
+    assert_matches!(timeline_stream.next().await, Some(VecDiff::UpdateAt { index: 0, .. }));
+    let reply = assert_matches!(
+        timeline_stream.next().await,
+        Some(VecDiff::UpdateAt { index: 0, value }) => value
+    );
+    let msg = reply.as_event().unwrap().content().as_message().unwrap();
+    let replied_to = assert_matches!(
+        &msg.in_reply_to().unwrap().details,
+        TimelineDetails::Ready(replied_to) => replied_to
+    );
+    assert_eq!(replied_to.sender(), user_id!("@alice:example.org"));
+    assert_matches!(replied_to.content(), TimelineItemContent::OtherState(_));
+}
+
+#[async_test]
+async fn back_pagination() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = EventBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = Arc::new(room.timeline().await);
+    let mut timeline_stream = timeline.signal().to_stream();
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::ROOM_MESSAGES_BATCH_1))
+        .expect(1)
+        .named("messages_batch_1")
+        .mount(&server)
+        .await;
+
+    timeline.paginate_backwards(uint!(10)).await.unwrap();
+
+    let message = assert_matches!(
+        timeline_stream.next().await,
+        Some(VecDiff::Push { value }) => value
+    );
+    let msg = assert_matches!(
+        message.as_event().unwrap().content(),
+        TimelineItemContent::Message(msg) => msg
+    );
+    let text = assert_matches!(msg.msgtype(), MessageType::Text(text) => text);
+    assert_eq!(text.body, "hello world");
+
+    let message = assert_matches!(
+        timeline_stream.next().await,
+        Some(VecDiff::InsertAt { index: 0, value }) => value
+    );
+    let msg = assert_matches!(
+        message.as_event().unwrap().content(),
+        TimelineItemContent::Message(msg) => msg
+    );
+    let text = assert_matches!(msg.msgtype(), MessageType::Text(text) => text);
+    assert_eq!(text.body, "the world is big");
+}
+
+#[async_test]
+async fn reaction() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = EventBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room.timeline().await;
+    let mut timeline_stream = timeline.signal().to_stream();
+
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_timeline_event(TimelineTestEvent::Custom(json!({
+                "content": {
+                    "body": "hello",
+                    "msgtype": "m.text",
+                },
+                "event_id": "$TTvQUp1e17qkw41rBSjpZ",
+                "origin_server_ts": 152037280,
+                "sender": "@alice:example.org",
+                "type": "m.room.message",
+            })))
+            .add_timeline_event(TimelineTestEvent::Custom(json!({
+                "content": {
+                    "m.relates_to": {
+                        "event_id": "$TTvQUp1e17qkw41rBSjpZ",
+                        "key": "👍",
+                        "rel_type": "m.annotation",
+                    },
+                },
+                "event_id": "$031IXQRi27504",
+                "origin_server_ts": 152038300,
+                "sender": "@bob:example.org",
+                "type": "m.reaction",
+            }))),
+    );
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let message =
+        assert_matches!(timeline_stream.next().await, Some(VecDiff::Push { value }) => value);
+    assert_matches!(message.as_event().unwrap().content(), TimelineItemContent::Message(_));
+
+    let updated_message = assert_matches!(
+        timeline_stream.next().await,
+        Some(VecDiff::UpdateAt { index: 0, value }) => value
+    );
+    let event_item = updated_message.as_event().unwrap();
+    let msg = assert_matches!(event_item.content(), TimelineItemContent::Message(msg) => msg);
+    assert!(!msg.is_edited());
+    assert_eq!(event_item.reactions().len(), 1);
+    let details = &event_item.reactions()["👍"];
+    assert_eq!(details.count, uint!(1));
+    let senders = assert_matches!(&details.senders, TimelineDetails::Ready(s) => s);
+    assert_eq!(*senders, vec![user_id!("@bob:example.org").to_owned()]);
+
+    // TODO: After adding raw timeline items, check for one here
+
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_timeline_event(
+        TimelineTestEvent::Custom(json!({
+            "content": {},
+            "redacts": "$031IXQRi27504",
+            "event_id": "$N6eUCBc3vu58PL8TobGaVQzM",
+            "sender": "@bob:example.org",
+            "origin_server_ts": 152037280,
+            "type": "m.room.redaction",
+        })),
+    ));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let updated_message = assert_matches!(
+        timeline_stream.next().await,
+        Some(VecDiff::UpdateAt { index: 0, value }) => value
+    );
+    let event_item = updated_message.as_event().unwrap();
+    let msg = assert_matches!(event_item.content(), TimelineItemContent::Message(msg) => msg);
+    assert!(!msg.is_edited());
+    assert_eq!(event_item.reactions().len(), 0);
+}
+
+#[async_test]
+async fn redacted_message() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = EventBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room.timeline().await;
+    let mut timeline_stream = timeline.signal().to_stream();
+
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_timeline_event(TimelineTestEvent::Custom(json!({
+                "content": {},
+                "event_id": "$eeG0HA0FAZ37wP8kXlNkxx3I",
+                "origin_server_ts": 152035910,
+                "sender": "@alice:example.org",
+                "type": "m.room.message",
+                "unsigned": {
+                    "redacted_because": {
+                        "content": {},
+                        "redacts": "$eeG0HA0FAZ37wP8kXlNkxx3I",
+                        "event_id": "$N6eUCBc3vu58PL8TobGaVQzM",
+                        "sender": "@alice:example.org",
+                        "origin_server_ts": 152037280,
+                        "type": "m.room.redaction",
+                    },
+                },
+            })))
+            .add_timeline_event(TimelineTestEvent::Custom(json!({
+                "content": {},
+                "redacts": "$eeG0HA0FAZ37wP8kXlNkxx3I",
+                "event_id": "$N6eUCBc3vu58PL8TobGaVQzM",
+                "sender": "@alice:example.org",
+                "origin_server_ts": 152037280,
+                "type": "m.room.redaction",
+            }))),
+    );
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let first =
+        assert_matches!(timeline_stream.next().await, Some(VecDiff::Push { value }) => value);
+    assert_matches!(first.as_event().unwrap().content(), TimelineItemContent::RedactedMessage);
+
+    // TODO: After adding raw timeline items, check for one here
+}
+
+#[async_test]
+async fn read_marker() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = EventBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room.timeline().await;
+    let mut timeline_stream = timeline.signal().to_stream();
+
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_timeline_event(
+        TimelineTestEvent::Custom(json!({
+            "content": {
+                "body": "hello",
+                "msgtype": "m.text",
+            },
+            "event_id": "$someplace:example.org",
+            "origin_server_ts": 152037280,
+            "sender": "@alice:example.org",
+            "type": "m.room.message",
+        })),
+    ));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let message =
+        assert_matches!(timeline_stream.next().await, Some(VecDiff::Push { value }) => value);
+    assert_matches!(message.as_event().unwrap().content(), TimelineItemContent::Message(_));
+
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id).add_account_data(RoomAccountDataTestEvent::FullyRead),
+    );
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let marker =
+        assert_matches!(timeline_stream.next().await, Some(VecDiff::Push { value }) => value);
+    assert_matches!(marker.as_virtual().unwrap(), VirtualTimelineItem::ReadMarker);
+}
+
+#[async_test]
+async fn events_from_cache() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = EventBuilder::new();
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .set_timeline_prev_batch("t392-516".to_owned())
+            .add_timeline_event(TimelineTestEvent::Custom(json!({
+                "content": {
+                    "body": "hello",
+                    "msgtype": "m.text",
+                },
+                "event_id": "$msda7m:localhost",
+                "origin_server_ts": 152037280,
+                "sender": "@alice:example.org",
+                "type": "m.room.message",
+            }))),
+    );
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    // The event received before the timeline was created is loaded from the
+    // event cache.
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room.timeline().await;
+    let latest_event = timeline.latest_event().unwrap();
+    assert_eq!(latest_event.event_id(), Some(event_id!("$msda7m:localhost")));
+
+    // A `prev_batch` token doesn't mean that this is the start of the room, so
+    // the server is asked for older events.
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(query_param("from", "t392-516"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "start": "t392-516",
+            "chunk": [],
+        })))
+        .expect(1)
+        .named("messages")
+        .mount(&server)
+        .await;
+
+    let outcome = timeline.paginate_backwards(uint!(10)).await.unwrap();
+    assert!(!outcome.more_messages);
+
+    // Now the cache knows that this is the start of the room.
+    let chunk = client.store().get_last_event_chunk(room_id).await.unwrap().unwrap();
+    assert!(chunk.is_start_of_timeline());
+}
+
+#[async_test]
+async fn sender_profile() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = EventBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room.timeline().await;
+
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_timeline_event(TimelineTestEvent::Custom(json!({
+                "content": {
+                    "avatar_url": "mxc://example.org/SEsfnsuifSDFSSEF",
+                    "displayname": "Alice Margatroid",
+                    "membership": "join",
+                },
+                "event_id": "$143273582443PhrSn:example.org",
+                "origin_server_ts": 152037270,
+                "sender": "@alice:example.org",
+                "state_key": "@alice:example.org",
+                "type": "m.room.member",
+            })))
+            .add_timeline_event(TimelineTestEvent::Custom(json!({
+                "content": {
+                    "body": "hello",
+                    "msgtype": "m.text",
+                },
+                "event_id": "$msda7m:localhost",
+                "origin_server_ts": 152037280,
+                "sender": "@alice:example.org",
+                "type": "m.room.message",
+            }))),
+    );
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let latest_event = timeline.latest_event().unwrap();
+    let profile =
+        assert_matches!(latest_event.sender_profile(), TimelineDetails::Ready(profile) => profile);
+    assert_eq!(profile.display_name.as_deref(), Some("Alice Margatroid"));
+    assert!(!profile.display_name_ambiguous);
+    assert_eq!(
+        profile.avatar_url.as_deref().map(|url| url.as_str()),
+        Some("mxc://example.org/SEsfnsuifSDFSSEF")
+    );
+}
+
+#[async_test]
+async fn sender_profile_at_event() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = EventBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_state_event(
+        StateTestEvent::Custom(json!({
+            "content": { "displayname": "Alice", "membership": "join" },
+            "event_id": "$join:example.org",
+            "origin_server_ts": 152037260,
+            "sender": "@alice:example.org",
+            "state_key": "@alice:example.org",
+            "type": "m.room.member",
+        })),
+    ));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room.timeline().await;
+    let mut timeline_stream = timeline.signal().to_stream();
+
+    // The store already has the new display name when the message is added,
+    // but it was sent before the change.
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_timeline_event(TimelineTestEvent::Custom(json!({
+                "content": { "body": "hello", "msgtype": "m.text" },
+                "event_id": "$msda7m:localhost",
+                "origin_server_ts": 152037270,
+                "sender": "@alice:example.org",
+                "type": "m.room.message",
+            })))
+            .add_timeline_event(TimelineTestEvent::Custom(json!({
+                "content": { "displayname": "Alice Margatroid", "membership": "join" },
+                "event_id": "$rename:example.org",
+                "origin_server_ts": 152037280,
+                "sender": "@alice:example.org",
+                "state_key": "@alice:example.org",
+                "type": "m.room.member",
+                "unsigned": {
+                    "prev_content": { "displayname": "Alice", "membership": "join" },
+                },
+            })))
+            .add_timeline_event(TimelineTestEvent::Custom(json!({
+                "content": { "body": "hello again", "msgtype": "m.text" },
+                "event_id": "$jdf8sa:localhost",
+                "origin_server_ts": 152037290,
+                "sender": "@alice:example.org",
+                "type": "m.room.message",
+            }))),
+    );
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let display_name = |item: Arc<TimelineItem>| {
+        let profile = assert_matches!(
+            item.as_event().unwrap().sender_profile(),
+            TimelineDetails::Ready(profile) => profile
+        );
+        profile.display_name.clone().unwrap()
+    };
+
+    // The first message is corrected once the display name change is seen.
+    let item =
+        assert_matches!(timeline_stream.next().await, Some(VecDiff::Push { value }) => value);
+    assert_eq!(display_name(item), "Alice Margatroid");
+    let item = assert_matches!(
+        timeline_stream.next().await,
+        Some(VecDiff::UpdateAt { index: 0, value }) => value
+    );
+    assert_eq!(display_name(item), "Alice");
+
+    let item =
+        assert_matches!(timeline_stream.next().await, Some(VecDiff::Push { value }) => value);
+    assert_eq!(display_name(item), "Alice Margatroid");
+    let item =
+        assert_matches!(timeline_stream.next().await, Some(VecDiff::Push { value }) => value);
+    assert_eq!(display_name(item), "Alice Margatroid");
+}
+
+#[async_test]
+async fn sender_profile_fetch_members() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = EventBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room.timeline().await;
+    let mut timeline_stream = timeline.signal().to_stream();
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/members"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::MEMBERS))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    // The sender is not in the store, so the members of the room are fetched.
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_timeline_event(
+        TimelineTestEvent::Custom(json!({
+            "content": { "body": "hello", "msgtype": "m.text" },
+            "event_id": "$msda7m:localhost",
+            "origin_server_ts": 152037280,
+            "sender": "@example:localhost",
+            "type": "m.room.message",
+        })),
+    ));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+
+    let item =
+        assert_matches!(timeline_stream.next().await, Some(VecDiff::Push { value }) => value);
+    assert_matches!(item.as_event().unwrap().sender_profile(), TimelineDetails::Unavailable);
+    let item = assert_matches!(
+        timeline_stream.next().await,
+        Some(VecDiff::UpdateAt { index: 0, value }) => value
+    );
+    assert_matches!(item.as_event().unwrap().sender_profile(), TimelineDetails::Pending);
+    let item = assert_matches!(
+        timeline_stream.next().await,
+        Some(VecDiff::UpdateAt { index: 0, value }) => value
+    );
+    let profile = assert_matches!(
+        item.as_event().unwrap().sender_profile(),
+        TimelineDetails::Ready(profile) => profile
+    );
+    assert_eq!(profile.display_name.as_deref(), Some("example"));
+}
+
+#[async_test]
+async fn back_pagination_into_predecessor() {
+    let old_room_id = room_id!("!old:example.org");
+    let room_id = room_id!("!new:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = EventBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(old_room_id)).add_joined_room(
+        JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::Custom(json!({
+            "content": {
+                "creator": "@example:localhost",
+                "predecessor": {
+                    "event_id": "$tombstone:example.org",
+                    "room_id": old_room_id,
+                },
+                "room_version": "9",
+            },
+            "event_id": "$create:example.org",
+            "origin_server_ts": 152037290,
+            "sender": "@example:localhost",
+            "state_key": "",
+            "type": "m.room.create",
+        }))),
+    );
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room.timeline().await;
+    let mut timeline_stream = timeline.signal().to_stream();
+
+    // The start of the new room is reached.
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*new.*/messages$"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "start": "t392-516_47314_0_7_1_1_1_11444_1",
+            "chunk": [{
+                "content": {
+                    "body": "hello",
+                    "msgtype": "m.text",
+                },
+                "event_id": "$new_msg:example.org",
+                "origin_server_ts": 152037300,
+                "room_id": room_id,
+                "sender": "@example:localhost",
+                "type": "m.room.message",
+            }],
+            "state": [],
+        })))
+        .expect(1)
+        .named("new_room_messages")
+        .mount(&server)
+        .await;
+
+    let outcome = timeline.paginate_backwards(uint!(10)).await.unwrap();
+    assert!(outcome.more_messages);
+    let item =
+        assert_matches!(timeline_stream.next().await, Some(VecDiff::Push { value }) => value);
+    assert_eq!(item.as_event().unwrap().event_id().unwrap().as_str(), "$new_msg:example.org");
+
+    // Pagination continues in the old room.
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*old.*/messages$"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "start": "t392-516_47314_0_7_1_1_1_11444_1",
+            "chunk": [{
+                "content": {
+                    "body": "hello from the past",
+                    "msgtype": "m.text",
+                },
+                "event_id": "$old_msg:example.org",
+                "origin_server_ts": 152037200,
+                "room_id": old_room_id,
+                "sender": "@example:localhost",
+                "type": "m.room.message",
+            }],
+            "state": [],
+        })))
+        .expect(1)
+        .named("old_room_messages")
+        .mount(&server)
+        .await;
+
+    let outcome = timeline.paginate_backwards(uint!(10)).await.unwrap();
+    // The old room doesn't have a predecessor.
+    assert!(!outcome.more_messages);
+    let item = assert_matches!(
+        timeline_stream.next().await,
+        Some(VecDiff::InsertAt { index: 0, value }) => value
+    );
+    assert_eq!(item.as_event().unwrap().event_id().unwrap().as_str(), "$old_msg:example.org");
+}
+
+#[async_test]
+async fn focused_timeline() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let message = |event_id: &str, ts: u64| {
+        json!({
+            "content": {
+                "body": "hello",
+                "msgtype": "m.text",
+            },
+            "event_id": event_id,
+            "origin_server_ts": ts,
+            "room_id": room_id,
+            "sender": "@alice:example.org",
+            "type": "m.room.message",
+        })
+    };
+
+    let mut ev_builder = EventBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/context/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "start": "t1",
+            "end": "t2",
+            "events_before": [message("$before:localhost", 152037280)],
+            "event": message("$focused:localhost", 152037281),
+            "events_after": [],
+            "state": [],
+        })))
+        .expect(1)
+        .named("context")
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline =
+        room.timeline_focused_on(event_id!("$focused:localhost"), uint!(10)).await.unwrap();
+    let latest_event = timeline.latest_event().unwrap();
+    assert_eq!(latest_event.event_id(), Some(event_id!("$focused:localhost")));
+
+    // Live events are not added while there is a gap to the live end.
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_timeline_event(TimelineTestEvent::Custom(message("$live1:localhost", 152037290))),
+    );
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let latest_event = timeline.latest_event().unwrap();
+    assert_eq!(latest_event.event_id(), Some(event_id!("$focused:localhost")));
+
+    // Forward pagination fills the gap…
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(query_param("from", "t2"))
+        .and(query_param("dir", "f"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "start": "t2",
+            "end": "t3",
+            "chunk": [
+                message("$after:localhost", 152037282),
+                message("$live1:localhost", 152037290),
+            ],
+        })))
+        .expect(1)
+        .named("messages_1")
+        .mount(&server)
+        .await;
+
+    let outcome = timeline.paginate_forwards(uint!(10)).await.unwrap();
+    assert!(outcome.more_messages);
+    let latest_event = timeline.latest_event().unwrap();
+    assert_eq!(latest_event.event_id(), Some(event_id!("$live1:localhost")));
+
+    // …until the live end is reached.
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(query_param("from", "t3"))
+        .and(query_param("dir", "f"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "start": "t3",
+            "chunk": [],
+        })))
+        .expect(1)
+        .named("messages_2")
+        .mount(&server)
+        .await;
+
+    let outcome = timeline.paginate_forwards(uint!(10)).await.unwrap();
+    assert!(!outcome.more_messages);
+
+    let outcome = timeline.paginate_forwards(uint!(10)).await.unwrap();
+    assert!(!outcome.more_messages);
+
+    // Now live events are added.
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_timeline_event(TimelineTestEvent::Custom(message("$live2:localhost", 152037300))),
+    );
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let latest_event = timeline.latest_event().unwrap();
+    assert_eq!(latest_event.event_id(), Some(event_id!("$live2:localhost")));
 }
@@ -10,7 +10,7 @@ pub mod test_json;
 
 pub use event_builder::{
     bulk_room_members, EphemeralTestEvent, EventBuilder, GlobalAccountDataTestEvent,
-    InvitedRoomBuilder, JoinedRoomBuilder, LeftRoomBuilder, PresenceTestEvent,
+    InvitedRoomBuilder, JoinedRoomBuilder, KnockedRoomBuilder, LeftRoomBuilder, PresenceTestEvent,
     RoomAccountDataTestEvent, StateTestEvent, StrippedStateTestEvent, TimelineTestEvent,
 };
 